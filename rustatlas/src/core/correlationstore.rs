@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use crate::math::ad::genericnumber::Real;
+use crate::time::date::Date;
+use crate::utils::errors::{AtlasError, Result};
+
+/// # CorrelationStore
+/// Store for constant correlations between underliers. Currency pairs are named `EUR/USD`, any
+/// other name refers to an equity. Pairs without a correlation are treated as independent.
+#[derive(Clone)]
+pub struct CorrelationStore<T: Real> {
+    reference_date: Date,
+    correlation_map: HashMap<(String, String), T>,
+}
+
+impl<T: Real> CorrelationStore<T> {
+    pub fn new(reference_date: Date) -> Self {
+        Self {
+            reference_date,
+            correlation_map: HashMap::new(),
+        }
+    }
+
+    pub fn reference_date(&self) -> Date {
+        self.reference_date
+    }
+
+    pub fn add_correlation(&mut self, first: String, second: String, correlation: T) -> Result<()> {
+        if correlation > T::from(1.0) || correlation < T::from(-1.0) {
+            return Err(AtlasError::InvalidValueErr(format!(
+                "Correlation between {} and {} must be within [-1, 1]",
+                first, second
+            )));
+        }
+        self.correlation_map.insert((first, second), correlation);
+        Ok(())
+    }
+
+    pub fn get_correlation(&self, first: &str, second: &str) -> T {
+        if first == second {
+            return T::from(1.0);
+        }
+        self.correlation_map
+            .get(&(first.to_string(), second.to_string()))
+            .or(self
+                .correlation_map
+                .get(&(second.to_string(), first.to_string())))
+            .cloned()
+            .unwrap_or(T::from(0.0))
+    }
+
+    pub fn get_correlation_map(&self) -> &HashMap<(String, String), T> {
+        &self.correlation_map
+    }
+
+    /// # cholesky
+    /// Lower triangular factor of the correlations between the simulated drivers, in the given
+    /// order. Perfectly correlated drivers are allowed, inconsistent correlations fail naming the
+    /// driver where the matrix stops being positive semi-definite.
+    pub fn cholesky(&self, drivers: &[String]) -> Result<Vec<Vec<T>>> {
+        let n = drivers.len();
+        let tolerance = T::from(1e-12);
+        let mut l = vec![vec![T::from(0.0); n]; n];
+        for i in 0..n {
+            for j in 0..=i {
+                let correlation = self.get_correlation(&drivers[i], &drivers[j]);
+                let sum = (0..j).fold(correlation, |acc, k| acc - l[i][k] * l[j][k]);
+                if i == j {
+                    if sum < -tolerance {
+                        return Err(AtlasError::InvalidValueErr(format!(
+                            "Correlations of {} with {} are not positive semi-definite",
+                            drivers[i],
+                            drivers[..i].join(", ")
+                        )));
+                    }
+                    l[i][j] = if sum > tolerance {
+                        sum.sqrt()
+                    } else {
+                        T::from(0.0)
+                    };
+                } else if l[j][j] > tolerance {
+                    l[i][j] = sum / l[j][j];
+                }
+            }
+        }
+        Ok(l)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_correlation() {
+        let mut store = CorrelationStore::<f64>::new(Date::new(2024, 1, 1));
+        store
+            .add_correlation("AAPL".to_string(), "MSFT".to_string(), 0.6)
+            .unwrap();
+
+        assert_eq!(store.get_correlation("AAPL", "MSFT"), 0.6);
+        assert_eq!(store.get_correlation("MSFT", "AAPL"), 0.6);
+        assert_eq!(store.get_correlation("AAPL", "AAPL"), 1.0);
+        assert_eq!(store.get_correlation("AAPL", "EUR/USD"), 0.0);
+        assert!(store
+            .add_correlation("AAPL".to_string(), "EUR/USD".to_string(), 1.5)
+            .is_err());
+    }
+
+    #[test]
+    fn test_cholesky() {
+        let mut store = CorrelationStore::<f64>::new(Date::new(2024, 1, 1));
+        store
+            .add_correlation("AAPL".to_string(), "EUR/USD".to_string(), 0.5)
+            .unwrap();
+        let drivers = ["AAPL", "EUR/USD", "MSFT"].map(String::from);
+        let l = store.cholesky(&drivers).unwrap();
+        for i in 0..3 {
+            for j in 0..3 {
+                let product = (0..3).map(|k| l[i][k] * l[j][k]).sum::<f64>();
+                let expected = store.get_correlation(&drivers[i], &drivers[j]);
+                assert!((product - expected).abs() < 1e-12);
+            }
+        }
+
+        // MSFT can not move with AAPL and against EUR/USD that much
+        store
+            .add_correlation("AAPL".to_string(), "MSFT".to_string(), 0.9)
+            .unwrap();
+        store
+            .add_correlation("EUR/USD".to_string(), "MSFT".to_string(), -0.9)
+            .unwrap();
+        assert!(store.cholesky(&drivers).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use crate::{
+    core::volatilitysurface::VolatilitySurface,
+    equities::dividend::Dividend,
+    time::date::Date,
+    utils::errors::{AtlasError, Result},
+};
+
+/// # HistoricalData
+/// A store for published index fixings. Observations dated before the reference date are read
+/// from here instead of being simulated. Also holds the implied volatility surfaces quoted at the
+/// reference date and the announced dividends of the equities.
+///
+/// ## Parameters
+/// * `reference_date` - The reference date of the model
+/// * `fixings` - The published fixings, by index name and date
+/// * `volatility_surfaces` - The implied volatility surfaces, by underlier name
+/// * `dividends` - The dividends by equity name, sorted by ex-date
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoricalData {
+    reference_date: Date,
+    fixings: HashMap<String, HashMap<Date, f64>>,
+    volatility_surfaces: HashMap<String, VolatilitySurface>,
+    dividends: HashMap<String, Vec<(Date, Dividend)>>,
+}
+
+impl HistoricalData {
+    pub fn new(reference_date: Date) -> HistoricalData {
+        HistoricalData {
+            reference_date,
+            fixings: HashMap::new(),
+            volatility_surfaces: HashMap::new(),
+            dividends: HashMap::new(),
+        }
+    }
+
+    pub fn with_fixings(mut self, name: &str, fixings: HashMap<Date, f64>) -> Self {
+        self.fixings
+            .entry(name.to_string())
+            .or_default()
+            .extend(fixings);
+        self
+    }
+
+    pub fn add_fixing(&mut self, name: &str, date: Date, value: f64) {
+        self.fixings
+            .entry(name.to_string())
+            .or_default()
+            .insert(date, value);
+    }
+
+    /// # with_volatility_surface
+    /// Implied volatility surface of an underlier, named as in `VolatilityRequest`
+    pub fn with_volatility_surface(mut self, name: &str, surface: VolatilitySurface) -> Self {
+        self.volatility_surfaces.insert(name.to_string(), surface);
+        self
+    }
+
+    /// # with_dividends
+    /// Dividends of an equity by ex-date, added to the ones already known
+    pub fn with_dividends(mut self, name: &str, dividends: Vec<(Date, Dividend)>) -> Self {
+        let schedule = self.dividends.entry(name.to_string()).or_default();
+        schedule.extend(dividends);
+        schedule.sort_by_key(|(ex_date, _)| *ex_date);
+        self
+    }
+
+    pub fn reference_date(&self) -> Date {
+        self.reference_date
+    }
+
+    /// # is_historical
+    /// Whether an observation at the given date must be read from the published fixings
+    pub fn is_historical(&self, date: Date) -> bool {
+        date < self.reference_date
+    }
+
+    pub fn fixing(&self, name: &str, date: Date) -> Result<f64> {
+        self.fixings
+            .get(name)
+            .and_then(|fixings| fixings.get(&date))
+            .copied()
+            .ok_or(AtlasError::NotFoundErr(format!(
+                "Fixing for {} at {}",
+                name, date
+            )))
+    }
+
+    pub fn volatility_surface(&self, name: &str) -> Result<&VolatilitySurface> {
+        self.volatility_surfaces
+            .get(name)
+            .ok_or(AtlasError::NotFoundErr(format!(
+                "Volatility surface for {}",
+                name
+            )))
+    }
+
+    /// # volatility_surfaces
+    /// Implied volatility surfaces of every underlier, by name
+    pub fn volatility_surfaces(&self) -> &HashMap<String, VolatilitySurface> {
+        &self.volatility_surfaces
+    }
+
+    /// # dividends
+    /// Dividends of an equity sorted by ex-date, empty if it has none
+    pub fn dividends(&self, name: &str) -> &[(Date, Dividend)] {
+        self.dividends.get(name).map_or(&[], |dividends| dividends)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixing() {
+        let reference_date = Date::new(2024, 1, 15);
+        let mut data = HistoricalData::new(reference_date)
+            .with_fixings("SOFR-3M", HashMap::from([(Date::new(2024, 1, 10), 0.053)]));
+        data.add_fixing("SOFR-3M", Date::new(2024, 1, 11), 0.054);
+
+        assert_eq!(data.fixing("SOFR-3M", Date::new(2024, 1, 10)).unwrap(), 0.053);
+        assert_eq!(data.fixing("SOFR-3M", Date::new(2024, 1, 11)).unwrap(), 0.054);
+        assert!(data.fixing("SOFR-3M", Date::new(2024, 1, 12)).is_err());
+        assert!(data.fixing("ESTR", Date::new(2024, 1, 10)).is_err());
+    }
+
+    #[test]
+    fn test_volatility_surface() {
+        let surface = VolatilitySurface::new(vec![1.0], vec![1.0], vec![vec![0.2]]).unwrap();
+        let data = HistoricalData::new(Date::new(2024, 1, 15))
+            .with_volatility_surface("USD/CLP", surface.clone());
+        assert_eq!(data.volatility_surface("USD/CLP").unwrap(), &surface);
+        assert!(data.volatility_surface("EUR/USD").is_err());
+    }
+
+    #[test]
+    fn test_dividends() {
+        let (first, second) = (Date::new(2024, 3, 1), Date::new(2024, 9, 1));
+        let data = HistoricalData::new(Date::new(2024, 1, 15))
+            .with_dividends("AAPL", vec![(second, Dividend::Proportional(0.01))])
+            .with_dividends("AAPL", vec![(first, Dividend::Cash(0.25))]);
+        assert_eq!(
+            data.dividends("AAPL"),
+            &[
+                (first, Dividend::Cash(0.25)),
+                (second, Dividend::Proportional(0.01))
+            ]
+        );
+        assert!(data.dividends("MSFT").is_empty());
+    }
+
+    #[test]
+    fn test_is_historical() {
+        let data = HistoricalData::new(Date::new(2024, 1, 15));
+        assert!(data.is_historical(Date::new(2024, 1, 14)));
+        assert!(!data.is_historical(Date::new(2024, 1, 15)));
+    }
+}
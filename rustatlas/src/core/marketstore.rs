@@ -2,6 +2,7 @@ use core::fmt;
 use std::sync::{Arc, RwLock};
 
 use crate::{
+    core::correlationstore::CorrelationStore,
     currencies::{enums::Currency, exchangeratestore::ExchangeRateStore, traits::CurrencyDetails},
     equities::equitystore::EquityStore,
     rates::{
@@ -30,6 +31,7 @@ pub struct MarketStore<T: Real> {
     exchange_rate_store: ExchangeRateStore<T>,
     index_store: IndexStore<T>,
     equity_store: EquityStore<T>,
+    correlation_store: CorrelationStore<T>,
 }
 
 impl<T: Real> MarketStore<T> {
@@ -40,6 +42,7 @@ impl<T: Real> MarketStore<T> {
             exchange_rate_store: ExchangeRateStore::new(reference_date),
             index_store: IndexStore::new(reference_date),
             equity_store: EquityStore::new(reference_date),
+            correlation_store: CorrelationStore::new(reference_date),
         }
     }
 
@@ -63,6 +66,14 @@ impl<T: Real> MarketStore<T> {
         &mut self.equity_store
     }
 
+    pub fn correlation_store(&self) -> &CorrelationStore<T> {
+        &self.correlation_store
+    }
+
+    pub fn mut_correlation_store(&mut self) -> &mut CorrelationStore<T> {
+        &mut self.correlation_store
+    }
+
     pub fn index_store(&self) -> &IndexStore<T> {
         &self.index_store
     }
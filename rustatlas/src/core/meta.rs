@@ -116,6 +116,10 @@ impl ForwardRateRequest {
         self.provider_id
     }
 
+    pub fn fixing_date(&self) -> Date {
+        self.fixing_date
+    }
+
     pub fn start_date(&self) -> Date {
         self.start_date
     }
@@ -133,6 +137,129 @@ impl ForwardRateRequest {
     }
 }
 
+/// # EquityRequest
+/// Meta data for an equity price. Holds the equity name and the reference date required to fetch
+/// the price.
+///
+/// ## Parameters
+/// * `name` - The name of the equity.
+/// * `reference_date` - The reference date of the price.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EquityRequest {
+    name: String,
+    reference_date: Option<Date>,
+}
+
+impl EquityRequest {
+    pub fn new(name: String, reference_date: Option<Date>) -> EquityRequest {
+        EquityRequest {
+            name,
+            reference_date,
+        }
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn reference_date(&self) -> Option<Date> {
+        self.reference_date
+    }
+}
+
+/// # VolatilityRequest
+/// Meta data for a volatility. Holds the underlying name and the reference date required to fetch
+/// the volatility. Currency pairs are written as `EUR/USD`, any other name refers to an equity.
+///
+/// ## Parameters
+/// * `name` - The name of the underlying.
+/// * `reference_date` - The reference date of the volatility.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolatilityRequest {
+    name: String,
+    reference_date: Option<Date>,
+}
+
+impl VolatilityRequest {
+    pub fn new(name: String, reference_date: Option<Date>) -> VolatilityRequest {
+        VolatilityRequest {
+            name,
+            reference_date,
+        }
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn reference_date(&self) -> Option<Date> {
+        self.reference_date
+    }
+
+    /// # currency_pair
+    /// The currencies of the underlying, if it is an exchange rate
+    pub fn currency_pair(&self) -> Option<(Currency, Currency)> {
+        let (first, second) = self.name.split_once('/')?;
+        let first = Currency::try_from(first.to_string()).ok()?;
+        let second = Currency::try_from(second.to_string()).ok()?;
+        Some((first, second))
+    }
+}
+
+/// # FixingRequest
+/// Meta data for an observation dated before the model reference date. It is read from the
+/// published fixings in `HistoricalData` instead of being simulated.
+///
+/// ## Parameters
+/// * `name` - The name of the index or equity.
+/// * `date` - The observation date.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixingRequest {
+    name: String,
+    date: Date,
+}
+
+impl FixingRequest {
+    pub fn new(name: String, date: Date) -> FixingRequest {
+        FixingRequest { name, date }
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn date(&self) -> Date {
+        self.date
+    }
+}
+
+/// # CorrelationRequest
+/// Meta data for a correlation. Holds the names of the two underliers, following the naming of
+/// `VolatilityRequest`.
+///
+/// ## Parameters
+/// * `first` - The name of the first underlier.
+/// * `second` - The name of the second underlier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorrelationRequest {
+    first: String,
+    second: String,
+}
+
+impl CorrelationRequest {
+    pub fn new(first: String, second: String) -> CorrelationRequest {
+        CorrelationRequest { first, second }
+    }
+
+    pub fn first(&self) -> &String {
+        &self.first
+    }
+
+    pub fn second(&self) -> &String {
+        &self.second
+    }
+}
+
 /// # MarketRequest
 /// Meta data for market data. Holds all the meta data required to fetch the market data.
 ///
@@ -141,12 +268,18 @@ impl ForwardRateRequest {
 /// * `df` - The discount factor meta data.
 /// * `fwd` - The forward rate meta data.
 /// * `fx` - The exchange rate meta data.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// * `equity` - The equity price meta data.
+/// * `vol` - The volatility meta data.
+/// * `corr` - The correlation meta data.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MarketRequest {
     id: usize,
     df: Option<DiscountFactorRequest>,
     fwd: Option<ForwardRateRequest>,
     fx: Option<ExchangeRateRequest>,
+    equity: Option<EquityRequest>,
+    vol: Option<VolatilityRequest>,
+    corr: Option<CorrelationRequest>,
 }
 
 impl MarketRequest {
@@ -156,7 +289,30 @@ impl MarketRequest {
         fwd: Option<ForwardRateRequest>,
         fx: Option<ExchangeRateRequest>,
     ) -> MarketRequest {
-        MarketRequest { id, df, fwd, fx }
+        MarketRequest {
+            id,
+            df,
+            fwd,
+            fx,
+            equity: None,
+            vol: None,
+            corr: None,
+        }
+    }
+
+    pub fn with_equity(mut self, equity: EquityRequest) -> MarketRequest {
+        self.equity = Some(equity);
+        self
+    }
+
+    pub fn with_vol(mut self, vol: VolatilityRequest) -> MarketRequest {
+        self.vol = Some(vol);
+        self
+    }
+
+    pub fn with_corr(mut self, corr: CorrelationRequest) -> MarketRequest {
+        self.corr = Some(corr);
+        self
     }
 
     pub fn id(&self) -> usize {
@@ -174,6 +330,18 @@ impl MarketRequest {
     pub fn fx(&self) -> Option<ExchangeRateRequest> {
         self.fx
     }
+
+    pub fn equity(&self) -> Option<EquityRequest> {
+        self.equity.clone()
+    }
+
+    pub fn vol(&self) -> Option<VolatilityRequest> {
+        self.vol.clone()
+    }
+
+    pub fn corr(&self) -> Option<CorrelationRequest> {
+        self.corr.clone()
+    }
 }
 
 /// # MarketDataNode
@@ -184,6 +352,9 @@ impl MarketRequest {
 /// * `df` - The discount factor.
 /// * `fwd` - The forward rate.
 /// * `fx` - The exchange rate.
+/// * `equity` - The equity price.
+/// * `vol` - The volatility.
+/// * `corr` - The correlation.
 #[derive(Debug, Clone, Copy)]
 pub struct MarketData<T = f64> {
     id: usize,
@@ -191,6 +362,9 @@ pub struct MarketData<T = f64> {
     df: Option<T>,
     fwd: Option<T>,
     fx: Option<T>,
+    equity: Option<T>,
+    vol: Option<T>,
+    corr: Option<T>,
     numerarie: T,
 }
 
@@ -209,10 +383,28 @@ impl<T> MarketData<T> {
             df,
             fwd,
             fx,
+            equity: None,
+            vol: None,
+            corr: None,
             numerarie,
         }
     }
 
+    pub fn with_equity(mut self, equity: Option<T>) -> MarketData<T> {
+        self.equity = equity;
+        self
+    }
+
+    pub fn with_vol(mut self, vol: Option<T>) -> MarketData<T> {
+        self.vol = vol;
+        self
+    }
+
+    pub fn with_corr(mut self, corr: Option<T>) -> MarketData<T> {
+        self.corr = corr;
+        self
+    }
+
     pub fn id(&self) -> usize {
         self.id
     }
@@ -236,6 +428,19 @@ impl<T: Copy> MarketData<T> {
         self.fx.ok_or(AtlasError::ValueNotSetErr("fx".to_string()))
     }
 
+    pub fn equity(&self) -> Result<T> {
+        self.equity
+            .ok_or(AtlasError::ValueNotSetErr("equity".to_string()))
+    }
+
+    pub fn vol(&self) -> Result<T> {
+        self.vol.ok_or(AtlasError::ValueNotSetErr("vol".to_string()))
+    }
+
+    pub fn corr(&self) -> Result<T> {
+        self.corr.ok_or(AtlasError::ValueNotSetErr("corr".to_string()))
+    }
+
     pub fn numerarie(&self) -> T {
         self.numerarie
     }
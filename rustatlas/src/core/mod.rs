@@ -1,3 +1,6 @@
+pub mod correlationstore;
+pub mod historicaldata;
 pub mod marketstore;
 pub mod meta;
 pub mod traits;
+pub mod volatilitysurface;
@@ -0,0 +1,126 @@
+use crate::math::interpolation::{monotonecubic::MonotoneCubicInterpolator, traits::Interpolate};
+use crate::utils::errors::{AtlasError, Result};
+
+/// # VolatilitySurface
+/// Implied volatilities of an underlier by expiry, in years, and moneyness, the strike over the
+/// forward to the expiry. Volatilities are interpolated along the log-moneyness with a monotone
+/// cubic, so the surface has the smooth strike derivatives Dupire needs, and total variances
+/// linearly in time. Both are flat beyond the quotes.
+///
+/// ## Parameters
+/// * `expiries` - Increasing expiries, in years
+/// * `moneyness` - Increasing strikes over the forward
+/// * `volatilities` - Implied volatilities, one row of `moneyness.len()` quotes per expiry
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolatilitySurface {
+    expiries: Vec<f64>,
+    log_moneyness: Vec<f64>,
+    volatilities: Vec<Vec<f64>>,
+}
+
+impl VolatilitySurface {
+    pub fn new(
+        expiries: Vec<f64>,
+        moneyness: Vec<f64>,
+        volatilities: Vec<Vec<f64>>,
+    ) -> Result<VolatilitySurface> {
+        let increasing = |v: &[f64]| !v.is_empty() && v.windows(2).all(|w| w[0] < w[1]);
+        if !increasing(&expiries) || expiries[0] <= 0.0 {
+            return Err(AtlasError::InvalidValueErr(
+                "Volatility surface expiries must be positive and increasing".to_string(),
+            ));
+        }
+        if !increasing(&moneyness) || moneyness[0] <= 0.0 {
+            return Err(AtlasError::InvalidValueErr(
+                "Volatility surface moneyness must be positive and increasing".to_string(),
+            ));
+        }
+        if volatilities.len() != expiries.len()
+            || volatilities.iter().any(|row| row.len() != moneyness.len())
+        {
+            return Err(AtlasError::InvalidValueErr(
+                "Volatility surface needs one quote per expiry and moneyness".to_string(),
+            ));
+        }
+        if volatilities.iter().flatten().any(|vol| *vol <= 0.0) {
+            return Err(AtlasError::InvalidValueErr(
+                "Volatility surface quotes must be positive".to_string(),
+            ));
+        }
+        Ok(VolatilitySurface {
+            expiries,
+            log_moneyness: moneyness.iter().map(|m| m.ln()).collect(),
+            volatilities,
+        })
+    }
+
+    pub fn expiries(&self) -> &Vec<f64> {
+        &self.expiries
+    }
+
+    pub fn log_moneyness(&self) -> &Vec<f64> {
+        &self.log_moneyness
+    }
+
+    pub fn volatilities(&self) -> &Vec<Vec<f64>> {
+        &self.volatilities
+    }
+
+    /// Implied volatility of the quoted expiry `i` at the log-moneyness `y`
+    fn quoted_volatility(&self, i: usize, y: f64) -> f64 {
+        let ys = &self.log_moneyness;
+        if ys.len() == 1 {
+            return self.volatilities[i][0];
+        }
+        let y = y.clamp(ys[0], ys[ys.len() - 1]);
+        MonotoneCubicInterpolator::interpolate(y, ys, &self.volatilities[i], false)
+    }
+
+    /// # total_variance
+    /// Implied variance times the expiry `t` at the log-moneyness `y`
+    pub fn total_variance(&self, t: f64, y: f64) -> f64 {
+        let expiries = &self.expiries;
+        let last = expiries.len() - 1;
+        let quoted = |i: usize| self.quoted_volatility(i, y).powi(2) * expiries[i];
+        match expiries.iter().position(|expiry| *expiry >= t) {
+            Some(0) => quoted(0) * t / expiries[0],
+            Some(i) => {
+                let w = (t - expiries[i - 1]) / (expiries[i] - expiries[i - 1]);
+                quoted(i - 1) * (1.0 - w) + quoted(i) * w
+            }
+            None => quoted(last) * t / expiries[last],
+        }
+    }
+
+    /// # volatility
+    /// Implied volatility at the expiry `t` and the given moneyness
+    pub fn volatility(&self, t: f64, moneyness: f64) -> f64 {
+        (self.total_variance(t, moneyness.ln()) / t).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volatility_surface() {
+        let surface = VolatilitySurface::new(
+            vec![1.0, 2.0],
+            vec![0.8, 1.0, 1.2],
+            vec![vec![0.25, 0.2, 0.22], vec![0.3, 0.3, 0.3]],
+        )
+        .unwrap();
+
+        assert!((surface.volatility(1.0, 0.8) - 0.25).abs() < 1e-12);
+        assert!((surface.volatility(2.0, 1.1) - 0.3).abs() < 1e-12);
+        // flat beyond the quotes, linear total variance in between
+        assert!((surface.volatility(0.5, 1.0) - 0.2).abs() < 1e-12);
+        assert!((surface.volatility(1.0, 2.0) - 0.22).abs() < 1e-12);
+        let w = (0.2f64.powi(2) + 0.3f64.powi(2) * 2.0) / 2.0;
+        assert!((surface.total_variance(1.5, 0.0) - w).abs() < 1e-12);
+
+        assert!(VolatilitySurface::new(vec![1.0], vec![1.0, 0.9], vec![vec![0.2, 0.2]]).is_err());
+        assert!(VolatilitySurface::new(vec![1.0], vec![1.0], vec![vec![0.2, 0.2]]).is_err());
+    }
+}
@@ -0,0 +1,21 @@
+use crate::math::ad::genericnumber::Real;
+
+/// # Dividend
+/// Dividend paid by an equity at its ex-date, either a cash amount in the currency of the
+/// equity or a proportion of its spot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dividend {
+    Cash(f64),
+    Proportional(f64),
+}
+
+impl Dividend {
+    /// # apply
+    /// Spot right after the ex-date, given the spot right before
+    pub fn apply<T: Real>(&self, spot: T) -> T {
+        match self {
+            Dividend::Cash(amount) => spot - *amount,
+            Dividend::Proportional(ratio) => spot * (1.0 - ratio),
+        }
+    }
+}
@@ -4,18 +4,23 @@ use crate::math::ad::genericnumber::Real;
 use crate::time::date::Date;
 use crate::utils::errors::{AtlasError, Result};
 
-/// Store for asset volatilities. Currently maps currency pairs to constant volatilities.
+/// Store for equity spot prices, constant volatilities and continuous dividend yields, keyed by
+/// equity name. Equities without a dividend yield pay no dividends.
 #[derive(Clone)]
 pub struct EquityStore<T: Real> {
     reference_date: Date,
+    spot_map: HashMap<String, T>,
     volatility_map: HashMap<String, T>,
+    dividend_yield_map: HashMap<String, T>,
 }
 
 impl<T: Real> EquityStore<T> {
     pub fn new(reference_date: Date) -> Self {
         Self {
             reference_date,
+            spot_map: HashMap::new(),
             volatility_map: HashMap::new(),
+            dividend_yield_map: HashMap::new(),
         }
     }
 
@@ -23,6 +28,17 @@ impl<T: Real> EquityStore<T> {
         self.reference_date
     }
 
+    pub fn add_spot(&mut self, equity_name: String, spot: T) {
+        self.spot_map.insert(equity_name, spot);
+    }
+
+    pub fn get_spot(&self, equity_name: String) -> Result<T> {
+        self.spot_map
+            .get(&equity_name)
+            .cloned()
+            .ok_or_else(|| AtlasError::ValueNotSetErr(format!("Spot for {} not set", equity_name)))
+    }
+
     pub fn add_volatility(&mut self, equity_name: String, vol: T) {
         self.volatility_map.insert(equity_name, vol);
     }
@@ -39,4 +55,15 @@ impl<T: Real> EquityStore<T> {
     pub fn get_volatility_map(&self) -> &HashMap<String, T> {
         &self.volatility_map
     }
+
+    pub fn add_dividend_yield(&mut self, equity_name: String, dividend_yield: T) {
+        self.dividend_yield_map.insert(equity_name, dividend_yield);
+    }
+
+    pub fn get_dividend_yield(&self, equity_name: &str) -> T {
+        self.dividend_yield_map
+            .get(equity_name)
+            .cloned()
+            .unwrap_or(T::from(0.0))
+    }
 }
@@ -1 +1,2 @@
+pub mod dividend;
 pub mod equitystore;
@@ -1,10 +1,15 @@
 //! aad.rs  ―  Expression-template reverse-mode AD in pure Rust
-//! Public API:  ADNumber  +  free fns  exp, log, sqrt, fabs,
-//! normal_dens, normal_cdf, pow, max, min  +  flatten/propagation helpers.
+//! Public API:  ADNumber  +  free fns  exp, log, sqrt, fabs, tanh, atan, erf,
+//! normal_dens, normal_cdf, exp_m1, ln_1p, pow, powi, max, min  +  flatten/propagation helpers.
+//!
+//! Second order: tangents seeded on the leaves with `with_tangent` are carried forward while
+//! the tape is recorded (forward over reverse). After a reverse sweep `adjoint_tangent` holds
+//! the Hessian-vector product, e.g. the gamma of a price when the spot is seeded with 1.
 
 #![allow(clippy::needless_return)]
 
 use crate::prelude::*;
+use std::collections::HashMap;
 use std::ops::*;
 /* ═════════════════════════  REVERSE-MODE TAPE  ═══════════════════════ */
 
@@ -12,7 +17,11 @@ use std::ops::*;
 
 pub trait Expr: Clone {
     fn value(&self) -> f64;
-    fn push_adj(&self, parent: &mut Node, adj: f64);
+    /// Directional derivative of the value along the tangents seeded on the leaves
+    fn tangent(&self) -> f64;
+    /// Push the partial `adj` of the parent with respect to each leaf, `adj_tangent` being its
+    /// directional derivative
+    fn push_adj(&self, parent: &mut Node, adj: f64, adj_tangent: f64);
 }
 
 /* ═══════════════════════  LEAF: ADNumber  ═════════════════════════════ */
@@ -20,13 +29,31 @@ pub trait Expr: Clone {
 #[derive(Clone)]
 pub struct ADNumber {
     val: f64,
+    tan: f64,   // directional derivative along the seeded tangents
     idx: usize, // position on the tape
+    generation: u32, // generation of the tape the node was recorded in
 }
 
 impl ADNumber {
     pub fn new(v: f64) -> Self {
-        let idx = TAPE.with(|t| t.borrow_mut().new_leaf());
-        Self { val: v, idx }
+        let (idx, generation) = TAPE.with(|t| {
+            let mut t = t.borrow_mut();
+            let idx = t.new_leaf();
+            t.check_finite(idx, v, "input");
+            (idx, t.generation())
+        });
+        Self {
+            val: v,
+            tan: 0.0,
+            idx,
+            generation,
+        }
+    }
+
+    /// Seed the tangent of an input, the direction of the Hessian-vector product
+    pub fn with_tangent(mut self, tangent: f64) -> Self {
+        self.tan = tangent;
+        self
     }
 
     /* ---- accessors ---- */
@@ -35,34 +62,116 @@ impl ADNumber {
         self.val
     }
     #[inline]
+    pub fn tangent(&self) -> f64 {
+        self.tan
+    }
+    #[inline]
     pub fn adjoint(&self) -> f64 {
-        TAPE.with(|t| t.borrow().nodes[self.idx].adj)
+        TAPE.with(|t| self.live_node(&t.borrow()).adj)
+    }
+    /// Directional derivative of the adjoint along the seeded tangents, the component of the
+    /// Hessian-vector product for this input
+    #[inline]
+    pub fn adjoint_tangent(&self) -> f64 {
+        TAPE.with(|t| self.live_node(&t.borrow()).adj_tangent)
+    }
+
+    /// Whether the node is still on the tape, a number kept across a truncation of the tape
+    /// below its node is stale
+    pub fn is_live(&self) -> bool {
+        TAPE.with(|t| t.borrow().is_live(self.idx, self.generation))
+    }
+
+    fn live_node<'a>(&self, tape: &'a Tape) -> &'a Node {
+        assert!(
+            tape.is_live(self.idx, self.generation),
+            "stale ADNumber: node {} was dropped from the tape",
+            self.idx
+        );
+        &tape.nodes[self.idx]
     }
 
     /* ---- tape helpers ---- */
     pub fn reset_adjoints() {
         TAPE.with(|t| {
-            for n in &mut t.borrow_mut().nodes {
+            for n in t.borrow_mut().nodes.iter_mut() {
                 n.adj = 0.0;
+                n.adj_tangent = 0.0;
             }
         });
     }
 
+    /// Record `node` on the tape as a number of value `val` and tangent `tan`, computed by
+    /// `operation`
+    pub(crate) fn record(node: Node, val: f64, tan: f64, operation: &str) -> Self {
+        let (idx, generation) = TAPE.with(|t| {
+            let mut t = t.borrow_mut();
+            let idx = t.record(node);
+            t.check_finite(idx, val, operation);
+            (idx, t.generation())
+        });
+        ADNumber {
+            val,
+            tan,
+            idx,
+            generation,
+        }
+    }
+
+    /// Position of the node on the tape, also valid in a snapshot of the tape
+    pub fn index(&self) -> usize {
+        self.idx
+    }
+
     pub fn put_on_tape(&mut self) {
-        self.idx = TAPE.with(|t| t.borrow_mut().new_leaf());
+        (self.idx, self.generation) = TAPE.with(|t| {
+            let mut t = t.borrow_mut();
+            (t.new_leaf(), t.generation())
+        });
     }
 
     pub fn propagate_to_start(&self) {
-        TAPE.with(|t| t.borrow_mut().nodes[self.idx].adj = 1.0);
+        self.seed();
         propagate_range(self.idx, 0);
     }
 
     pub fn propagate_to_mark(&self) {
         let stop = TAPE.with(|t| t.borrow().mark);
-        TAPE.with(|t| t.borrow_mut().nodes[self.idx].adj = 1.0);
+        self.seed();
         propagate_range(self.idx, stop);
     }
 
+    fn seed(&self) {
+        TAPE.with(|t| {
+            let mut t = t.borrow_mut();
+            self.live_node(&t);
+            t.nodes[self.idx].adj = 1.0;
+        });
+    }
+
+    /// # propagate_pruned
+    /// `propagate_to_start` skipping the nodes the result does not depend on
+    pub fn propagate_pruned(&self) -> usize {
+        self.seed();
+        TAPE.with(|t| t.borrow_mut().propagate_reachable(&[self.idx]))
+    }
+
+    /// # propagate_checked
+    /// `propagate_to_start`, failing with the origin of the first NaN or infinite value the
+    /// result depends on when the tape tracks them, see `Tape::with_non_finite_tracking`
+    pub fn propagate_checked(&self) -> Result<()> {
+        self.propagate_to_start();
+        match non_finite_origin().filter(|origin| origin.node() <= self.idx) {
+            Some(origin) => Err(AtlasError::EvaluationErr(format!(
+                "non-finite value at node {} from {}\n{}",
+                origin.node(),
+                origin.operation(),
+                origin.backtrace()
+            ))),
+            None => Ok(()),
+        }
+    }
+
     pub fn propagate_mark_to_start() {
         let (from, to) = TAPE.with(|t| {
             let t = t.borrow();
@@ -70,6 +179,100 @@ impl ADNumber {
         });
         propagate_range(from, to);
     }
+
+    /// # backward_multi
+    /// Adjoints of several outputs in one reverse sweep: every node carries one adjoint per
+    /// output. Returns, for each output, its derivatives with respect to `inputs`. The adjoints
+    /// stored on the tape are left untouched.
+    pub fn backward_multi(outputs: &[ADNumber], inputs: &[ADNumber]) -> Vec<Vec<f64>> {
+        let m = outputs.len();
+        TAPE.with(|t| {
+            let t = t.borrow();
+            outputs.iter().chain(inputs).for_each(|x| {
+                x.live_node(&t);
+            });
+        });
+        let Some(last) = outputs.iter().map(|o| o.idx).max() else {
+            return Vec::new();
+        };
+        let mut adjs = vec![0.0; (last + 1) * m];
+        outputs
+            .iter()
+            .enumerate()
+            .for_each(|(k, o)| adjs[o.idx * m + k] += 1.0);
+
+        TAPE.with(|t| {
+            let t = t.borrow();
+            for i in (0..=last).rev() {
+                let node = &t.nodes[i];
+                for (&c, d) in node.childs.iter().zip(&node.derivs) {
+                    for k in 0..m {
+                        adjs[c * m + k] += adjs[i * m + k] * d;
+                    }
+                }
+            }
+        });
+
+        (0..m)
+            .map(|k| {
+                inputs
+                    .iter()
+                    .map(|x| adjs.get(x.idx * m + k).copied().unwrap_or(0.0))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// # backward_for
+    /// Derivatives of `self` with respect to a few `leaves` only, keyed by their index on the
+    /// tape. The sweep skips the nodes that do not depend on any of them, nor on a node older
+    /// than the oldest leaf. The adjoints stored on the tape are left untouched.
+    pub fn backward_for(&self, leaves: &[ADNumber]) -> HashMap<usize, f64> {
+        TAPE.with(|t| {
+            let t = t.borrow();
+            std::iter::once(self).chain(leaves).for_each(|x| {
+                x.live_node(&t);
+            });
+        });
+        let mut gradient: HashMap<usize, f64> = leaves.iter().map(|l| (l.idx, 0.0)).collect();
+        let Some(first) = leaves.iter().map(|l| l.idx).min().filter(|f| *f <= self.idx) else {
+            return gradient;
+        };
+        let n = self.idx - first + 1;
+
+        TAPE.with(|t| {
+            let t = t.borrow();
+            let nodes = (first..=self.idx).map(|i| &t.nodes[i]);
+            // whether each node depends on a requested leaf
+            let mut reaches = vec![false; n];
+            for (i, node) in nodes.enumerate() {
+                reaches[i] = gradient.contains_key(&(first + i))
+                    || node
+                        .childs
+                        .iter()
+                        .any(|c| *c >= first && reaches[c - first]);
+            }
+
+            let mut adjs = vec![0.0; n];
+            adjs[n - 1] = 1.0;
+            for i in (0..n).rev() {
+                if !reaches[i] || adjs[i] == 0.0 {
+                    continue;
+                }
+                let node = &t.nodes[first + i];
+                for (&c, d) in node.childs.iter().zip(&node.derivs) {
+                    if c >= first && reaches[c - first] {
+                        adjs[c - first] += adjs[i] * d;
+                    }
+                }
+            }
+            gradient
+                .iter_mut()
+                .filter(|(idx, _)| **idx <= self.idx)
+                .for_each(|(idx, g)| *g = adjs[*idx - first]);
+        });
+        gradient
+    }
 }
 
 impl Expr for ADNumber {
@@ -77,9 +280,15 @@ impl Expr for ADNumber {
         self.val
     }
 
-    fn push_adj(&self, parent: &mut Node, adj: f64) {
+    fn tangent(&self) -> f64 {
+        self.tan
+    }
+
+    fn push_adj(&self, parent: &mut Node, adj: f64, adj_tangent: f64) {
+        debug_assert!(self.is_live(), "stale ADNumber: node {} was dropped", self.idx);
         parent.childs.push(self.idx);
         parent.derivs.push(adj);
+        parent.deriv_tangents.push(adj_tangent);
     }
 }
 
@@ -98,7 +307,10 @@ impl Expr for Const {
     fn value(&self) -> f64 {
         self.0
     }
-    fn push_adj(&self, _parent: &mut Node, _adj: f64) {}
+    fn tangent(&self) -> f64 {
+        0.0
+    }
+    fn push_adj(&self, _parent: &mut Node, _adj: f64, _adj_tangent: f64) {}
 }
 
 /* ═════════════════════  OPERATOR “TYPE CLASSES”  ════════════════════ */
@@ -107,6 +319,16 @@ pub trait BinOp {
     fn eval(l: f64, r: f64) -> f64;
     fn d_left(l: f64, r: f64) -> f64;
     fn d_right(l: f64, r: f64) -> f64;
+    /* second derivatives, zero for operators linear in each argument */
+    fn d_left_left(_l: f64, _r: f64) -> f64 {
+        0.0
+    }
+    fn d_left_right(_l: f64, _r: f64) -> f64 {
+        0.0
+    }
+    fn d_right_right(_l: f64, _r: f64) -> f64 {
+        0.0
+    }
 }
 
 pub struct AddOp;
@@ -146,6 +368,9 @@ impl BinOp for MulOp {
     fn d_right(l: f64, _r: f64) -> f64 {
         l
     }
+    fn d_left_right(_l: f64, _r: f64) -> f64 {
+        1.0
+    }
 }
 
 pub struct DivOp;
@@ -159,6 +384,12 @@ impl BinOp for DivOp {
     fn d_right(l: f64, r: f64) -> f64 {
         -l / (r * r)
     }
+    fn d_left_right(_l: f64, r: f64) -> f64 {
+        -1.0 / (r * r)
+    }
+    fn d_right_right(l: f64, r: f64) -> f64 {
+        2.0 * l / (r * r * r)
+    }
 }
 
 pub struct PowOp;
@@ -172,6 +403,15 @@ impl BinOp for PowOp {
     fn d_right(l: f64, r: f64) -> f64 {
         l.powf(r) * l.ln()
     }
+    fn d_left_left(l: f64, r: f64) -> f64 {
+        r * (r - 1.0) * l.powf(r - 2.0)
+    }
+    fn d_left_right(l: f64, r: f64) -> f64 {
+        l.powf(r - 1.0) * (1.0 + r * l.ln())
+    }
+    fn d_right_right(l: f64, r: f64) -> f64 {
+        l.powf(r) * l.ln() * l.ln()
+    }
 }
 
 pub struct MaxOp;
@@ -262,16 +502,20 @@ pub struct BinExpr<L, R, O> {
     l: L,
     r: R,
     val: f64,
+    tan: f64,
     _ph: std::marker::PhantomData<O>,
 }
 
 impl<L: Expr, R: Expr, O: BinOp> BinExpr<L, R, O> {
     fn new(l: L, r: R) -> Self {
-        let val = O::eval(l.value(), r.value());
+        let (lv, rv) = (l.value(), r.value());
+        let val = O::eval(lv, rv);
+        let tan = O::d_left(lv, rv) * l.tangent() + O::d_right(lv, rv) * r.tangent();
         Self {
             l,
             r,
             val,
+            tan,
             _ph: std::marker::PhantomData,
         }
     }
@@ -282,11 +526,20 @@ impl<L: Expr, R: Expr, O: BinOp + Clone> Expr for BinExpr<L, R, O> {
         self.val
     }
 
-    fn push_adj(&self, parent: &mut Node, adj: f64) {
+    fn tangent(&self) -> f64 {
+        self.tan
+    }
+
+    fn push_adj(&self, parent: &mut Node, adj: f64, adj_tangent: f64) {
+        let (lv, rv) = (self.l.value(), self.r.value());
+        let (lt, rt) = (self.l.tangent(), self.r.tangent());
+        let (dl, dr) = (O::d_left(lv, rv), O::d_right(lv, rv));
+        let dl_tangent = O::d_left_left(lv, rv) * lt + O::d_left_right(lv, rv) * rt;
+        let dr_tangent = O::d_left_right(lv, rv) * lt + O::d_right_right(lv, rv) * rt;
         self.l
-            .push_adj(parent, adj * O::d_left(self.l.value(), self.r.value()));
+            .push_adj(parent, adj * dl, adj_tangent * dl + adj * dl_tangent);
         self.r
-            .push_adj(parent, adj * O::d_right(self.l.value(), self.r.value()));
+            .push_adj(parent, adj * dr, adj_tangent * dr + adj * dr_tangent);
     }
 }
 
@@ -295,10 +548,12 @@ impl<L: Expr, R: Expr, O: BinOp + Clone> Expr for BinExpr<L, R, O> {
 pub trait UnOp {
     fn eval(x: f64) -> f64;
     fn deriv(x: f64, v: f64) -> f64;
+    fn deriv2(x: f64, v: f64) -> f64;
 }
 
 macro_rules! un_op {
-    ($name:ident, $eval:expr, $d:expr) => {
+    ($name:ident, $eval:expr, $d:expr, $d2:expr) => {
+        #[derive(Clone)]
         pub struct $name;
         impl UnOp for $name {
             fn eval(x: f64) -> f64 {
@@ -307,30 +562,87 @@ macro_rules! un_op {
             fn deriv(x: f64, v: f64) -> f64 {
                 $d(x, v)
             }
+            fn deriv2(x: f64, v: f64) -> f64 {
+                $d2(x, v)
+            }
         }
     };
 }
 
-un_op!(ExpOp, f64::exp, |_x, v| v);
-un_op!(LogOp, f64::ln, |x, _v| 1.0 / x);
-un_op!(SqrtOp, f64::sqrt, |_x, v| 0.5 / v);
-un_op!(FabsOp, f64::abs, |x, _v| if x >= 0.0 { 1.0 } else { -1.0 });
-un_op!(SinOp, f64::sin, |x, v| v * f64::cos(x));
-un_op!(CosOp, f64::cos, |x, v| -v * f64::sin(x));
+un_op!(ExpOp, f64::exp, |_x, v| v, |_x, v| v);
+un_op!(LogOp, f64::ln, |x, _v| 1.0 / x, |x: f64, _v| -1.0 / (x * x));
+un_op!(SqrtOp, f64::sqrt, |_x, v| 0.5 / v, |x: f64, v: f64| -0.25 / (x * v));
+un_op!(FabsOp, f64::abs, |x, _v| if x >= 0.0 { 1.0 } else { -1.0 }, |_x, _v| 0.0);
+un_op!(SinOp, f64::sin, |x, v| v * f64::cos(x), |_x, v: f64| -v);
+un_op!(CosOp, f64::cos, |x: f64, v: f64| -v * f64::sin(x), |_x, v: f64| -v);
+un_op!(TanhOp, f64::tanh, |_x, v: f64| 1.0 - v * v, |_x, v: f64| -2.0 * v * (1.0 - v * v));
+un_op!(AtanOp, f64::atan, |x: f64, _v| 1.0 / (1.0 + x * x), |x: f64, _v| {
+    -2.0 * x / ((1.0 + x * x) * (1.0 + x * x))
+});
+un_op!(ErfOp, erf_f64, |x: f64, _v| 2.0 * FRAC_1_SQRT_PI * (-x * x).exp(), |x: f64, _v| {
+    -4.0 * x * FRAC_1_SQRT_PI * (-x * x).exp()
+});
+un_op!(NormCdfOp, normal_cdf_f64, |x: f64, _v| normal_dens_f64(x), |x: f64, _v| {
+    -x * normal_dens_f64(x)
+});
+un_op!(ExpM1Op, f64::exp_m1, |_x, v: f64| v + 1.0, |_x, v: f64| v + 1.0);
+un_op!(Ln1pOp, f64::ln_1p, |x: f64, _v| 1.0 / (1.0 + x), |x: f64, _v| {
+    -1.0 / ((1.0 + x) * (1.0 + x))
+});
+
+const FRAC_1_SQRT_PI: f64 = 0.5 * std::f64::consts::FRAC_2_SQRT_PI;
+const FRAC_1_SQRT_2PI: f64 = FRAC_1_SQRT_PI * std::f64::consts::FRAC_1_SQRT_2;
+
+/// Complementary error function: power series of erf close to zero, continued fraction in the
+/// tails, accurate to about 1e-14
+fn erfc_f64(x: f64) -> f64 {
+    if x < 0.0 {
+        return 2.0 - erfc_f64(-x);
+    }
+    if x < 2.5 {
+        let (mut term, mut sum, x2) = (x, x, x * x);
+        for n in 1..100 {
+            term *= -x2 / n as f64;
+            let next = term / (2 * n + 1) as f64;
+            sum += next;
+            if next.abs() < 1e-17 * sum.abs() {
+                break;
+            }
+        }
+        return 1.0 - 2.0 * FRAC_1_SQRT_PI * sum;
+    }
+    let t = (1..=60).rev().fold(x, |t, k| x + 0.5 * k as f64 / t);
+    (-x * x).exp() * FRAC_1_SQRT_PI / t
+}
+
+fn erf_f64(x: f64) -> f64 {
+    1.0 - erfc_f64(x)
+}
+
+fn normal_dens_f64(x: f64) -> f64 {
+    FRAC_1_SQRT_2PI * (-0.5 * x * x).exp()
+}
+
+fn normal_cdf_f64(x: f64) -> f64 {
+    0.5 * erfc_f64(-x * std::f64::consts::FRAC_1_SQRT_2)
+}
 
 #[derive(Clone)]
 pub struct UnExpr<A, O> {
     a: A,
     val: f64,
+    tan: f64,
     _ph: std::marker::PhantomData<O>,
 }
 
 impl<A: Expr, O: UnOp> UnExpr<A, O> {
     fn new(a: A) -> Self {
         let val = O::eval(a.value());
+        let tan = O::deriv(a.value(), val) * a.tangent();
         Self {
             a,
             val,
+            tan,
             _ph: std::marker::PhantomData,
         }
     }
@@ -341,9 +653,58 @@ impl<A: Expr, O: UnOp + Clone> Expr for UnExpr<A, O> {
         self.val
     }
 
-    fn push_adj(&self, parent: &mut Node, adj: f64) {
+    fn tangent(&self) -> f64 {
+        self.tan
+    }
+
+    fn push_adj(&self, parent: &mut Node, adj: f64, adj_tangent: f64) {
+        let x = self.a.value();
+        let d = O::deriv(x, self.val);
+        let d_tangent = O::deriv2(x, self.val) * self.a.tangent();
+        self.a
+            .push_adj(parent, adj * d, adj_tangent * d + adj * d_tangent);
+    }
+}
+
+/// Integer power, a single node with exact derivatives whatever the sign of the base
+#[derive(Clone)]
+pub struct PowiExpr<A> {
+    a: A,
+    n: i32,
+    val: f64,
+    tan: f64,
+}
+
+impl<A: Expr> PowiExpr<A> {
+    fn new(a: A, n: i32) -> Self {
+        let val = a.value().powi(n);
+        let tan = Self::deriv(a.value(), n) * a.tangent();
+        Self { a, n, val, tan }
+    }
+
+    fn deriv(x: f64, n: i32) -> f64 {
+        if n == 0 {
+            return 0.0;
+        }
+        n as f64 * x.powi(n - 1)
+    }
+}
+
+impl<A: Expr> Expr for PowiExpr<A> {
+    fn value(&self) -> f64 {
+        self.val
+    }
+
+    fn tangent(&self) -> f64 {
+        self.tan
+    }
+
+    fn push_adj(&self, parent: &mut Node, adj: f64, adj_tangent: f64) {
+        let (x, n) = (self.a.value(), self.n);
+        let d = Self::deriv(x, n);
+        let d_tangent = n as f64 * Self::deriv(x, n - 1) * self.a.tangent();
         self.a
-            .push_adj(parent, adj * O::deriv(self.a.value(), self.val));
+            .push_adj(parent, adj * d, adj_tangent * d + adj * d_tangent);
     }
 }
 
@@ -668,16 +1029,40 @@ pub fn fabs<A: Expr + Clone>(a: A) -> UnExpr<A, FabsOp> {
 // pub fn normal_dens<A: Expr + Clone>(a: A) -> UnExpr<A, NormDensOp> {
 //     UnExpr::new(a)
 // }
-// #[inline]
-// pub fn normal_cdf<A: Expr + Clone>(a: A) -> UnExpr<A, NormCdfOp> {
-//     UnExpr::new(a)
-// }
+#[inline]
+pub fn exp_m1<A: Expr + Clone>(a: A) -> UnExpr<A, ExpM1Op> {
+    UnExpr::new(a)
+}
+#[inline]
+pub fn ln_1p<A: Expr + Clone>(a: A) -> UnExpr<A, Ln1pOp> {
+    UnExpr::new(a)
+}
+#[inline]
+pub fn tanh<A: Expr + Clone>(a: A) -> UnExpr<A, TanhOp> {
+    UnExpr::new(a)
+}
+#[inline]
+pub fn atan<A: Expr + Clone>(a: A) -> UnExpr<A, AtanOp> {
+    UnExpr::new(a)
+}
+#[inline]
+pub fn erf<A: Expr + Clone>(a: A) -> UnExpr<A, ErfOp> {
+    UnExpr::new(a)
+}
+#[inline]
+pub fn normal_cdf<A: Expr + Clone>(a: A) -> UnExpr<A, NormCdfOp> {
+    UnExpr::new(a)
+}
 
 #[inline]
 pub fn pow<L: Expr + Clone, R: Expr + Clone>(l: L, r: R) -> BinExpr<L, R, PowOp> {
     BinExpr::new(l, r)
 }
 #[inline]
+pub fn powi<A: Expr + Clone>(a: A, n: i32) -> PowiExpr<A> {
+    PowiExpr::new(a, n)
+}
+#[inline]
 pub fn max<L: Expr + Clone, R: Expr + Clone>(l: L, r: R) -> BinExpr<L, R, MaxOp> {
     BinExpr::new(l, r)
 }
@@ -690,13 +1075,9 @@ pub fn min<L: Expr + Clone, R: Expr + Clone>(l: L, r: R) -> BinExpr<L, R, MinOp>
 
 /// “Flatten” an arbitrary expression into a concrete `ADNumber` node on the tape
 fn flatten<E: Expr + Clone>(e: &E) -> ADNumber {
-    let mut node = Node::default();
-    e.push_adj(&mut node, 1.0);
-    let idx = TAPE.with(|t| t.borrow_mut().record(node));
-    ADNumber {
-        val: e.value(),
-        idx,
-    }
+    let mut node = Node::new(TAPE.with(|t| t.borrow().precision()));
+    e.push_adj(&mut node, 1.0, 0.0);
+    ADNumber::record(node, e.value(), e.tangent(), std::any::type_name::<E>())
 }
 
 impl<L, R, O> From<BinExpr<L, R, O>> for ADNumber
@@ -722,6 +1103,12 @@ where
     }
 }
 
+impl<A: Expr + Clone> From<PowiExpr<A>> for ADNumber {
+    fn from(expr: PowiExpr<A>) -> Self {
+        flatten(&expr)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -762,4 +1149,203 @@ mod tests {
         assert_eq!(tape[1].adj, 1.0); // b's adjoint
         assert_eq!(tape[2].adj, 1.0); // c's adjoint
     }
+
+    #[test]
+    fn test_special_functions() {
+        let close = |a: f64, b: f64| assert!((a - b).abs() < 1e-13, "{} != {}", a, b);
+        close(erf_f64(0.5), 0.5204998778130465);
+        close(erf_f64(-1.0), -0.8427007929497149);
+        close(erf_f64(3.0), 0.9999779095030014);
+        close(normal_cdf_f64(1.0), 0.8413447460685429);
+        close(normal_cdf_f64(-6.0) * 1e9, 0.9865876450376946);
+
+        // derivatives against central differences
+        let h = 1e-6;
+        let x = ADNumber::new(0.7);
+        let functions: [fn(f64) -> f64; 4] = [f64::tanh, f64::atan, erf_f64, normal_cdf_f64];
+        let outputs: [ADNumber; 4] = [
+            tanh(x.clone()).into(),
+            atan(x.clone()).into(),
+            erf(x.clone()).into(),
+            normal_cdf(x.clone()).into(),
+        ];
+        for (y, f) in outputs.into_iter().zip(functions) {
+            ADNumber::reset_adjoints();
+            y.propagate_to_start();
+            let fd = (f(0.7 + h) - f(0.7 - h)) / (2.0 * h);
+            assert!((x.adjoint() - fd).abs() < 1e-8);
+            assert_eq!(y.value(), f(0.7));
+        }
+    }
+
+    #[test]
+    fn test_powi_and_small_arguments() {
+        // a negative base keeps finite derivatives, where pow would go through ln
+        let x = ADNumber::new(-2.0).with_tangent(1.0);
+        let y: ADNumber = powi(x.clone(), 3).into();
+        assert_eq!(y.value(), -8.0);
+        y.propagate_to_start();
+        assert_eq!(x.adjoint(), 12.0);
+        assert_eq!(x.adjoint_tangent(), -12.0);
+
+        ADNumber::reset_adjoints();
+        let z: ADNumber = powi(x.clone(), 0).into();
+        z.propagate_to_start();
+        assert_eq!((z.value(), x.adjoint()), (1.0, 0.0));
+
+        // no cancellation for tiny arguments
+        let eps = ADNumber::new(1e-12);
+        let e: ADNumber = exp_m1(eps.clone()).into();
+        let l: ADNumber = ln_1p(eps.clone()).into();
+        assert!((e.value() - 1e-12).abs() < 1e-24);
+        assert!((l.value() - 1e-12).abs() < 1e-24);
+        e.propagate_to_start();
+        assert!((eps.adjoint() - 1.0).abs() < 1e-11);
+    }
+
+    #[test]
+    fn test_compound_expression_is_one_node() {
+        let (a, b, c, d) = (
+            ADNumber::new(1.0),
+            ADNumber::new(2.0),
+            ADNumber::new(3.0),
+            ADNumber::new(4.0),
+        );
+        let len = tape_len();
+        let f: ADNumber = (a.clone() * b.clone() + exp(c.clone() * d.clone()) / 2.0).into();
+        assert_eq!(tape_len(), len + 1);
+
+        f.propagate_to_start();
+        let e = 12.0_f64.exp() / 2.0;
+        assert_eq!((a.adjoint(), b.adjoint()), (2.0, 1.0));
+        assert!((c.adjoint() - 4.0 * e).abs() < 1e-9 * e);
+        assert!((d.adjoint() - 3.0 * e).abs() < 1e-9 * e);
+    }
+
+    #[test]
+    fn test_propagate_pruned() {
+        let x = ADNumber::new(2.0);
+        let y = ADNumber::new(3.0);
+        let diagnostic: ADNumber = (x.clone() * 10.0).into();
+        let _report: ADNumber = (diagnostic.clone() + y.clone()).into();
+        let f: ADNumber = (x.clone() * y.clone()).into();
+
+        // a stale adjoint on the diagnostic would leak into x with a full sweep
+        TAPE.with(|t| t.borrow_mut().nodes[diagnostic.index()].adj = 1.0);
+        assert_eq!(f.propagate_pruned(), 3);
+        assert_eq!((x.adjoint(), y.adjoint()), (3.0, 2.0));
+    }
+
+    #[test]
+    fn test_backward_multi() {
+        let x = ADNumber::new(2.0);
+        let y = ADNumber::new(3.0);
+        let f: ADNumber = (x.clone() * y.clone()).into();
+        let g: ADNumber = (f.clone() + x.clone() * x.clone()).into();
+        let unused = ADNumber::new(1.0);
+
+        let grads = ADNumber::backward_multi(&[f, g], &[x.clone(), y.clone(), unused]);
+        assert_eq!(grads, vec![vec![3.0, 2.0, 0.0], vec![7.0, 2.0, 0.0]]);
+        assert_eq!(x.adjoint(), 0.0);
+    }
+
+    #[test]
+    fn test_backward_for() {
+        let x = ADNumber::new(2.0);
+        let y = ADNumber::new(3.0);
+        let z = ADNumber::new(4.0);
+        let f: ADNumber = (x.clone() * y.clone() + z.clone() * z.clone()).into();
+        let g: ADNumber = (f.clone() * y.clone()).into();
+        let later = ADNumber::new(1.0);
+
+        let grad = g.backward_for(&[y.clone(), z.clone(), later.clone()]);
+        // g = (x y + z^2) y
+        assert_eq!(grad[&y.index()], 22.0 + 6.0);
+        assert_eq!(grad[&z.index()], 24.0);
+        assert_eq!(grad[&later.index()], 0.0);
+        assert_eq!(y.adjoint(), 0.0);
+    }
+
+    #[test]
+    fn test_stale_numbers() {
+        let market = ADNumber::new(2.0);
+        set_mark();
+        let x: ADNumber = (market.clone() * 3.0).into();
+        truncate_tape(tape_len() - 1);
+        let y = ADNumber::new(1.0);
+
+        // the node of x was dropped and its index reused by y
+        assert_eq!(x.index(), y.index());
+        assert!(!x.is_live());
+        assert!(y.is_live() && market.is_live());
+        let caught = std::panic::catch_unwind(|| x.adjoint());
+        assert!(caught.is_err());
+    }
+
+    #[test]
+    fn test_single_precision() {
+        let gradient = || {
+            let x = ADNumber::new(0.3);
+            let y = ADNumber::new(1.7);
+            let f: ADNumber = (exp(x.clone() * y.clone()) / y.clone()).into();
+            f.propagate_to_start();
+            (x.adjoint(), y.adjoint(), tape_stats().bytes())
+        };
+        let (dx, dy, bytes) = gradient();
+
+        truncate_tape(0);
+        set_tape_precision(TapePrecision::Single);
+        let (dx_single, dy_single, bytes_single) = gradient();
+        assert!((dx_single - dx).abs() < 1e-6 * dx.abs());
+        assert!((dy_single - dy).abs() < 1e-6 * dy.abs());
+        assert!(bytes_single < bytes);
+        set_tape_precision(TapePrecision::Double);
+    }
+
+    #[test]
+    fn test_non_finite_origin() {
+        set_non_finite_tracking(true);
+        let x = ADNumber::new(0.0);
+        let y = ADNumber::new(2.0);
+        let fine: ADNumber = (y.clone() * y.clone()).into();
+        fine.propagate_checked().unwrap();
+
+        let exploded: ADNumber = (y.clone() / x.clone()).into();
+        let result: ADNumber = (exploded.clone() + 1.0).into();
+        let origin = non_finite_origin().unwrap();
+        assert_eq!(origin.node(), exploded.index());
+        assert!(origin.operation().contains("DivOp"));
+
+        let err = result.propagate_checked().unwrap_err();
+        assert!(err.to_string().contains("DivOp"));
+        set_non_finite_tracking(false);
+    }
+
+    #[test]
+    fn test_hessian_vector_product() {
+        // f = x² y + exp(x), seeded along x
+        let x = ADNumber::new(2.0).with_tangent(1.0);
+        let y = ADNumber::new(3.0);
+        let f: ADNumber = (x.clone() * x.clone() * y.clone() + exp(x.clone())).into();
+        f.propagate_to_start();
+
+        let e2 = 2.0_f64.exp();
+        assert!((f.tangent() - (12.0 + e2)).abs() < 1e-12);
+        assert!((x.adjoint() - (12.0 + e2)).abs() < 1e-12);
+        assert!((x.adjoint_tangent() - (6.0 + e2)).abs() < 1e-12);
+        assert!((y.adjoint_tangent() - 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_second_order_through_intermediates() {
+        // g = x³ / y + ln(x) built in two steps, seeded along y
+        let x = ADNumber::new(2.0);
+        let y = ADNumber::new(3.0).with_tangent(1.0);
+        let ratio: ADNumber = (pow(x.clone(), Const(3.0)) / y.clone()).into();
+        let g: ADNumber = (ratio + log(x.clone())).into();
+        g.propagate_to_start();
+
+        assert!((y.adjoint_tangent() - 16.0 / 27.0).abs() < 1e-12);
+        assert!((x.adjoint_tangent() + 12.0 / 9.0).abs() < 1e-12);
+    }
 }
@@ -0,0 +1,193 @@
+use std::ops::{Index, IndexMut};
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use super::node::Node;
+
+/// Number of nodes per block of a `NodeArena`
+pub const CHUNK_SIZE: usize = 1 << 16;
+
+/// # NodeArena
+/// Nodes of a tape stored in blocks of `CHUNK_SIZE`. Pushing never moves the nodes already
+/// recorded, so a tape of hundreds of millions of nodes grows one block at a time instead of
+/// reallocating and copying everything, and dropping nodes frees the emptied blocks.
+#[derive(Default, Clone, Deserialize)]
+#[serde(from = "Vec<Node>")]
+pub struct NodeArena {
+    chunks: Vec<Vec<Node>>,
+    len: usize,
+}
+
+impl NodeArena {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of nodes the allocated blocks can hold
+    pub fn capacity(&self) -> usize {
+        self.chunks.iter().map(Vec::capacity).sum()
+    }
+
+    pub fn push(&mut self, node: Node) -> usize {
+        if self.len == self.chunks.len() * CHUNK_SIZE {
+            self.chunks.push(Vec::with_capacity(CHUNK_SIZE));
+        }
+        self.chunks[self.len / CHUNK_SIZE].push(node);
+        self.len += 1;
+        self.len - 1
+    }
+
+    /// Keep the first `len` nodes, freeing the blocks left empty
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        self.chunks.truncate(len.div_ceil(CHUNK_SIZE));
+        if let Some(last) = self.chunks.last_mut() {
+            last.truncate(len - (len - 1) / CHUNK_SIZE * CHUNK_SIZE);
+        }
+        self.len = len;
+    }
+
+    /// Move out the nodes from `at` on, for work that needs them contiguous
+    pub fn split_off(&mut self, at: usize) -> Vec<Node> {
+        let mut nodes = Vec::with_capacity(self.len.saturating_sub(at));
+        let first = at / CHUNK_SIZE;
+        for (k, chunk) in self.chunks.iter_mut().enumerate().skip(first) {
+            let from = if k == first { at % CHUNK_SIZE } else { 0 };
+            nodes.extend(chunk.drain(from..));
+        }
+        self.chunks.truncate(at.div_ceil(CHUNK_SIZE));
+        self.len = self.len.min(at);
+        nodes
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Node> {
+        (idx < self.len).then(|| &self.chunks[idx / CHUNK_SIZE][idx % CHUNK_SIZE])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Node> {
+        self.chunks.iter().flatten()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Node> {
+        self.chunks.iter_mut().flatten()
+    }
+
+    /// The node `idx` together with the nodes below it, which are its only possible inputs
+    pub fn split_at_mut(&mut self, idx: usize) -> (NodesBelow<'_>, &mut Node) {
+        let (full, rest) = self.chunks.split_at_mut(idx / CHUNK_SIZE);
+        let (last, node) = rest[0].split_at_mut(idx % CHUNK_SIZE);
+        (NodesBelow { full, last }, &mut node[0])
+    }
+}
+
+impl Extend<Node> for NodeArena {
+    fn extend<I: IntoIterator<Item = Node>>(&mut self, nodes: I) {
+        nodes.into_iter().for_each(|node| {
+            self.push(node);
+        });
+    }
+}
+
+impl From<Vec<Node>> for NodeArena {
+    fn from(nodes: Vec<Node>) -> Self {
+        let mut arena = NodeArena::default();
+        arena.extend(nodes);
+        arena
+    }
+}
+
+impl Serialize for NodeArena {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl Index<usize> for NodeArena {
+    type Output = Node;
+    fn index(&self, idx: usize) -> &Node {
+        assert!(idx < self.len, "node {} is not on the tape", idx);
+        &self.chunks[idx / CHUNK_SIZE][idx % CHUNK_SIZE]
+    }
+}
+
+impl IndexMut<usize> for NodeArena {
+    fn index_mut(&mut self, idx: usize) -> &mut Node {
+        assert!(idx < self.len, "node {} is not on the tape", idx);
+        &mut self.chunks[idx / CHUNK_SIZE][idx % CHUNK_SIZE]
+    }
+}
+
+/// # NodesBelow
+/// Mutable view of the nodes recorded before a given one, indexed by tape position
+pub struct NodesBelow<'a> {
+    full: &'a mut [Vec<Node>],
+    last: &'a mut [Node],
+}
+
+impl Index<usize> for NodesBelow<'_> {
+    type Output = Node;
+    fn index(&self, idx: usize) -> &Node {
+        match self.full.get(idx / CHUNK_SIZE) {
+            Some(chunk) => &chunk[idx % CHUNK_SIZE],
+            None => &self.last[idx - self.full.len() * CHUNK_SIZE],
+        }
+    }
+}
+
+impl IndexMut<usize> for NodesBelow<'_> {
+    fn index_mut(&mut self, idx: usize) -> &mut Node {
+        let first_of_last = self.full.len() * CHUNK_SIZE;
+        match self.full.get_mut(idx / CHUNK_SIZE) {
+            Some(chunk) => &mut chunk[idx % CHUNK_SIZE],
+            None => &mut self.last[idx - first_of_last],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(adj: f64) -> Node {
+        Node {
+            adj,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_blocks() {
+        let mut arena = NodeArena::default();
+        (0..CHUNK_SIZE + 10).for_each(|i| {
+            arena.push(leaf(i as f64));
+        });
+        assert_eq!(arena.len(), CHUNK_SIZE + 10);
+        assert_eq!(arena[CHUNK_SIZE + 3].adj, (CHUNK_SIZE + 3) as f64);
+        assert_eq!(arena.capacity(), 2 * CHUNK_SIZE);
+
+        // the view below a node of the second block reaches both blocks
+        let (mut below, node) = arena.split_at_mut(CHUNK_SIZE + 5);
+        node.adj = -1.0;
+        below[3].adj += 0.5;
+        below[CHUNK_SIZE + 4].adj += 0.5;
+        assert_eq!(arena[3].adj, 3.5);
+        assert_eq!(arena[CHUNK_SIZE + 4].adj, CHUNK_SIZE as f64 + 4.5);
+
+        let tail = arena.split_off(CHUNK_SIZE - 2);
+        assert_eq!(tail.len(), 12);
+        assert_eq!((arena.len(), arena.capacity()), (CHUNK_SIZE - 2, CHUNK_SIZE));
+        arena.extend(tail);
+        assert_eq!(arena[CHUNK_SIZE + 5].adj, -1.0);
+
+        arena.truncate(CHUNK_SIZE);
+        assert_eq!((arena.len(), arena.capacity()), (CHUNK_SIZE, CHUNK_SIZE));
+        assert!(arena.get(CHUNK_SIZE).is_none());
+        assert_eq!(arena.push(leaf(0.0)), CHUNK_SIZE);
+    }
+}
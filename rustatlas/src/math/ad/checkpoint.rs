@@ -0,0 +1,75 @@
+use super::adnumber::ADNumber;
+use super::tape::{propagate_range, tape_len, truncate_tape, TAPE};
+
+/// # checkpointed_adjoints
+/// Run `steps` applications of `step`, each mapping a state to the next one, and return the
+/// final state with the adjoints of the initial state for the final state adjoints `seed`.
+///
+/// Only the states between steps are kept: the tape is truncated after every step and each step
+/// is recorded again during the backward pass, so the tape never holds more than a single step,
+/// e.g. one event of a long schedule. Leaves recorded before the call and captured by `step`,
+/// such as market data, keep their nodes and accumulate their adjoints over all steps.
+pub fn checkpointed_adjoints<F>(
+    initial: &[f64],
+    steps: usize,
+    step: F,
+    seed: &[f64],
+) -> (Vec<f64>, Vec<f64>)
+where
+    F: Fn(usize, &[ADNumber]) -> Vec<ADNumber>,
+{
+    let mark = tape_len();
+    let record = |k: usize, state: &[f64]| {
+        let leaves: Vec<ADNumber> = state.iter().map(|v| ADNumber::new(*v)).collect();
+        let outputs = step(k, &leaves);
+        (leaves, outputs)
+    };
+
+    // forward: keep the state at every step boundary
+    let mut states = vec![initial.to_vec()];
+    for k in 0..steps {
+        let (_, outputs) = record(k, &states[k]);
+        states.push(outputs.iter().map(|output| output.value()).collect());
+        truncate_tape(mark);
+    }
+
+    // backward: replay each step from its checkpoint, last step first
+    let mut adjoints = seed.to_vec();
+    for k in (0..steps).rev() {
+        let (leaves, outputs) = record(k, &states[k]);
+        TAPE.with(|t| {
+            let mut t = t.borrow_mut();
+            outputs
+                .iter()
+                .zip(&adjoints)
+                .for_each(|(output, adj)| t.nodes[output.index()].adj += adj);
+        });
+        propagate_range(tape_len() - 1, mark);
+        adjoints = leaves.iter().map(|leaf| leaf.adjoint()).collect();
+        truncate_tape(mark);
+    }
+
+    (states.pop().unwrap_or_default(), adjoints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpointed_adjoints() {
+        // x_{k+1} = s x_k + 1 from x_0 = 1 with s = 2: x_3 = 15, dx_3/dx_0 = 8, dx_3/ds = 17
+        let s = ADNumber::new(2.0);
+        let mark = tape_len();
+        let step = |_: usize, state: &[ADNumber]| -> Vec<ADNumber> {
+            vec![(state[0].clone() * s.clone() + 1.0).into()]
+        };
+
+        let (values, adjoints) = checkpointed_adjoints(&[1.0], 3, step, &[1.0]);
+
+        assert_eq!(values, vec![15.0]);
+        assert_eq!(adjoints, vec![8.0]);
+        assert_eq!(s.adjoint(), 17.0);
+        assert_eq!(tape_len(), mark);
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::adnumber::ADNumber;
+use super::node::Node;
+use super::tape::TAPE;
+use crate::utils::errors::{AtlasError, Result};
+
+type ValueFn = Arc<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+type PartialsFn = Arc<dyn Fn(&[f64]) -> Vec<f64> + Send + Sync>;
+
+fn registry() -> &'static RwLock<HashMap<String, (ValueFn, PartialsFn)>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, (ValueFn, PartialsFn)>>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// # register_op
+/// Register a differentiable operation under `name`, given its value and its partial derivatives
+/// with respect to each argument. Applying it with `apply_op` records a single node whatever the
+/// size of the formula, e.g. a Black price instead of the hundreds of nodes of its elementary
+/// operations. Registering a name again replaces the operation, for every thread.
+pub fn register_op<F, D>(name: &str, value: F, partials: D)
+where
+    F: Fn(&[f64]) -> f64 + Send + Sync + 'static,
+    D: Fn(&[f64]) -> Vec<f64> + Send + Sync + 'static,
+{
+    registry()
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(name.to_string(), (Arc::new(value), Arc::new(partials)));
+}
+
+/// # apply_op
+/// Value of the operation registered under `name` at `args`, recorded as one node. Second order
+/// terms of the operation are not known, so Hessian-vector products only see its first order.
+pub fn apply_op(name: &str, args: &[ADNumber]) -> Result<ADNumber> {
+    let (value, partials) = registry()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
+        .ok_or(AtlasError::NotFoundErr(format!("operation {}", name)))?;
+
+    let values: Vec<f64> = args.iter().map(|arg| arg.value()).collect();
+    let derivs = partials(&values);
+    if derivs.len() != args.len() {
+        return Err(AtlasError::InvalidValueErr(format!(
+            "operation {} gave {} partials for {} arguments",
+            name,
+            derivs.len(),
+            args.len()
+        )));
+    }
+
+    let mut node = Node::new(TAPE.with(|t| t.borrow().precision()));
+    let mut tangent = 0.0;
+    for (arg, d) in args.iter().zip(derivs) {
+        node.childs.push(arg.index());
+        node.derivs.push(d);
+        node.deriv_tangents.push(0.0);
+        tangent += d * arg.tangent();
+    }
+    Ok(ADNumber::record(node, value(&values), tangent, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::ad::tape::tape_len;
+
+    #[test]
+    fn test_custom_op() {
+        register_op(
+            "hypot",
+            |x| x[0].hypot(x[1]),
+            |x| {
+                let r = x[0].hypot(x[1]);
+                vec![x[0] / r, x[1] / r]
+            },
+        );
+        let x = ADNumber::new(3.0);
+        let y = ADNumber::new(4.0);
+        let len = tape_len();
+        let r = apply_op("hypot", &[x.clone(), y.clone()]).unwrap();
+        assert_eq!(tape_len(), len + 1);
+        assert_eq!(r.value(), 5.0);
+
+        r.propagate_to_start();
+        assert_eq!((x.adjoint(), y.adjoint()), (0.6, 0.8));
+
+        assert!(apply_op("unknown", &[y]).is_err());
+        register_op("constant", |_| 1.0, |_| Vec::new());
+        assert!(apply_op("constant", &[x]).is_err());
+    }
+}
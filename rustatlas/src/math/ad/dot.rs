@@ -0,0 +1,70 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use super::adnumber::ADNumber;
+use super::tape::TAPE;
+
+/// # export_dot
+/// Graphviz DOT graph of the nodes `result` depends on. Edges go from an input to the node using
+/// it and carry the partial derivative, nodes show their adjoint and the name given in `labels`,
+/// or their position on the tape. Meant for small reproductions: render with `dot -Tsvg`.
+pub fn export_dot(result: &ADNumber, labels: &[(&ADNumber, &str)]) -> String {
+    let names: HashMap<usize, &str> = labels
+        .iter()
+        .map(|(number, name)| (number.index(), *name))
+        .collect();
+
+    TAPE.with(|t| {
+        let t = t.borrow();
+        let mut reached = BTreeSet::new();
+        let mut pending = vec![result.index()];
+        while let Some(idx) = pending.pop() {
+            if reached.insert(idx) {
+                pending.extend(&t.nodes[idx].childs);
+            }
+        }
+
+        let mut dot = String::from("digraph ad {\n    rankdir=BT;\n");
+        for &idx in &reached {
+            let node = &t.nodes[idx];
+            let name = names
+                .get(&idx)
+                .map(|name| name.replace('"', "\\\""))
+                .unwrap_or_else(|| format!("#{}", idx));
+            let shape = if node.childs.is_empty() { "box" } else { "ellipse" };
+            let _ = writeln!(
+                dot,
+                "    n{} [label=\"{}\\nadj={}\", shape={}];",
+                idx, name, node.adj, shape
+            );
+            for (child, deriv) in node.childs.iter().zip(&node.derivs) {
+                let _ = writeln!(dot, "    n{} -> n{} [label=\"{}\"];", child, idx, deriv);
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_dot() {
+        let x = ADNumber::new(2.0);
+        let y = ADNumber::new(3.0);
+        let unrelated = ADNumber::new(5.0);
+        let f: ADNumber = (x.clone() * y.clone()).into();
+        f.propagate_to_start();
+
+        let dot = export_dot(&f, &[(&x, "x"), (&y, "y"), (&unrelated, "z")]);
+        let (xi, yi, fi) = (x.index(), y.index(), f.index());
+        assert!(dot.starts_with("digraph ad {"));
+        assert!(dot.contains(&format!("n{} [label=\"x\\nadj=3\", shape=box];", xi)));
+        assert!(dot.contains(&format!("n{} [label=\"#{}\\nadj=1\", shape=ellipse];", fi, fi)));
+        assert!(dot.contains(&format!("n{} -> n{} [label=\"3\"];", xi, fi)));
+        assert!(dot.contains(&format!("n{} -> n{} [label=\"2\"];", yi, fi)));
+        assert!(!dot.contains("\"z"));
+    }
+}
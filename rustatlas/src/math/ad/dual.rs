@@ -0,0 +1,283 @@
+use core::cmp::Ordering;
+use core::fmt::{Display, Formatter};
+use core::ops::*;
+
+use super::genericnumber::GenericNumber;
+
+/// # Dual
+/// Forward-mode AD number: a value and its derivatives with respect to `N` inputs, computed
+/// alongside the value without a tape. Suited to few inputs and many outputs, such as bumping
+/// one curve pillar through a whole portfolio. Comparisons only look at the value.
+///
+/// ## Parameters
+/// * `val` - The value.
+/// * `eps` - The derivatives with respect to each input.
+#[derive(Debug, Clone, Copy)]
+pub struct Dual<const N: usize> {
+    val: f64,
+    eps: [f64; N],
+}
+
+impl<const N: usize> Dual<N> {
+    /// A value that depends on no input
+    pub fn constant(val: f64) -> Self {
+        Dual { val, eps: [0.0; N] }
+    }
+
+    /// The input number `i`, its derivative with respect to itself is one
+    pub fn variable(val: f64, i: usize) -> Self {
+        let mut eps = [0.0; N];
+        eps[i] = 1.0;
+        Dual { val, eps }
+    }
+
+    pub fn value(&self) -> f64 {
+        self.val
+    }
+
+    /// Derivative with respect to the input number `i`
+    pub fn derivative(&self, i: usize) -> f64 {
+        self.eps[i]
+    }
+
+    pub fn derivatives(&self) -> &[f64; N] {
+        &self.eps
+    }
+
+    /// Apply a function of value `val` and derivative `d` at `self.val`
+    fn chain(self, val: f64, d: f64) -> Self {
+        Dual {
+            val,
+            eps: self.eps.map(|e| e * d),
+        }
+    }
+
+    /// `a * self + b * other`, derivative wise
+    fn combine(self, a: f64, other: Self, b: f64, val: f64) -> Self {
+        let mut eps = self.eps;
+        eps.iter_mut()
+            .zip(other.eps.iter())
+            .for_each(|(e, o)| *e = a * *e + b * o);
+        Dual { val, eps }
+    }
+}
+
+impl<const N: usize> Default for Dual<N> {
+    fn default() -> Self {
+        Dual::constant(0.0)
+    }
+}
+
+impl<const N: usize> Display for Dual<N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.val)
+    }
+}
+
+impl<const N: usize> PartialEq for Dual<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.val == other.val
+    }
+}
+
+impl<const N: usize> PartialOrd for Dual<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.val.partial_cmp(&other.val)
+    }
+}
+
+/* ═══════════════════════  CONVERSIONS  ══════════════════════════════ */
+
+impl<const N: usize> From<f64> for Dual<N> {
+    fn from(val: f64) -> Self {
+        Dual::constant(val)
+    }
+}
+
+impl<const N: usize> From<f32> for Dual<N> {
+    fn from(val: f32) -> Self {
+        Dual::constant(val as f64)
+    }
+}
+
+impl<const N: usize> From<i32> for Dual<N> {
+    fn from(val: i32) -> Self {
+        Dual::constant(val as f64)
+    }
+}
+
+impl<const N: usize> From<Dual<N>> for f64 {
+    fn from(dual: Dual<N>) -> f64 {
+        dual.val
+    }
+}
+
+/* ═══════════════════════  OPERATORS  ════════════════════════════════ */
+
+impl<const N: usize> Add for Dual<N> {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        self.combine(1.0, rhs, 1.0, self.val + rhs.val)
+    }
+}
+
+impl<const N: usize> Sub for Dual<N> {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        self.combine(1.0, rhs, -1.0, self.val - rhs.val)
+    }
+}
+
+impl<const N: usize> Mul for Dual<N> {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        self.combine(rhs.val, rhs, self.val, self.val * rhs.val)
+    }
+}
+
+impl<const N: usize> Div for Dual<N> {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        let inv = 1.0 / rhs.val;
+        self.combine(inv, rhs, -self.val * inv * inv, self.val * inv)
+    }
+}
+
+impl<const N: usize> Add<f64> for Dual<N> {
+    type Output = Self;
+    fn add(self, rhs: f64) -> Self {
+        self.chain(self.val + rhs, 1.0)
+    }
+}
+
+impl<const N: usize> Sub<f64> for Dual<N> {
+    type Output = Self;
+    fn sub(self, rhs: f64) -> Self {
+        self.chain(self.val - rhs, 1.0)
+    }
+}
+
+impl<const N: usize> Mul<f64> for Dual<N> {
+    type Output = Self;
+    fn mul(self, rhs: f64) -> Self {
+        self.chain(self.val * rhs, rhs)
+    }
+}
+
+impl<const N: usize> Div<f64> for Dual<N> {
+    type Output = Self;
+    fn div(self, rhs: f64) -> Self {
+        self.chain(self.val / rhs, 1.0 / rhs)
+    }
+}
+
+impl<const N: usize> Neg for Dual<N> {
+    type Output = Self;
+    fn neg(self) -> Self {
+        self.chain(-self.val, -1.0)
+    }
+}
+
+macro_rules! impl_assign {
+    ($Trait:ident, $func:ident, $sym:tt) => {
+        impl<const N: usize> $Trait for Dual<N> {
+            fn $func(&mut self, rhs: Self) {
+                *self = *self $sym rhs;
+            }
+        }
+    };
+}
+
+impl_assign!(AddAssign, add_assign, +);
+impl_assign!(SubAssign, sub_assign, -);
+impl_assign!(MulAssign, mul_assign, *);
+impl_assign!(DivAssign, div_assign, /);
+
+/* ═══════════════════════  ELEMENTARY MATHS  ═════════════════════════ */
+
+impl<const N: usize> GenericNumber for Dual<N> {
+    fn ln(self) -> Self {
+        self.chain(self.val.ln(), 1.0 / self.val)
+    }
+
+    fn exp(self) -> Self {
+        let val = self.val.exp();
+        self.chain(val, val)
+    }
+
+    fn powf(self, rhs: Self) -> Self {
+        let val = self.val.powf(rhs.val);
+        let d_base = rhs.val * self.val.powf(rhs.val - 1.0);
+        // a constant exponent must not bring the NaN of ln of a negative base
+        if rhs.eps.iter().all(|e| *e == 0.0) {
+            return self.chain(val, d_base);
+        }
+        self.combine(d_base, rhs, val * self.val.ln(), val)
+    }
+
+    fn sqrt(self) -> Self {
+        let val = self.val.sqrt();
+        self.chain(val, 0.5 / val)
+    }
+
+    fn sin(self) -> Self {
+        self.chain(self.val.sin(), self.val.cos())
+    }
+
+    fn cos(self) -> Self {
+        self.chain(self.val.cos(), -self.val.sin())
+    }
+
+    fn abs(self) -> Self {
+        let sign = if self.val >= 0.0 { 1.0 } else { -1.0 };
+        self.chain(self.val.abs(), sign)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_arithmetics() {
+        // f = x y + x / y - 2
+        let x = Dual::<2>::variable(3.0, 0);
+        let y = Dual::<2>::variable(4.0, 1);
+        let f = x * y + x / y - 2.0;
+
+        assert_close(f.value(), 12.0 + 0.75 - 2.0);
+        assert_close(f.derivative(0), 4.0 + 0.25);
+        assert_close(f.derivative(1), 3.0 - 3.0 / 16.0);
+    }
+
+    #[test]
+    fn test_elementary_maths() {
+        let x = Dual::<1>::variable(2.0, 0);
+        assert_close(x.exp().derivative(0), 2.0_f64.exp());
+        assert_close(x.ln().derivative(0), 0.5);
+        assert_close(x.sqrt().derivative(0), 0.5 / 2.0_f64.sqrt());
+        assert_close(x.powf(Dual::constant(3.0)).derivative(0), 12.0);
+        assert_close((-x).abs().derivative(0), 1.0);
+
+        // a negative base with a constant exponent keeps a finite derivative
+        let y = Dual::<1>::variable(-2.0, 0).powf(Dual::constant(2.0));
+        assert_close(y.derivative(0), -4.0);
+    }
+
+    #[test]
+    fn test_generic_code() {
+        // a growth factor written for any number type, differentiated along the rate
+        fn growth<T: GenericNumber>(rate: T) -> T {
+            rate.exp().sqrt()
+        }
+        let rate = Dual::<1>::variable(0.1, 0);
+        let factor = growth(rate);
+        assert_close(factor.value(), 0.05_f64.exp());
+        assert_close(factor.derivative(0), 0.5 * 0.05_f64.exp());
+        assert!(Dual::<1>::from(1.0) < Dual::<1>::variable(2.0, 0));
+    }
+}
@@ -1,4 +1,11 @@
 pub mod node;
 pub mod adnumber;
+pub mod arena;
+pub mod checkpoint;
+pub mod customop;
+pub mod dot;
+pub mod dual;
 pub mod genericnumber;
 pub mod tape;
+
+pub use dot::export_dot;
@@ -1,16 +1,148 @@
-#[derive(Default, Clone)]
+use serde::{Deserialize, Serialize};
+use std::mem::size_of;
+use std::ops::IndexMut;
+
+use super::tape::TapePrecision;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Node {
-    pub childs: Vec<usize>, // indices of children on the tape
-    pub derivs: Vec<f64>,   // matching ∂parent / ∂child
-    pub adj: f64,           // this node’s adjoint
+    pub childs: Vec<usize>,         // indices of children on the tape
+    pub derivs: Partials,           // matching ∂parent / ∂child
+    pub deriv_tangents: Partials,   // directional derivatives of `derivs` along the seeded tangents
+    pub adj: f64,                   // this node’s adjoint
+    pub adj_tangent: f64,           // directional derivative of the adjoint
+    pub generation: u32,            // generation of the tape the node was recorded in
 }
 
 impl Node {
+    /// Empty node storing its partials in `precision`
+    pub fn new(precision: TapePrecision) -> Self {
+        let partials = || match precision {
+            TapePrecision::Double => Partials::Double(Vec::new()),
+            TapePrecision::Single => Partials::Single(Vec::new()),
+        };
+        Node {
+            derivs: partials(),
+            deriv_tangents: partials(),
+            ..Default::default()
+        }
+    }
+
     #[inline]
-    pub fn propagate_into(&self, tape: &mut [Node]) {
-        let a = self.adj;
-        for (&c, &d) in self.childs.iter().zip(&self.derivs) {
+    pub fn propagate_into<T: IndexMut<usize, Output = Node> + ?Sized>(&self, tape: &mut T) {
+        match (&self.derivs, &self.deriv_tangents) {
+            (Partials::Double(d), Partials::Double(dt)) => self.push_adjoints(tape, d, dt),
+            (Partials::Single(d), Partials::Single(dt)) => self.push_adjoints(tape, d, dt),
+            (d, dt) => {
+                let (d, dt): (Vec<f64>, Vec<f64>) = (d.iter().collect(), dt.iter().collect());
+                self.push_adjoints(tape, &d, &dt)
+            }
+        }
+    }
+
+    #[inline]
+    fn push_adjoints<T, D>(&self, tape: &mut T, derivs: &[D], deriv_tangents: &[D])
+    where
+        T: IndexMut<usize, Output = Node> + ?Sized,
+        D: Copy + Into<f64>,
+    {
+        let (a, at) = (self.adj, self.adj_tangent);
+        for ((&c, &d), &dt) in self.childs.iter().zip(derivs).zip(deriv_tangents) {
+            let (d, dt): (f64, f64) = (d.into(), dt.into());
             tape[c].adj += a * d;
+            tape[c].adj_tangent += at * d + a * dt;
+        }
+    }
+}
+
+/// # Partials
+/// Partial derivatives of a node, in single precision on tapes recorded with
+/// `TapePrecision::Single`. Values are pushed and read as `f64` either way.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Partials {
+    Double(Vec<f64>),
+    Single(Vec<f32>),
+}
+
+impl Default for Partials {
+    fn default() -> Self {
+        Partials::Double(Vec::new())
+    }
+}
+
+impl From<Vec<f64>> for Partials {
+    fn from(partials: Vec<f64>) -> Self {
+        Partials::Double(partials)
+    }
+}
+
+impl Partials {
+    pub fn push(&mut self, partial: f64) {
+        match self {
+            Partials::Double(v) => v.push(partial),
+            Partials::Single(v) => v.push(partial as f32),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Partials::Double(v) => v.len(),
+            Partials::Single(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> PartialsIter<'_> {
+        match self {
+            Partials::Double(v) => PartialsIter::Double(v.iter()),
+            Partials::Single(v) => PartialsIter::Single(v.iter()),
+        }
+    }
+
+    /// Heap memory held, including unused capacity
+    pub fn heap_bytes(&self) -> usize {
+        match self {
+            Partials::Double(v) => v.capacity() * size_of::<f64>(),
+            Partials::Single(v) => v.capacity() * size_of::<f32>(),
+        }
+    }
+
+    /// Store the partials in `precision`
+    pub fn convert(&mut self, precision: TapePrecision) {
+        match (&*self, precision) {
+            (Partials::Double(v), TapePrecision::Single) => {
+                *self = Partials::Single(v.iter().map(|d| *d as f32).collect())
+            }
+            (Partials::Single(v), TapePrecision::Double) => {
+                *self = Partials::Double(v.iter().map(|d| *d as f64).collect())
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Partials {
+    type Item = f64;
+    type IntoIter = PartialsIter<'a>;
+    fn into_iter(self) -> PartialsIter<'a> {
+        self.iter()
+    }
+}
+
+pub enum PartialsIter<'a> {
+    Double(std::slice::Iter<'a, f64>),
+    Single(std::slice::Iter<'a, f32>),
+}
+
+impl Iterator for PartialsIter<'_> {
+    type Item = f64;
+    fn next(&mut self) -> Option<f64> {
+        match self {
+            PartialsIter::Double(it) => it.next().copied(),
+            PartialsIter::Single(it) => it.next().map(|d| *d as f64),
         }
     }
 }
@@ -1,29 +1,437 @@
+use super::arena::NodeArena;
 use super::node::Node;
+use crate::utils::errors::{AtlasError, Result};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::mem::size_of;
 
-#[derive(Default)]
+/// Recorded nodes, serializable so that the backward pass of a computation can run in another
+/// process: `snapshot_tape` on the worker that recorded it, then `propagate_from` on the copy.
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Tape {
-    pub nodes: Vec<Node>,
+    pub nodes: NodeArena,
     pub mark: usize,
+    sections: Vec<(String, usize)>, // name and first node of each labelled section
+    generation: u32,                // bumped when nodes are dropped, to detect stale handles
+    precision: TapePrecision,       // storage of the partials of the nodes recorded
+    marks: Vec<(usize, usize)>,     // nested marks with the mark they replaced
+    track_non_finite: bool,         // debug mode recording where NaN and Inf appear
+    non_finite: Option<NonFiniteOrigin>,
+}
+
+/// # NonFiniteOrigin
+/// First node of a tape whose value or partials were NaN or infinite, recorded when the tape
+/// tracks non-finite values
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NonFiniteOrigin {
+    node: usize,
+    operation: String,
+    backtrace: String,
+}
+
+impl NonFiniteOrigin {
+    pub fn node(&self) -> usize {
+        self.node
+    }
+
+    /// Expression recorded by the node, or `input` for a leaf
+    pub fn operation(&self) -> &str {
+        &self.operation
+    }
+
+    /// Where the node was recorded
+    pub fn backtrace(&self) -> &str {
+        &self.backtrace
+    }
+}
+
+/// # TapePrecision
+/// Storage of the partial derivatives on a tape. Single precision saves a third of the memory of
+/// each edge, for large Monte Carlo runs where sensitivities accurate to about 1e-7 are enough.
+/// Adjoints are always accumulated in double precision.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TapePrecision {
+    #[default]
+    Double,
+    Single,
 }
 
 impl Tape {
-    pub fn record(&mut self, n: Node) -> usize {
-        self.nodes.push(n);
-        self.nodes.len() - 1
+    pub fn with_precision(mut self, precision: TapePrecision) -> Self {
+        self.precision = precision;
+        self
+    }
+    pub fn precision(&self) -> TapePrecision {
+        self.precision
+    }
+    /// # with_non_finite_tracking
+    /// Debug mode keeping the first node recorded with a NaN or infinite value or partial, with
+    /// the expression it comes from and a backtrace. Capturing it is slow, tracking is not.
+    pub fn with_non_finite_tracking(mut self, track: bool) -> Self {
+        self.track_non_finite = track;
+        self
+    }
+    pub fn non_finite_origin(&self) -> Option<&NonFiniteOrigin> {
+        self.non_finite.as_ref()
+    }
+    /// Keep the node `idx` as the origin of non-finite values if it is the first one
+    pub fn check_finite(&mut self, idx: usize, value: f64, operation: &str) {
+        if !self.track_non_finite || self.non_finite.is_some() {
+            return;
+        }
+        if value.is_finite() && self.nodes[idx].derivs.iter().all(f64::is_finite) {
+            return;
+        }
+        self.non_finite = Some(NonFiniteOrigin {
+            node: idx,
+            operation: operation.to_string(),
+            backtrace: std::backtrace::Backtrace::force_capture().to_string(),
+        });
+    }
+    pub fn record(&mut self, mut n: Node) -> usize {
+        n.generation = self.generation;
+        n.derivs.convert(self.precision);
+        n.deriv_tangents.convert(self.precision);
+        self.nodes.push(n)
     }
     pub fn new_leaf(&mut self) -> usize {
         self.record(Node::default())
     }
+    /// Drop the nodes recorded after the first `len` ones
+    pub fn truncate(&mut self, len: usize) {
+        self.nodes.truncate(len);
+        self.mark = self.mark.min(len);
+        self.generation += 1;
+        self.sections.retain(|(_, start)| *start < len);
+        self.marks.retain(|(position, _)| *position <= len);
+        if self.non_finite.as_ref().is_some_and(|origin| origin.node >= len) {
+            self.non_finite = None;
+        }
+    }
+    /// Mark the current end of the tape, nested in the marks already pushed
+    pub fn push_mark(&mut self) {
+        self.marks.push((self.nodes.len(), self.mark));
+        self.mark = self.nodes.len();
+    }
+    /// Drop the nodes recorded since the last pushed mark and restore the mark it replaced,
+    /// returning the length of the tape. Does nothing without a pushed mark.
+    pub fn pop_and_rewind(&mut self) -> Option<usize> {
+        let (position, previous) = self.marks.pop()?;
+        self.truncate(position);
+        self.mark = previous.min(position);
+        Some(position)
+    }
+    /// Generation of the nodes recorded from now on: a handle to a node recorded in an earlier
+    /// generation is stale if the node was dropped, even when its index was reused since
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+    /// Whether the node `idx` still is the one recorded in `generation`
+    pub fn is_live(&self, idx: usize, generation: u32) -> bool {
+        self.nodes.get(idx).is_some_and(|n| n.generation == generation)
+    }
+    /// Name the nodes recorded from now on, until the next section starts
+    pub fn begin_section(&mut self, name: &str) {
+        self.sections.push((name.to_string(), self.nodes.len()));
+    }
+    /// Reverse sweep from the node `idx`, seeded with an adjoint of one
+    pub fn propagate_from(&mut self, idx: usize) {
+        self.nodes[idx].adj = 1.0;
+        for i in (0..=idx).rev() {
+            let n = self.nodes[i].clone();
+            n.propagate_into(&mut self.nodes);
+        }
+    }
+    /// # propagate_reachable
+    /// Reverse sweep of the adjoints seeded on `results` through the nodes they depend on only,
+    /// skipping the nodes no result uses, such as diagnostic quantities computed along the way.
+    /// Returns the number of nodes swept.
+    pub fn propagate_reachable(&mut self, results: &[usize]) -> usize {
+        let Some(&last) = results.iter().max() else {
+            return 0;
+        };
+        let mut reachable = vec![false; last + 1];
+        results.iter().for_each(|r| reachable[*r] = true);
+        let mut swept = 0;
+        for i in (0..=last).rev() {
+            if !reachable[i] {
+                continue;
+            }
+            let (mut below, node) = self.nodes.split_at_mut(i);
+            node.childs.iter().for_each(|c| reachable[*c] = true);
+            node.propagate_into(&mut below);
+            swept += 1;
+        }
+        swept
+    }
+    /// # propagate_segments
+    /// Reverse sweep of independent segments in parallel. Each section begun after the mark is
+    /// a segment, e.g. one instrument of a portfolio, using only its own nodes and the nodes
+    /// below the mark such as market data. The adjoints the segments push below the mark are
+    /// summed at the end, then the nodes below the mark are swept. The results of the segments
+    /// must be seeded beforehand.
+    pub fn propagate_segments(&mut self) -> Result<()> {
+        let len = self.nodes.len();
+        let mark = self.mark.min(len);
+        let mut starts = vec![mark];
+        starts.extend(self.sections.iter().map(|(_, start)| *start).filter(|s| *s > mark));
+        starts.dedup();
+        let ends: Vec<usize> = starts.iter().skip(1).copied().chain([len]).collect();
+
+        for (&start, &end) in starts.iter().zip(&ends) {
+            for i in start..end {
+                if let Some(c) = self.nodes[i].childs.iter().find(|c| (mark..start).contains(*c)) {
+                    return Err(AtlasError::InvalidValueErr(format!(
+                        "node {} of the segment starting at {} uses node {} of an earlier segment",
+                        i, start, c
+                    )));
+                }
+            }
+        }
+
+        // segments are swept from contiguous slices, the nodes below the mark stay in place
+        let mut above = self.nodes.split_off(mark);
+        let mut rest = above.as_mut_slice();
+        let mut segments = Vec::new();
+        for (&start, &end) in starts.iter().zip(&ends) {
+            let (segment, tail) = rest.split_at_mut(end - start);
+            segments.push((start, segment));
+            rest = tail;
+        }
+
+        let junctions: Vec<HashMap<usize, (f64, f64)>> = segments
+            .into_par_iter()
+            .map(|(start, segment)| {
+                let mut junction = HashMap::new();
+                for i in (0..segment.len()).rev() {
+                    let (below, node) = segment.split_at_mut(i);
+                    let node = &node[0];
+                    let (a, at) = (node.adj, node.adj_tangent);
+                    let pushes = node.childs.iter().zip(&node.derivs).zip(&node.deriv_tangents);
+                    for ((&c, d), dt) in pushes {
+                        let (adj, adj_tangent) = match c.checked_sub(start) {
+                            Some(j) => (&mut below[j].adj, &mut below[j].adj_tangent),
+                            None => {
+                                let (adj, adj_tangent) = junction.entry(c).or_insert((0.0, 0.0));
+                                (adj, adj_tangent)
+                            }
+                        };
+                        *adj += a * d;
+                        *adj_tangent += at * d + a * dt;
+                    }
+                }
+                junction
+            })
+            .collect();
+
+        self.nodes.extend(above);
+        for (c, (adj, adj_tangent)) in junctions.into_iter().flatten() {
+            self.nodes[c].adj += adj;
+            self.nodes[c].adj_tangent += adj_tangent;
+        }
+        for i in (0..mark).rev() {
+            let n = self.nodes[i].clone();
+            n.propagate_into(&mut self.nodes);
+        }
+        Ok(())
+    }
+    pub fn adjoint(&self, idx: usize) -> f64 {
+        self.nodes[idx].adj
+    }
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|e| AtlasError::SerializationErr(e.to_string()))
+    }
+    pub fn from_json(json: &str) -> Result<Tape> {
+        serde_json::from_str(json).map_err(|e| AtlasError::DeserializationErr(e.to_string()))
+    }
+    pub fn stats(&self) -> TapeStats {
+        let mut by_arity = BTreeMap::new();
+        let mut bytes = self.nodes.capacity() * size_of::<Node>();
+        for node in self.nodes.iter() {
+            *by_arity.entry(node.childs.len()).or_insert(0) += 1;
+            bytes += node.childs.capacity() * size_of::<usize>()
+                + node.derivs.heap_bytes()
+                + node.deriv_tangents.heap_bytes();
+        }
+
+        // a name used for several sections adds up their sizes
+        let mut sections = BTreeMap::new();
+        let ends = self.sections.iter().skip(1).map(|(_, start)| *start);
+        let ends = ends.chain(std::iter::once(self.nodes.len()));
+        for ((name, start), end) in self.sections.iter().zip(ends) {
+            *sections.entry(name.clone()).or_insert(0) += end - start;
+        }
+
+        TapeStats {
+            nodes: self.nodes.len(),
+            by_arity,
+            bytes,
+            before_mark: self.mark.min(self.nodes.len()),
+            after_mark: self.nodes.len().saturating_sub(self.mark),
+            sections,
+        }
+    }
+}
+
+/// # TapeStats
+/// Size of the tape, to find which part of a model makes it grow. Expressions are flattened into
+/// one node each, so nodes are counted by their number of inputs rather than by operation: leaves
+/// have none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapeStats {
+    nodes: usize,
+    by_arity: BTreeMap<usize, usize>,
+    bytes: usize,
+    before_mark: usize,
+    after_mark: usize,
+    sections: BTreeMap<String, usize>,
+}
+
+impl TapeStats {
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    /// Number of nodes by number of inputs
+    pub fn by_arity(&self) -> &BTreeMap<usize, usize> {
+        &self.by_arity
+    }
+
+    pub fn leaves(&self) -> usize {
+        self.by_arity.get(&0).copied().unwrap_or(0)
+    }
+
+    /// Heap memory held by the nodes, including unused capacity
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn before_mark(&self) -> usize {
+        self.before_mark
+    }
+
+    pub fn after_mark(&self) -> usize {
+        self.after_mark
+    }
+
+    /// Nodes recorded in each section named with `begin_tape_section`
+    pub fn sections(&self) -> &BTreeMap<String, usize> {
+        &self.sections
+    }
 }
 
 thread_local! {
     pub static TAPE: RefCell<Tape> = RefCell::new(Tape::default());
 }
 
+/// # with_tape
+/// Run `f` recording on `tape` instead of the tape of the current thread, which is put back
+/// afterwards, even if `f` panics. A tape held this way can move between web workers or async
+/// tasks: every model and evaluator recording through `TAPE` records on it while `f` runs.
+pub fn with_tape<R>(tape: &mut Tape, f: impl FnOnce() -> R) -> R {
+    struct Restore<'a>(&'a mut Tape);
+    impl Drop for Restore<'_> {
+        fn drop(&mut self) {
+            TAPE.with(|t| std::mem::swap(&mut *t.borrow_mut(), self.0));
+        }
+    }
+
+    TAPE.with(|t| std::mem::swap(&mut *t.borrow_mut(), tape));
+    let _restore = Restore(tape);
+    f()
+}
+
+/// Number of nodes recorded on the tape of the current thread
+pub fn tape_len() -> usize {
+    TAPE.with(|t| t.borrow().nodes.len())
+}
+
+/// Drop the nodes of the tape of the current thread recorded after the first `len` ones
+pub fn truncate_tape(len: usize) {
+    TAPE.with(|t| t.borrow_mut().truncate(len));
+}
+
+/// Storage of the partials recorded from now on by the tape of the current thread
+pub fn set_tape_precision(precision: TapePrecision) {
+    TAPE.with(|t| t.borrow_mut().precision = precision);
+}
+
+/// Track where NaN and Inf appear on the tape of the current thread, see
+/// `Tape::with_non_finite_tracking`
+pub fn set_non_finite_tracking(track: bool) {
+    TAPE.with(|t| t.borrow_mut().track_non_finite = track);
+}
+
+/// First node of the tape of the current thread with a non-finite value, when tracked
+pub fn non_finite_origin() -> Option<NonFiniteOrigin> {
+    TAPE.with(|t| t.borrow().non_finite.clone())
+}
+
+/// Copy of the tape of the current thread
+pub fn snapshot_tape() -> Tape {
+    TAPE.with(|t| t.borrow().clone())
+}
+
+/// Statistics of the tape of the current thread
+pub fn tape_stats() -> TapeStats {
+    TAPE.with(|t| t.borrow().stats())
+}
+
+/// Count the nodes recorded from now on under `name` in `tape_stats`, until the next section
+pub fn begin_tape_section(name: &str) {
+    TAPE.with(|t| t.borrow_mut().begin_section(name));
+}
+
+/// Push a mark on the tape of the current thread, see `Tape::push_mark`
+pub fn push_mark() {
+    TAPE.with(|t| t.borrow_mut().push_mark());
+}
+
+/// Rewind the tape of the current thread to its last pushed mark, see `Tape::pop_and_rewind`
+pub fn pop_and_rewind() -> Option<usize> {
+    TAPE.with(|t| t.borrow_mut().pop_and_rewind())
+}
+
+/// # TapeScope
+/// Pushes a mark on the tape of the current thread and rewinds to it when dropped, so that what
+/// a scenario or an event records is dropped at the end of its scope. Scopes nest.
+pub struct TapeScope {
+    _private: (),
+}
+
+impl TapeScope {
+    pub fn new() -> Self {
+        push_mark();
+        TapeScope { _private: () }
+    }
+}
+
+impl Default for TapeScope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TapeScope {
+    fn drop(&mut self) {
+        pop_and_rewind();
+    }
+}
+
+/// Parallel reverse sweep of the segments of the tape of the current thread, see
+/// `Tape::propagate_segments`
+pub fn propagate_segments() -> Result<()> {
+    TAPE.with(|t| t.borrow_mut().propagate_segments())
+}
+
 /// Mark the current end of the tape (useful to propagate only a suffix)
 pub fn set_mark() {
-    TAPE.with(|t| t.borrow_mut().mark = t.borrow().nodes.len());
+    TAPE.with(|t| {
+        let mut t = t.borrow_mut();
+        t.mark = t.nodes.len();
+    });
 }
 
 /// Full reverse sweep (every node, single adjoint)
@@ -50,3 +458,131 @@ pub fn propagate_range(from: usize, to: usize) {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::ad::adnumber::ADNumber;
+
+    #[test]
+    fn test_tape_stats() {
+        let mut tape = Tape::default();
+        tape.begin_section("inputs");
+        tape.new_leaf();
+        tape.new_leaf();
+        tape.mark = tape.nodes.len();
+        tape.begin_section("payoff");
+        tape.record(Node {
+            childs: vec![0, 1],
+            derivs: vec![1.0, 1.0].into(),
+            deriv_tangents: vec![0.0, 0.0].into(),
+            ..Default::default()
+        });
+        tape.begin_section("inputs");
+        tape.new_leaf();
+
+        let stats = tape.stats();
+        assert_eq!(stats.nodes(), 4);
+        assert_eq!(stats.leaves(), 3);
+        assert_eq!(stats.by_arity().get(&2), Some(&1));
+        assert_eq!((stats.before_mark(), stats.after_mark()), (2, 2));
+        assert_eq!(stats.sections().get("inputs"), Some(&3));
+        assert_eq!(stats.sections().get("payoff"), Some(&1));
+        assert!(stats.bytes() >= 4 * size_of::<Node>() + 6 * 8);
+
+        tape.truncate(2);
+        assert_eq!(tape.stats().sections().get("payoff"), None);
+    }
+
+    #[test]
+    fn test_snapshot_replay() {
+        let x = ADNumber::new(2.0);
+        let y = ADNumber::new(3.0);
+        let f: ADNumber = (x.clone() * y.clone() + x.clone()).into();
+
+        let json = snapshot_tape().to_json().unwrap();
+        let mut replayed = Tape::from_json(&json).unwrap();
+        replayed.propagate_from(f.index());
+        assert_eq!(replayed.adjoint(x.index()), 4.0);
+        assert_eq!(replayed.adjoint(y.index()), 2.0);
+        // the tape of the thread is left untouched
+        assert_eq!(x.adjoint(), 0.0);
+
+        assert!(Tape::from_json("{").is_err());
+    }
+
+    #[test]
+    fn test_propagate_segments() {
+        let x = ADNumber::new(2.0);
+        let y = ADNumber::new(3.0);
+        set_mark();
+        begin_tape_section("f");
+        let u: ADNumber = (x.clone() * y.clone()).into();
+        let f: ADNumber = (u.clone() * u.clone()).into();
+        begin_tape_section("g");
+        let g: ADNumber = (x.clone() * x.clone() + y.clone()).into();
+
+        TAPE.with(|t| {
+            let mut t = t.borrow_mut();
+            t.nodes[f.index()].adj = 1.0;
+            t.nodes[g.index()].adj = 1.0;
+        });
+        propagate_segments().unwrap();
+        // f = (x y)^2 and g = x^2 + y at x = 2, y = 3
+        assert_eq!(x.adjoint(), 36.0 + 4.0);
+        assert_eq!(y.adjoint(), 24.0 + 1.0);
+        assert_eq!(u.adjoint(), 12.0);
+
+        // a segment reading another one is rejected
+        begin_tape_section("h");
+        let _h: ADNumber = (u.clone() + 1.0).into();
+        assert!(propagate_segments().is_err());
+    }
+
+    #[test]
+    fn test_with_tape() {
+        let mut tape = Tape::default();
+        let own = ADNumber::new(1.0);
+        let (x, f) = with_tape(&mut tape, || {
+            let x = ADNumber::new(2.0);
+            let f: ADNumber = (x.clone() * x.clone()).into();
+            (x, f)
+        });
+        assert_eq!((tape.nodes.len(), tape_len()), (2, 1));
+
+        // the tape moves to another thread for the backward pass
+        let adjoint = std::thread::spawn(move || {
+            with_tape(&mut tape, || {
+                f.propagate_to_start();
+                x.adjoint()
+            })
+        })
+        .join()
+        .unwrap();
+        assert_eq!(adjoint, 4.0);
+        assert!(own.is_live());
+    }
+
+    #[test]
+    fn test_nested_scopes() {
+        let market = ADNumber::new(1.0);
+        set_mark();
+        let base = tape_len();
+        {
+            let _scenario = TapeScope::new();
+            let x: ADNumber = (market.clone() * 2.0).into();
+            {
+                let _event = TapeScope::new();
+                let _y: ADNumber = (x.clone() * 3.0).into();
+                assert_eq!(tape_len(), base + 2);
+            }
+            assert_eq!(tape_len(), base + 1);
+            assert!(x.is_live());
+            assert_eq!(TAPE.with(|t| t.borrow().mark), base);
+        }
+        assert_eq!(tape_len(), base);
+        assert_eq!(TAPE.with(|t| t.borrow().mark), base);
+        assert!(market.is_live());
+        assert_eq!(pop_and_rewind(), None);
+    }
+}
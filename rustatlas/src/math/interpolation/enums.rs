@@ -19,6 +19,7 @@ use crate::prelude::*;
 pub enum Interpolator {
     Linear,
     LogLinear,
+    MonotoneCubic,
 }
 
 impl Interpolator {
@@ -36,6 +37,9 @@ impl Interpolator {
             Interpolator::LogLinear => {
                 LogLinearInterpolator::interpolate(x, x_, y_, enable_extrapolation)
             }
+            Interpolator::MonotoneCubic => {
+                MonotoneCubicInterpolator::interpolate(x, x_, y_, enable_extrapolation)
+            }
         }
     }
 }
@@ -1,4 +1,5 @@
 pub mod linear;
 pub mod loglinear;
+pub mod monotonecubic;
 pub mod traits;
 pub mod enums;
\ No newline at end of file
@@ -0,0 +1,144 @@
+use std::cmp::Ordering;
+
+use crate::prelude::*;
+
+/// # Monotone Cubic Interpolator
+/// Fritsch-Carlson monotone cubic interpolator. Its first derivative is continuous at the
+/// pillars, so sensitivities to the curve do not spike there as with linear interpolation, and
+/// it does not overshoot between monotone pillars. Extrapolates linearly with the end slopes,
+/// and is flat on a curve of a single pillar.
+#[derive(Clone)]
+pub struct MonotoneCubicInterpolator {}
+
+impl MonotoneCubicInterpolator {
+    fn secant<T: GenericNumber>(x_: &[T], y_: &[T], k: usize) -> T {
+        (y_[k + 1] - y_[k]) / (x_[k + 1] - x_[k])
+    }
+
+    /// Slope at the pillar `k`: weighted harmonic mean of the secants around it, zero at a
+    /// local extremum, the secant at both ends
+    fn slope<T: GenericNumber>(x_: &[T], y_: &[T], k: usize) -> T {
+        let n = x_.len();
+        if k == 0 {
+            return Self::secant(x_, y_, 0);
+        }
+        if k == n - 1 {
+            return Self::secant(x_, y_, n - 2);
+        }
+        let (left, right) = (Self::secant(x_, y_, k - 1), Self::secant(x_, y_, k));
+        let zero = T::from(0.0);
+        if left * right <= zero {
+            return zero;
+        }
+        let (h_left, h_right) = (x_[k] - x_[k - 1], x_[k + 1] - x_[k]);
+        let w_left = h_right * 2.0 + h_left;
+        let w_right = h_left * 2.0 + h_right;
+        (w_left + w_right) / (w_left / left + w_right / right)
+    }
+}
+
+impl<T: GenericNumber> Interpolate<T> for MonotoneCubicInterpolator {
+    fn interpolate(x: T, x_: &Vec<T>, y_: &Vec<T>, enable_extrapolation: bool) -> T {
+        let index =
+            match x_.binary_search_by(|&probe| probe.partial_cmp(&x).unwrap_or(Ordering::Less)) {
+                Ok(index) => index,
+                Err(index) => index,
+            };
+
+        if !enable_extrapolation && (x < *x_.first().unwrap() || x > *x_.last().unwrap()) {
+            panic!("Extrapolation is not enabled, and the provided value is outside the range.");
+        }
+
+        // a single pillar has no slope, the curve is flat
+        let n = x_.len();
+        if n < 2 {
+            return y_[0];
+        }
+        match index {
+            0 => y_[0] + (x - x_[0]) * Self::slope(x_, y_, 0),
+            idx if idx == n => {
+                let slope = Self::slope(x_, y_, n - 1);
+                y_[n - 1] + (x - x_[n - 1]) * slope
+            }
+            _ => {
+                let (i, j) = (index - 1, index);
+                let h = x_[j] - x_[i];
+                let t = (x - x_[i]) / h;
+                let t2 = t * t;
+                let t3 = t2 * t;
+                // cubic Hermite basis
+                let h00 = t3 * 2.0 - t2 * 3.0 + 1.0;
+                let h10 = t3 - t2 * 2.0 + t;
+                let h01 = t2 * 3.0 - t3 * 2.0;
+                let h11 = t3 - t2;
+                let m_i = h * Self::slope(x_, y_, i);
+                let m_j = h * Self::slope(x_, y_, j);
+                h00 * y_[i] + h10 * m_i + h01 * y_[j] + h11 * m_j
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monotone_cubic_interpolation() {
+        let x_ = vec![0.0, 1.0, 2.0, 4.0];
+        let y_ = vec![0.0, 1.0, 1.5, 1.6];
+
+        // goes through the pillars and stays monotone in between
+        for (x, y) in x_.iter().zip(&y_) {
+            assert!(
+                (MonotoneCubicInterpolator::interpolate(*x, &x_, &y_, false) - y).abs() < 1e-12
+            );
+        }
+        let values: Vec<f64> = (0..=40)
+            .map(|i| MonotoneCubicInterpolator::interpolate(i as f64 * 0.1, &x_, &y_, false))
+            .collect();
+        assert!(values.windows(2).all(|w| w[1] >= w[0]));
+
+        // the derivative along x is continuous at a pillar
+        let h = 1e-6;
+        let at = |x: f64| MonotoneCubicInterpolator::interpolate(x, &x_, &y_, true);
+        let left = (at(1.0) - at(1.0 - h)) / h;
+        let right = (at(1.0 + h) - at(1.0)) / h;
+        assert!((left - right).abs() < 1e-4);
+
+        // and so is the sensitivity to a pillar value
+        let bumped = |x: f64| {
+            let mut y = y_.clone();
+            y[1] += h;
+            (MonotoneCubicInterpolator::interpolate(x, &x_, &y, true) - at(x)) / h
+        };
+        assert!((bumped(1.0 - 1e-4) - bumped(1.0 + 1e-4)).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_single_pillar() {
+        let (x_, y_) = (vec![1.0], vec![0.03]);
+        assert_eq!(
+            MonotoneCubicInterpolator::interpolate(1.0, &x_, &y_, false),
+            0.03
+        );
+        assert_eq!(
+            MonotoneCubicInterpolator::interpolate(5.0, &x_, &y_, true),
+            0.03
+        );
+    }
+
+    #[test]
+    fn test_sensitivities_with_dual_numbers() {
+        let x_: Vec<Dual<1>> = [0.0, 1.0, 2.0].iter().map(|x| Dual::constant(*x)).collect();
+        let y_ = vec![
+            Dual::constant(1.0),
+            Dual::variable(2.0, 0),
+            Dual::constant(2.5),
+        ];
+        let at_pillar = MonotoneCubicInterpolator::interpolate(Dual::constant(1.0), &x_, &y_, true);
+        assert_eq!(at_pillar.derivative(0), 1.0);
+        let between = MonotoneCubicInterpolator::interpolate(Dual::constant(0.5), &x_, &y_, true);
+        assert!(between.derivative(0) > 0.0 && between.derivative(0) < 1.0);
+    }
+}
@@ -1,11 +1,14 @@
+use std::collections::HashMap;
+
 use rand::{rngs::StdRng, Rng, SeedableRng};
 use rand_distr::StandardNormal;
 
+use crate::core::historicaldata::HistoricalData;
 use crate::core::meta::{MarketData, MarketRequest};
 use crate::math::ad::genericnumber::Real;
 use crate::prelude::{
-    Actual360, DayCountProvider, DiscountFactorRequest, ExchangeRateRequest, ForwardRateRequest,
-    HasReferenceDate, SimpleModel,
+    Actual360, CorrelationRequest, Currency, DayCountProvider, DiscountFactorRequest, EquityRequest, ExchangeRateRequest,
+    ForwardRateRequest, HasReferenceDate, SimpleModel, VolatilityRequest,
 };
 use crate::time::date::Date;
 use crate::utils::errors::Result;
@@ -13,15 +16,320 @@ use crate::utils::errors::Result;
 use super::deterministicmodel::DeterministicModel;
 use super::stochasticmodel::{Scenario, StochasticModel};
 
-/// Simple Black-Scholes Monte Carlo generator
+/// # JumpParameters
+/// Merton jumps of an underlier: jumps arrive with the yearly `intensity` and multiply the spot
+/// by a lognormal factor whose log has the given `mean` and `volatility`. The intensity is a
+/// plain number since it sets how many jumps are drawn.
+#[derive(Clone, Copy, Debug)]
+pub struct JumpParameters<T: Real> {
+    intensity: f64,
+    mean: T,
+    volatility: T,
+}
+
+impl<T: Real> JumpParameters<T> {
+    pub fn new(intensity: f64, mean: T, volatility: T) -> Self {
+        Self {
+            intensity,
+            mean,
+            volatility,
+        }
+    }
+
+    pub fn intensity(&self) -> f64 {
+        self.intensity
+    }
+
+    pub fn mean(&self) -> T {
+        self.mean
+    }
+
+    pub fn volatility(&self) -> T {
+        self.volatility
+    }
+
+    /// Expected relative move of the spot in a jump, `E[J] - 1`
+    fn mean_jump(&self) -> T {
+        (self.mean + self.volatility * self.volatility * 0.5).exp() - 1.0
+    }
+
+    /// Jump times, in years, and log-sizes up to `horizon`, with negated size shocks if
+    /// `antithetic`
+    fn gen_arrivals<R: Rng>(&self, horizon: f64, antithetic: bool, rng: &mut R) -> Vec<(f64, T)> {
+        let mut arrivals = Vec::new();
+        if self.intensity <= 0.0 {
+            return arrivals;
+        }
+        let mut time = 0.0;
+        loop {
+            time -= (1.0 - rng.gen::<f64>()).ln() / self.intensity;
+            if time > horizon {
+                return arrivals;
+            }
+            let z = standard_normal(rng, antithetic);
+            arrivals.push((time, self.mean + self.volatility * z));
+        }
+    }
+}
+
+/// Simple Black-Scholes Monte Carlo generator. Each currency is discounted on its curve of the
+/// index store unless a discounting curve is mapped to it, forward rates are projected on the
+/// provider of their request. Underliers with jump parameters follow Merton's jump-diffusion,
+/// with the jumps compensated in the drift so forwards are unchanged. Equities drop by the
+/// dividends of the historical data at their ex-dates.
 #[derive(Clone)]
 pub struct BlackScholesModel<'a, T: Real> {
     pub simple: SimpleModel<'a, T>,
+    discount_curves: HashMap<Currency, usize>,
+    jumps: HashMap<String, JumpParameters<T>>,
+    seed: u64,
+    antithetic: bool,
 }
 
 impl<'a, T: Real> BlackScholesModel<'a, T> {
     pub fn new(simple: SimpleModel<'a, T>) -> Self {
-        Self { simple }
+        Self {
+            simple,
+            discount_curves: HashMap::new(),
+            jumps: HashMap::new(),
+            seed: 0xA55AA55Au64,
+            antithetic: false,
+        }
+    }
+
+    /// # with_jumps
+    /// Merton jumps of an underlier, named as in `VolatilityRequest` (`"USD/CLP"`, `"AAPL"`)
+    pub fn with_jumps(mut self, underlier: &str, jumps: JumpParameters<T>) -> Self {
+        self.jumps.insert(underlier.to_string(), jumps);
+        self
+    }
+
+    /// # with_historical_data
+    /// Historical data with the discrete dividends of the equities
+    pub fn with_historical_data(mut self, historical_data: &'a HistoricalData) -> Self {
+        self.simple = self.simple.with_historical_data(historical_data);
+        self
+    }
+
+    /// # with_seed
+    /// Seed of the random numbers of the generated scenario
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// # with_antithetic
+    /// Negate the diffusion and jump size shocks, see `standard_normal`
+    pub fn with_antithetic(mut self, antithetic: bool) -> Self {
+        self.antithetic = antithetic;
+        self
+    }
+
+    /// # gen_scenario_pair
+    /// Scenario of the seed and its antithetic one, drawn from the same random numbers
+    pub fn gen_scenario_pair(
+        &self,
+        market_requests: &[MarketRequest],
+    ) -> Result<(Scenario<T>, Scenario<T>)> {
+        let plain = self.clone().with_antithetic(false);
+        let antithetic = self.clone().with_antithetic(true);
+        Ok((
+            plain.gen_scenario(market_requests)?,
+            antithetic.gen_scenario(market_requests)?,
+        ))
+    }
+
+    pub fn jumps(&self, underlier: &str) -> Option<JumpParameters<T>> {
+        self.jumps.get(underlier).copied()
+    }
+
+    /// # with_discount_curve
+    /// Discount the given currency on the provider `id`, typically its OIS curve, instead of
+    /// the currency curve of the index store
+    pub fn with_discount_curve(mut self, currency: Currency, id: usize) -> Self {
+        self.discount_curves.insert(currency, id);
+        self
+    }
+
+    /// Provider of the discounting curve of a currency
+    fn discount_curve(&self, currency: Currency) -> Result<usize> {
+        match self.discount_curves.get(&currency) {
+            Some(id) => Ok(*id),
+            None => self
+                .simple
+                .market_store()
+                .index_store()
+                .get_currency_curve(currency),
+        }
+    }
+
+    /// # equity_forward
+    /// Forward of an equity to `date`, discounted on the curve of the local currency, see
+    /// `SimpleModel::equity_forward`
+    pub fn equity_forward(&self, name: &str, date: Date) -> Result<T> {
+        let local_curve = self.discount_curve(self.simple.market_store().local_currency())?;
+        self.simple.equity_forward(name, date, local_curve)
+    }
+
+    /// # gen_nodes
+    /// Nodes of a scenario. FX and equity underliers move by their risk-neutral drift plus
+    /// `log_return(request, t)`, the simulated part of their log-return `t` years ahead.
+    pub(crate) fn gen_nodes<F>(
+        &self,
+        market_requests: &[MarketRequest],
+        mut log_return: F,
+    ) -> Result<Scenario<T>>
+    where
+        F: FnMut(&MarketRequest, T) -> Result<T>,
+    {
+        let store = self.simple.market_store();
+        let ref_date = store.reference_date();
+        let local_ccy = store.local_currency();
+
+        /* collect the nodes of this scenario */
+        let mut nodes = Vec::with_capacity(market_requests.len());
+
+        for req in market_requests {
+            /* ======================================================
+             *  FX NODE  (Monte-Carlo path)
+             * ====================================================*/
+            if let Some(fx_req) = req.fx() {
+                /* maturity ....................................... */
+                let mat = fx_req.reference_date().unwrap_or(ref_date);
+                let t = Actual360::year_fraction::<T>(ref_date, mat);
+
+                /* spot ........................................... */
+                let spot_req = ExchangeRateRequest::new(
+                    fx_req.first_currency(),  // base  (a)
+                    fx_req.second_currency(), // quote (b)
+                    Some(ref_date),
+                );
+                let s0 = self.simple.gen_fx_data(spot_req).unwrap();
+
+                /* discount factors at maturity .................. */
+                let second_ccy = match fx_req.second_currency() {
+                    Some(ccy) => ccy,
+                    None => local_ccy, // if no second currency is given, use local currency
+                };
+                let base_curve = self.discount_curve(fx_req.first_currency()).unwrap();
+                let quote_curve = self.discount_curve(second_ccy).unwrap();
+                let local_curve = self.discount_curve(local_ccy).unwrap();
+
+                let p_base = self
+                    .simple
+                    .gen_df_data(DiscountFactorRequest::new(base_curve, mat))
+                    .unwrap();
+                let p_quote = self
+                    .simple
+                    .gen_df_data(DiscountFactorRequest::new(quote_curve, mat))
+                    .unwrap();
+                let p_local = self
+                    .simple
+                    .gen_df_data(DiscountFactorRequest::new(local_curve, mat))
+                    .unwrap();
+
+                /* continuous short-rates ........................ */
+                let r_base = -p_base.ln() / t;
+                let r_quote = -p_quote.ln() / t;
+                let r_local = -p_local.ln() / t;
+
+                /* risk-neutral drift plus the simulated move ..... */
+                let s_t = s0 * ((r_quote - r_base) * t + log_return(req, t)?).exp();
+
+                /* ---------------- numerarie (local-currency) -----------
+                 *
+                 *  For a payoff settled in the **quote** currency *b* :
+                 *      N_T =  FX_{b→L}(T) / P_L(0,T)
+                 *
+                 *  FX_{b→L}(T) is handled case-by-case:
+                 *    1. L == b  → FX = 1
+                 *    2. L == a  → FX = 1 / S_{a,b}(T)
+                 *    3. else     → use interest-parity forward
+                 * ----------------------------------------------------*/
+
+                let fx_b_to_l: T = if local_ccy == second_ccy {
+                    T::from(1.0) // case (1)
+                } else if local_ccy == fx_req.first_currency() {
+                    T::from(1.0) / s_t // case (2)
+                } else {
+                    /* case (3) – build forward B/L using interest parity */
+                    let spot_b_l = self
+                        .simple
+                        .gen_fx_data(ExchangeRateRequest::new(
+                            second_ccy,
+                            Some(local_ccy),
+                            Some(ref_date),
+                        ))
+                        .unwrap();
+
+                    let fwd = spot_b_l * ((r_quote - r_local) * t).exp();
+                    fwd
+                };
+                let numerarie = fx_b_to_l / p_local;
+
+                // other values
+                let fwd = match req.fwd() {
+                    Some(fwd_req) => Some(self.simple.gen_fwd_data(fwd_req).unwrap()),
+                    None => None,
+                };
+                let df = match req.df() {
+                    Some(df_req) => Some(self.simple.gen_df_data(df_req).unwrap()),
+                    None => None,
+                };
+
+                nodes.push(MarketData::new(
+                    req.id(),
+                    mat,
+                    /* df  */ df,
+                    /* fwd */ fwd,
+                    /* fx  */ Some(s_t),
+                    /* num */ numerarie,
+                ));
+            }
+            /* ======================================================
+             *  EQUITY NODE  (Monte-Carlo path, local currency)
+             * ====================================================*/
+            else if let Some(eq_req) = req.equity() {
+                let mat = eq_req.reference_date().unwrap_or(ref_date);
+                let t = Actual360::year_fraction::<T>(ref_date, mat);
+
+                let s0 = store.equity_store().get_spot(eq_req.name().clone())?;
+                let (s_t, numerarie) = if mat > ref_date {
+                    let local_curve = self.discount_curve(local_ccy)?;
+                    let p_local = self
+                        .simple
+                        .gen_df_data(DiscountFactorRequest::new(local_curve, mat))?;
+
+                    /* forward under the local risk-neutral measure times the simulated move */
+                    let forward = self.equity_forward(eq_req.name(), mat)?;
+                    let s_t = forward * log_return(req, t)?.exp();
+                    (s_t, T::from(1.0) / p_local)
+                } else {
+                    (s0, T::from(1.0))
+                };
+
+                let fwd = match req.fwd() {
+                    Some(fwd_req) => Some(self.simple.gen_fwd_data(fwd_req)?),
+                    None => None,
+                };
+                let df = match req.df() {
+                    Some(df_req) => Some(self.simple.gen_df_data(df_req)?),
+                    None => None,
+                };
+
+                nodes.push(
+                    MarketData::new(req.id(), mat, df, fwd, None, numerarie)
+                        .with_equity(Some(s_t)),
+                );
+            }
+            /* ======================================================
+             *  ALL OTHER NODES – deterministic
+             * ====================================================*/
+            else {
+                nodes.push(self.simple.gen_node(req).unwrap());
+            }
+        } // loop over requests
+        Ok(nodes)
     }
 }
 
@@ -42,6 +350,19 @@ impl<T: Real> DeterministicModel<T> for BlackScholesModel<'_, T> {
         self.simple.gen_fwd_data(fwd)
     }
 
+    fn gen_equity_data(&self, equity: EquityRequest) -> Result<T> {
+        let date = equity.reference_date().unwrap_or(self.reference_date());
+        self.equity_forward(equity.name(), date)
+    }
+
+    fn gen_vol_data(&self, vol: VolatilityRequest) -> Result<T> {
+        self.simple.gen_vol_data(vol)
+    }
+
+    fn gen_corr_data(&self, corr: CorrelationRequest) -> Result<T> {
+        self.simple.gen_corr_data(corr)
+    }
+
     fn gen_numerarie(&self, market_request: &MarketRequest) -> Result<T> {
         self.simple.gen_numerarie(market_request)
     }
@@ -52,132 +373,162 @@ impl<'a, T: Real> StochasticModel<T> for BlackScholesModel<'a, T> {
         let store = self.simple.market_store();
         let ref_date = store.reference_date();
         let local_ccy = store.local_currency();
-        let idx = store.index_store();
-
-        /* --- parallel over all paths ------------------------------------ */
-        let scenario: Vec<Scenario<T>> = {
-            /* each path gets its own reproducible RNG */
-            let mut rng = StdRng::seed_from_u64(0xA55AA55Au64 as u64);
-
-            /* collect the nodes of this scenario */
-            let mut nodes = Vec::with_capacity(market_requests.len());
-
-            for req in market_requests {
-                /* ======================================================
-                 *  FX NODE  (Monte-Carlo path)
-                 * ====================================================*/
-                if let Some(fx_req) = req.fx() {
-                    /* maturity ....................................... */
-                    let mat = fx_req.reference_date().unwrap_or(ref_date);
-                    let t = Actual360::year_fraction::<T>(ref_date, mat);
-
-                    /* spot ........................................... */
-                    let spot_req = ExchangeRateRequest::new(
-                        fx_req.first_currency(),  // base  (a)
-                        fx_req.second_currency(), // quote (b)
-                        Some(ref_date),
-                    );
-                    let s0 = self.simple.gen_fx_data(spot_req).unwrap();
-
-                    /* discount factors at maturity .................. */
-                    let second_ccy = match fx_req.second_currency() {
-                        Some(ccy) => ccy,
-                        None => local_ccy, // if no second currency is given, use local currency
-                    };
-                    let base_curve = idx.get_currency_curve(fx_req.first_currency()).unwrap();
-                    let quote_curve = idx.get_currency_curve(second_ccy).unwrap();
-                    let local_curve = idx.get_currency_curve(local_ccy).unwrap();
-
-                    let p_base = self
-                        .simple
-                        .gen_df_data(DiscountFactorRequest::new(base_curve, mat))
-                        .unwrap();
-                    let p_quote = self
-                        .simple
-                        .gen_df_data(DiscountFactorRequest::new(quote_curve, mat))
-                        .unwrap();
-                    let p_local = self
-                        .simple
-                        .gen_df_data(DiscountFactorRequest::new(local_curve, mat))
-                        .unwrap();
 
-                    /* continuous short-rates ........................ */
-                    let r_base = -p_base.ln() / t;
-                    let r_quote = -p_quote.ln() / t;
-                    let r_local = -p_local.ln() / t;
-
-                    /* one-step GBM .................................. */
-                    let sigma =
-                        store.get_exchange_rate_volatility(fx_req.first_currency(), second_ccy)?;
-                    let z = rng.sample::<f64, _>(StandardNormal);
-
-                    let drift = (r_quote - r_base) - sigma * sigma * 0.5;
-                    let s_t = s0 * (drift * t + sigma * t.sqrt() * z).exp();
-
-                    /* ---------------- numerarie (local-currency) -----------
-                     *
-                     *  For a payoff settled in the **quote** currency *b* :
-                     *      N_T =  FX_{b→L}(T) / P_L(0,T)
-                     *
-                     *  FX_{b→L}(T) is handled case-by-case:
-                     *    1. L == b  → FX = 1
-                     *    2. L == a  → FX = 1 / S_{a,b}(T)
-                     *    3. else     → use interest-parity forward
-                     * ----------------------------------------------------*/
-
-                    let fx_b_to_l: T = if local_ccy == second_ccy {
-                        T::from(1.0) // case (1)
-                    } else if local_ccy == fx_req.first_currency() {
-                        T::from(1.0) / s_t // case (2)
-                    } else {
-                        /* case (3) – build forward B/L using interest parity */
-                        let spot_b_l = self
-                            .simple
-                            .gen_fx_data(ExchangeRateRequest::new(
-                                second_ccy,
-                                Some(local_ccy),
-                                Some(ref_date),
-                            ))
-                            .unwrap();
-
-                        let fwd = spot_b_l * ((r_quote - r_local) * t).exp();
-                        fwd
-                    };
-                    let numerarie = fx_b_to_l / p_local;
-
-                    // other values
-                    let fwd = match req.fwd() {
-                        Some(fwd_req) => Some(self.simple.gen_fwd_data(fwd_req).unwrap()),
-                        None => None,
-                    };
-                    let df = match req.df() {
-                        Some(df_req) => Some(self.simple.gen_df_data(df_req).unwrap()),
-                        None => None,
-                    };
-
-                    nodes.push(MarketData::new(
-                        req.id(),
-                        mat,
-                        /* df  */ df,
-                        /* fwd */ fwd,
-                        /* fx  */ Some(s_t),
-                        /* num */ numerarie,
-                    ));
-                }
-                /* ======================================================
-                 *  ALL OTHER NODES – deterministic
-                 * ====================================================*/
-                else {
-                    nodes.push(self.simple.gen_node(req).unwrap());
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let underliers = simulated_underliers(market_requests, local_ccy);
+        let chol = store.correlation_store().cholesky(&underliers)?;
+
+        /* correlated Brownian motions of the underliers at each node date, built from
+         * independent sqrt(dt) increments ............................. */
+        let mut brownians = vec![T::from(0.0); underliers.len()];
+        let mut paths = HashMap::new();
+        let mut t_prev = 0.0;
+        let dates = future_node_dates(market_requests, ref_date);
+        for date in &dates {
+            let t = Actual360::year_fraction::<f64>(ref_date, *date);
+            let shocks = correlated_normals(&chol, self.antithetic, &mut rng);
+            let sqrt_dt = T::from((t - t_prev).sqrt());
+            brownians = brownians
+                .iter()
+                .zip(&shocks)
+                .map(|(w, z)| *w + sqrt_dt * *z)
+                .collect();
+            for (name, w) in underliers.iter().zip(&brownians) {
+                paths.insert((name.clone(), *date), *w);
+            }
+            t_prev = t;
+        }
+
+        /* jumps up to the last node, shared by all nodes of an underlier */
+        let horizon = dates
+            .last()
+            .map_or(0.0, |date| Actual360::year_fraction::<f64>(ref_date, *date));
+        let arrivals = underliers
+            .iter()
+            .filter_map(|name| {
+                let jumps = self.jumps(name)?;
+                Some((
+                    name.clone(),
+                    jumps.gen_arrivals(horizon, self.antithetic, &mut rng),
+                ))
+            })
+            .collect::<HashMap<String, Vec<(f64, T)>>>();
+
+        /* GBM with the volatility of the store along the path ......... */
+        self.gen_nodes(market_requests, |req, t| {
+            let name = underlier_name(req, local_ccy).unwrap();
+            let date = node_date(req, ref_date).unwrap();
+            let sigma = match req.fx() {
+                Some(fx_req) => store.get_exchange_rate_volatility(
+                    fx_req.first_currency(),
+                    fx_req.second_currency().unwrap_or(local_ccy),
+                )?,
+                None => store.equity_store().get_volatility(name.clone())?,
+            };
+            // nodes at the reference date have not moved
+            let brownian = paths
+                .get(&(name.clone(), date))
+                .copied()
+                .unwrap_or(T::from(0.0));
+            let diffusion = sigma * brownian - sigma * sigma * 0.5 * t;
+            match (self.jumps(&name), arrivals.get(&name)) {
+                (Some(jumps), Some(path)) => {
+                    let jumped = path
+                        .iter()
+                        .filter(|(time, _)| T::from(*time) <= t)
+                        .fold(T::from(0.0), |acc, (_, size)| acc + *size);
+                    let compensator = jumps.mean_jump() * jumps.intensity() * t;
+                    Ok(diffusion + jumped - compensator)
                 }
-            } // loop over requests
-            nodes
-        };
+                _ => Ok(diffusion),
+            }
+        })
+    }
+}
+
+/// Name of the underlier simulated for a request, following the naming of `VolatilityRequest`
+pub(crate) fn underlier_name(req: &MarketRequest, local_ccy: Currency) -> Option<String> {
+    if let Some(fx_req) = req.fx() {
+        let second_ccy = fx_req.second_currency().unwrap_or(local_ccy);
+        Some(format!(
+            "{}/{}",
+            String::from(fx_req.first_currency()),
+            String::from(second_ccy)
+        ))
+    } else {
+        req.equity().map(|eq_req| eq_req.name().clone())
+    }
+}
 
-        Ok(scenario)
+/// Date of an FX or equity node
+pub(crate) fn node_date(req: &MarketRequest, ref_date: Date) -> Option<Date> {
+    match (req.fx(), req.equity()) {
+        (Some(fx_req), _) => Some(fx_req.reference_date().unwrap_or(ref_date)),
+        (None, Some(eq_req)) => Some(eq_req.reference_date().unwrap_or(ref_date)),
+        (None, None) => None,
     }
 }
 
+/// Sorted dates of the FX and equity nodes after the reference date, the grid of path models
+pub(crate) fn future_node_dates(market_requests: &[MarketRequest], ref_date: Date) -> Vec<Date> {
+    let mut dates = market_requests
+        .iter()
+        .filter_map(|req| node_date(req, ref_date))
+        .filter(|date| *date > ref_date)
+        .collect::<Vec<Date>>();
+    dates.sort();
+    dates.dedup();
+    dates
+}
+
+/// Underliers simulated for the requests, in order of first appearance
+pub(crate) fn simulated_underliers(
+    market_requests: &[MarketRequest],
+    local_ccy: Currency,
+) -> Vec<String> {
+    market_requests
+        .iter()
+        .filter_map(|req| underlier_name(req, local_ccy))
+        .fold(Vec::new(), |mut acc, name| {
+            if !acc.contains(&name) {
+                acc.push(name);
+            }
+            acc
+        })
+}
+
+/// Standard normal draw, negated if `antithetic`. The models draw every shock of a scenario
+/// through it from their seed, so the scenarios of a seed with and without `antithetic` are an
+/// antithetic pair.
+pub(crate) fn standard_normal<R: Rng>(rng: &mut R, antithetic: bool) -> f64 {
+    let z = rng.sample::<f64, _>(StandardNormal);
+    if antithetic {
+        -z
+    } else {
+        z
+    }
+}
+
+/// Standard normals correlated by the Cholesky factor `chol`, from independent draws of
+/// `standard_normal`
+pub(crate) fn correlated_normals<T: Real, R: Rng>(
+    chol: &[Vec<T>],
+    antithetic: bool,
+    rng: &mut R,
+) -> Vec<T> {
+    let independent = chol
+        .iter()
+        .map(|_| standard_normal(rng, antithetic))
+        .collect::<Vec<f64>>();
+    chol.iter()
+        .map(|row| {
+            row.iter()
+                .zip(&independent)
+                .fold(T::from(0.0), |acc, (l, z)| acc + *l * T::from(*z))
+        })
+        .collect()
+}
+
 fn norm_pdf<T: Real>(x: T) -> T {
     let inv_sqrt_2pi = T::from(1.0 / (2.0_f64 * std::f64::consts::PI).sqrt());
     inv_sqrt_2pi * (-(x * x) * T::from(0.5)).exp()
@@ -225,6 +576,43 @@ pub fn bs_theta<T: Real>(s: T, k: T, r: T, vol: T, t: T) -> T {
     -(s * norm_pdf(d1) * vol) / (sqt * 2.0) - r * k * (-r * t).exp() * norm_cdf(d2)
 }
 
+/// Probability that a geometric Brownian motion observed at `s1` and `s2`, `t` years apart,
+/// crossed `level` in between (Brownian-bridge correction for continuous monitoring).
+pub fn bridge_hit_probability<T: Real>(s1: T, s2: T, level: T, vol: T, t: T, up: bool) -> T {
+    let crossed = if up {
+        s1 >= level || s2 >= level
+    } else {
+        s1 <= level || s2 <= level
+    };
+    if crossed {
+        return T::from(1.0);
+    }
+    let variance = vol * vol * t;
+    if variance <= T::from(0.0) {
+        return T::from(0.0);
+    }
+    (-((level / s1).ln() * (level / s2).ln() * 2.0) / variance).exp()
+}
+
+/// Standardised log-return of a geometric Brownian motion observed at `s_t`, `t` years after
+/// starting from `s0`
+fn gbm_shock<T: Real>(s_t: T, s0: T, r: T, vol: T, t: T) -> T {
+    ((s_t / s0).ln() - (r - vol * vol * 0.5) * t) / (vol * t.sqrt())
+}
+
+/// Likelihood-ratio weight for the delta: derivative of the log density of `s_t` with respect to
+/// `s0`. The delta is the expectation of the discounted payoff times this weight.
+pub fn lr_delta_weight<T: Real>(s_t: T, s0: T, r: T, vol: T, t: T) -> T {
+    gbm_shock(s_t, s0, r, vol, t) / (s0 * vol * t.sqrt())
+}
+
+/// Likelihood-ratio weight for the vega: derivative of the log density of `s_t` with respect to
+/// the volatility
+pub fn lr_vega_weight<T: Real>(s_t: T, s0: T, r: T, vol: T, t: T) -> T {
+    let z = gbm_shock(s_t, s0, r, vol, t);
+    (z * z - 1.0) / vol - z * t.sqrt()
+}
+
 /// Return price and Greeks for convenience
 pub fn bs_price_delta_gamma_theta<T: Real>(s: T, k: T, r: T, vol: T, t: T) -> (T, T, T, T) {
     (
@@ -234,3 +622,243 @@ pub fn bs_price_delta_gamma_theta<T: Real>(s: T, k: T, r: T, vol: T, t: T) -> (T
         bs_theta(s, k, r, vol, t),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+    use crate::prelude::*;
+
+    /// Store with a flat 3% USD curve and AAPL at 100 with 20% volatility
+    fn equity_market(ref_date: Date) -> Result<MarketStore<f64>> {
+        let mut store = MarketStore::new(ref_date, Currency::USD);
+        let curve = Arc::new(FlatForwardTermStructure::new(
+            ref_date,
+            0.03,
+            RateDefinition::default(),
+        ));
+        let index = OvernightIndex::new(ref_date).with_term_structure(curve);
+        store
+            .mut_index_store()
+            .add_index(0, Arc::new(RwLock::new(index)))?;
+        store.mut_index_store().add_currency_curve(Currency::USD, 0);
+        store.mut_equity_store().add_spot("AAPL".to_string(), 100.0);
+        store
+            .mut_equity_store()
+            .add_volatility("AAPL".to_string(), 0.2);
+        Ok(store)
+    }
+
+    #[test]
+    fn test_jumps_keep_the_forward() -> Result<()> {
+        let ref_date = Date::new(2024, 1, 2);
+        let store = equity_market(ref_date)?;
+        let maturity = Date::new(2025, 1, 2);
+        let request = MarketRequest::new(0, None, None, None)
+            .with_equity(EquityRequest::new("AAPL".to_string(), Some(maturity)));
+        let diffusion = BlackScholesModel::new(SimpleModel::new(&store));
+        let jumps = diffusion
+            .clone()
+            .with_jumps("AAPL", JumpParameters::new(0.5, -0.2, 0.1));
+
+        let n = 20_000;
+        let (mut forward, mut tail, mut diffusion_tail) = (0.0, 0.0, 0.0);
+        for seed in 0..n {
+            let s = jumps
+                .clone()
+                .with_seed(seed)
+                .gen_scenario(&[request.clone()])?;
+            let s_diffusion = diffusion
+                .clone()
+                .with_seed(seed)
+                .gen_scenario(&[request.clone()])?;
+            forward += s[0].equity()? / n as f64;
+            if s[0].equity()? < 60.0 {
+                tail += 1.0 / n as f64;
+            }
+            if s_diffusion[0].equity()? < 60.0 {
+                diffusion_tail += 1.0 / n as f64;
+            }
+        }
+
+        // compensated jumps leave the forward alone and fatten the left tail
+        let p = diffusion.gen_df_data(DiscountFactorRequest::new(0, maturity))?;
+        assert!((forward - 100.0 / p).abs() < 0.5);
+        assert!(tail > 2.0 * diffusion_tail);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dividend_yield() -> Result<()> {
+        let ref_date = Date::new(2024, 1, 2);
+        let mut store = equity_market(ref_date)?;
+        store
+            .mut_equity_store()
+            .add_dividend_yield("AAPL".to_string(), 0.02);
+
+        let maturity = Date::new(2025, 1, 2);
+        let equity = EquityRequest::new("AAPL".to_string(), Some(maturity));
+        let request = MarketRequest::new(0, None, None, None).with_equity(equity.clone());
+        let model = BlackScholesModel::new(SimpleModel::new(&store));
+
+        let n = 5_000;
+        let mut forward = 0.0;
+        for seed in 0..n {
+            let scenario = model
+                .clone()
+                .with_seed(seed)
+                .gen_scenario(&[request.clone()])?;
+            forward += scenario[0].equity()? / n as f64;
+        }
+
+        // simulated spots drift with the rate net of the dividend yield
+        let expected = model.gen_equity_data(equity)?;
+        let p = model.gen_df_data(DiscountFactorRequest::new(0, maturity))?;
+        assert!(expected < 100.0 / p - 1.5);
+        assert!((forward - expected).abs() < 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nodes_follow_a_path() -> Result<()> {
+        let ref_date = Date::new(2024, 1, 2);
+        let store = equity_market(ref_date)?;
+        let model = BlackScholesModel::new(SimpleModel::new(&store));
+        let (d1, d2) = (Date::new(2024, 4, 2), Date::new(2025, 1, 2));
+        let requests = [d1, d2].map(|date| {
+            MarketRequest::new(0, None, None, None)
+                .with_equity(EquityRequest::new("AAPL".to_string(), Some(date)))
+        });
+
+        let n = 10_000;
+        let samples = (0..n)
+            .map(|seed| {
+                let scenario = model.clone().with_seed(seed).gen_scenario(&requests)?;
+                Ok((scenario[0].equity()?.ln(), scenario[1].equity()?.ln()))
+            })
+            .collect::<Result<Vec<(f64, f64)>>>()?;
+        let mean = |f: &dyn Fn(&(f64, f64)) -> f64| samples.iter().map(f).sum::<f64>() / n as f64;
+        let (m1, m2) = (mean(&|x| x.0), mean(&|x| x.1));
+        let cov = mean(&|x| (x.0 - m1) * (x.1 - m2));
+        let var1 = mean(&|x| (x.0 - m1).powi(2));
+        let var2 = mean(&|x| (x.1 - m2).powi(2));
+
+        // log-spots of a Brownian path have Corr(ln S(t1), ln S(t2)) = sqrt(t1 / t2)
+        let (t1, t2) = (
+            Actual360::year_fraction::<f64>(ref_date, d1),
+            Actual360::year_fraction::<f64>(ref_date, d2),
+        );
+        let corr = cov / (var1 * var2).sqrt();
+        assert!((corr - (t1 / t2).sqrt()).abs() < 0.03);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate_nodes_follow_the_curve() -> Result<()> {
+        let ref_date = Date::new(2024, 1, 2);
+        let store = equity_market(ref_date)?;
+        let model = BlackScholesModel::new(SimpleModel::new(&store));
+
+        let (start, end) = (Date::new(2024, 7, 2), Date::new(2025, 1, 2));
+        let fwd = |compounding| {
+            ForwardRateRequest::new(0, start, start, end, compounding, Frequency::Annual)
+        };
+        let requests = vec![
+            MarketRequest::new(0, None, Some(fwd(Compounding::Simple)), None),
+            MarketRequest::new(1, None, Some(fwd(Compounding::Continuous)), None)
+                .with_equity(EquityRequest::new("AAPL".to_string(), Some(end))),
+        ];
+        let scenario = model.gen_scenario(&requests)?;
+
+        // forwards of plain and simulated nodes come from the curve with their own compounding
+        let compound = model.gen_df_data(DiscountFactorRequest::new(0, start))?
+            / model.gen_df_data(DiscountFactorRequest::new(0, end))?;
+        let t = Actual360::year_fraction::<f64>(start, end);
+        assert!((scenario[0].fwd()? - (compound - 1.0) / t).abs() < 1e-12);
+        assert!((scenario[1].fwd()? - compound.ln() / t).abs() < 1e-12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_discrete_dividends() -> Result<()> {
+        let ref_date = Date::new(2024, 1, 2);
+        let store = equity_market(ref_date)?;
+        let (cash, proportional) = (Date::new(2024, 7, 2), Date::new(2024, 10, 2));
+        let maturity = Date::new(2025, 1, 2);
+        let historical_data = HistoricalData::new(ref_date).with_dividends(
+            "AAPL",
+            vec![
+                (Date::new(2023, 12, 1), Dividend::Cash(5.0)),
+                (cash, Dividend::Cash(2.0)),
+                (proportional, Dividend::Proportional(0.01)),
+                (Date::new(2025, 6, 2), Dividend::Cash(5.0)),
+            ],
+        );
+        let model =
+            BlackScholesModel::new(SimpleModel::new(&store)).with_historical_data(&historical_data);
+
+        // only the dividends between the reference date and the maturity count
+        let df = |date| model.gen_df_data(DiscountFactorRequest::new(0, date));
+        let expected = ((100.0 / df(cash)? - 2.0) * df(cash)? / df(proportional)? * 0.99)
+            * df(proportional)?
+            / df(maturity)?;
+        let equity = EquityRequest::new("AAPL".to_string(), Some(maturity));
+        assert!((model.gen_equity_data(equity.clone())? - expected).abs() < 1e-10);
+
+        // the other models read the same forward
+        let simple = SimpleModel::new(&store).with_historical_data(&historical_data);
+        assert!((simple.gen_equity_data(equity.clone())? - expected).abs() < 1e-10);
+        let hull_white = HullWhiteModel::new(SimpleModel::new(&store), 0.05, 0.01)?
+            .with_historical_data(&historical_data);
+        assert!((hull_white.gen_equity_data(equity.clone())? - expected).abs() < 1e-10);
+
+        let request = MarketRequest::new(0, None, None, None).with_equity(equity);
+        let n = 5_000;
+        let mut forward = 0.0;
+        for seed in 0..n {
+            let scenario = model
+                .clone()
+                .with_seed(seed)
+                .gen_scenario(&[request.clone()])?;
+            forward += scenario[0].equity()? / n as f64;
+        }
+        assert!((forward - expected).abs() < 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_antithetic_pairs() -> Result<()> {
+        let ref_date = Date::new(2024, 1, 2);
+        let store = equity_market(ref_date)?;
+        let maturity = Date::new(2025, 1, 2);
+        let equity = EquityRequest::new("AAPL".to_string(), Some(maturity));
+        let request = MarketRequest::new(0, None, None, None).with_equity(equity.clone());
+        let model = BlackScholesModel::new(SimpleModel::new(&store));
+        let forward = model.gen_equity_data(equity)?;
+        let t = Actual360::year_fraction::<f64>(ref_date, maturity);
+
+        // shocks of the log-spot, without the convexity correction
+        let shock = |scenario: &Scenario<f64>| -> Result<f64> {
+            Ok((scenario[0].equity()? / forward).ln() + 0.02 * t)
+        };
+        let (mut odd, mut paired_odd) = ([0.0; 2], [0.0; 2]);
+        for seed in 0..100 {
+            let (plain, antithetic) = model
+                .clone()
+                .with_seed(seed)
+                .gen_scenario_pair(&[request.clone()])?;
+            let (x, x_anti) = (shock(&plain)?, shock(&antithetic)?);
+            assert!(x.abs() > 1e-6);
+            odd[0] += x / 100.0;
+            odd[1] += x.powi(3) / 100.0;
+            paired_odd[0] += (x + x_anti) / 200.0;
+            paired_odd[1] += (x.powi(3) + x_anti.powi(3)) / 200.0;
+        }
+
+        // the mean of the pairs cancels the odd moments of the shocks
+        assert!(odd.iter().all(|m| m.abs() > 1e-8));
+        assert!(paired_odd.iter().all(|m| m.abs() < 1e-12));
+        Ok(())
+    }
+}
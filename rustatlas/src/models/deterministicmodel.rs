@@ -1,8 +1,8 @@
 use crate::math::ad::genericnumber::Real;
 use crate::{
     prelude::{
-        Date, DiscountFactorRequest, ExchangeRateRequest, ForwardRateRequest, MarketData,
-        MarketRequest,
+        CorrelationRequest, Date, DiscountFactorRequest, EquityRequest, ExchangeRateRequest,
+        ForwardRateRequest, MarketData, MarketRequest, VolatilityRequest,
     },
     utils::errors::Result,
 };
@@ -14,6 +14,9 @@ pub trait DeterministicModel<T: Real> {
     fn gen_df_data(&self, df: DiscountFactorRequest) -> Result<T>;
     fn gen_fx_data(&self, fx: ExchangeRateRequest) -> Result<T>;
     fn gen_fwd_data(&self, fwd: ForwardRateRequest) -> Result<T>;
+    fn gen_equity_data(&self, equity: EquityRequest) -> Result<T>;
+    fn gen_vol_data(&self, vol: VolatilityRequest) -> Result<T>;
+    fn gen_corr_data(&self, corr: CorrelationRequest) -> Result<T>;
     fn gen_numerarie(&self, market_request: &MarketRequest) -> Result<T>;
     fn gen_node(&self, market_request: &MarketRequest) -> Result<MarketData<T>> {
         let id = market_request.id();
@@ -32,6 +35,21 @@ pub trait DeterministicModel<T: Real> {
             None => None,
         };
 
+        let equity = match market_request.equity() {
+            Some(equity) => Some(self.gen_equity_data(equity)?),
+            None => None,
+        };
+
+        let vol = match market_request.vol() {
+            Some(vol) => Some(self.gen_vol_data(vol)?),
+            None => None,
+        };
+
+        let corr = match market_request.corr() {
+            Some(corr) => Some(self.gen_corr_data(corr)?),
+            None => None,
+        };
+
         let numerarie = self.gen_numerarie(market_request)?;
 
         return Ok(MarketData::new(
@@ -41,7 +59,10 @@ pub trait DeterministicModel<T: Real> {
             fwd,
             fx,
             numerarie,
-        ));
+        )
+        .with_equity(equity)
+        .with_vol(vol)
+        .with_corr(corr));
     }
 
     fn gen_market_data(&self, market_request: &[MarketRequest]) -> Result<Vec<MarketData<T>>> {
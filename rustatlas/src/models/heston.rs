@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+
+use rand::{distributions::Open01, rngs::StdRng, Rng, SeedableRng};
+
+use crate::core::historicaldata::HistoricalData;
+use crate::core::meta::MarketRequest;
+use crate::math::ad::genericnumber::Real;
+use crate::prelude::{
+    Actual360, CorrelationRequest, Currency, DayCountProvider, DiscountFactorRequest,
+    EquityRequest, ExchangeRateRequest, ForwardRateRequest, HasReferenceDate, SimpleModel,
+    VolatilityRequest,
+};
+use crate::time::date::Date;
+use crate::utils::errors::{AtlasError, Result};
+
+use super::blackscholes::{
+    correlated_normals, future_node_dates, node_date, simulated_underliers, standard_normal,
+    underlier_name, BlackScholesModel,
+};
+use super::deterministicmodel::DeterministicModel;
+use super::stochasticmodel::{Scenario, StochasticModel};
+
+/// # HestonParameters
+/// Variance dynamics of an underlier: initial variance `v0`, mean reversion speed `kappa`
+/// towards the long-run variance `theta`, volatility of the variance `sigma` (positive) and
+/// correlation `rho` between the spot and its variance. The mean reversion speed and the
+/// volatility of the variance must be positive, the variances non-negative and `rho` within
+/// [-1, 1].
+#[derive(Clone, Copy, Debug)]
+pub struct HestonParameters<T: Real> {
+    v0: T,
+    kappa: T,
+    theta: T,
+    sigma: T,
+    rho: T,
+}
+
+impl<T: Real> HestonParameters<T> {
+    pub fn new(v0: T, kappa: T, theta: T, sigma: T, rho: T) -> Result<Self> {
+        let zero = T::from(0.0);
+        let invalid = |msg: &str| Err(AtlasError::InvalidValueErr(format!("Heston {}", msg)));
+        if v0 < zero || theta < zero {
+            return invalid("variances must not be negative");
+        }
+        if kappa <= zero {
+            return invalid("mean reversion speed must be positive");
+        }
+        if sigma <= zero {
+            return invalid("volatility of the variance must be positive");
+        }
+        if rho < T::from(-1.0) || rho > T::from(1.0) {
+            return invalid("correlation must be within [-1, 1]");
+        }
+        Ok(Self {
+            v0,
+            kappa,
+            theta,
+            sigma,
+            rho,
+        })
+    }
+
+    pub fn v0(&self) -> T {
+        self.v0
+    }
+
+    pub fn kappa(&self) -> T {
+        self.kappa
+    }
+
+    pub fn theta(&self) -> T {
+        self.theta
+    }
+
+    pub fn sigma(&self) -> T {
+        self.sigma
+    }
+
+    pub fn rho(&self) -> T {
+        self.rho
+    }
+}
+
+/// # HestonModel
+/// Heston stochastic volatility Monte Carlo generator. Variances are simulated with the
+/// quadratic-exponential scheme of Andersen on a grid through the node dates, spots drift and
+/// are discounted as in `BlackScholesModel`. Spot shocks of different underliers are correlated
+/// through the correlation store.
+#[derive(Clone)]
+pub struct HestonModel<'a, T: Real> {
+    black_scholes: BlackScholesModel<'a, T>,
+    parameters: HashMap<String, HestonParameters<T>>,
+    steps_per_year: usize,
+    seed: u64,
+    antithetic: bool,
+}
+
+impl<'a, T: Real> HestonModel<'a, T> {
+    pub fn new(simple: SimpleModel<'a, T>) -> Self {
+        Self {
+            black_scholes: BlackScholesModel::new(simple),
+            parameters: HashMap::new(),
+            steps_per_year: 52,
+            seed: 0xA55AA55Au64,
+            antithetic: false,
+        }
+    }
+
+    /// # with_parameters
+    /// Variance dynamics of an underlier, named as in `VolatilityRequest` (`"USD/CLP"`, `"AAPL"`)
+    pub fn with_parameters(mut self, underlier: &str, parameters: HestonParameters<T>) -> Self {
+        self.parameters.insert(underlier.to_string(), parameters);
+        self
+    }
+
+    /// # with_steps_per_year
+    /// Time steps per year of the simulation grid, 52 by default
+    pub fn with_steps_per_year(mut self, steps_per_year: usize) -> Self {
+        self.steps_per_year = steps_per_year.max(1);
+        self
+    }
+
+    /// # with_seed
+    /// Seed of the random numbers of the generated scenario
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// # with_antithetic
+    /// Negate the spot and variance shocks and reflect the uniforms `u` of the variance scheme
+    /// into `1 - u`, see `standard_normal`
+    pub fn with_antithetic(mut self, antithetic: bool) -> Self {
+        self.antithetic = antithetic;
+        self
+    }
+
+    /// # gen_scenario_pair
+    /// Scenario of the seed and its antithetic one, drawn from the same random numbers
+    pub fn gen_scenario_pair(
+        &self,
+        market_requests: &[MarketRequest],
+    ) -> Result<(Scenario<T>, Scenario<T>)> {
+        let plain = self.clone().with_antithetic(false);
+        let antithetic = self.clone().with_antithetic(true);
+        Ok((
+            plain.gen_scenario(market_requests)?,
+            antithetic.gen_scenario(market_requests)?,
+        ))
+    }
+
+    /// # with_historical_data
+    /// Historical data with the discrete dividends of the equities
+    pub fn with_historical_data(mut self, historical_data: &'a HistoricalData) -> Self {
+        self.black_scholes = self.black_scholes.with_historical_data(historical_data);
+        self
+    }
+
+    /// # with_discount_curve
+    /// Discount the given currency on the provider `id` instead of the currency curve of the
+    /// index store
+    pub fn with_discount_curve(mut self, currency: Currency, id: usize) -> Self {
+        self.black_scholes = self.black_scholes.with_discount_curve(currency, id);
+        self
+    }
+
+    pub fn parameters(&self, underlier: &str) -> Result<HestonParameters<T>> {
+        self.parameters
+            .get(underlier)
+            .copied()
+            .ok_or_else(|| AtlasError::NotFoundErr(format!("Heston parameters for {}", underlier)))
+    }
+}
+
+impl<T: Real> DeterministicModel<T> for HestonModel<'_, T> {
+    fn reference_date(&self) -> Date {
+        self.black_scholes.reference_date()
+    }
+
+    fn gen_df_data(&self, df: DiscountFactorRequest) -> Result<T> {
+        self.black_scholes.gen_df_data(df)
+    }
+
+    fn gen_fx_data(&self, fx: ExchangeRateRequest) -> Result<T> {
+        self.black_scholes.gen_fx_data(fx)
+    }
+
+    fn gen_fwd_data(&self, fwd: ForwardRateRequest) -> Result<T> {
+        self.black_scholes.gen_fwd_data(fwd)
+    }
+
+    fn gen_equity_data(&self, equity: EquityRequest) -> Result<T> {
+        self.black_scholes.gen_equity_data(equity)
+    }
+
+    fn gen_vol_data(&self, vol: VolatilityRequest) -> Result<T> {
+        self.black_scholes.gen_vol_data(vol)
+    }
+
+    fn gen_corr_data(&self, corr: CorrelationRequest) -> Result<T> {
+        self.black_scholes.gen_corr_data(corr)
+    }
+
+    fn gen_numerarie(&self, market_request: &MarketRequest) -> Result<T> {
+        self.black_scholes.gen_numerarie(market_request)
+    }
+}
+
+impl<T: Real> StochasticModel<T> for HestonModel<'_, T> {
+    fn gen_scenario(&self, market_requests: &[MarketRequest]) -> Result<Scenario<T>> {
+        let store = self.black_scholes.simple.market_store();
+        let ref_date = store.reference_date();
+        let local_ccy = store.local_currency();
+        let mut rng = StdRng::seed_from_u64(self.seed);
+
+        let underliers = simulated_underliers(market_requests, local_ccy);
+        let parameters = underliers
+            .iter()
+            .map(|name| self.parameters(name))
+            .collect::<Result<Vec<_>>>()?;
+        let chol = store.correlation_store().cholesky(&underliers)?;
+
+        /* drift-free log-return of each underlier at each node date ...... */
+        let mut log_returns = vec![T::from(0.0); underliers.len()];
+        let mut variances = parameters.iter().map(|p| p.v0()).collect::<Vec<T>>();
+        let mut paths = HashMap::new();
+        let mut t_prev = 0.0;
+        for date in future_node_dates(market_requests, ref_date) {
+            let t = Actual360::year_fraction::<f64>(ref_date, date);
+            let steps = ((t - t_prev) * self.steps_per_year as f64).ceil().max(1.0) as usize;
+            let dt = T::from((t - t_prev) / steps as f64);
+            for _ in 0..steps {
+                let shocks = correlated_normals(&chol, self.antithetic, &mut rng);
+                for (i, p) in parameters.iter().enumerate() {
+                    let zv = standard_normal(&mut rng, self.antithetic);
+                    // open interval, so that neither `u` nor `1 - u` is 1
+                    let u: f64 = rng.sample(Open01);
+                    let u = if self.antithetic { 1.0 - u } else { u };
+                    let v = qe_variance(p, variances[i], dt, zv, u);
+                    log_returns[i] =
+                        log_returns[i] + log_return_step(p, variances[i], v, dt, shocks[i]);
+                    variances[i] = v;
+                }
+            }
+            for (name, x) in underliers.iter().zip(&log_returns) {
+                paths.insert((name.clone(), date), *x);
+            }
+            t_prev = t;
+        }
+
+        self.black_scholes.gen_nodes(market_requests, |req, _| {
+            let name = underlier_name(req, local_ccy).unwrap();
+            let date = node_date(req, ref_date).unwrap();
+            // nodes at the reference date have not moved
+            Ok(paths.get(&(name, date)).copied().unwrap_or(T::from(0.0)))
+        })
+    }
+}
+
+/// Variance `dt` years after `v` with the quadratic-exponential scheme, from a standard normal
+/// `zv` and an independent uniform `u` in (0, 1)
+fn qe_variance<T: Real>(p: &HestonParameters<T>, v: T, dt: T, zv: f64, u: f64) -> T {
+    let one = T::from(1.0);
+    let decay = (-p.kappa * dt).exp();
+    let sigma2 = p.sigma * p.sigma;
+
+    /* conditional mean and variance of the CIR process */
+    let m = p.theta + (v - p.theta) * decay;
+    let s2 = v * sigma2 * decay * (one - decay) / p.kappa
+        + p.theta * sigma2 * (one - decay) * (one - decay) / (p.kappa * 2.0);
+    let psi = s2 / (m * m);
+
+    if psi <= T::from(1.5) {
+        /* moment-matched squared normal */
+        let inv = T::from(2.0) / psi;
+        let b2 = inv - 1.0 + inv.sqrt() * (inv - 1.0).sqrt();
+        let b = b2.sqrt() + zv;
+        m / (b2 + 1.0) * b * b
+    } else {
+        /* mass at zero plus an exponential tail */
+        let p_zero = (psi - 1.0) / (psi + 1.0);
+        if T::from(u) <= p_zero {
+            T::from(0.0)
+        } else {
+            let beta = (one - p_zero) / m;
+            ((one - p_zero) / T::from(1.0 - u)).ln() / beta
+        }
+    }
+}
+
+/// Log-spot increment over `dt`, without the risk-neutral drift, given the variances `v` and
+/// `v_next` at both ends and a standard normal `z` independent of the variance shock
+fn log_return_step<T: Real>(p: &HestonParameters<T>, v: T, v_next: T, dt: T, z: T) -> T {
+    let one = T::from(1.0);
+    let rho_sigma = p.rho / p.sigma;
+    let k0 = -(rho_sigma * p.kappa * p.theta * dt);
+    let k = (rho_sigma * p.kappa - 0.5) * dt * 0.5;
+    let k1 = k - rho_sigma;
+    let k2 = k + rho_sigma;
+    let k3 = (one - p.rho * p.rho) * dt * 0.5;
+    k0 + k1 * v + k2 * v_next + (k3 * (v + v_next)).sqrt() * z
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use rand_distr::StandardNormal;
+
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn test_quadratic_exponential_scheme() {
+        let p = HestonParameters::new(0.04, 1.5, 0.06, 0.6, -0.7).unwrap();
+        let mut rng = StdRng::seed_from_u64(42);
+        let (n, steps, dt) = (20_000, 52, 1.0 / 52.0);
+
+        let (mut mean_v, mut mean_s) = (0.0, 0.0);
+        for _ in 0..n {
+            let (mut v, mut x) = (p.v0(), 0.0);
+            for _ in 0..steps {
+                let zv = rng.sample::<f64, _>(StandardNormal);
+                let v_next = qe_variance(&p, v, dt, zv, rng.sample(Open01));
+                x += log_return_step(&p, v, v_next, dt, rng.sample(StandardNormal));
+                v = v_next;
+            }
+            mean_v += v / n as f64;
+            mean_s += x.exp() / n as f64;
+        }
+
+        // variances keep the CIR mean and spots without drift stay martingales
+        let expected_v = p.theta() + (p.v0() - p.theta()) * (-p.kappa()).exp();
+        assert!((mean_v - expected_v).abs() < 1e-3);
+        assert!((mean_s - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_exponential_tail_bounds() {
+        // a small variance puts the scheme in its exponential branch
+        let p = HestonParameters::new(0.001, 1.5, 0.06, 1.0, -0.7).unwrap();
+        for u in [f64::EPSILON, 1.0 - f64::EPSILON] {
+            let v = qe_variance(&p, p.v0(), 1.0 / 52.0, 0.0, u);
+            assert!(v.is_finite() && v >= 0.0);
+        }
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        assert!(HestonParameters::new(0.04, 1.5, 0.06, 0.6, -0.7).is_ok());
+        assert!(HestonParameters::new(0.0, 1.5, 0.0, 0.6, 1.0).is_ok());
+        for (v0, kappa, theta, sigma, rho) in [
+            (0.04, 0.0, 0.06, 0.6, -0.7),
+            (0.04, 1.5, 0.06, 0.0, -0.7),
+            (-0.01, 1.5, 0.06, 0.6, -0.7),
+            (0.04, 1.5, -0.01, 0.6, -0.7),
+            (0.04, 1.5, 0.06, 0.6, -1.1),
+            (0.04, 1.5, 0.06, 0.6, 1.1),
+        ] {
+            assert!(HestonParameters::new(v0, kappa, theta, sigma, rho).is_err());
+        }
+    }
+
+    #[test]
+    fn test_scenarios_follow_the_seed() -> Result<()> {
+        let ref_date = Date::new(2024, 1, 2);
+        let mut store = MarketStore::new(ref_date, Currency::USD);
+        let curve = Arc::new(FlatForwardTermStructure::new(
+            ref_date,
+            0.03,
+            RateDefinition::default(),
+        ));
+        let index = OvernightIndex::new(ref_date).with_term_structure(curve);
+        store
+            .mut_index_store()
+            .add_index(0, Arc::new(RwLock::new(index)))?;
+        store.mut_index_store().add_currency_curve(Currency::USD, 0);
+        store.mut_equity_store().add_spot("AAPL".to_string(), 100.0);
+
+        let request = MarketRequest::new(0, None, None, None).with_equity(EquityRequest::new(
+            "AAPL".to_string(),
+            Some(Date::new(2025, 1, 2)),
+        ));
+        let model = HestonModel::new(SimpleModel::new(&store))
+            .with_parameters("AAPL", HestonParameters::new(0.04, 1.5, 0.06, 0.6, -0.7)?);
+        let spot = |seed| -> Result<f64> {
+            model
+                .clone()
+                .with_seed(seed)
+                .gen_scenario(&[request.clone()])?[0]
+                .equity()
+        };
+
+        // a scenario only depends on its seed
+        assert_eq!(spot(1)?, spot(1)?);
+        assert_ne!(spot(1)?, spot(2)?);
+
+        // antithetic pairs of paths average closer to the forward
+        let forward = model
+            .black_scholes
+            .equity_forward("AAPL", Date::new(2025, 1, 2))?;
+        let anti = model.clone().with_antithetic(true);
+        let (mut plain, mut paired) = (0.0, 0.0);
+        for seed in 0..200 {
+            let s = spot(seed)?;
+            let s_anti = anti
+                .clone()
+                .with_seed(seed)
+                .gen_scenario(&[request.clone()])?[0]
+                .equity()?;
+            assert_ne!(s, s_anti);
+            plain += (s - forward) / 200.0;
+            paired += ((s + s_anti) / 2.0 - forward) / 200.0;
+        }
+        assert!(paired.abs() < plain.abs());
+        Ok(())
+    }
+}
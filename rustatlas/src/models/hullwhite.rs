@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::core::historicaldata::HistoricalData;
+use crate::core::meta::MarketRequest;
+use crate::math::ad::genericnumber::Real;
+use crate::prelude::{
+    Actual360, CorrelationRequest, DayCountProvider, DayCounter, DiscountFactorRequest,
+    EquityRequest, ExchangeRateRequest, ForwardRateRequest, InterestRate, SimpleModel,
+    VolatilityRequest,
+};
+use crate::time::date::Date;
+use crate::utils::errors::{AtlasError, Result};
+
+use super::blackscholes::standard_normal;
+use super::deterministicmodel::DeterministicModel;
+use super::stochasticmodel::{Scenario, StochasticModel};
+
+/// # HullWhiteModel
+/// Hull-White one-factor Monte Carlo generator for the interest rate curves. The short rate is
+/// `r(t) = x(t) + α(t)` with `dx = -a x dt + σ dW`, and `α` fits every curve, so all curves move
+/// with the same factor and keep their initial discount factors on average. Discount factors are
+/// the path deflators `exp(-∫r)` to their date, forward rates are read from their curve as seen
+/// on the path at the fixing date. The numerarie is the bank account `exp(∫r)` to the date of the
+/// discount factor requested with it, the inverse of that deflator, and 1 for a request without
+/// one. Other nodes are deterministic.
+#[derive(Clone)]
+pub struct HullWhiteModel<'a, T: Real> {
+    simple: SimpleModel<'a, T>,
+    mean_reversion: T,
+    volatility: T,
+    day_counter: DayCounter,
+    seed: u64,
+    antithetic: bool,
+}
+
+impl<'a, T: Real> HullWhiteModel<'a, T> {
+    pub fn new(
+        simple: SimpleModel<'a, T>,
+        mean_reversion: T,
+        volatility: T,
+    ) -> Result<HullWhiteModel<'a, T>> {
+        if mean_reversion == T::from(0.0) {
+            return Err(AtlasError::InvalidValueErr(
+                "Hull-White mean reversion must not be zero".to_string(),
+            ));
+        }
+        Ok(Self {
+            simple,
+            mean_reversion,
+            volatility,
+            day_counter: DayCounter::Actual360,
+            seed: 0xA55AA55Au64,
+            antithetic: false,
+        })
+    }
+
+    /// # with_historical_data
+    /// Historical data with the discrete dividends of the equities, whose forwards are read off
+    /// the initial curve
+    pub fn with_historical_data(mut self, historical_data: &'a HistoricalData) -> Self {
+        self.simple = self.simple.with_historical_data(historical_data);
+        self
+    }
+
+    /// # with_day_counter
+    /// Day counter of the simulated forward rates, Actual360 by default
+    pub fn with_day_counter(mut self, day_counter: DayCounter) -> Self {
+        self.day_counter = day_counter;
+        self
+    }
+
+    /// # with_seed
+    /// Seed of the random numbers of the generated scenario
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// # with_antithetic
+    /// Negate the shocks of the factor and of its integral, see `standard_normal`
+    pub fn with_antithetic(mut self, antithetic: bool) -> Self {
+        self.antithetic = antithetic;
+        self
+    }
+
+    /// # gen_scenario_pair
+    /// Scenario of the seed and its antithetic one, drawn from the same random numbers
+    pub fn gen_scenario_pair(
+        &self,
+        market_requests: &[MarketRequest],
+    ) -> Result<(Scenario<T>, Scenario<T>)> {
+        let plain = self.clone().with_antithetic(false);
+        let antithetic = self.clone().with_antithetic(true);
+        Ok((
+            plain.gen_scenario(market_requests)?,
+            antithetic.gen_scenario(market_requests)?,
+        ))
+    }
+
+    pub fn mean_reversion(&self) -> T {
+        self.mean_reversion
+    }
+
+    pub fn volatility(&self) -> T {
+        self.volatility
+    }
+
+    /// Model time of a date, in years from the reference date
+    fn time(&self, date: Date) -> T {
+        Actual360::year_fraction::<T>(self.simple.reference_date(), date)
+    }
+
+    /// `(1 - exp(-a t)) / a`, sensitivity of a bond of maturity `t` to the factor
+    fn bond_sensitivity(&self, t: T) -> T {
+        (T::from(1.0) - (-self.mean_reversion * t).exp()) / self.mean_reversion
+    }
+
+    /// Variance of the factor `x` at `t`
+    fn factor_variance(&self, t: T) -> T {
+        let a = self.mean_reversion;
+        self.volatility * self.volatility / (a * 2.0) * (T::from(1.0) - (-a * t * 2.0).exp())
+    }
+
+    /// Variance of the integral of the factor `x` from 0 to `t`
+    fn integral_variance(&self, t: T) -> T {
+        let a = self.mean_reversion;
+        let one = T::from(1.0);
+        self.volatility * self.volatility / (a * a)
+            * (t - (one - (-a * t).exp()) * 2.0 / a + (one - (-a * t * 2.0).exp()) / (a * 2.0))
+    }
+
+    /// Exact step of the factor and its integral over `dt` from correlated Gaussian draws
+    fn step(&self, x: T, y: T, dt: T, z1: f64, z2: f64) -> (T, T) {
+        let a = self.mean_reversion;
+        let one = T::from(1.0);
+        let decay = (-a * dt).exp();
+        let var_x = self.factor_variance(dt);
+        let var_y = self.integral_variance(dt);
+        let cov = self.volatility * self.volatility / (a * a * 2.0) * (one - decay) * (one - decay);
+
+        let sd_x = var_x.sqrt();
+        let sd_y = (var_y - cov * cov / var_x).max(T::from(0.0)).sqrt();
+        (
+            x * decay + sd_x * z1,
+            y + x * (one - decay) / a + cov / sd_x * z1 + sd_y * z2,
+        )
+    }
+}
+
+impl<T: Real> DeterministicModel<T> for HullWhiteModel<'_, T> {
+    fn reference_date(&self) -> Date {
+        self.simple.reference_date()
+    }
+
+    fn gen_df_data(&self, df: DiscountFactorRequest) -> Result<T> {
+        self.simple.gen_df_data(df)
+    }
+
+    fn gen_fx_data(&self, fx: ExchangeRateRequest) -> Result<T> {
+        self.simple.gen_fx_data(fx)
+    }
+
+    fn gen_fwd_data(&self, fwd: ForwardRateRequest) -> Result<T> {
+        self.simple.gen_fwd_data(fwd)
+    }
+
+    fn gen_equity_data(&self, equity: EquityRequest) -> Result<T> {
+        self.simple.gen_equity_data(equity)
+    }
+
+    fn gen_vol_data(&self, vol: VolatilityRequest) -> Result<T> {
+        self.simple.gen_vol_data(vol)
+    }
+
+    fn gen_corr_data(&self, corr: CorrelationRequest) -> Result<T> {
+        self.simple.gen_corr_data(corr)
+    }
+
+    fn gen_numerarie(&self, market_request: &MarketRequest) -> Result<T> {
+        self.simple.gen_numerarie(market_request)
+    }
+}
+
+impl<T: Real> StochasticModel<T> for HullWhiteModel<'_, T> {
+    fn gen_scenario(&self, market_requests: &[MarketRequest]) -> Result<Scenario<T>> {
+        let ref_date = self.simple.reference_date();
+
+        /* dates where the path is observed ................................ */
+        let mut dates = market_requests
+            .iter()
+            .flat_map(|req| {
+                [
+                    req.df().map(|df| df.date()),
+                    req.fwd().map(|fwd| fwd.fixing_date()),
+                ]
+            })
+            .flatten()
+            .filter(|date| *date > ref_date)
+            .collect::<Vec<Date>>();
+        dates.sort();
+        dates.dedup();
+
+        /* factor and its integral at each date ............................ */
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let (mut x, mut y) = (T::from(0.0), T::from(0.0));
+        let mut t_prev = T::from(0.0);
+        let mut states = HashMap::new();
+        for date in dates {
+            let t = self.time(date);
+            let z1 = standard_normal(&mut rng, self.antithetic);
+            let z2 = standard_normal(&mut rng, self.antithetic);
+            (x, y) = self.step(x, y, t - t_prev, z1, z2);
+            states.insert(date, (x, y));
+            t_prev = t;
+        }
+
+        HullWhitePath {
+            model: self,
+            states,
+        }
+        .gen_market_data(market_requests)
+    }
+}
+
+/// Market seen along one Hull-White path, from the factor and its integral at each date
+struct HullWhitePath<'m, 'a, T: Real> {
+    model: &'m HullWhiteModel<'a, T>,
+    states: HashMap<Date, (T, T)>,
+}
+
+impl<T: Real> HullWhitePath<'_, '_, T> {
+    /// Price at `fixing` of a bond paying at `date`, on the curve of the provider `id`
+    fn bond(&self, id: usize, fixing: Date, date: Date, x: T) -> Result<T> {
+        let model = self.model;
+        let initial = |d: Date| model.simple.gen_df_data(DiscountFactorRequest::new(id, d));
+        let a = model.mean_reversion;
+        let t = model.time(fixing);
+        let b = model.bond_sensitivity(model.time(date) - t);
+        let decay = T::from(1.0) - (-a * t).exp();
+        let drift = model.volatility * model.volatility / (a * a * 2.0) * decay * decay;
+        Ok(initial(date)? / initial(fixing)?
+            * (-(b * x) - b * b * model.factor_variance(t) * 0.5 - b * drift).exp())
+    }
+}
+
+impl<T: Real> DeterministicModel<T> for HullWhitePath<'_, '_, T> {
+    fn reference_date(&self) -> Date {
+        self.model.reference_date()
+    }
+
+    fn gen_df_data(&self, df: DiscountFactorRequest) -> Result<T> {
+        let initial = self.model.simple.gen_df_data(df)?;
+        match self.states.get(&df.date()) {
+            Some((_, y)) => {
+                let t = self.model.time(df.date());
+                Ok(initial * (-*y - self.model.integral_variance(t) * 0.5).exp())
+            }
+            None => Ok(initial),
+        }
+    }
+
+    fn gen_fx_data(&self, fx: ExchangeRateRequest) -> Result<T> {
+        self.model.gen_fx_data(fx)
+    }
+
+    fn gen_fwd_data(&self, fwd: ForwardRateRequest) -> Result<T> {
+        let fixing = fwd.fixing_date();
+        match self.states.get(&fixing) {
+            Some((x, _)) => {
+                let id = fwd.provider_id();
+                let compound = self.bond(id, fixing, fwd.start_date(), *x)?
+                    / self.bond(id, fixing, fwd.end_date(), *x)?;
+                let day_counter = self.model.day_counter;
+                let t = day_counter.year_fraction::<T>(fwd.start_date(), fwd.end_date());
+                Ok(InterestRate::implied_rate(
+                    compound,
+                    day_counter,
+                    fwd.compounding(),
+                    fwd.frequency(),
+                    t,
+                )?
+                .rate())
+            }
+            None => self.model.gen_fwd_data(fwd),
+        }
+    }
+
+    fn gen_equity_data(&self, equity: EquityRequest) -> Result<T> {
+        self.model.gen_equity_data(equity)
+    }
+
+    fn gen_vol_data(&self, vol: VolatilityRequest) -> Result<T> {
+        self.model.gen_vol_data(vol)
+    }
+
+    fn gen_corr_data(&self, corr: CorrelationRequest) -> Result<T> {
+        self.model.gen_corr_data(corr)
+    }
+
+    fn gen_numerarie(&self, market_request: &MarketRequest) -> Result<T> {
+        match market_request.df() {
+            Some(df) => Ok(T::from(1.0) / self.gen_df_data(df)?),
+            None => Ok(T::from(1.0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn test_paths_keep_the_curve() -> Result<()> {
+        let ref_date = Date::new(2024, 1, 2);
+        let mut store = MarketStore::new(ref_date, Currency::USD);
+        let curve = Arc::new(FlatForwardTermStructure::new(
+            ref_date,
+            0.04,
+            RateDefinition::default(),
+        ));
+        let index = IborIndex::new(ref_date).with_term_structure(curve);
+        store
+            .mut_index_store()
+            .add_index(0, Arc::new(RwLock::new(index)))?;
+
+        let (fixing, end) = (Date::new(2025, 1, 2), Date::new(2026, 1, 2));
+        let requests = vec![
+            MarketRequest::new(0, Some(DiscountFactorRequest::new(0, fixing)), None, None),
+            MarketRequest::new(
+                1,
+                Some(DiscountFactorRequest::new(0, end)),
+                Some(ForwardRateRequest::new(
+                    0,
+                    fixing,
+                    fixing,
+                    end,
+                    Compounding::Simple,
+                    Frequency::Annual,
+                )),
+                None,
+            ),
+        ];
+
+        let simple = SimpleModel::new(&store);
+        let model = HullWhiteModel::new(simple.clone(), 0.05, 0.01)?;
+        let n = 4000;
+        let (mut df, mut deflated_fwd, mut fwd_dispersion) = (0.0, 0.0, 0.0);
+        for seed in 0..n {
+            let scenario = model.clone().with_seed(seed).gen_scenario(&requests)?;
+            let fwd = scenario[1].fwd()?;
+            df += scenario[0].df()? / n as f64;
+            deflated_fwd += scenario[1].df()? * fwd / n as f64;
+            fwd_dispersion += (fwd - 0.04).abs() / n as f64;
+        }
+
+        // discount factors and forwards are martingales of the initial curve, and do move
+        let p_fixing = simple.gen_df_data(DiscountFactorRequest::new(0, fixing))?;
+        let p_end = simple.gen_df_data(requests[1].df().unwrap())?;
+        let fwd0 = simple.gen_fwd_data(requests[1].fwd().unwrap())?;
+        assert!((df - p_fixing).abs() < 1e-3);
+        assert!((deflated_fwd - p_end * fwd0).abs() < 3e-4);
+        assert!(fwd_dispersion > 1e-3);
+
+        // the numerarie is the bank account of the path, deflating the payments to today
+        let (mut deflator, mut dispersion) = (0.0, 0.0);
+        for seed in 0..n {
+            let scenario = model.clone().with_seed(seed).gen_scenario(&requests)?;
+            assert!((scenario[0].numerarie() * scenario[0].df()? - 1.0).abs() < 1e-12);
+            deflator += 1.0 / scenario[0].numerarie() / n as f64;
+            dispersion += (scenario[0].numerarie() * p_fixing - 1.0).abs() / n as f64;
+        }
+        assert!((deflator - p_fixing).abs() < 1e-3);
+        assert!(dispersion > 1e-3);
+
+        assert!(HullWhiteModel::new(simple, 0.0, 0.01).is_err());
+        Ok(())
+    }
+}
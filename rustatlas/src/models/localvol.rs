@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::core::historicaldata::HistoricalData;
+use crate::core::meta::MarketRequest;
+use crate::core::volatilitysurface::VolatilitySurface;
+use crate::math::ad::genericnumber::Real;
+use crate::prelude::{
+    Actual360, CorrelationRequest, Currency, DayCountProvider, DiscountFactorRequest,
+    EquityRequest, ExchangeRateRequest, ForwardRateRequest, HasReferenceDate, SimpleModel,
+    VolatilityRequest,
+};
+use crate::time::date::Date;
+use crate::utils::errors::{AtlasError, Result};
+
+use super::blackscholes::{
+    correlated_normals, future_node_dates, node_date, simulated_underliers, underlier_name,
+    BlackScholesModel,
+};
+use super::deterministicmodel::DeterministicModel;
+use super::stochasticmodel::{Scenario, StochasticModel};
+
+/// Log-moneyness points of a `LocalVolGrid`
+const LOG_MONEYNESS_POINTS: usize = 41;
+
+/// # LocalVolGrid
+/// Dupire local volatilities of an underlier on the expiries of its implied surface and a
+/// uniform log-moneyness grid across its quotes. Local volatilities are constant from one
+/// expiry to the next and linear in log-moneyness, flat beyond the grid.
+#[derive(Debug, Clone)]
+pub struct LocalVolGrid {
+    expiries: Vec<f64>,
+    log_moneyness: Vec<f64>,
+    local_vols: Vec<Vec<f64>>,
+}
+
+impl LocalVolGrid {
+    pub fn from_surface(surface: &VolatilitySurface) -> LocalVolGrid {
+        let quoted = surface.log_moneyness();
+        let (low, high) = (quoted[0], quoted[quoted.len() - 1]);
+        let points = if high > low { LOG_MONEYNESS_POINTS } else { 1 };
+        let log_moneyness = (0..points)
+            .map(|j| low + (high - low) * j as f64 / (points - 1).max(1) as f64)
+            .collect::<Vec<f64>>();
+
+        let mut start = 0.0;
+        let mut local_vols = Vec::with_capacity(surface.expiries().len());
+        for expiry in surface.expiries() {
+            // local variance in the middle of the period, so it prices the quotes at its end
+            let t = (start + expiry) / 2.0;
+            local_vols.push(
+                log_moneyness
+                    .iter()
+                    .map(|y| dupire_variance(surface, t, *y).sqrt())
+                    .collect(),
+            );
+            start = *expiry;
+        }
+
+        LocalVolGrid {
+            expiries: surface.expiries().clone(),
+            log_moneyness,
+            local_vols,
+        }
+    }
+
+    pub fn expiries(&self) -> &Vec<f64> {
+        &self.expiries
+    }
+
+    pub fn log_moneyness(&self) -> &Vec<f64> {
+        &self.log_moneyness
+    }
+
+    pub fn local_vols(&self) -> &Vec<Vec<f64>> {
+        &self.local_vols
+    }
+
+    /// # local_volatility
+    /// Local volatility at the time `t` and the log-moneyness `y`
+    pub fn local_volatility<T: Real>(&self, t: f64, y: T) -> T {
+        let i = self
+            .expiries
+            .iter()
+            .position(|expiry| *expiry >= t)
+            .unwrap_or(self.expiries.len() - 1);
+        let (ys, vols) = (&self.log_moneyness, &self.local_vols[i]);
+        let n = ys.len();
+        if n == 1 || y <= T::from(ys[0]) {
+            return T::from(vols[0]);
+        }
+        if y >= T::from(ys[n - 1]) {
+            return T::from(vols[n - 1]);
+        }
+        let j = ys.iter().position(|k| T::from(*k) > y).unwrap();
+        let w = (y - ys[j - 1]) / (ys[j] - ys[j - 1]);
+        T::from(vols[j - 1]) + w * (vols[j] - vols[j - 1])
+    }
+}
+
+/// Dupire local variance at the time `t` and log-moneyness `y` from the total implied variance
+/// of the surface. Falls back to the implied variance where the surface allows arbitrage.
+fn dupire_variance(surface: &VolatilitySurface, t: f64, y: f64) -> f64 {
+    let (ht, hy) = (1e-4 * t, 1e-3);
+    let w = surface.total_variance(t, y);
+    let w_t = (surface.total_variance(t + ht, y) - surface.total_variance(t - ht, y)) / (2.0 * ht);
+    let (w_up, w_down) = (
+        surface.total_variance(t, y + hy),
+        surface.total_variance(t, y - hy),
+    );
+    let w_y = (w_up - w_down) / (2.0 * hy);
+    let w_yy = (w_up - 2.0 * w + w_down) / (hy * hy);
+
+    let denominator =
+        1.0 - y / w * w_y + 0.25 * (-0.25 - 1.0 / w + y * y / (w * w)) * w_y * w_y + 0.5 * w_yy;
+    if w_t > 0.0 && denominator > 0.0 {
+        w_t / denominator
+    } else {
+        w / t
+    }
+}
+
+/// # LocalVolModel
+/// Local volatility Monte Carlo generator. Each underlier diffuses with the Dupire local
+/// volatility of its implied surface in `HistoricalData`, quoted in moneyness to the forward, on
+/// a grid through the node dates. Spots drift and are discounted as in `BlackScholesModel`, net
+/// of the dividends of the same historical data, and shocks of different underliers are
+/// correlated through the correlation store. The local volatility grids are built once, when
+/// the model is created, and shared by its clones.
+#[derive(Clone)]
+pub struct LocalVolModel<'a, T: Real> {
+    black_scholes: BlackScholesModel<'a, T>,
+    grids: Arc<HashMap<String, LocalVolGrid>>,
+    steps_per_year: usize,
+    seed: u64,
+    antithetic: bool,
+}
+
+impl<'a, T: Real> LocalVolModel<'a, T> {
+    pub fn new(simple: SimpleModel<'a, T>, historical_data: &'a HistoricalData) -> Self {
+        Self {
+            black_scholes: BlackScholesModel::new(simple).with_historical_data(historical_data),
+            grids: Arc::new(
+                historical_data
+                    .volatility_surfaces()
+                    .iter()
+                    .map(|(name, surface)| (name.clone(), LocalVolGrid::from_surface(surface)))
+                    .collect(),
+            ),
+            steps_per_year: 52,
+            seed: 0xA55AA55Au64,
+            antithetic: false,
+        }
+    }
+
+    /// # with_steps_per_year
+    /// Time steps per year of the simulation grid, 52 by default
+    pub fn with_steps_per_year(mut self, steps_per_year: usize) -> Self {
+        self.steps_per_year = steps_per_year.max(1);
+        self
+    }
+
+    /// # with_seed
+    /// Seed of the random numbers of the generated scenario
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// # with_antithetic
+    /// Negate the spot shocks of every time step, see `standard_normal`
+    pub fn with_antithetic(mut self, antithetic: bool) -> Self {
+        self.antithetic = antithetic;
+        self
+    }
+
+    /// # gen_scenario_pair
+    /// Scenario of the seed and its antithetic one, drawn from the same random numbers
+    pub fn gen_scenario_pair(
+        &self,
+        market_requests: &[MarketRequest],
+    ) -> Result<(Scenario<T>, Scenario<T>)> {
+        let plain = self.clone().with_antithetic(false);
+        let antithetic = self.clone().with_antithetic(true);
+        Ok((
+            plain.gen_scenario(market_requests)?,
+            antithetic.gen_scenario(market_requests)?,
+        ))
+    }
+
+    /// # with_discount_curve
+    /// Discount the given currency on the provider `id` instead of the currency curve of the
+    /// index store
+    pub fn with_discount_curve(mut self, currency: Currency, id: usize) -> Self {
+        self.black_scholes = self.black_scholes.with_discount_curve(currency, id);
+        self
+    }
+}
+
+impl<T: Real> DeterministicModel<T> for LocalVolModel<'_, T> {
+    fn reference_date(&self) -> Date {
+        self.black_scholes.reference_date()
+    }
+
+    fn gen_df_data(&self, df: DiscountFactorRequest) -> Result<T> {
+        self.black_scholes.gen_df_data(df)
+    }
+
+    fn gen_fx_data(&self, fx: ExchangeRateRequest) -> Result<T> {
+        self.black_scholes.gen_fx_data(fx)
+    }
+
+    fn gen_fwd_data(&self, fwd: ForwardRateRequest) -> Result<T> {
+        self.black_scholes.gen_fwd_data(fwd)
+    }
+
+    fn gen_equity_data(&self, equity: EquityRequest) -> Result<T> {
+        self.black_scholes.gen_equity_data(equity)
+    }
+
+    fn gen_vol_data(&self, vol: VolatilityRequest) -> Result<T> {
+        self.black_scholes.gen_vol_data(vol)
+    }
+
+    fn gen_corr_data(&self, corr: CorrelationRequest) -> Result<T> {
+        self.black_scholes.gen_corr_data(corr)
+    }
+
+    fn gen_numerarie(&self, market_request: &MarketRequest) -> Result<T> {
+        self.black_scholes.gen_numerarie(market_request)
+    }
+}
+
+impl<T: Real> StochasticModel<T> for LocalVolModel<'_, T> {
+    fn gen_scenario(&self, market_requests: &[MarketRequest]) -> Result<Scenario<T>> {
+        let store = self.black_scholes.simple.market_store();
+        let ref_date = store.reference_date();
+        let local_ccy = store.local_currency();
+        let mut rng = StdRng::seed_from_u64(self.seed);
+
+        let underliers = simulated_underliers(market_requests, local_ccy);
+        let grids = underliers
+            .iter()
+            .map(|name| {
+                self.grids.get(name).ok_or(AtlasError::NotFoundErr(format!(
+                    "Volatility surface for {}",
+                    name
+                )))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let chol = store.correlation_store().cholesky(&underliers)?;
+
+        /* log-moneyness to the forward of each underlier at each node date */
+        let mut log_returns = vec![T::from(0.0); underliers.len()];
+        let mut paths = HashMap::new();
+        let mut t_prev = 0.0;
+        for date in future_node_dates(market_requests, ref_date) {
+            let t = Actual360::year_fraction::<f64>(ref_date, date);
+            let steps = ((t - t_prev) * self.steps_per_year as f64).ceil().max(1.0) as usize;
+            let dt = (t - t_prev) / steps as f64;
+            for step in 0..steps {
+                let time = t_prev + step as f64 * dt;
+                let shocks = correlated_normals(&chol, self.antithetic, &mut rng);
+                for (i, grid) in grids.iter().enumerate() {
+                    let sigma = grid.local_volatility(time, log_returns[i]);
+                    log_returns[i] =
+                        log_returns[i] + sigma * dt.sqrt() * shocks[i] - sigma * sigma * 0.5 * dt;
+                }
+            }
+            for (name, x) in underliers.iter().zip(&log_returns) {
+                paths.insert((name.clone(), date), *x);
+            }
+            t_prev = t;
+        }
+
+        self.black_scholes.gen_nodes(market_requests, |req, _| {
+            let name = underlier_name(req, local_ccy).unwrap();
+            let date = node_date(req, ref_date).unwrap();
+            // nodes at the reference date have not moved
+            Ok(paths.get(&(name, date)).copied().unwrap_or(T::from(0.0)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::RwLock;
+
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn test_flat_surface() {
+        let surface =
+            VolatilitySurface::new(vec![1.0, 2.0], vec![0.8, 1.2], vec![vec![0.2, 0.2]; 2])
+                .unwrap();
+        let grid = LocalVolGrid::from_surface(&surface);
+        assert!(grid
+            .local_vols()
+            .iter()
+            .flatten()
+            .all(|vol| (vol - 0.2).abs() < 1e-6));
+        assert!((grid.local_volatility(0.5, 3.0) - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_term_structure() {
+        // implied variance 0.04 over the first year and 0.18 over two years
+        let surface =
+            VolatilitySurface::new(vec![1.0, 2.0], vec![1.0], vec![vec![0.2], vec![0.3]]).unwrap();
+        let grid = LocalVolGrid::from_surface(&surface);
+        assert!((grid.local_volatility(0.5, 0.0) - 0.2).abs() < 1e-6);
+        assert!((grid.local_volatility(1.5, 0.0) - 0.14f64.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_skew_reprices_the_quotes() {
+        let surface = VolatilitySurface::new(
+            vec![0.5, 1.0],
+            vec![0.8, 0.9, 1.0, 1.1, 1.2],
+            vec![
+                vec![0.28, 0.24, 0.21, 0.19, 0.18],
+                vec![0.26, 0.23, 0.21, 0.195, 0.19],
+            ],
+        )
+        .unwrap();
+        let grid = LocalVolGrid::from_surface(&surface);
+
+        // local volatilities keep the skew and stay close to the implied ones at the money
+        let at = |y: f64| grid.local_volatility(0.75, y);
+        assert!(at(-0.2) > at(0.0) && at(0.0) > at(0.15));
+        assert!((at(0.0) - 0.21).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_grids_built_once() -> Result<()> {
+        let ref_date = Date::new(2024, 1, 2);
+        let mut store = MarketStore::new(ref_date, Currency::USD);
+        let curve = Arc::new(FlatForwardTermStructure::new(
+            ref_date,
+            0.03,
+            RateDefinition::default(),
+        ));
+        let index = OvernightIndex::new(ref_date).with_term_structure(curve);
+        store
+            .mut_index_store()
+            .add_index(0, Arc::new(RwLock::new(index)))?;
+        store.mut_index_store().add_currency_curve(Currency::USD, 0);
+        store.mut_equity_store().add_spot("AAPL".to_string(), 100.0);
+        store.mut_equity_store().add_spot("MSFT".to_string(), 100.0);
+        let surface =
+            VolatilitySurface::new(vec![1.0, 2.0], vec![0.8, 1.2], vec![vec![0.2; 2]; 2])?;
+        let historical_data =
+            HistoricalData::new(ref_date).with_volatility_surface("AAPL", surface);
+
+        let model = LocalVolModel::new(SimpleModel::new(&store), &historical_data);
+        let seeded = model.clone().with_seed(7);
+        assert!(Arc::ptr_eq(&model.grids, &seeded.grids));
+
+        let request = |name: &str| {
+            MarketRequest::new(0, None, None, None).with_equity(EquityRequest::new(
+                name.to_string(),
+                Some(Date::new(2025, 1, 2)),
+            ))
+        };
+        assert!(seeded.gen_scenario(&[request("AAPL")])?[0].equity()? > 0.0);
+        assert!(seeded.gen_scenario(&[request("MSFT")]).is_err());
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
 pub mod blackscholes;
 pub mod deterministicmodel;
+pub mod heston;
+pub mod hullwhite;
+pub mod localvol;
 pub mod model;
 pub mod simplemodel;
 pub mod stochasticmodel;
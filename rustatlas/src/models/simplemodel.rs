@@ -8,10 +8,12 @@ use crate::prelude::*;
 /// ## Parameters
 /// * `market_store` - The market store.
 /// * `transform_currencies` - If true, the model will transform the currencies to the local currency of the market store.
+/// * `historical_data` - The discrete dividends of the equities, if any.
 #[derive(Clone)]
 pub struct SimpleModel<'a, T: GenericNumber> {
     market_store: &'a MarketStore<T>,
     transform_currencies: bool,
+    historical_data: Option<&'a HistoricalData>,
 }
 
 impl<'a, T: GenericNumber> SimpleModel<'a, T> {
@@ -19,6 +21,7 @@ impl<'a, T: GenericNumber> SimpleModel<'a, T> {
         SimpleModel {
             market_store,
             transform_currencies: false,
+            historical_data: None,
         }
     }
 
@@ -27,9 +30,62 @@ impl<'a, T: GenericNumber> SimpleModel<'a, T> {
         self
     }
 
+    /// # with_historical_data
+    /// Historical data with the discrete dividends of the equities
+    pub fn with_historical_data(
+        mut self,
+        historical_data: &'a HistoricalData,
+    ) -> SimpleModel<'a, T> {
+        self.historical_data = Some(historical_data);
+        self
+    }
+
     pub fn market_store(&self) -> &MarketStore<T> {
         self.market_store
     }
+
+    /// # equity_forward
+    /// Forward of an equity to `date` with the rates of the curve `discount_curve`, net of its
+    /// continuous dividend yield and of the discrete dividends with an ex-date after the
+    /// reference date. Every model derives its equity forwards from here.
+    pub fn equity_forward(&self, name: &str, date: Date, discount_curve: usize) -> Result<T> {
+        let ref_date = self.market_store.reference_date();
+        let spot = self
+            .market_store
+            .equity_store()
+            .get_spot(name.to_string())?;
+        if date <= ref_date {
+            return Ok(spot);
+        }
+
+        let q = self.market_store.equity_store().get_dividend_yield(name);
+        let growth = |start: Date, end: Date| -> Result<T> {
+            let df = |d: Date| self.gen_df_data(DiscountFactorRequest::new(discount_curve, d));
+            let t = Actual360::year_fraction::<T>(start, end);
+            Ok(df(start)? / df(end)? * (-q * t).exp())
+        };
+
+        let dividends = self
+            .historical_data
+            .map_or(&[][..], |data| data.dividends(name));
+        let (mut forward, mut last) = (spot, ref_date);
+        for (ex_date, dividend) in dividends
+            .iter()
+            .filter(|(ex_date, _)| *ex_date > ref_date && *ex_date <= date)
+        {
+            forward = dividend.apply(forward * growth(last, *ex_date)?);
+            last = *ex_date;
+        }
+        let forward = forward * growth(last, date)?;
+
+        if forward <= T::from(0.0) {
+            return Err(AtlasError::InvalidValueErr(format!(
+                "Dividends of {} exceed its forward to {}",
+                name, date
+            )));
+        }
+        Ok(forward)
+    }
 }
 
 impl<'a, T: GenericNumber> DeterministicModel<T> for SimpleModel<'a, T> {
@@ -73,6 +129,44 @@ impl<'a, T: GenericNumber> DeterministicModel<T> for SimpleModel<'a, T> {
         )?)
     }
 
+    fn gen_equity_data(&self, equity: EquityRequest) -> Result<T> {
+        match equity.reference_date() {
+            // equities are assumed to be quoted in the local currency
+            Some(date) if date > self.market_store.reference_date() => {
+                let local_curve = self
+                    .market_store
+                    .index_store()
+                    .get_currency_curve(self.market_store.local_currency())?;
+                self.equity_forward(equity.name(), date, local_curve)
+            }
+            _ => self
+                .market_store
+                .equity_store()
+                .get_spot(equity.name().clone()),
+        }
+    }
+
+    fn gen_vol_data(&self, vol: VolatilityRequest) -> Result<T> {
+        // volatilities are constant, the reference date does not matter
+        match vol.currency_pair() {
+            Some((first, second)) => self
+                .market_store
+                .exchange_rate_store()
+                .get_volatility(first, second),
+            None => self
+                .market_store
+                .equity_store()
+                .get_volatility(vol.name().clone()),
+        }
+    }
+
+    fn gen_corr_data(&self, corr: CorrelationRequest) -> Result<T> {
+        Ok(self
+            .market_store
+            .correlation_store()
+            .get_correlation(corr.first(), corr.second()))
+    }
+
     fn gen_numerarie(&self, _: &crate::prelude::MarketRequest) -> Result<T> {
         Ok(T::from(1.0))
     }
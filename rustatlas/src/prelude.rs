@@ -5,22 +5,31 @@ pub use crate::{
         cashflow::*, fixedratecoupon::*, floatingratecoupon::*, simplecashflow::*, traits::*,
     },
     core::meta::*,
-    core::{marketstore::MarketStore, traits::*},
+    core::{
+        correlationstore::*, historicaldata::*, marketstore::MarketStore, traits::*,
+        volatilitysurface::*,
+    },
     currencies::{enums::*, structs::*, traits::*},
-    equities::equitystore::*,
+    equities::{dividend::*, equitystore::*},
     instruments::{
         fixedrateinstrument::*, floatingrateinstrument::*, instrument::*,
         makefixedrateinstrument::*, makefloatingrateinstrument::*, traits::*,
     },
     math::ad::adnumber::*,
+    math::ad::arena::*,
+    math::ad::checkpoint::*,
+    math::ad::customop::*,
+    math::ad::dot::*,
+    math::ad::dual::*,
     math::ad::genericnumber::*,
     math::ad::node::*,
     math::ad::tape::*,
     math::interpolation::enums::*,
     math::interpolation::linear::*,
     math::interpolation::loglinear::*,
+    math::interpolation::monotonecubic::*,
     math::interpolation::traits::*,
-    models::{blackscholes::*, simplemodel::*},
+    models::{blackscholes::*, heston::*, hullwhite::*, localvol::*, simplemodel::*},
     rates::{
         enums::*,
         indexstore::*,
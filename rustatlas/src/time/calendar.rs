@@ -1,7 +1,7 @@
 use serde::Serialize;
 
 use super::calendars::{
-    brazil::Brazil, chile::Chile, nullcalendar::NullCalendar, target::TARGET, traits::{ImplCalendar, IsCalendar}, unitedstates::UnitedStates, weekendsonly::WeekendsOnly
+    brazil::Brazil, chile::Chile, nullcalendar::NullCalendar, target::TARGET, traits::{ImplCalendar, IsCalendar}, unitedstates::{Market, UnitedStates}, weekendsonly::WeekendsOnly
 };
 use crate::{
     time::date::Date,
@@ -71,6 +71,7 @@ impl TryFrom<String> for Calendar {
             "WeekendsOnly" => Ok(Calendar::WeekendsOnly(WeekendsOnly::new())),
             "TARGET" => Ok(Calendar::TARGET(TARGET::new())),
             "UnitedStates" => Ok(Calendar::UnitedStates(UnitedStates::default())),
+            "NYSE" => Ok(Calendar::UnitedStates(UnitedStates::new(Market::Nyse))),
             "Brazil" => Ok(Calendar::Brazil(Brazil::default())),
             "Chile" => Ok(Calendar::Chile(Chile::default())),
             _ => Err(AtlasError::InvalidValueErr(format!(
@@ -207,4 +208,11 @@ mod test {
         assert_eq!(calendar.impl_name(), "Chile(SSE)");
     }
 
+    #[test]
+    fn test_calendar_try_from() {
+        let calendar = Calendar::try_from("NYSE".to_string()).unwrap();
+        assert_eq!(calendar.impl_name(), "UnitedStates(Nyse)");
+        assert!(Calendar::try_from("Mars".to_string()).is_err());
+    }
+
 }
\ No newline at end of file
@@ -15,7 +15,7 @@ fn main() -> Result<()> {
 
     let evaluator = ExprEvaluator::new()
         .with_variables(indexer.get_variables_size());
-    evaluator.const_visit(expr)?;
+    evaluator.const_visit(&expr)?;
 
     println!("Variables: {:?}", evaluator.variables());
     Ok(())
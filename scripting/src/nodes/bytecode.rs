@@ -0,0 +1,605 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use rustatlas::{currencies::enums::Currency, math::ad::num::Real};
+
+use crate::prelude::*;
+use crate::utils::errors::{Result, ScriptingError};
+
+/// # Instruction
+/// Operation of the bytecode stack machine. Numbers and booleans live on separate stacks, as in
+/// `ExprEvaluator`. Jump targets are absolute positions in the program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Constant(f64),
+    Bool(bool),
+    Load(usize),
+    Store(usize),
+
+    // market data, by scenario index
+    Spot(usize),
+    Rate(usize),
+    Equity(usize),
+    Vol(usize),
+    Corr(usize),
+    Pays {
+        id: usize,
+        settled: bool,
+        leg: Option<(String, Option<Currency>)>,
+    },
+
+    // math
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Min,
+    Max,
+    Pow,
+    Ln,
+    Exp,
+    Negate,
+
+    // logic
+    Equal,
+    NotEqual,
+    Superior,
+    Inferior,
+    SuperiorOrEqual,
+    InferiorOrEqual,
+    And,
+    Or,
+    Not,
+
+    // control flow
+    Jump(usize),
+    JumpIfFalse(usize),
+    Assert(String),
+}
+
+/// # Program
+/// Linear bytecode of an indexed event stream
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+}
+
+impl Program {
+    pub fn instructions(&self) -> &Vec<Instruction> {
+        &self.instructions
+    }
+}
+
+/// # Compiler
+/// Visitor that flattens an indexed tree into a `Program`, so scenarios are evaluated without
+/// walking and cloning the tree. It covers numeric and boolean scripts: strings, arrays, dates,
+/// loops, exercises, barriers, parameters and past fixings are rejected, and such scripts are
+/// evaluated with `EventStreamEvaluator` instead.
+pub struct Compiler {
+    code: RefCell<Vec<Instruction>>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler {
+            code: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// # compile
+    /// Compile a single indexed tree
+    pub fn compile(&self, node: &ExprTree) -> Result<Program> {
+        self.code.borrow_mut().clear();
+        self.visit(node)?;
+        Ok(Program {
+            instructions: self.code.take(),
+        })
+    }
+
+    /// # compile_events
+    /// Compile the events of an indexed stream, in order, into a single program
+    pub fn compile_events(&self, events: &EventStream) -> Result<Program> {
+        self.code.borrow_mut().clear();
+        events
+            .events()
+            .iter()
+            .try_for_each(|event| self.visit(event.expr()))?;
+        Ok(Program {
+            instructions: self.code.take(),
+        })
+    }
+
+    fn emit(&self, instruction: Instruction) -> usize {
+        let mut code = self.code.borrow_mut();
+        code.push(instruction);
+        code.len() - 1
+    }
+
+    /// Point the jump at `position` to the next instruction
+    fn patch(&self, position: usize) {
+        let mut code = self.code.borrow_mut();
+        let target = code.len();
+        match &mut code[position] {
+            Instruction::Jump(to) | Instruction::JumpIfFalse(to) => *to = target,
+            _ => (),
+        }
+    }
+
+    fn children(&self, children: &[ExprTree], instruction: Instruction) -> Result<()> {
+        children.iter().try_for_each(|child| self.visit(child))?;
+        self.emit(instruction);
+        Ok(())
+    }
+
+    fn id(index: &std::sync::OnceLock<usize>, what: &str) -> Result<usize> {
+        index
+            .get()
+            .copied()
+            .ok_or(ScriptingError::EvaluationError(format!(
+                "{} not indexed",
+                what
+            )))
+    }
+
+    fn unsupported(what: &str) -> ScriptingError {
+        ScriptingError::EvaluationError(format!(
+            "{} is not supported by the bytecode compiler",
+            what
+        ))
+    }
+}
+
+impl NodeVisitor for Compiler {
+    type Output = Result<()>;
+
+    fn visit(&self, node: &Box<Node>) -> Self::Output {
+        match node.as_ref() {
+            Node::Base(children) => children.iter().try_for_each(|child| self.visit(child)),
+            Node::Spanned(children, span) => children
+                .iter()
+                .try_for_each(|child| self.visit(child))
+                .map_err(|err| err.with_span(*span)),
+
+            // variables
+            Node::Variable(_, name, index) => {
+                self.emit(Instruction::Load(Self::id(
+                    index,
+                    &format!("Variable {}", name),
+                )?));
+                Ok(())
+            }
+            Node::Assign(children) => {
+                let id = match children.get(0).unwrap().as_ref() {
+                    Node::Variable(_, name, index) => {
+                        Self::id(index, &format!("Variable {}", name))?
+                    }
+                    _ => {
+                        return Err(ScriptingError::EvaluationError(
+                            "Invalid variable assignment".to_string(),
+                        ))
+                    }
+                };
+                self.visit(children.get(1).unwrap())?;
+                self.emit(Instruction::Store(id));
+                Ok(())
+            }
+            Node::Constant(value) => {
+                self.emit(Instruction::Constant(*value));
+                Ok(())
+            }
+            Node::True => {
+                self.emit(Instruction::Bool(true));
+                Ok(())
+            }
+            Node::False => {
+                self.emit(Instruction::Bool(false));
+                Ok(())
+            }
+
+            // financial
+            Node::Spot(_, _, index) => {
+                self.emit(Instruction::Spot(Self::id(index, "Spot")?));
+                Ok(())
+            }
+            Node::RateIndex(_, _, _, index) => {
+                self.emit(Instruction::Rate(Self::id(index, "RateIndex")?));
+                Ok(())
+            }
+            Node::Fixing(_, _, index) => match index.get() {
+                Some(id) => {
+                    self.emit(Instruction::Rate(*id));
+                    Ok(())
+                }
+                None => Err(Self::unsupported("A past fixing")),
+            },
+            Node::Equity(_, _, index) => {
+                self.emit(Instruction::Equity(Self::id(index, "Equity")?));
+                Ok(())
+            }
+            Node::Volatility(_, _, index) => {
+                self.emit(Instruction::Vol(Self::id(index, "Vol")?));
+                Ok(())
+            }
+            Node::Correlation(_, _, index) => {
+                self.emit(Instruction::Corr(Self::id(index, "Corr")?));
+                Ok(())
+            }
+            Node::Pays(children, data, index) => {
+                let instruction = Instruction::Pays {
+                    id: Self::id(index, "Event")?,
+                    settled: data.settlement_days().is_some(),
+                    leg: data.leg().map(|leg| (leg.clone(), data.currency())),
+                };
+                self.children(children, instruction)
+            }
+
+            // math
+            Node::Add(children) => self.children(children, Instruction::Add),
+            Node::Subtract(children) => self.children(children, Instruction::Subtract),
+            Node::Multiply(children) => self.children(children, Instruction::Multiply),
+            Node::Divide(children) => self.children(children, Instruction::Divide),
+            Node::Min(children) => self.children(children, Instruction::Min),
+            Node::Max(children) => self.children(children, Instruction::Max),
+            Node::Pow(children) => self.children(children, Instruction::Pow),
+            Node::Ln(children) => self.children(children, Instruction::Ln),
+            Node::Exp(children) => self.children(children, Instruction::Exp),
+            Node::UnaryPlus(children) => children.iter().try_for_each(|child| self.visit(child)),
+            Node::UnaryMinus(children) => self.children(children, Instruction::Negate),
+
+            // logic
+            Node::Equal(children) => self.children(children, Instruction::Equal),
+            Node::NotEqual(children) => self.children(children, Instruction::NotEqual),
+            Node::Superior(children) => self.children(children, Instruction::Superior),
+            Node::Inferior(children) => self.children(children, Instruction::Inferior),
+            Node::SuperiorOrEqual(children) => {
+                self.children(children, Instruction::SuperiorOrEqual)
+            }
+            Node::InferiorOrEqual(children) => {
+                self.children(children, Instruction::InferiorOrEqual)
+            }
+            Node::And(children) => self.children(children, Instruction::And),
+            Node::Or(children) => self.children(children, Instruction::Or),
+            Node::Not(children) => self.children(children, Instruction::Not),
+
+            // control flow
+            Node::If(children, first_else) => {
+                // the else index is relative to the body that follows the condition
+                let first_else = first_else.map_or(children.len(), |i| i + 1);
+                self.visit(children.get(0).unwrap())?;
+                let skip_then = self.emit(Instruction::JumpIfFalse(0));
+                children[1..first_else]
+                    .iter()
+                    .try_for_each(|child| self.visit(child))?;
+                if first_else < children.len() {
+                    let skip_else = self.emit(Instruction::Jump(0));
+                    self.patch(skip_then);
+                    children[first_else..]
+                        .iter()
+                        .try_for_each(|child| self.visit(child))?;
+                    self.patch(skip_else);
+                } else {
+                    self.patch(skip_then);
+                }
+                Ok(())
+            }
+            Node::Assert(children, message) => {
+                self.children(children, Instruction::Assert(message.clone()))
+            }
+
+            Node::String(_)
+            | Node::Cvg(_)
+            | Node::Accrual(_)
+            | Node::Adjust(_)
+            | Node::IsBusinessDay(_)
+            | Node::AddTenor(_)
+            | Node::Schedule(_) => Err(Self::unsupported("A string or date expression")),
+            Node::List(_)
+            | Node::Index(_)
+            | Node::Append(_)
+            | Node::MatMul(_)
+            | Node::Transpose(_)
+            | Node::Solve(_) => Err(Self::unsupported("An array expression")),
+            Node::ForEach(_) | Node::Break | Node::Continue => Err(Self::unsupported("A loop")),
+            Node::Param(_, _) => Err(Self::unsupported("A parameter")),
+            Node::Exercise(_, _) => Err(Self::unsupported("An exercise")),
+            Node::BarrierHit(..) => Err(Self::unsupported("A barrier")),
+        }
+    }
+}
+
+fn pop<V>(stack: &mut Vec<V>) -> Result<V> {
+    stack.pop().ok_or(ScriptingError::EvaluationError(
+        "Stack underflow".to_string(),
+    ))
+}
+
+/// # BytecodeEvaluator
+/// Runs a compiled `Program` on every scenario and averages the variables, as
+/// `EventStreamEvaluator` does for the tree
+pub struct BytecodeEvaluator<'a, T: Real = f64> {
+    n_vars: usize,
+    scenarios: Option<&'a Vec<Scenario<T>>>,
+}
+
+impl<'a, T: Real> BytecodeEvaluator<'a, T> {
+    pub fn new(n_vars: usize) -> Self {
+        BytecodeEvaluator {
+            n_vars,
+            scenarios: None,
+        }
+    }
+
+    pub fn with_scenarios(mut self, scenarios: &'a Vec<Scenario<T>>) -> Self {
+        self.scenarios = Some(scenarios);
+        self
+    }
+
+    /// # run
+    /// Execute the program on a single scenario, returning the variables and the discounted
+    /// cashflows of each leg
+    pub fn run(
+        &self,
+        program: &Program,
+        scenario: &Scenario<T>,
+    ) -> Result<(Vec<Value<T>>, CashflowReport<T>)> {
+        let mut variables = vec![Value::Null; self.n_vars];
+        let mut cashflows = CashflowReport::<T>::new();
+        let mut digits: Vec<T> = Vec::new();
+        let mut booleans: Vec<bool> = Vec::new();
+
+        let market_data = |id: usize| {
+            scenario.get(id).ok_or(ScriptingError::EvaluationError(
+                "Market data not found".to_string(),
+            ))
+        };
+
+        let instructions = program.instructions();
+        let mut pc = 0;
+        while pc < instructions.len() {
+            match &instructions[pc] {
+                Instruction::Constant(value) => digits.push(T::from(*value)),
+                Instruction::Bool(value) => booleans.push(*value),
+                Instruction::Load(id) => match variables.get(*id) {
+                    Some(Value::Number(value)) => digits.push(*value),
+                    Some(Value::Bool(value)) => booleans.push(*value),
+                    _ => {
+                        return Err(ScriptingError::EvaluationError(format!(
+                            "Variable {} not initialized",
+                            id
+                        )))
+                    }
+                },
+                Instruction::Store(id) => {
+                    let value = match booleans.pop() {
+                        Some(value) => Value::Bool(value),
+                        None => Value::Number(pop(&mut digits)?),
+                    };
+                    variables[*id] = value;
+                }
+                Instruction::Spot(id) => digits.push(market_data(*id)?.fx()?),
+                Instruction::Rate(id) => digits.push(market_data(*id)?.fwd()?),
+                Instruction::Equity(id) => digits.push(market_data(*id)?.equity()?),
+                Instruction::Vol(id) => digits.push(market_data(*id)?.vol()?),
+                Instruction::Corr(id) => digits.push(market_data(*id)?.corr()?),
+                Instruction::Pays { id, settled, leg } => {
+                    let amount = pop(&mut digits)?;
+                    let value = match settled {
+                        true => {
+                            let delay = market_data(*id + 1)?.df()? / market_data(*id)?.df()?;
+                            amount * delay / market_data(*id)?.numerarie()
+                        }
+                        false => amount / market_data(*id)?.numerarie(),
+                    };
+                    if let Some(key) = leg {
+                        let total = cashflows.entry(key.clone()).or_insert(T::from(0.0));
+                        *total = *total + value;
+                    }
+                    digits.push(value);
+                }
+                Instruction::Negate => {
+                    let value = pop(&mut digits)?;
+                    digits.push(-value);
+                }
+                Instruction::Ln => {
+                    let value = pop(&mut digits)?;
+                    digits.push(value.ln());
+                }
+                Instruction::Exp => {
+                    let value = pop(&mut digits)?;
+                    digits.push(value.exp());
+                }
+                Instruction::Add
+                | Instruction::Subtract
+                | Instruction::Multiply
+                | Instruction::Divide
+                | Instruction::Min
+                | Instruction::Max
+                | Instruction::Pow => {
+                    let right = pop(&mut digits)?;
+                    let left = pop(&mut digits)?;
+                    digits.push(match &instructions[pc] {
+                        Instruction::Add => left + right,
+                        Instruction::Subtract => left - right,
+                        Instruction::Multiply => left * right,
+                        Instruction::Divide => left / right,
+                        Instruction::Min => left.min(right),
+                        Instruction::Max => left.max(right),
+                        _ => left.powf(right),
+                    });
+                }
+                Instruction::Equal
+                | Instruction::NotEqual
+                | Instruction::Superior
+                | Instruction::Inferior
+                | Instruction::SuperiorOrEqual
+                | Instruction::InferiorOrEqual => {
+                    let right = pop(&mut digits)?;
+                    let left = pop(&mut digits)?;
+                    booleans.push(match &instructions[pc] {
+                        Instruction::Equal => (right - left).abs() < T::from(f64::EPSILON),
+                        Instruction::NotEqual => (right - left).abs() >= T::from(f64::EPSILON),
+                        Instruction::Superior => left > right,
+                        Instruction::Inferior => left < right,
+                        Instruction::SuperiorOrEqual => left >= right,
+                        _ => left <= right,
+                    });
+                }
+                Instruction::And | Instruction::Or => {
+                    let right = pop(&mut booleans)?;
+                    let left = pop(&mut booleans)?;
+                    booleans.push(match &instructions[pc] {
+                        Instruction::And => left && right,
+                        _ => left || right,
+                    });
+                }
+                Instruction::Not => {
+                    let value = pop(&mut booleans)?;
+                    booleans.push(!value);
+                }
+                Instruction::Jump(target) => {
+                    pc = *target;
+                    continue;
+                }
+                Instruction::JumpIfFalse(target) => {
+                    if !pop(&mut booleans)? {
+                        pc = *target;
+                        continue;
+                    }
+                }
+                Instruction::Assert(message) => {
+                    if !pop(&mut booleans)? {
+                        return Err(ScriptingError::AssertionFailed(message.clone()));
+                    }
+                }
+            }
+            pc += 1;
+        }
+        Ok((variables, cashflows))
+    }
+
+    /// # visit_events
+    /// Run the program on every scenario and return the averaged variables. Non numeric
+    /// variables keep the value of the first scenario.
+    pub fn visit_events(
+        &self,
+        program: &Program,
+        var_indexes: &HashMap<String, usize>,
+    ) -> Result<HashMap<String, Value<T>>> {
+        let scenarios = self.scenarios.ok_or(ScriptingError::EvaluationError(
+            "No scenarios set".to_string(),
+        ))?;
+
+        // numbers are summed with compensation, like in `EventStreamEvaluator`
+        let mut first: Option<Vec<Value<T>>> = None;
+        let mut sums: Vec<CompensatedSum<T>> = Vec::new();
+        for scenario in scenarios.iter() {
+            let (variables, _) = self.run(program, scenario)?;
+            sums.resize(variables.len(), CompensatedSum::new());
+            sums.iter_mut()
+                .zip(variables.iter())
+                .for_each(|(sum, value)| {
+                    if let Value::Number(value) = value {
+                        sum.add(*value);
+                    }
+                });
+            first.get_or_insert(variables);
+        }
+
+        let len = T::from(scenarios.len() as f64);
+        let first = first.unwrap_or_default();
+        Ok(var_indexes
+            .iter()
+            .filter_map(|(name, idx)| {
+                first.get(*idx).map(|value| match value {
+                    Value::Number(_) => (name.clone(), Value::Number(sums[*idx].value() / len)),
+                    _ => (name.clone(), value.clone()),
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustatlas::prelude::{Date, MarketData};
+
+    fn indexed(script: &str) -> (EventStream, EventIndexer) {
+        let event_date = Date::new(2024, 6, 3);
+        let events = EventStream::new()
+            .with_events(vec![Event::new(event_date, script.try_into().unwrap())]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        (events, indexer)
+    }
+
+    #[test]
+    fn test_if_else_jumps() {
+        let (events, indexer) = indexed("x = 2; if x > 1 { y = 1; } else { y = 2; } z = x == 2;");
+        let program = Compiler::new().compile_events(&events).unwrap();
+        assert!(program
+            .instructions()
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::JumpIfFalse(_))));
+
+        let scenarios: Vec<Scenario> = vec![Scenario::new()];
+        let results = BytecodeEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .visit_events(&program, &indexer.get_variable_indexes())
+            .unwrap();
+        assert_eq!(results.get("y"), Some(&Value::Number(1.0)));
+        assert_eq!(results.get("z"), Some(&Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_matches_tree_evaluator() {
+        let script = "
+            payoff = 0;
+            s = Stock(\"AAPL\");
+            if s > 100 and s <= 120 {
+                d = s - 100;
+                payoff pays d ^ 2 / 10 leg \"call\";
+            } else {
+                payoff = max(ln(s / 100), 0 - 1);
+            }
+        ";
+        let (events, indexer) = indexed(script);
+        let event_date = Date::new(2024, 6, 3);
+        let scenarios: Vec<Scenario> = [90.0, 110.0, 130.0]
+            .iter()
+            .map(|spot| {
+                (0..2)
+                    .map(|id| {
+                        MarketData::new(id, event_date, None, None, None, 2.0)
+                            .with_equity(Some(*spot))
+                    })
+                    .collect()
+            })
+            .collect();
+        let var_map = indexer.get_variable_indexes();
+
+        let program = Compiler::new().compile_events(&events).unwrap();
+        let compiled = BytecodeEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .visit_events(&program, &var_map)
+            .unwrap();
+        let expected = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .visit_events(&events, &var_map)
+            .unwrap();
+        assert_eq!(compiled, expected);
+
+        let (_, cashflows) = BytecodeEvaluator::new(indexer.get_variables_size())
+            .run(&program, &scenarios[1])
+            .unwrap();
+        assert_eq!(cashflows.get(&("call".to_string(), None)), Some(&5.0));
+    }
+
+    #[test]
+    fn test_unsupported_nodes() {
+        let (events, _) = indexed("x = 0; for v in [1, 2] { x = x + v; }");
+        let err = Compiler::new().compile_events(&events).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("not supported by the bytecode compiler"));
+    }
+}
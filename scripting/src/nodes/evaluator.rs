@@ -1,14 +1,23 @@
-use rustatlas::{math::ad::num::Real, prelude::*};
+use rustatlas::{
+    math::ad::num::Real, models::blackscholes::bridge_hit_probability, prelude::*,
+    time::calendars::traits::IsCalendar,
+};
 use serde::{Deserialize, Serialize};
 
 use std::{
     collections::HashMap,
     ops::{Add, AddAssign, Div, Mul, Sub, SubAssign},
-    sync::Mutex,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
+use crate::nodes::lsm::regress;
 use crate::prelude::*;
 use crate::utils::errors::{Result, ScriptingError};
+use crate::utils::linalg::{self, Matrix};
 
 /// # Value
 /// Enum representing the possible values of a variable
@@ -19,6 +28,7 @@ pub enum Value<T: Real = f64> {
     Bool(bool),
     Number(T),
     String(String),
+    Array(Vec<Value<T>>),
     Null,
 }
 
@@ -89,6 +99,252 @@ impl Div for Value {
 pub type Scenario<T = f64> = Vec<MarketData<T>>;
 pub type Numeraries<T = f64> = Vec<T>;
 
+/// # CashflowReport
+/// Discounted cashflows of the labeled `pays` statements, by leg and payment currency
+pub type CashflowReport<T = f64> = HashMap<(String, Option<Currency>), T>;
+
+/// # ExpectedCashflow
+/// Expected payments of the `pays` statements of one event, leg and currency, averaged over the
+/// scenarios: the amount in the payment currency and its value discounted by the numeraire
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectedCashflow<T: Real = f64> {
+    event_date: Date,
+    currency: Option<Currency>,
+    leg: Option<String>,
+    expected_amount: T,
+    discounted: T,
+}
+
+impl<T: Real> ExpectedCashflow<T> {
+    pub fn event_date(&self) -> Date {
+        self.event_date
+    }
+
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    pub fn leg(&self) -> Option<&String> {
+        self.leg.as_ref()
+    }
+
+    pub fn expected_amount(&self) -> T {
+        self.expected_amount
+    }
+
+    pub fn discounted(&self) -> T {
+        self.discounted
+    }
+}
+
+/// A `pays` statement evaluated on a scenario
+#[derive(Debug, Clone)]
+struct Payment<T: Real> {
+    event_date: Option<Date>,
+    currency: Option<Currency>,
+    leg: Option<String>,
+    amount: T,
+    discounted: T,
+}
+
+/// Averages over the scenarios of an event stream
+struct Aggregate<T: Real> {
+    variables: HashMap<String, Value<T>>,
+    report: CashflowReport<T>,
+    cashflows: Vec<ExpectedCashflow<T>>,
+    diagnostics: EvaluationDiagnostics,
+    // scenarios that met the condition, all evaluated ones without a condition
+    included: usize,
+}
+
+/// Pending `break` or `continue`, consumed by the innermost loop
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LoopControl {
+    Break,
+    Continue,
+}
+
+/// # AssertionPolicy
+/// What a failed `assert` aborts when evaluating an event stream: the whole run, or only the
+/// scenario where it failed, which is then left out of the averages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AssertionPolicy {
+    #[default]
+    AbortRun,
+    SkipScenario,
+}
+
+/// # NonFinitePolicy
+/// What happens when a division, `ln`, `exp` or `^` produces an infinite or NaN value:
+/// propagate it, fail the run, skip the scenario, or clamp it (NaN to zero, infinities to the
+/// largest finite value of the same sign).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonFinitePolicy {
+    #[default]
+    Propagate,
+    Error,
+    SkipScenario,
+    Clamp,
+}
+
+/// # EvaluationDiagnostics
+/// Counts of the scenarios of an evaluation: evaluated, skipped by a failed assertion or a
+/// non-finite value, and affected by a non-finite value whatever the policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EvaluationDiagnostics {
+    evaluated: usize,
+    skipped: usize,
+    non_finite: usize,
+}
+
+impl EvaluationDiagnostics {
+    pub fn evaluated(&self) -> usize {
+        self.evaluated
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    pub fn non_finite(&self) -> usize {
+        self.non_finite
+    }
+}
+
+/// # EvaluationLimits
+/// Guards against malformed scripts: the number of loop iterations and the nesting depth of `if`
+/// statements and loops allowed for a single scenario. Expressions do not count towards the
+/// depth, however long. Exceeding them raises `ScriptingError::LimitExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EvaluationLimits {
+    max_loop_iterations: usize,
+    max_depth: usize,
+}
+
+impl Default for EvaluationLimits {
+    fn default() -> Self {
+        EvaluationLimits {
+            max_loop_iterations: 1_000_000,
+            max_depth: 32,
+        }
+    }
+}
+
+impl EvaluationLimits {
+    pub fn new() -> Self {
+        EvaluationLimits::default()
+    }
+
+    pub fn with_max_loop_iterations(mut self, max_loop_iterations: usize) -> Self {
+        self.max_loop_iterations = max_loop_iterations;
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn max_loop_iterations(&self) -> usize {
+        self.max_loop_iterations
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+}
+
+/// # TapeBudget
+/// Maximum number of nodes the AAD tape of the evaluating thread may hold. The evaluator reads
+/// the tape through `tape_len`, so any tape can be guarded, e.g.
+/// `rustatlas::math::ad::tape::tape_len`. Exceeding the budget raises
+/// `ScriptingError::ResourceExhausted` instead of letting the tape grow until the process runs
+/// out of memory.
+#[derive(Debug, Clone, Copy)]
+pub struct TapeBudget {
+    max_nodes: usize,
+    tape_len: fn() -> usize,
+}
+
+impl TapeBudget {
+    pub fn new(max_nodes: usize, tape_len: fn() -> usize) -> Self {
+        TapeBudget {
+            max_nodes,
+            tape_len,
+        }
+    }
+
+    pub fn max_nodes(&self) -> usize {
+        self.max_nodes
+    }
+
+    fn check(&self) -> Result<()> {
+        let len = (self.tape_len)();
+        if len > self.max_nodes {
+            return Err(ScriptingError::ResourceExhausted(format!(
+                "AAD tape holds {} nodes, the budget is {}",
+                len, self.max_nodes
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// # CancellationToken
+/// Lets a host abort a running evaluation, either explicitly from another thread or once a
+/// wall-clock timeout has elapsed. Clones share the same flag. Evaluators check it before every
+/// scenario and stop with `ScriptingError::Cancelled`.
+///
+/// Timeouts rely on `std::time::Instant`, which is not available on `wasm32-unknown-unknown`:
+/// wasm hosts should call `cancel` from their own timer instead.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<(Instant, Duration)>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// # with_timeout
+    /// Cancel the evaluation once `timeout` has elapsed from now
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some((Instant::now() + timeout, timeout));
+        self
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .deadline
+                .is_some_and(|(deadline, _)| Instant::now() >= deadline)
+    }
+
+    /// # check
+    /// Fail with `ScriptingError::Cancelled` if the evaluation should stop
+    pub fn check(&self) -> Result<()> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(ScriptingError::Cancelled("cancelled by the caller".to_string()));
+        }
+        match self.deadline {
+            Some((deadline, timeout)) if Instant::now() >= deadline => Err(
+                ScriptingError::Cancelled(format!("timed out after {:?}", timeout)),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// # Parameters
+/// Values bound to the `param` declarations of a script template, by name
+pub type Parameters<T = f64> = HashMap<String, Value<T>>;
+
 /// # ExprEvaluator
 /// Visitor that evaluates the expression tree
 pub struct ExprEvaluator<'a, T: Real = f64> {
@@ -96,9 +352,25 @@ pub struct ExprEvaluator<'a, T: Real = f64> {
     digit_stack: Mutex<Vec<T>>,
     boolean_stack: Mutex<Vec<bool>>,
     string_stack: Mutex<Vec<String>>,
+    array_stack: Mutex<Vec<Vec<Value<T>>>>,
     is_lhs_variable: Mutex<bool>,
     lhs_variable: Mutex<Option<Box<Node>>>,
     scenario: Option<&'a Scenario<T>>,
+    historical_data: Option<&'a HistoricalData>,
+    exercise_policy: Option<&'a ExercisePolicy<T>>,
+    parameters: Option<&'a Parameters<T>>,
+    globals: Option<&'a HashMap<String, Value<T>>>,
+    loop_control: Mutex<Option<LoopControl>>,
+    limits: EvaluationLimits,
+    tape_budget: Option<TapeBudget>,
+    loop_iterations: Mutex<usize>,
+    depth: Mutex<usize>,
+    exercise_records: Mutex<Vec<(usize, T, T)>>,
+    cashflows: Mutex<CashflowReport<T>>,
+    event_date: Mutex<Option<Date>>,
+    payments: Mutex<Vec<Payment<T>>>,
+    non_finite_policy: NonFinitePolicy,
+    non_finite: Mutex<usize>,
 }
 
 impl<'a, T: Real> ExprEvaluator<'a, T> {
@@ -108,9 +380,25 @@ impl<'a, T: Real> ExprEvaluator<'a, T> {
             digit_stack: Mutex::new(Vec::new()),
             boolean_stack: Mutex::new(Vec::new()),
             string_stack: Mutex::new(Vec::new()),
+            array_stack: Mutex::new(Vec::new()),
             is_lhs_variable: Mutex::new(false),
             lhs_variable: Mutex::new(None),
             scenario: None,
+            historical_data: None,
+            exercise_policy: None,
+            parameters: None,
+            globals: None,
+            loop_control: Mutex::new(None),
+            limits: EvaluationLimits::default(),
+            tape_budget: None,
+            loop_iterations: Mutex::new(0),
+            depth: Mutex::new(0),
+            exercise_records: Mutex::new(Vec::new()),
+            cashflows: Mutex::new(HashMap::new()),
+            event_date: Mutex::new(None),
+            payments: Mutex::new(Vec::new()),
+            non_finite_policy: NonFinitePolicy::default(),
+            non_finite: Mutex::new(0),
         }
     }
 
@@ -119,6 +407,54 @@ impl<'a, T: Real> ExprEvaluator<'a, T> {
         self
     }
 
+    pub fn with_historical_data(mut self, historical_data: &'a HistoricalData) -> Self {
+        self.historical_data = Some(historical_data);
+        self
+    }
+
+    pub fn with_exercise_policy(mut self, exercise_policy: &'a ExercisePolicy<T>) -> Self {
+        self.exercise_policy = Some(exercise_policy);
+        self
+    }
+
+    pub fn with_parameters(mut self, parameters: &'a Parameters<T>) -> Self {
+        self.parameters = Some(parameters);
+        self
+    }
+
+    /// # with_globals
+    /// Host constants, such as a notional or a strike, readable by name from every event.
+    /// Assigning to them is an error.
+    pub fn with_globals(mut self, globals: &'a HashMap<String, Value<T>>) -> Self {
+        self.globals = Some(globals);
+        self
+    }
+
+    pub fn with_limits(mut self, limits: EvaluationLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// # with_non_finite_policy
+    /// How infinite and NaN results of arithmetic are handled
+    pub fn with_non_finite_policy(mut self, non_finite_policy: NonFinitePolicy) -> Self {
+        self.non_finite_policy = non_finite_policy;
+        self
+    }
+
+    /// # non_finite_count
+    /// Number of infinite or NaN results produced since the last reset
+    pub fn non_finite_count(&self) -> usize {
+        *self.non_finite.lock().unwrap()
+    }
+
+    /// # with_tape_budget
+    /// Budget on the AAD tape, checked before every node is evaluated
+    pub fn with_tape_budget(mut self, tape_budget: TapeBudget) -> Self {
+        self.tape_budget = Some(tape_budget);
+        self
+    }
+
     pub fn with_variables(self, n: usize) -> Self {
         self.variables.lock().unwrap().resize(n, Value::Null);
         self
@@ -143,6 +479,204 @@ impl<'a, T: Real> ExprEvaluator<'a, T> {
     pub fn boolean_stack(&self) -> Vec<bool> {
         self.boolean_stack.lock().unwrap().clone()
     }
+
+    pub fn array_stack(&self) -> Vec<Vec<Value<T>>> {
+        self.array_stack.lock().unwrap().clone()
+    }
+
+    /// # reset
+    /// Clear the variables, stacks and records in place, keeping their allocations, so the
+    /// evaluator can be reused for another scenario
+    pub fn reset(&self) {
+        self.variables
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .for_each(|v| *v = Value::Null);
+        self.digit_stack.lock().unwrap().clear();
+        self.boolean_stack.lock().unwrap().clear();
+        self.string_stack.lock().unwrap().clear();
+        self.array_stack.lock().unwrap().clear();
+        *self.is_lhs_variable.lock().unwrap() = false;
+        *self.lhs_variable.lock().unwrap() = None;
+        *self.loop_control.lock().unwrap() = None;
+        *self.loop_iterations.lock().unwrap() = 0;
+        *self.depth.lock().unwrap() = 0;
+        self.exercise_records.lock().unwrap().clear();
+        self.cashflows.lock().unwrap().clear();
+        *self.event_date.lock().unwrap() = None;
+        self.payments.lock().unwrap().clear();
+        *self.non_finite.lock().unwrap() = 0;
+    }
+
+    /// # cashflows
+    /// Discounted cashflows of the labeled `pays` statements evaluated so far
+    pub fn cashflows(&self) -> CashflowReport<T> {
+        self.cashflows.lock().unwrap().clone()
+    }
+
+    /// # set_event_date
+    /// Date of the event being evaluated, attached to the payments it makes
+    pub fn set_event_date(&self, event_date: Date) {
+        *self.event_date.lock().unwrap() = Some(event_date);
+    }
+
+    /// # exercise_records
+    /// The index and value of the continuation variable and the deflated intrinsic value seen
+    /// at each exercise opportunity, in order
+    pub fn exercise_records(&self) -> Vec<(usize, T, T)> {
+        self.exercise_records.lock().unwrap().clone()
+    }
+
+    /// Push a value on the stack matching its type
+    fn push_value(&self, value: Value<T>) -> Result<()> {
+        match value {
+            Value::Number(v) => self.digit_stack.lock().unwrap().push(v),
+            Value::Bool(v) => self.boolean_stack.lock().unwrap().push(v),
+            Value::String(v) => self.string_stack.lock().unwrap().push(v),
+            Value::Array(v) => self.array_stack.lock().unwrap().push(v),
+            Value::Null => {
+                return Err(ScriptingError::EvaluationError(
+                    "Null value used in an expression".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate a node and pop its result from whichever stack received it
+    fn eval_value(&self, node: &ExprTree) -> Result<Value<T>> {
+        let digits = self.digit_stack.lock().unwrap().len();
+        let booleans = self.boolean_stack.lock().unwrap().len();
+        let strings = self.string_stack.lock().unwrap().len();
+        let arrays = self.array_stack.lock().unwrap().len();
+
+        self.const_visit(node)?;
+
+        if self.boolean_stack.lock().unwrap().len() > booleans {
+            Ok(Value::Bool(
+                self.boolean_stack.lock().unwrap().pop().unwrap(),
+            ))
+        } else if self.string_stack.lock().unwrap().len() > strings {
+            Ok(Value::String(
+                self.string_stack.lock().unwrap().pop().unwrap(),
+            ))
+        } else if self.array_stack.lock().unwrap().len() > arrays {
+            Ok(Value::Array(
+                self.array_stack.lock().unwrap().pop().unwrap(),
+            ))
+        } else if self.digit_stack.lock().unwrap().len() > digits {
+            Ok(Value::Number(
+                self.digit_stack.lock().unwrap().pop().unwrap(),
+            ))
+        } else {
+            Err(ScriptingError::EvaluationError(
+                "Expression does not produce a value".to_string(),
+            ))
+        }
+    }
+
+    /// Evaluate a node into a matrix. A flat array is read as a column vector,
+    /// in which case the returned flag is `true`.
+    fn eval_matrix(&self, node: &ExprTree) -> Result<(Matrix<T>, bool)> {
+        let not_a_matrix =
+            || ScriptingError::EvaluationError("Expected a matrix or a vector".to_string());
+        let number = |value: &Value<T>| match value {
+            Value::Number(v) => Ok(*v),
+            _ => Err(not_a_matrix()),
+        };
+
+        let items = match self.eval_value(node)? {
+            Value::Array(items) => items,
+            _ => return Err(not_a_matrix()),
+        };
+        if items.iter().all(|item| matches!(item, Value::Number(_))) {
+            let column = items
+                .iter()
+                .map(|item| number(item).map(|v| vec![v]))
+                .collect::<Result<Matrix<T>>>()?;
+            return Ok((column, true));
+        }
+        let matrix = items
+            .iter()
+            .map(|row| match row {
+                Value::Array(row) => row.iter().map(number).collect::<Result<Vec<T>>>(),
+                _ => Err(not_a_matrix()),
+            })
+            .collect::<Result<Matrix<T>>>()?;
+        Ok((matrix, false))
+    }
+
+    /// Push a matrix on the array stack, flattening it if it is a column vector
+    fn push_matrix(&self, matrix: Matrix<T>, is_vector: bool) {
+        let value = if is_vector {
+            matrix
+                .into_iter()
+                .flatten()
+                .map(Value::Number)
+                .collect()
+        } else {
+            matrix
+                .into_iter()
+                .map(|row| Value::Array(row.into_iter().map(Value::Number).collect()))
+                .collect()
+        };
+        self.array_stack.lock().unwrap().push(value);
+    }
+
+    /// Whether a `break` or `continue` skips the remaining statements of the loop body
+    fn is_interrupted(&self) -> bool {
+        self.loop_control.lock().unwrap().is_some()
+    }
+
+    /// Evaluate statements in order, stopping at a `break` or `continue`
+    fn visit_statements<'b>(&self, statements: impl Iterator<Item = &'b ExprTree>) -> Result<()> {
+        for statement in statements {
+            if self.is_interrupted() {
+                break;
+            }
+            self.const_visit(statement)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the non-finite policy to the result of `operation`
+    fn finite(&self, value: T, operation: &str) -> Result<T> {
+        #[allow(clippy::eq_op)]
+        let is_nan = value != value;
+        let is_infinite = !is_nan && value.abs() > T::from(f64::MAX);
+        if !is_nan && !is_infinite {
+            return Ok(value);
+        }
+        *self.non_finite.lock().unwrap() += 1;
+        match self.non_finite_policy {
+            NonFinitePolicy::Propagate => Ok(value),
+            NonFinitePolicy::Error | NonFinitePolicy::SkipScenario => {
+                Err(ScriptingError::NonFiniteValue(format!("{} gave {}", operation, value)))
+            }
+            NonFinitePolicy::Clamp if is_nan => Ok(T::from(0.0)),
+            NonFinitePolicy::Clamp if value > T::from(0.0) => Ok(T::from(f64::MAX)),
+            NonFinitePolicy::Clamp => Ok(T::from(f64::MIN)),
+        }
+    }
+
+    /// Get the index of a variable node
+    fn variable_id(node: &ExprTree) -> Result<usize> {
+        match node.as_ref() {
+            Node::Variable(_, name, index) => {
+                index
+                    .get()
+                    .copied()
+                    .ok_or(ScriptingError::EvaluationError(format!(
+                        "Variable {} not indexed",
+                        name
+                    )))
+            }
+            _ => Err(ScriptingError::EvaluationError(
+                "Expected a variable".to_string(),
+            )),
+        }
+    }
 }
 
 impl<'a> ExprEvaluator<'a> {
@@ -153,96 +687,347 @@ impl<'a> ExprEvaluator<'a> {
 
 impl<'a, T: Real> NodeConstVisitor for ExprEvaluator<'a, T> {
     type Output = Result<()>;
-    fn const_visit(&self, node: Box<Node>) -> Self::Output {
-        let eval: Result<()> = match node.as_ref() {
-            Node::Base(children) => {
+    fn const_visit(&self, node: &Node) -> Self::Output {
+        let nested = matches!(node, Node::If(..) | Node::ForEach(_));
+        if nested {
+            let mut depth = self.depth.lock().unwrap();
+            if *depth >= self.limits.max_depth() {
+                return Err(ScriptingError::LimitExceeded(format!(
+                    "Maximum nesting depth of {} reached",
+                    self.limits.max_depth()
+                )));
+            }
+            *depth += 1;
+        }
+        let result = match &self.tape_budget {
+            Some(tape_budget) => tape_budget.check().and_then(|_| self.eval(node)),
+            None => self.eval(node),
+        };
+        if nested {
+            *self.depth.lock().unwrap() -= 1;
+        }
+        result
+    }
+}
+
+impl<'a, T: Real> ExprEvaluator<'a, T> {
+    /// # eval
+    /// Evaluates a node. Statements, control flow and operators are what nests deeply in a
+    /// script, so they are evaluated in small stack frames and every other node is handed to
+    /// `eval_node`.
+    fn eval(&self, node: &Node) -> Result<()> {
+        match node {
+            Node::Base(children) => self.visit_statements(children.iter()),
+            Node::Spanned(children, span) => self
+                .visit_statements(children.iter())
+                .map_err(|err| err.with_span(*span)),
+            Node::If(children, first_else) => self.eval_if(children, *first_else),
+            Node::ForEach(children) => self.eval_for_each(children),
+            Node::Add(children)
+            | Node::Subtract(children)
+            | Node::Multiply(children)
+            | Node::Divide(children)
+            | Node::NotEqual(children)
+            | Node::And(children)
+            | Node::Or(children)
+            | Node::Not(children)
+            | Node::Superior(children)
+            | Node::Inferior(children)
+            | Node::SuperiorOrEqual(children)
+            | Node::InferiorOrEqual(children)
+            | Node::Equal(children)
+            | Node::UnaryPlus(children)
+            | Node::UnaryMinus(children)
+            | Node::Min(children)
+            | Node::Max(children)
+            | Node::Pow(children)
+            | Node::Ln(children)
+            | Node::Exp(children) => {
                 children
                     .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
-                Ok(())
+                    .try_for_each(|child| self.const_visit(child))?;
+                self.apply_operator(node)
             }
-            Node::Variable(_, name, index) => {
-                if *self.is_lhs_variable.lock().unwrap() {
-                    *self.lhs_variable.lock().unwrap() = Some(node.clone());
-                    Ok(())
-                } else {
-                    match index.get() {
-                        None => {
-                            return Err(ScriptingError::EvaluationError(format!(
-                                "Variable {} not indexed",
-                                name
-                            )))
-                        }
-                        Some(id) => {
-                            let vars = self.variables.lock().unwrap();
-                            let value = vars.get(*id).unwrap();
-                            match value {
-                                Value::Number(v) => self.digit_stack.lock().unwrap().push(*v),
-                                Value::Bool(v) => self.boolean_stack.lock().unwrap().push(*v),
-                                Value::String(v) => {
-                                    self.string_stack.lock().unwrap().push(v.clone())
-                                }
-                                Value::Null => {
-                                    return Err(ScriptingError::EvaluationError(format!(
-                                        "Variable {} not initialized",
-                                        name
-                                    )))
-                                }
-                            }
-                            Ok(())
-                        }
-                    }
-                }
+            _ => self.eval_node(node),
+        }
+    }
+
+    /// # eval_if
+    /// Evaluates the condition, then the statements of the branch it selects.
+    fn eval_if(&self, children: &[ExprTree], first_else: Option<usize>) -> Result<()> {
+        // Evaluate the condition
+        self.const_visit(children.get(0).unwrap())?;
+        // Pop the condition result
+        let is_true = self.boolean_stack.lock().unwrap().pop().unwrap();
+
+        // Find the first else node, the index is relative to the body
+        // that follows the condition
+        let first_else = first_else.map_or(children.len(), |i| i + 1);
+        if is_true {
+            self.visit_statements(children[1..first_else].iter())
+        } else {
+            self.visit_statements(children[first_else..].iter())
+        }
+    }
+
+    /// # eval_for_each
+    /// Evaluates the loop body once per item of the array.
+    fn eval_for_each(&self, children: &[ExprTree]) -> Result<()> {
+        let id = Self::variable_id(children.get(0).unwrap())?;
+        let items = match self.eval_value(children.get(1).unwrap())? {
+            Value::Array(items) => items,
+            _ => {
+                return Err(ScriptingError::EvaluationError(
+                    "Iterating over a non-array value".to_string(),
+                ))
             }
-            Node::Spot(_, _, index) => {
-                let id = index.get().ok_or(ScriptingError::EvaluationError(
-                    "Spot not indexed".to_string(),
-                ))?;
+        };
 
-                let market_data = self
-                    .scenario
-                    .ok_or(ScriptingError::EvaluationError(
-                        "No scenario set".to_string(),
-                    ))?
-                    .get(*id)
-                    .ok_or(ScriptingError::EvaluationError(
-                        "Spot not found".to_string(),
-                    ))?;
+        for item in items {
+            {
+                let mut iterations = self.loop_iterations.lock().unwrap();
+                if *iterations >= self.limits.max_loop_iterations() {
+                    return Err(ScriptingError::LimitExceeded(format!(
+                        "Maximum of {} loop iterations reached",
+                        self.limits.max_loop_iterations()
+                    )));
+                }
+                *iterations += 1;
+            }
+            self.variables.lock().unwrap()[id] = item;
+            self.visit_statements(children.iter().skip(2))?;
+            // a `continue` only ends the current iteration
+            if self.loop_control.lock().unwrap().take() == Some(LoopControl::Break) {
+                break;
+            }
+        }
+        Ok(())
+    }
 
-                self.digit_stack
+    /// # apply_operator
+    /// Applies an operator to the operands on top of the stacks.
+    fn apply_operator(&self, node: &Node) -> Result<()> {
+        match node {
+            Node::Add(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+                self.digit_stack.lock().unwrap().push(left + right);
+                Ok(())
+            }
+            Node::Subtract(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+                self.digit_stack.lock().unwrap().push(left - right);
+                Ok(())
+            }
+            Node::Multiply(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+                self.digit_stack.lock().unwrap().push(left * right);
+                Ok(())
+            }
+            Node::Divide(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+                let value = self.finite(left / right, "Division")?;
+                self.digit_stack.lock().unwrap().push(value);
+                Ok(())
+            }
+            Node::NotEqual(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+                self.boolean_stack
                     .lock()
                     .unwrap()
-                    .push(T::from(market_data.fx()?));
+                    .push((right - left).abs() >= T::from(f64::EPSILON));
+
                 Ok(())
             }
-            Node::RateIndex(_, _, _, index) => {
-                let id = index.get().ok_or(ScriptingError::EvaluationError(
-                    "RateIndex not indexed".to_string(),
-                ))?;
+            Node::And(_) => {
+                let right = self.boolean_stack.lock().unwrap().pop().unwrap();
+                let left = self.boolean_stack.lock().unwrap().pop().unwrap();
+                self.boolean_stack.lock().unwrap().push(left && right);
 
-                let market_data = self
-                    .scenario
-                    .ok_or(ScriptingError::EvaluationError(
-                        "No scenario set".to_string(),
-                    ))?
-                    .get(*id)
+                Ok(())
+            }
+            Node::Or(_) => {
+                let right = self.boolean_stack.lock().unwrap().pop().unwrap();
+                let left = self.boolean_stack.lock().unwrap().pop().unwrap();
+                self.boolean_stack.lock().unwrap().push(left || right);
+
+                Ok(())
+            }
+            Node::Not(_) => {
+                let value = self.boolean_stack.lock().unwrap().pop().unwrap();
+                self.boolean_stack.lock().unwrap().push(!value);
+
+                Ok(())
+            }
+            Node::Superior(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+                self.boolean_stack.lock().unwrap().push(left > right);
+
+                Ok(())
+            }
+            Node::Inferior(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+                self.boolean_stack.lock().unwrap().push(left < right);
+
+                Ok(())
+            }
+            Node::SuperiorOrEqual(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+                self.boolean_stack.lock().unwrap().push(left >= right);
+
+                Ok(())
+            }
+            Node::InferiorOrEqual(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+                self.boolean_stack.lock().unwrap().push(left <= right);
+
+                Ok(())
+            }
+            Node::Equal(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+
+                self.boolean_stack
+                    .lock()
+                    .unwrap()
+                    .push((right - left).abs() < T::from(f64::EPSILON));
+
+                Ok(())
+            }
+            Node::UnaryPlus(_) => Ok(()),
+            Node::UnaryMinus(_) => {
+                let top = self.digit_stack.lock().unwrap().pop().unwrap();
+                self.digit_stack.lock().unwrap().push(-top);
+
+                Ok(())
+            }
+            Node::Min(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+                self.digit_stack.lock().unwrap().push(left.min(right));
+
+                Ok(())
+            }
+            Node::Max(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+                self.digit_stack.lock().unwrap().push(left.max(right));
+
+                Ok(())
+            }
+            Node::Pow(_) => {
+                let right = self.digit_stack.lock().unwrap().pop().unwrap();
+                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+                let value = self.finite(left.powf(right), "Power")?;
+                self.digit_stack.lock().unwrap().push(value);
+
+                Ok(())
+            }
+            Node::Ln(_) => {
+                let top = self.digit_stack.lock().unwrap().pop().unwrap();
+                let value = self.finite(top.ln(), "ln")?;
+                self.digit_stack.lock().unwrap().push(value);
+
+                Ok(())
+            }
+            Node::Exp(_) => {
+                let top = self.digit_stack.lock().unwrap().pop().unwrap();
+                let value = self.finite(top.exp(), "exp")?;
+                self.digit_stack.lock().unwrap().push(value);
+                Ok(())
+            }
+            _ => Err(ScriptingError::EvaluationError(
+                "Expected an operator".to_string(),
+            )),
+        }
+    }
+
+    fn eval_node(&self, node: &Node) -> Result<()> {
+        let eval: Result<()> = match node {
+            Node::Break => {
+                *self.loop_control.lock().unwrap() = Some(LoopControl::Break);
+                Ok(())
+            }
+            Node::Continue => {
+                *self.loop_control.lock().unwrap() = Some(LoopControl::Continue);
+                Ok(())
+            }
+            Node::Variable(_, name, index) => {
+                let global = self.globals.and_then(|globals| globals.get(name));
+                if *self.is_lhs_variable.lock().unwrap() {
+                    if global.is_some() {
+                        return Err(ScriptingError::EvaluationError(format!(
+                            "Cannot assign to global constant {}",
+                            name
+                        )));
+                    }
+                    *self.lhs_variable.lock().unwrap() = Some(Box::new(node.clone()));
+                    Ok(())
+                } else if let Some(value) = global {
+                    self.push_value(value.clone())
+                } else {
+                    match index.get() {
+                        None => {
+                            return Err(ScriptingError::EvaluationError(format!(
+                                "Variable {} not indexed",
+                                name
+                            )))
+                        }
+                        Some(id) => {
+                            let vars = self.variables.lock().unwrap();
+                            let value = vars.get(*id).unwrap();
+                            match value {
+                                Value::Number(v) => self.digit_stack.lock().unwrap().push(*v),
+                                Value::Bool(v) => self.boolean_stack.lock().unwrap().push(*v),
+                                Value::String(v) => {
+                                    self.string_stack.lock().unwrap().push(v.clone())
+                                }
+                                Value::Array(v) => self.array_stack.lock().unwrap().push(v.clone()),
+                                Value::Null => {
+                                    return Err(ScriptingError::EvaluationError(format!(
+                                        "Variable {} not initialized",
+                                        name
+                                    )))
+                                }
+                            }
+                            Ok(())
+                        }
+                    }
+                }
+            }
+            Node::Spot(_, _, index) => {
+                let id = index.get().ok_or(ScriptingError::EvaluationError(
+                    "Spot not indexed".to_string(),
+                ))?;
+
+                let market_data = self
+                    .scenario
                     .ok_or(ScriptingError::EvaluationError(
-                        "RateIndex not found".to_string(),
+                        "No scenario set".to_string(),
+                    ))?
+                    .get(*id)
+                    .ok_or(ScriptingError::EvaluationError(
+                        "Spot not found".to_string(),
                     ))?;
 
                 self.digit_stack
                     .lock()
                     .unwrap()
-                    .push(T::from(market_data.fwd()?));
+                    .push(T::from(market_data.fx()?));
                 Ok(())
             }
-            Node::Pays(children, index) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
-                let id = index
-                    .get()
-                    .ok_or(ScriptingError::EvaluationError("No event set".to_string()))?;
+            Node::RateIndex(_, _, _, index) => {
+                let id = index.get().ok_or(ScriptingError::EvaluationError(
+                    "RateIndex not indexed".to_string(),
+                ))?;
 
                 let market_data = self
                     .scenario
@@ -251,71 +1036,284 @@ impl<'a, T: Real> NodeConstVisitor for ExprEvaluator<'a, T> {
                     ))?
                     .get(*id)
                     .ok_or(ScriptingError::EvaluationError(
-                        "Event not found".to_string(),
-                    ))?
-                    .clone();
+                        "RateIndex not found".to_string(),
+                    ))?;
 
-                let current_value = self.digit_stack.lock().unwrap().pop().unwrap();
                 self.digit_stack
                     .lock()
                     .unwrap()
-                    .push(current_value / market_data.numerarie());
+                    .push(T::from(market_data.fwd()?));
                 Ok(())
             }
-            Node::Constant(value) => {
-                self.digit_stack.lock().unwrap().push(T::from(*value));
+            Node::Equity(name, date, index) => {
+                let value = match index.get() {
+                    Some(id) => self
+                        .scenario
+                        .ok_or(ScriptingError::EvaluationError(
+                            "No scenario set".to_string(),
+                        ))?
+                        .get(*id)
+                        .ok_or(ScriptingError::EvaluationError(
+                            "Equity not found".to_string(),
+                        ))?
+                        .equity()?,
+                    // unindexed prices were observed before the reference date
+                    None => {
+                        let historical_data = self.historical_data.ok_or(
+                            ScriptingError::EvaluationError("Equity not indexed".to_string()),
+                        )?;
+                        let date = date.or(*self.event_date.lock().unwrap()).ok_or(
+                            ScriptingError::EvaluationError("No event date set".to_string()),
+                        )?;
+                        T::from(historical_data.fixing(name, date)?)
+                    }
+                };
+                self.digit_stack.lock().unwrap().push(value);
                 Ok(())
             }
-            Node::String(value) => {
-                self.string_stack.lock().unwrap().push(value.clone());
+            Node::Volatility(_, _, index) => {
+                let id = index.get().ok_or(ScriptingError::EvaluationError(
+                    "Vol not indexed".to_string(),
+                ))?;
+
+                let market_data = self
+                    .scenario
+                    .ok_or(ScriptingError::EvaluationError(
+                        "No scenario set".to_string(),
+                    ))?
+                    .get(*id)
+                    .ok_or(ScriptingError::EvaluationError(
+                        "Vol not found".to_string(),
+                    ))?;
+
+                self.digit_stack.lock().unwrap().push(market_data.vol()?);
                 Ok(())
             }
-            Node::Add(children) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+            Node::BarrierHit(children, _, _, up, out, observations, index) => {
+                let id = *index.get().ok_or(ScriptingError::EvaluationError(
+                    "Barrier not indexed".to_string(),
+                ))?;
+                let dates = observations.get().ok_or(ScriptingError::EvaluationError(
+                    "Barrier not indexed".to_string(),
+                ))?;
 
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.digit_stack.lock().unwrap().push(left + right);
+                self.const_visit(children.get(1).unwrap())?;
+                let level = self.digit_stack.lock().unwrap().pop().unwrap();
+
+                let scenario = self.scenario.ok_or(ScriptingError::EvaluationError(
+                    "No scenario set".to_string(),
+                ))?;
+                let market_data = |i: usize| {
+                    scenario.get(i).ok_or(ScriptingError::EvaluationError(
+                        "Barrier observation not found".to_string(),
+                    ))
+                };
+                let observe = |i: usize| -> Result<T> {
+                    match children.get(0).unwrap().as_ref() {
+                        Node::Spot(..) => Ok(market_data(i)?.fx()?),
+                        _ => Ok(market_data(i)?.equity()?),
+                    }
+                };
+
+                // the barrier survives the window if it survives each bridge between
+                // consecutive monitored dates
+                let vol = market_data(id + dates.len())?.vol()?;
+                let survival = dates.windows(2).enumerate().try_fold(
+                    T::from(1.0),
+                    |survival, (i, window)| -> Result<T> {
+                        let (s1, s2) = (observe(id + i)?, observe(id + i + 1)?);
+                        let t = Actual360::year_fraction::<T>(window[0], window[1]);
+                        let hit = bridge_hit_probability(s1, s2, level, vol, t, *up);
+                        Ok(survival * (T::from(1.0) - hit))
+                    },
+                )?;
+                let value = if *out {
+                    survival
+                } else {
+                    T::from(1.0) - survival
+                };
+                self.digit_stack.lock().unwrap().push(value);
                 Ok(())
             }
-            Node::Subtract(children) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+            Node::Correlation(_, _, index) => {
+                let id = index.get().ok_or(ScriptingError::EvaluationError(
+                    "Corr not indexed".to_string(),
+                ))?;
 
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.digit_stack.lock().unwrap().push(left - right);
+                let market_data = self
+                    .scenario
+                    .ok_or(ScriptingError::EvaluationError(
+                        "No scenario set".to_string(),
+                    ))?
+                    .get(*id)
+                    .ok_or(ScriptingError::EvaluationError(
+                        "Corr not found".to_string(),
+                    ))?;
+
+                self.digit_stack.lock().unwrap().push(market_data.corr()?);
                 Ok(())
             }
-            Node::Multiply(children) => {
+            Node::Fixing(name, date, index) => {
+                let value = match index.get() {
+                    Some(id) => {
+                        let market_data = self
+                            .scenario
+                            .ok_or(ScriptingError::EvaluationError(
+                                "No scenario set".to_string(),
+                            ))?
+                            .get(*id)
+                            .ok_or(ScriptingError::EvaluationError(
+                                "Fixing not found".to_string(),
+                            ))?;
+                        market_data.fwd()?
+                    }
+                    // unindexed fixings were observed before the reference date
+                    None => {
+                        let historical_data = self.historical_data.ok_or(
+                            ScriptingError::EvaluationError("No historical data set".to_string()),
+                        )?;
+                        T::from(historical_data.fixing(name, *date)?)
+                    }
+                };
+                self.digit_stack.lock().unwrap().push(value);
+                Ok(())
+            }
+            Node::Pays(children, data, index) => {
                 children
                     .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+                    .try_for_each(|child| self.const_visit(child))?;
+                let id = index
+                    .get()
+                    .ok_or(ScriptingError::EvaluationError("No event set".to_string()))?;
 
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.digit_stack.lock().unwrap().push(left * right);
+                let scenario = self.scenario.ok_or(ScriptingError::EvaluationError(
+                    "No scenario set".to_string(),
+                ))?;
+                let market_data = |i: usize| {
+                    scenario.get(i).ok_or(ScriptingError::EvaluationError(
+                        "Event not found".to_string(),
+                    ))
+                };
+
+                let current_value = self.digit_stack.lock().unwrap().pop().unwrap();
+                let value = match data.settlement_days() {
+                    // settled payments are deferred from the event to the payment date
+                    Some(_) => {
+                        let delay = market_data(id + 1)?.df()? / market_data(*id)?.df()?;
+                        current_value * delay / market_data(*id)?.numerarie()
+                    }
+                    None => current_value / market_data(*id)?.numerarie(),
+                };
+                self.payments.lock().unwrap().push(Payment {
+                    event_date: *self.event_date.lock().unwrap(),
+                    currency: data.currency(),
+                    leg: data.leg().cloned(),
+                    amount: current_value,
+                    discounted: value,
+                });
+                if let Some(leg) = data.leg() {
+                    let mut cashflows = self.cashflows.lock().unwrap();
+                    let total = cashflows
+                        .entry((leg.clone(), data.currency()))
+                        .or_insert(T::from(0.0));
+                    *total = *total + value;
+                }
+                self.digit_stack.lock().unwrap().push(value);
                 Ok(())
             }
-            Node::Divide(children) => {
+            Node::Exercise(children, index) => {
                 children
                     .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+                    .try_for_each(|child| self.const_visit(child))?;
+                let id = index
+                    .get()
+                    .ok_or(ScriptingError::EvaluationError("No event set".to_string()))?;
+                let variable = match children.first().unwrap().as_ref() {
+                    Node::Variable(_, _, variable) => *variable.get().ok_or(
+                        ScriptingError::EvaluationError("Variable not indexed".to_string()),
+                    )?,
+                    _ => {
+                        return Err(ScriptingError::EvaluationError(
+                            "Expected a continuation variable".to_string(),
+                        ))
+                    }
+                };
 
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.digit_stack.lock().unwrap().push(left / right);
+                let market_data = self
+                    .scenario
+                    .ok_or(ScriptingError::EvaluationError(
+                        "No scenario set".to_string(),
+                    ))?
+                    .get(*id)
+                    .ok_or(ScriptingError::EvaluationError(
+                        "Event not found".to_string(),
+                    ))?;
+
+                let intrinsic = self.digit_stack.lock().unwrap().pop().unwrap();
+                let continuation = self.digit_stack.lock().unwrap().pop().unwrap();
+                let deflated = intrinsic / market_data.numerarie();
+
+                let mut records = self.exercise_records.lock().unwrap();
+                let exercise = match self.exercise_policy {
+                    Some(policy) => policy.exercise(records.len(), deflated),
+                    None => false,
+                };
+                records.push((variable, continuation, deflated));
+
+                self.digit_stack
+                    .lock()
+                    .unwrap()
+                    .push(T::from(if exercise { 1.0 } else { 0.0 }));
+                Ok(())
+            }
+            Node::Param(children, value_type) => {
+                let variable = children.get(0).unwrap();
+                let name = match variable.as_ref() {
+                    Node::Variable(_, name, _) => name,
+                    _ => {
+                        return Err(ScriptingError::EvaluationError(
+                            "Expected a variable".to_string(),
+                        ))
+                    }
+                };
+                let value = self
+                    .parameters
+                    .and_then(|parameters| parameters.get(name))
+                    .ok_or(ScriptingError::EvaluationError(format!(
+                        "Parameter {} is not bound",
+                        name
+                    )))?;
+                let matches = matches!(
+                    (value_type, value),
+                    (ValueType::Number, Value::Number(_))
+                        | (ValueType::Bool, Value::Bool(_))
+                        | (ValueType::String, Value::String(_))
+                        | (ValueType::Array, Value::Array(_))
+                        | (ValueType::Unknown, _)
+                );
+                if !matches {
+                    return Err(ScriptingError::EvaluationError(format!(
+                        "Parameter {} expects a {:?} value, found {:?}",
+                        name, value_type, value
+                    )));
+                }
+                self.set_variable(Self::variable_id(variable)?, value.clone());
+                Ok(())
+            }
+            Node::Constant(value) => {
+                self.digit_stack.lock().unwrap().push(T::from(*value));
+                Ok(())
+            }
+            Node::String(value) => {
+                self.string_stack.lock().unwrap().push(value.clone());
                 Ok(())
             }
             Node::Assign(children) => {
                 *self.is_lhs_variable.lock().unwrap() = true;
-                self.const_visit(children.get(0).unwrap().clone())?;
+                self.const_visit(children.get(0).unwrap())?;
 
                 *self.is_lhs_variable.lock().unwrap() = false;
-                self.const_visit(children.get(1).unwrap().clone())?;
+                self.const_visit(children.get(1).unwrap())?;
 
                 let v = self.lhs_variable.lock().unwrap().clone().unwrap();
                 let variable = v.as_ref();
@@ -339,6 +1337,11 @@ impl<'a, T: Real> NodeConstVisitor for ExprEvaluator<'a, T> {
                                 let value = self.string_stack.lock().unwrap().pop().unwrap();
                                 variables[*id] = Value::String(value);
                                 Ok(())
+                            } else if !self.array_stack.lock().unwrap().is_empty() {
+                                // Pop from array stack and store the array value
+                                let value = self.array_stack.lock().unwrap().pop().unwrap();
+                                variables[*id] = Value::Array(value);
+                                Ok(())
                             } else {
                                 // Pop from digit stack and store the numeric value
                                 let value = self.digit_stack.lock().unwrap().pop().unwrap();
@@ -355,94 +1358,204 @@ impl<'a, T: Real> NodeConstVisitor for ExprEvaluator<'a, T> {
                     }
                 }
             }
-            Node::NotEqual(children) => {
+            Node::Cvg(children) => {
                 children
                     .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+                    .try_for_each(|child| self.const_visit(child))?;
 
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.boolean_stack
-                    .lock()
-                    .unwrap()
-                    .push((right - left).abs() >= T::from(f64::EPSILON));
+                let basis_str = self.string_stack.lock().unwrap().pop().unwrap();
+                let end_str = self.string_stack.lock().unwrap().pop().unwrap();
+                let start_str = self.string_stack.lock().unwrap().pop().unwrap();
 
+                let start = Date::from_str(&start_str, "%Y-%m-%d")?;
+                let end = Date::from_str(&end_str, "%Y-%m-%d")?;
+                let basis = DayCounter::try_from(basis_str)?;
+                let yf = basis.year_fraction(start, end);
+                self.digit_stack.lock().unwrap().push(yf);
                 Ok(())
             }
-            Node::And(children) => {
+            Node::Accrual(children) => {
                 children
                     .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
-
-                let right = self.boolean_stack.lock().unwrap().pop().unwrap();
-                let left = self.boolean_stack.lock().unwrap().pop().unwrap();
-                self.boolean_stack.lock().unwrap().push(left && right);
-
+                    .try_for_each(|child| self.const_visit(child))?;
+
+                // compounding and frequency default to simple annual accrual
+                let mut strings = {
+                    let mut stack = self.string_stack.lock().unwrap();
+                    let len = stack.len();
+                    stack.split_off(len - (children.len() - 1))
+                };
+                let definition = RateDefinition::default();
+                let frequency = match strings.len() {
+                    5 => Frequency::try_from(strings.pop().unwrap())?,
+                    _ => definition.frequency(),
+                };
+                let compounding = match strings.len() {
+                    4 => Compounding::try_from(strings.pop().unwrap())?,
+                    _ => definition.compounding(),
+                };
+                let basis = DayCounter::try_from(strings.pop().unwrap())?;
+                let end = Date::from_str(&strings.pop().unwrap(), "%Y-%m-%d")?;
+                let start = Date::from_str(&strings.pop().unwrap(), "%Y-%m-%d")?;
+                let rate = self.digit_stack.lock().unwrap().pop().unwrap();
+
+                let interest_rate = InterestRate::new(rate, compounding, frequency, basis);
+                self.digit_stack
+                    .lock()
+                    .unwrap()
+                    .push(interest_rate.compound_factor(start, end) - T::from(1.0));
                 Ok(())
             }
-            Node::Or(children) => {
+            Node::Adjust(children) => {
                 children
                     .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+                    .try_for_each(|child| self.const_visit(child))?;
 
-                let right = self.boolean_stack.lock().unwrap().pop().unwrap();
-                let left = self.boolean_stack.lock().unwrap().pop().unwrap();
-                self.boolean_stack.lock().unwrap().push(left || right);
+                let calendar_str = self.string_stack.lock().unwrap().pop().unwrap();
+                let convention_str = self.string_stack.lock().unwrap().pop().unwrap();
+                let date_str = self.string_stack.lock().unwrap().pop().unwrap();
 
+                let date = Date::from_str(&date_str, "%Y-%m-%d")?;
+                let convention = BusinessDayConvention::try_from(convention_str)?;
+                let calendar = Calendar::try_from(calendar_str)?;
+                let adjusted = calendar.adjust(date, Some(convention));
+                self.string_stack.lock().unwrap().push(adjusted.to_string());
                 Ok(())
             }
-            Node::Not(children) => {
+            Node::AddTenor(children) => {
                 children
                     .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
-
-                let value = self.boolean_stack.lock().unwrap().pop().unwrap();
-                self.boolean_stack.lock().unwrap().push(!value);
+                    .try_for_each(|child| self.const_visit(child))?;
 
+                // with a calendar, days are business days and the result is adjusted
+                let calendar_str = if children.len() == 3 {
+                    self.string_stack.lock().unwrap().pop()
+                } else {
+                    None
+                };
+                let tenor_str = self.string_stack.lock().unwrap().pop().unwrap();
+                let date_str = self.string_stack.lock().unwrap().pop().unwrap();
+
+                let date = Date::from_str(&date_str, "%Y-%m-%d")?;
+                let tenor = Period::from_str(&tenor_str)?;
+                let result = match calendar_str {
+                    Some(calendar_str) => {
+                        Calendar::try_from(calendar_str)?.advance(date, tenor, None, false)
+                    }
+                    None => date + tenor,
+                };
+                self.string_stack.lock().unwrap().push(result.to_string());
                 Ok(())
             }
-            Node::Superior(children) => {
+            Node::IsBusinessDay(children) => {
                 children
                     .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+                    .try_for_each(|child| self.const_visit(child))?;
 
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.boolean_stack.lock().unwrap().push(left > right);
+                let calendar_str = self.string_stack.lock().unwrap().pop().unwrap();
+                let date_str = self.string_stack.lock().unwrap().pop().unwrap();
 
+                let date = Date::from_str(&date_str, "%Y-%m-%d")?;
+                let calendar = Calendar::try_from(calendar_str)?;
+                self.boolean_stack
+                    .lock()
+                    .unwrap()
+                    .push(calendar.is_business_day(&date));
                 Ok(())
             }
-            Node::Inferior(children) => {
-                children
+            Node::Assert(children, message) => {
+                self.const_visit(children.get(0).unwrap())?;
+                let holds = self.boolean_stack.lock().unwrap().pop().unwrap();
+                if holds {
+                    Ok(())
+                } else {
+                    Err(ScriptingError::AssertionFailed(message.clone()))
+                }
+            }
+            Node::List(children) => {
+                let items = children
                     .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
-
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.boolean_stack.lock().unwrap().push(left < right);
-
+                    .map(|child| self.eval_value(child))
+                    .collect::<Result<Vec<Value<T>>>>()?;
+                self.array_stack.lock().unwrap().push(items);
                 Ok(())
             }
-            Node::SuperiorOrEqual(children) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
-
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.boolean_stack.lock().unwrap().push(left >= right);
+            Node::Index(children) => {
+                let items = match self.eval_value(children.get(0).unwrap())? {
+                    Value::Array(items) => items,
+                    _ => {
+                        return Err(ScriptingError::EvaluationError(
+                            "Indexing a non-array value".to_string(),
+                        ))
+                    }
+                };
+                self.const_visit(children.get(1).unwrap())?;
+                let index = self.digit_stack.lock().unwrap().pop().unwrap();
 
+                let position = (0..items.len())
+                    .find(|i| (index - T::from(*i as f64)).abs() < T::from(0.5))
+                    .ok_or(ScriptingError::EvaluationError(
+                        "Index out of bounds".to_string(),
+                    ))?;
+                self.push_value(items[position].clone())
+            }
+            Node::Append(children) => {
+                let id = Self::variable_id(children.get(0).unwrap())?;
+                let value = self.eval_value(children.get(1).unwrap())?;
+                match self.variables.lock().unwrap().get_mut(id) {
+                    Some(Value::Array(items)) => {
+                        items.push(value);
+                        Ok(())
+                    }
+                    _ => Err(ScriptingError::EvaluationError(
+                        "Appending to a non-array value".to_string(),
+                    )),
+                }
+            }
+            Node::MatMul(children) => {
+                let (a, _) = self.eval_matrix(children.get(0).unwrap())?;
+                let (b, is_vector) = self.eval_matrix(children.get(1).unwrap())?;
+                self.push_matrix(linalg::matmul(&a, &b)?, is_vector);
                 Ok(())
             }
-            Node::InferiorOrEqual(children) => {
+            Node::Transpose(children) => {
+                // a vector is transposed into a single-row matrix
+                let (m, _) = self.eval_matrix(children.get(0).unwrap())?;
+                self.push_matrix(linalg::transpose(&m)?, false);
+                Ok(())
+            }
+            Node::Solve(children) => {
+                let (a, _) = self.eval_matrix(children.get(0).unwrap())?;
+                let (b, is_vector) = self.eval_matrix(children.get(1).unwrap())?;
+                self.push_matrix(linalg::solve(&a, &b)?, is_vector);
+                Ok(())
+            }
+            Node::Schedule(children) => {
                 children
                     .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+                    .try_for_each(|child| self.const_visit(child))?;
 
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.boolean_stack.lock().unwrap().push(left <= right);
+                let calendar_str = self.string_stack.lock().unwrap().pop().unwrap();
+                let convention_str = self.string_stack.lock().unwrap().pop().unwrap();
+                let tenor_str = self.string_stack.lock().unwrap().pop().unwrap();
+                let end_str = self.string_stack.lock().unwrap().pop().unwrap();
+                let start_str = self.string_stack.lock().unwrap().pop().unwrap();
 
+                let schedule = MakeSchedule::new(
+                    Date::from_str(&start_str, "%Y-%m-%d")?,
+                    Date::from_str(&end_str, "%Y-%m-%d")?,
+                )
+                .with_tenor(Period::from_str(&tenor_str)?)
+                .with_convention(BusinessDayConvention::try_from(convention_str)?)
+                .with_calendar(Calendar::try_from(calendar_str)?)
+                .build()?;
+
+                let dates = schedule
+                    .dates()
+                    .iter()
+                    .map(|date| Value::String(date.to_string()))
+                    .collect();
+                self.array_stack.lock().unwrap().push(dates);
                 Ok(())
             }
             Node::True => {
@@ -455,146 +1568,180 @@ impl<'a, T: Real> NodeConstVisitor for ExprEvaluator<'a, T> {
 
                 Ok(())
             }
-            Node::Equal(children) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
-
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
+            _ => Err(ScriptingError::EvaluationError(
+                "Unexpected node".to_string(),
+            )),
+        };
+        eval
+    }
+}
 
-                self.boolean_stack
-                    .lock()
-                    .unwrap()
-                    .push((right - left).abs() < T::from(f64::EPSILON));
+/// # Estimate
+/// Monte Carlo estimate of a numeric variable: its mean over the scenarios, the standard error
+/// of the mean and the number of scenarios behind it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate<T: Real = f64> {
+    mean: T,
+    stderr: T,
+    n: usize,
+}
 
-                Ok(())
-            }
-            Node::UnaryPlus(children) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+impl<T: Real> Estimate<T> {
+    pub fn mean(&self) -> T {
+        self.mean
+    }
 
-                Ok(())
-            }
-            Node::UnaryMinus(children) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+    pub fn stderr(&self) -> T {
+        self.stderr
+    }
 
-                let top = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.digit_stack.lock().unwrap().push(-top);
+    pub fn n(&self) -> usize {
+        self.n
+    }
+}
 
-                Ok(())
-            }
-            Node::Min(children) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+/// Running mean and sum of squared deviations (Welford's algorithm)
+#[derive(Debug, Clone, Copy)]
+struct RunningMoments<T: Real> {
+    n: usize,
+    mean: T,
+    m2: T,
+}
 
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.digit_stack.lock().unwrap().push(left.min(right));
+impl<T: Real> RunningMoments<T> {
+    fn new() -> Self {
+        RunningMoments {
+            n: 0,
+            mean: T::from(0.0),
+            m2: T::from(0.0),
+        }
+    }
 
-                Ok(())
-            }
-            Node::Max(children) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+    fn push(&mut self, x: T) {
+        self.n += 1;
+        let delta = x - self.mean;
+        self.mean = self.mean + delta / T::from(self.n as f64);
+        self.m2 = self.m2 + delta * (x - self.mean);
+    }
 
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.digit_stack.lock().unwrap().push(left.max(right));
+    fn estimate(&self) -> Estimate<T> {
+        let stderr = if self.n > 1 {
+            let variance = self.m2 / T::from((self.n - 1) as f64);
+            (variance / T::from(self.n as f64)).sqrt()
+        } else {
+            T::from(0.0)
+        };
+        Estimate {
+            mean: self.mean,
+            stderr,
+            n: self.n,
+        }
+    }
+}
 
-                Ok(())
-            }
-            Node::Pow(children) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+/// Neumaier's compensated sum: the low order bits lost by each addition are accumulated
+/// separately, so the error does not grow with the number of scenarios
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CompensatedSum<T: Real> {
+    sum: T,
+    compensation: T,
+}
 
-                let right = self.digit_stack.lock().unwrap().pop().unwrap();
-                let left = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.digit_stack.lock().unwrap().push(left.powf(right));
+impl<T: Real> CompensatedSum<T> {
+    pub(crate) fn new() -> Self {
+        CompensatedSum {
+            sum: T::from(0.0),
+            compensation: T::from(0.0),
+        }
+    }
 
-                Ok(())
-            }
-            Node::Ln(children) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+    pub(crate) fn add(&mut self, x: T) {
+        let total = self.sum + x;
+        self.compensation = if self.sum.abs() >= x.abs() {
+            self.compensation + (self.sum - total) + x
+        } else {
+            self.compensation + (x - total) + self.sum
+        };
+        self.sum = total;
+    }
 
-                let top = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.digit_stack.lock().unwrap().push(top.ln());
+    pub(crate) fn value(&self) -> T {
+        // the compensation of an infinite sum is NaN
+        if self.sum.abs() > T::from(f64::MAX) {
+            return self.sum;
+        }
+        self.sum + self.compensation
+    }
+}
 
-                Ok(())
-            }
-            Node::Exp(children) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
+/// Tracks the standard error of the designated variable and reports convergence at the end of
+/// each batch of scenarios
+struct ConvergenceMonitor<T: Real> {
+    name: String,
+    index: usize,
+    target: T,
+    batch_size: usize,
+    moments: RunningMoments<T>,
+}
 
-                let top = self.digit_stack.lock().unwrap().pop().unwrap();
-                self.digit_stack.lock().unwrap().push(top.exp());
-                Ok(())
+impl<T: Real> ConvergenceMonitor<T> {
+    /// Record the variables of an evaluated scenario, `true` once the target is reached
+    fn converged(&mut self, variables: &[Value<T>]) -> Result<bool> {
+        match variables.get(self.index) {
+            Some(Value::Number(x)) => self.moments.push(*x),
+            _ => {
+                return Err(ScriptingError::EvaluationError(format!(
+                    "Variable {} is not a number",
+                    self.name
+                )))
             }
-            Node::Cvg(children) => {
-                children
-                    .iter()
-                    .try_for_each(|child| self.const_visit(child.clone()))?;
-
-                let basis_str = self.string_stack.lock().unwrap().pop().unwrap();
-                let end_str = self.string_stack.lock().unwrap().pop().unwrap();
-                let start_str = self.string_stack.lock().unwrap().pop().unwrap();
+        }
+        let n = self.moments.n;
+        Ok(n > 1
+            && n.is_multiple_of(self.batch_size)
+            && self.moments.estimate().stderr <= self.target)
+    }
+}
 
-                let start = Date::from_str(&start_str, "%Y-%m-%d")?;
-                let end = Date::from_str(&end_str, "%Y-%m-%d")?;
-                let basis = DayCounter::try_from(basis_str)?;
-                let yf = basis.year_fraction(start, end);
-                self.digit_stack.lock().unwrap().push(yf);
-                Ok(())
-            }
-            Node::If(children, first_else) => {
-                // Evaluate the condition
-                children.get(0).unwrap().const_accept(self);
-                // Pop the condition result
-                let is_true = self.boolean_stack.lock().unwrap().pop().unwrap();
-
-                // Find the first else node
-                if is_true {
-                    // then, the following expressions are either conditions or
-                    // the logic block
-                    let last_condition = if first_else.is_none() {
-                        children.len()
-                    } else {
-                        first_else.unwrap()
-                    };
-
-                    // Evaluate the conditions
-                    for i in 1..last_condition {
-                        children.get(i).unwrap().const_accept(self);
-                    }
-                }
-                // Evaluate the else block
-                else if first_else.is_some() {
-                    // the following conditions are the else block
-                    for i in first_else.unwrap()..children.len() {
-                        children.get(i).unwrap().const_accept(self);
-                    }
-                }
-                Ok(())
-            }
-        };
-        eval
+/// Quantile of sorted values at `level` in [0, 1], interpolating linearly between order
+/// statistics
+fn quantile<T: Real>(sorted: &[T], level: f64) -> Result<T> {
+    if !(0.0..=1.0).contains(&level) {
+        return Err(ScriptingError::EvaluationError(format!(
+            "Quantile level {} is outside [0, 1]",
+            level
+        )));
     }
+    if sorted.is_empty() {
+        return Err(ScriptingError::EvaluationError(
+            "No scenarios to compute quantiles from".to_string(),
+        ));
+    }
+    let position = level * (sorted.len() - 1) as f64;
+    let (lower, upper) = (position.floor() as usize, position.ceil() as usize);
+    let weight = T::from(position - lower as f64);
+    Ok(sorted[lower] + (sorted[upper] - sorted[lower]) * weight)
 }
 
+/// Exercise records of a scenario with its variables at the end of the stream
+type ExercisePath<T> = (Vec<(usize, T, T)>, Vec<Value<T>>);
+
 /// # EventStreamEvaluator
 /// Visitor that evaluates the event stream
 pub struct EventStreamEvaluator<'a, T: Real = f64> {
     n_vars: usize,
     scenarios: Option<&'a Vec<Scenario<T>>>,
+    historical_data: Option<&'a HistoricalData>,
+    exercise_policy: Option<&'a ExercisePolicy<T>>,
+    parameters: Option<&'a Parameters<T>>,
+    globals: Option<&'a HashMap<String, Value<T>>>,
+    limits: EvaluationLimits,
+    tape_budget: Option<TapeBudget>,
+    assertion_policy: AssertionPolicy,
+    non_finite_policy: NonFinitePolicy,
+    target_stderr: Option<(String, f64)>,
+    batch_size: usize,
+    cancellation: Option<CancellationToken>,
 }
 
 impl<'a, T: Real> EventStreamEvaluator<'a, T> {
@@ -602,6 +1749,17 @@ impl<'a, T: Real> EventStreamEvaluator<'a, T> {
         EventStreamEvaluator {
             n_vars,
             scenarios: None,
+            historical_data: None,
+            exercise_policy: None,
+            parameters: None,
+            globals: None,
+            limits: EvaluationLimits::default(),
+            tape_budget: None,
+            assertion_policy: AssertionPolicy::default(),
+            non_finite_policy: NonFinitePolicy::default(),
+            target_stderr: None,
+            batch_size: 1000,
+            cancellation: None,
         }
     }
 
@@ -610,108 +1768,814 @@ impl<'a, T: Real> EventStreamEvaluator<'a, T> {
         self
     }
 
-    pub fn visit_events(
-        &self,
-        event_stream: &EventStream,
-        var_indexes: &HashMap<String, usize>,
-    ) -> Result<HashMap<String, Value<T>>> {
-        let scenarios = self.scenarios.ok_or(ScriptingError::EvaluationError(
-            "No scenarios set".to_string(),
-        ))?;
-
-        // Evaluate the events to get the variables using the first scenario
-        let mut evaluator = ExprEvaluator::<'_, T>::new_with_type().with_variables(self.n_vars);
-        if let Some(first) = scenarios.first() {
-            evaluator = evaluator.with_scenario(first);
-        }
-        event_stream
-            .events()
-            .iter()
-            .try_for_each(|event| -> Result<()> {
-                evaluator.const_visit(event.expr().clone())?;
-                Ok(())
-            })?;
-
-        let v: Vec<Value<T>> = evaluator
-            .variables()
-            .iter()
-            .map(|v| match v {
-                Value::Number(_) => Value::Number(T::from(0.0)),
-                _ => v.clone(),
-            })
-            .collect();
-
-        let global_variables = Mutex::new(v);
-
-        scenarios.iter().try_for_each(|scenario| -> Result<()> {
-            let evaluator = ExprEvaluator::<T>::new_with_type()
-                .with_variables(self.n_vars)
-                .with_scenario(scenario);
+    pub fn with_historical_data(mut self, historical_data: &'a HistoricalData) -> Self {
+        self.historical_data = Some(historical_data);
+        self
+    }
 
-            event_stream
-                .events()
-                .iter()
-                .try_for_each(|event| -> Result<()> {
-                    evaluator.const_visit(event.expr().clone())?;
-                    Ok(())
-                })?;
+    pub fn with_exercise_policy(mut self, exercise_policy: &'a ExercisePolicy<T>) -> Self {
+        self.exercise_policy = Some(exercise_policy);
+        self
+    }
 
-            let local_variables = evaluator.variables();
-            let mut vars = global_variables.lock().unwrap();
-            vars.iter_mut()
-                .zip(local_variables.iter())
-                .for_each(|(g, l)| match (g, l) {
-                    (Value::Number(g), Value::Number(l)) => *g = *g + *l,
-                    _ => (),
-                });
+    pub fn with_parameters(mut self, parameters: &'a Parameters<T>) -> Self {
+        self.parameters = Some(parameters);
+        self
+    }
 
-            Ok(())
-        })?;
+    pub fn with_globals(mut self, globals: &'a HashMap<String, Value<T>>) -> Self {
+        self.globals = Some(globals);
+        self
+    }
 
-        //avg
-        let mut vars = global_variables.lock().unwrap();
-        let len = T::from(scenarios.len() as f64);
+    /// # with_limits
+    /// Limits applied to the evaluation of each scenario
+    pub fn with_limits(mut self, limits: EvaluationLimits) -> Self {
+        self.limits = limits;
+        self
+    }
 
-        vars.iter_mut().for_each(|v| match v {
-            Value::Number(v) => *v = *v / len,
-            _ => (),
-        });
+    /// # with_tape_budget
+    /// Budget on the AAD tape of the evaluating thread, shared by all scenarios
+    pub fn with_tape_budget(mut self, tape_budget: TapeBudget) -> Self {
+        self.tape_budget = Some(tape_budget);
+        self
+    }
 
-        let mut map = HashMap::new();
-        for (name, idx) in var_indexes.iter() {
-            if let Some(v) = vars.get(*idx) {
-                map.insert(name.clone(), v.clone());
-            }
-        }
+    /// # with_assertion_policy
+    /// Whether a failed `assert` aborts the run or only skips its scenario
+    pub fn with_assertion_policy(mut self, assertion_policy: AssertionPolicy) -> Self {
+        self.assertion_policy = assertion_policy;
+        self
+    }
 
-        Ok(map)
+    /// # with_target_stderr
+    /// Stop consuming scenarios once the standard error of the mean of `variable` falls below
+    /// `target`. Convergence is checked after every batch of scenarios; the results only cover
+    /// the scenarios actually evaluated, their number is given by `Estimate::n` or the length
+    /// of `visit_events_per_scenario`.
+    pub fn with_target_stderr(mut self, variable: &str, target: f64) -> Self {
+        self.target_stderr = Some((variable.to_string(), target));
+        self
     }
-}
 
-#[cfg(test)]
-mod general_tests {
-    use super::*;
+    /// # with_batch_size
+    /// Number of scenarios evaluated between two convergence checks, 1000 by default
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
 
-    #[test]
-    fn test_add_node() {
-        let mut base = Box::new(Node::new_base());
-        let mut add = Box::new(Node::new_add());
+    /// # with_cancellation
+    /// Token checked before every scenario, so the host can abort a long run
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = Some(cancellation);
+        self
+    }
 
-        let c1 = Box::new(Node::new_constant(1.0));
-        let c2 = Box::new(Node::new_constant(1.0));
+    fn convergence_monitor(
+        &self,
+        var_indexes: &HashMap<String, usize>,
+    ) -> Result<Option<ConvergenceMonitor<T>>> {
+        let (name, target) = match &self.target_stderr {
+            Some(target_stderr) => target_stderr,
+            None => return Ok(None),
+        };
+        let index = var_indexes
+            .get(name)
+            .ok_or(ScriptingError::EvaluationError(format!(
+                "Variable {} not found",
+                name
+            )))?;
+        Ok(Some(ConvergenceMonitor {
+            name: name.clone(),
+            index: *index,
+            target: T::from(*target),
+            batch_size: self.batch_size,
+            moments: RunningMoments::new(),
+        }))
+    }
 
-        add.add_child(c1);
-        add.add_child(c2);
-        base.add_child(add);
+    /// Whether the run can stop after the scenario that produced `variables`
+    fn converged(
+        monitor: &mut Option<ConvergenceMonitor<T>>,
+        variables: &[Value<T>],
+    ) -> Result<bool> {
+        match monitor {
+            Some(monitor) => monitor.converged(variables),
+            None => Ok(false),
+        }
+    }
 
-        let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+    /// # with_non_finite_policy
+    /// How infinite and NaN results of arithmetic are handled in every scenario
+    pub fn with_non_finite_policy(mut self, non_finite_policy: NonFinitePolicy) -> Self {
+        self.non_finite_policy = non_finite_policy;
+        self
+    }
 
-        assert_eq!(evaluator.digit_stack().pop().unwrap(), 2.0);
+    /// Tolerate the error of a failed `assert` or a non-finite value when the policy skips
+    /// their scenario
+    fn skip_scenario(&self, result: Result<()>) -> Result<bool> {
+        match result {
+            Ok(()) => Ok(true),
+            Err(err)
+                if self.assertion_policy == AssertionPolicy::SkipScenario
+                    && err.is_assertion_failure() =>
+            {
+                Ok(false)
+            }
+            Err(err)
+                if self.non_finite_policy == NonFinitePolicy::SkipScenario
+                    && err.is_non_finite() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
     }
 
-    #[test]
-    fn test_subtract_node() {
+    fn new_evaluator(&self) -> ExprEvaluator<'a, T> {
+        let mut evaluator = ExprEvaluator::<'a, T>::new_with_type()
+            .with_variables(self.n_vars)
+            .with_limits(self.limits)
+            .with_non_finite_policy(self.non_finite_policy);
+        if let Some(historical_data) = self.historical_data {
+            evaluator = evaluator.with_historical_data(historical_data);
+        }
+        if let Some(exercise_policy) = self.exercise_policy {
+            evaluator = evaluator.with_exercise_policy(exercise_policy);
+        }
+        if let Some(parameters) = self.parameters {
+            evaluator = evaluator.with_parameters(parameters);
+        }
+        if let Some(globals) = self.globals {
+            evaluator = evaluator.with_globals(globals);
+        }
+        if let Some(tape_budget) = self.tape_budget {
+            evaluator = evaluator.with_tape_budget(tape_budget);
+        }
+        evaluator
+    }
+
+    /// Evaluate the events of the stream, up to `until` when given, calling `after_event` once
+    /// every event is evaluated
+    fn evaluate_events(
+        evaluator: &ExprEvaluator<'a, T>,
+        event_stream: &EventStream,
+        until: Option<Date>,
+        mut after_event: impl FnMut() -> Result<()>,
+    ) -> Result<()> {
+        event_stream
+            .events()
+            .iter()
+            .filter(|event| until.is_none_or(|until| event.event_date() <= until))
+            .try_for_each(|event| -> Result<()> {
+                evaluator.set_event_date(event.event_date());
+                evaluator.const_visit(event.expr())?;
+                after_event()
+            })
+    }
+
+    /// Evaluate the event stream on the scenario of a reset evaluator, `false` when a failed
+    /// assertion skips it
+    fn evaluate_scenario(
+        &self,
+        evaluator: &ExprEvaluator<'a, T>,
+        event_stream: &EventStream,
+        until: Option<Date>,
+        after_event: impl FnMut() -> Result<()>,
+    ) -> Result<bool> {
+        if let Some(cancellation) = &self.cancellation {
+            cancellation.check()?;
+        }
+        evaluator.reset();
+        self.skip_scenario(Self::evaluate_events(
+            evaluator,
+            event_stream,
+            until,
+            after_event,
+        ))
+    }
+
+    pub fn visit_events(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+    ) -> Result<HashMap<String, Value<T>>> {
+        let (variables, _) = self.visit_events_with_report(event_stream, var_indexes)?;
+        Ok(variables)
+    }
+
+    /// # visit_events_per_scenario
+    /// Evaluate the event stream, returning the variables of every scenario instead of their
+    /// average. Scenarios skipped by a failed assertion are left out.
+    pub fn visit_events_per_scenario(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+    ) -> Result<Vec<HashMap<String, Value<T>>>> {
+        let scenarios = self.scenarios.ok_or(ScriptingError::EvaluationError(
+            "No scenarios set".to_string(),
+        ))?;
+
+        let mut monitor = self.convergence_monitor(var_indexes)?;
+        let mut evaluator = self.new_evaluator();
+        let mut results = Vec::with_capacity(scenarios.len());
+        for scenario in scenarios.iter() {
+            evaluator = evaluator.with_scenario(scenario);
+            if !self.evaluate_scenario(&evaluator, event_stream, None, || Ok(()))? {
+                continue;
+            }
+            let variables = evaluator.variables();
+            results.push(
+                var_indexes
+                    .iter()
+                    .filter_map(|(name, idx)| {
+                        variables.get(*idx).map(|v| (name.clone(), v.clone()))
+                    })
+                    .collect(),
+            );
+            if Self::converged(&mut monitor, &variables)? {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    /// # visit_events_with_statistics
+    /// Evaluate the event stream, returning for every numeric variable its mean over the
+    /// scenarios together with the standard error of the mean
+    pub fn visit_events_with_statistics(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+    ) -> Result<HashMap<String, Estimate<T>>> {
+        self.visit_events_streaming(event_stream, var_indexes, usize::MAX, |_| ())
+    }
+
+    /// # visit_events_streaming
+    /// Evaluate the event stream like `visit_events_with_statistics`, calling `on_batch` with
+    /// the running estimates every `batch_size` evaluated scenarios so callers can show the
+    /// convergence of a long run
+    pub fn visit_events_streaming(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+        batch_size: usize,
+        mut on_batch: impl FnMut(&HashMap<String, Estimate<T>>),
+    ) -> Result<HashMap<String, Estimate<T>>> {
+        let scenarios = self.scenarios.ok_or(ScriptingError::EvaluationError(
+            "No scenarios set".to_string(),
+        ))?;
+        let batch_size = batch_size.max(1);
+        let estimates = |moments: &HashMap<String, RunningMoments<T>>| {
+            moments
+                .iter()
+                .map(|(name, moments)| (name.clone(), moments.estimate()))
+                .collect::<HashMap<String, Estimate<T>>>()
+        };
+
+        let mut monitor = self.convergence_monitor(var_indexes)?;
+        let mut evaluator = self.new_evaluator();
+        let mut moments = HashMap::new();
+        let mut evaluated: usize = 0;
+        for scenario in scenarios.iter() {
+            evaluator = evaluator.with_scenario(scenario);
+            if !self.evaluate_scenario(&evaluator, event_stream, None, || Ok(()))? {
+                continue;
+            }
+            evaluated += 1;
+            let variables = evaluator.variables();
+            for (name, idx) in var_indexes.iter() {
+                if let Some(Value::Number(x)) = variables.get(*idx) {
+                    moments
+                        .entry(name.clone())
+                        .or_insert_with(RunningMoments::new)
+                        .push(*x);
+                }
+            }
+            if evaluated.is_multiple_of(batch_size) {
+                on_batch(&estimates(&moments));
+            }
+            if Self::converged(&mut monitor, &variables)? {
+                break;
+            }
+        }
+
+        Ok(estimates(&moments))
+    }
+
+    /// # visit_events_with_likelihood_ratio
+    /// Sensitivities of `variable` estimated without a tape: for every parameter, the mean over
+    /// the scenarios of the variable times the likelihood-ratio weight of the scenario, the
+    /// derivative of the log density of the path (see `lr_delta_weight` and `lr_vega_weight`).
+    /// Weights are given per parameter in the order of the scenarios.
+    pub fn visit_events_with_likelihood_ratio(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+        variable: &str,
+        weights: &HashMap<String, Vec<T>>,
+    ) -> Result<HashMap<String, Estimate<T>>> {
+        let scenarios = self.scenarios.ok_or(ScriptingError::EvaluationError(
+            "No scenarios set".to_string(),
+        ))?;
+        let idx = *var_indexes
+            .get(variable)
+            .ok_or(ScriptingError::EvaluationError(format!(
+                "Variable {} not found",
+                variable
+            )))?;
+        if let Some((name, _)) = weights.iter().find(|(_, w)| w.len() != scenarios.len()) {
+            return Err(ScriptingError::EvaluationError(format!(
+                "Expected {} weights for {}",
+                scenarios.len(),
+                name
+            )));
+        }
+
+        let mut evaluator = self.new_evaluator();
+        let mut moments: HashMap<&String, RunningMoments<T>> = weights
+            .keys()
+            .map(|name| (name, RunningMoments::new()))
+            .collect();
+        for (i, scenario) in scenarios.iter().enumerate() {
+            evaluator = evaluator.with_scenario(scenario);
+            if !self.evaluate_scenario(&evaluator, event_stream, None, || Ok(()))? {
+                continue;
+            }
+            let value = match evaluator.variables().get(idx) {
+                Some(Value::Number(value)) => *value,
+                _ => {
+                    return Err(ScriptingError::EvaluationError(format!(
+                        "Variable {} is not a number",
+                        variable
+                    )))
+                }
+            };
+            moments
+                .iter_mut()
+                .for_each(|(name, moments)| moments.push(value * weights[*name][i]));
+        }
+
+        Ok(moments
+            .into_iter()
+            .map(|(name, moments)| (name.clone(), moments.estimate()))
+            .collect())
+    }
+
+    /// # visit_events_with_quantiles
+    /// Evaluate the event stream, collecting the selected variables across scenarios and
+    /// returning their quantiles at the requested levels, e.g. `[0.01, 0.05, 0.95]` for VaR or
+    /// PFE. The quantiles of each variable follow the order of `levels`.
+    pub fn visit_events_with_quantiles(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+        variables: &[String],
+        levels: &[f64],
+    ) -> Result<HashMap<String, Vec<T>>> {
+        let paths = self.visit_events_per_scenario(event_stream, var_indexes)?;
+
+        variables
+            .iter()
+            .map(|name| {
+                let mut values = paths
+                    .iter()
+                    .map(|path| match path.get(name) {
+                        Some(Value::Number(value)) => Ok(*value),
+                        Some(_) => Err(ScriptingError::EvaluationError(format!(
+                            "Variable {} is not a number",
+                            name
+                        ))),
+                        None => Err(ScriptingError::EvaluationError(format!(
+                            "Variable {} not found",
+                            name
+                        ))),
+                    })
+                    .collect::<Result<Vec<T>>>()?;
+                values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                let quantiles = levels
+                    .iter()
+                    .map(|level| quantile(&values, *level))
+                    .collect::<Result<Vec<T>>>()?;
+                Ok((name.clone(), quantiles))
+            })
+            .collect()
+    }
+
+    /// # visit_events_with_report
+    /// Evaluate the event stream, returning the averaged variables together with the expected
+    /// discounted cashflows of each leg
+    pub fn visit_events_with_report(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+    ) -> Result<(HashMap<String, Value<T>>, CashflowReport<T>)> {
+        let aggregate = self.aggregate(event_stream, var_indexes, None, None)?;
+        Ok((aggregate.variables, aggregate.report))
+    }
+
+    /// # visit_events_with_diagnostics
+    /// Evaluate the event stream, returning the averaged variables together with the number of
+    /// scenarios evaluated, skipped and affected by non-finite values
+    pub fn visit_events_with_diagnostics(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+    ) -> Result<(HashMap<String, Value<T>>, EvaluationDiagnostics)> {
+        let aggregate = self.aggregate(event_stream, var_indexes, None, None)?;
+        Ok((aggregate.variables, aggregate.diagnostics))
+    }
+
+    /// # visit_events_with_cashflows
+    /// Evaluate the event stream, returning the averaged variables together with the expected
+    /// payments of every event, leg and currency, ordered by event date
+    pub fn visit_events_with_cashflows(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+    ) -> Result<(HashMap<String, Value<T>>, Vec<ExpectedCashflow<T>>)> {
+        let aggregate = self.aggregate(event_stream, var_indexes, None, None)?;
+        Ok((aggregate.variables, aggregate.cashflows))
+    }
+
+    /// # visit_events_with_profile
+    /// Evaluate the event stream, recording the selected variables after every event. Returns
+    /// one entry per event with the average of each variable over the scenarios where it held a
+    /// number at that point, giving expected exposure or accrual profiles through time.
+    pub fn visit_events_with_profile(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+        variables: &[String],
+    ) -> Result<Vec<(Date, HashMap<String, T>)>> {
+        let scenarios = self.scenarios.ok_or(ScriptingError::EvaluationError(
+            "No scenarios set".to_string(),
+        ))?;
+        let selected = variables
+            .iter()
+            .map(|name| {
+                var_indexes
+                    .get(name)
+                    .map(|idx| (name, *idx))
+                    .ok_or(ScriptingError::EvaluationError(format!(
+                        "Variable {} not found",
+                        name
+                    )))
+            })
+            .collect::<Result<Vec<(&String, usize)>>>()?;
+
+        let events = event_stream.events();
+        // per event and selected variable, the sum of its values and the number of scenarios
+        let mut sums =
+            vec![vec![(CompensatedSum::<T>::new(), 0usize); selected.len()]; events.len()];
+        let mut evaluator = self.new_evaluator();
+        for scenario in scenarios.iter() {
+            evaluator = evaluator.with_scenario(scenario);
+            let mut snapshots = Vec::with_capacity(events.len());
+            let kept = self.evaluate_scenario(&evaluator, event_stream, None, || {
+                let values = evaluator.variables();
+                snapshots.push(
+                    selected
+                        .iter()
+                        .map(|(_, idx)| match values.get(*idx) {
+                            Some(Value::Number(x)) => Some(*x),
+                            _ => None,
+                        })
+                        .collect::<Vec<Option<T>>>(),
+                );
+                Ok(())
+            })?;
+            if !kept {
+                continue;
+            }
+
+            sums.iter_mut()
+                .zip(snapshots.iter())
+                .for_each(|(sums, snapshot)| {
+                    sums.iter_mut().zip(snapshot.iter()).for_each(|(sum, x)| {
+                        if let Some(x) = x {
+                            sum.0.add(*x);
+                            sum.1 += 1;
+                        }
+                    })
+                });
+        }
+
+        Ok(events
+            .iter()
+            .zip(sums.iter())
+            .map(|(event, sums)| {
+                let averages = selected
+                    .iter()
+                    .zip(sums.iter())
+                    .filter(|(_, (_, n))| *n > 0)
+                    .map(|((name, _), (sum, n))| {
+                        ((*name).clone(), sum.value() / T::from(*n as f64))
+                    })
+                    .collect();
+                (event.event_date(), averages)
+            })
+            .collect())
+    }
+
+    /// # visit_events_until
+    /// Evaluate only the events dated on or before `date` and return the averaged state of the
+    /// variables at that point, e.g. what a trade has accrued or realized as of today
+    pub fn visit_events_until(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+        date: Date,
+    ) -> Result<HashMap<String, Value<T>>> {
+        Ok(self.aggregate(event_stream, var_indexes, Some(date), None)?.variables)
+    }
+
+    /// # visit_events_conditional
+    /// Evaluate the event stream and average the variables over the scenarios where the boolean
+    /// variable `condition` ends up true, e.g. a knocked-in flag. Also returns the probability of
+    /// the condition, the fraction of the evaluated scenarios that met it.
+    pub fn visit_events_conditional(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+        condition: &str,
+    ) -> Result<(HashMap<String, Value<T>>, T)> {
+        let aggregate = self.aggregate(event_stream, var_indexes, None, Some(condition))?;
+        let probability =
+            T::from(aggregate.included as f64) / T::from(aggregate.diagnostics.evaluated as f64);
+        Ok((aggregate.variables, probability))
+    }
+
+    /// # fit_exercise_policy
+    /// Longstaff-Schwartz estimate of the exercise policy of the `exercise(continuation,
+    /// intrinsic)` nodes of the stream. Going backwards through the opportunities, the scenarios
+    /// are evaluated holding the current one and following the policy fitted for the later ones.
+    /// The continuation realised on a path is what its continuation variable gains from the
+    /// opportunity to the end of the stream, regressed on the deflated intrinsic value over the
+    /// paths in the money.
+    ///
+    /// `exercise` nodes must be reached on every path, so they should not sit inside conditional
+    /// branches.
+    pub fn fit_exercise_policy(&self, event_stream: &EventStream) -> Result<ExercisePolicy<T>> {
+        let zero = T::from(0.0);
+        let mut policy = ExercisePolicy::new(Vec::new()).with_held(usize::MAX);
+        let mut paths = Self::exercise_paths(self, event_stream, &policy)?;
+
+        let n_exercises = paths.first().map(|(records, _)| records.len()).unwrap_or(0);
+        if paths
+            .iter()
+            .any(|(records, _)| records.len() != n_exercises)
+        {
+            return Err(ScriptingError::EvaluationError(
+                "Exercise opportunities differ across scenarios".to_string(),
+            ));
+        }
+
+        let mut coefficients = vec![[zero; 3]; n_exercises];
+        for k in (0..n_exercises).rev() {
+            if k + 1 < n_exercises {
+                policy = ExercisePolicy::new(coefficients.clone()).with_held(k + 1);
+                paths = Self::exercise_paths(self, event_stream, &policy)?;
+            }
+
+            let mut intrinsics = Vec::new();
+            let mut continuations = Vec::new();
+            for (records, variables) in paths.iter() {
+                let (variable, continuation, intrinsic) = records[k];
+                if intrinsic <= zero {
+                    continue;
+                }
+                let last = match variables.get(variable) {
+                    Some(Value::Number(last)) => *last,
+                    _ => {
+                        return Err(ScriptingError::EvaluationError(
+                            "Continuation variable is not a number".to_string(),
+                        ))
+                    }
+                };
+                intrinsics.push(intrinsic);
+                continuations.push(last - continuation);
+            }
+            coefficients[k] = regress(&intrinsics, &continuations);
+        }
+
+        Ok(ExercisePolicy::new(coefficients))
+    }
+
+    /// Exercise records and final variables of every scenario evaluated under `policy`
+    fn exercise_paths<'b>(
+        this: &EventStreamEvaluator<'b, T>,
+        event_stream: &EventStream,
+        policy: &'b ExercisePolicy<T>,
+    ) -> Result<Vec<ExercisePath<T>>> {
+        let scenarios = this.scenarios.ok_or(ScriptingError::EvaluationError(
+            "No scenarios set".to_string(),
+        ))?;
+
+        let mut evaluator = this.new_evaluator().with_exercise_policy(policy);
+        let mut paths = Vec::with_capacity(scenarios.len());
+        for scenario in scenarios.iter() {
+            evaluator = evaluator.with_scenario(scenario);
+            if this.evaluate_scenario(&evaluator, event_stream, None, || Ok(()))? {
+                paths.push((evaluator.exercise_records(), evaluator.variables()));
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Average the variables and cashflows of the events up to `until` over the scenarios
+    /// meeting `condition`
+    fn aggregate(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+        until: Option<Date>,
+        condition: Option<&str>,
+    ) -> Result<Aggregate<T>> {
+        let scenarios = self.scenarios.ok_or(ScriptingError::EvaluationError(
+            "No scenarios set".to_string(),
+        ))?;
+        let condition = condition
+            .map(|name| {
+                var_indexes
+                    .get(name)
+                    .map(|idx| (name, *idx))
+                    .ok_or(ScriptingError::EvaluationError(format!(
+                        "Variable {} not found",
+                        name
+                    )))
+            })
+            .transpose()?;
+
+        // Evaluate the events to get the variables using the first scenario
+        let mut evaluator = self.new_evaluator();
+        if let Some(first) = scenarios.first() {
+            evaluator = evaluator.with_scenario(first);
+        }
+        self.skip_scenario(Self::evaluate_events(
+            &evaluator,
+            event_stream,
+            until,
+            || Ok(()),
+        ))?;
+
+        // numeric variables are summed with compensation, in scenario order, so the averages
+        // are reproducible and do not drift over millions of scenarios
+        let mut vars = evaluator.variables();
+        let mut sums = vec![CompensatedSum::<T>::new(); vars.len()];
+        let mut flows: HashMap<(String, Option<Currency>), CompensatedSum<T>> = HashMap::new();
+        // payments by event date, leg and currency in the order they are first made
+        let mut payments: Vec<(Payment<T>, CompensatedSum<T>, CompensatedSum<T>)> = Vec::new();
+        let mut payment_index = HashMap::new();
+        let mut diagnostics = EvaluationDiagnostics::default();
+        let mut included = 0;
+        let mut monitor = self.convergence_monitor(var_indexes)?;
+
+        // the evaluator of the first pass is reset and reused for every scenario
+        for scenario in scenarios.iter() {
+            evaluator = evaluator.with_scenario(scenario);
+            let kept = self.evaluate_scenario(&evaluator, event_stream, until, || Ok(()))?;
+            if evaluator.non_finite_count() > 0 {
+                diagnostics.non_finite += 1;
+            }
+            if !kept {
+                diagnostics.skipped += 1;
+                continue;
+            }
+            diagnostics.evaluated += 1;
+
+            let local_variables = evaluator.variables();
+            if let Some((name, idx)) = condition {
+                match local_variables.get(idx) {
+                    Some(Value::Bool(true)) => (),
+                    Some(Value::Bool(false)) => continue,
+                    _ => {
+                        return Err(ScriptingError::EvaluationError(format!(
+                            "Condition {} is not a boolean",
+                            name
+                        )))
+                    }
+                }
+            }
+            included += 1;
+
+            // non numeric values are those of the first scenario included in the averages
+            if included == 1 {
+                vars = local_variables.clone();
+            }
+            sums.iter_mut()
+                .zip(local_variables.iter())
+                .for_each(|(sum, l)| {
+                    if let Value::Number(l) = l {
+                        sum.add(*l);
+                    }
+                });
+
+            evaluator.cashflows().into_iter().for_each(|(key, value)| {
+                flows
+                    .entry(key)
+                    .or_insert_with(CompensatedSum::new)
+                    .add(value);
+            });
+            for payment in evaluator.payments.lock().unwrap().drain(..) {
+                let key = (payment.event_date, payment.leg.clone(), payment.currency);
+                let idx = *payment_index.entry(key).or_insert_with(|| {
+                    payments.push((payment.clone(), CompensatedSum::new(), CompensatedSum::new()));
+                    payments.len() - 1
+                });
+                payments[idx].1.add(payment.amount);
+                payments[idx].2.add(payment.discounted);
+            }
+            if Self::converged(&mut monitor, &local_variables)? {
+                break;
+            }
+        }
+
+        if diagnostics.evaluated == 0 && !scenarios.is_empty() {
+            return Err(ScriptingError::EvaluationError(
+                "Every scenario failed an assertion or produced a non-finite value".to_string(),
+            ));
+        }
+
+        if let Some((name, _)) = condition.filter(|_| included == 0) {
+            return Err(ScriptingError::EvaluationError(format!(
+                "No scenario meets the condition {}",
+                name
+            )));
+        }
+
+        //avg
+        let len = T::from(included as f64);
+
+        vars.iter_mut().zip(sums.iter()).for_each(|(v, sum)| {
+            if let Value::Number(v) = v {
+                *v = sum.value() / len;
+            }
+        });
+
+        let report: CashflowReport<T> = flows
+            .into_iter()
+            .map(|(key, sum)| (key, sum.value() / len))
+            .collect();
+
+        let mut cashflows: Vec<ExpectedCashflow<T>> = payments
+            .into_iter()
+            .filter_map(|(payment, amount, discounted)| {
+                Some(ExpectedCashflow {
+                    event_date: payment.event_date?,
+                    currency: payment.currency,
+                    leg: payment.leg,
+                    expected_amount: amount.value() / len,
+                    discounted: discounted.value() / len,
+                })
+            })
+            .collect();
+        cashflows.sort_by_key(|cashflow| cashflow.event_date);
+
+        let mut map = HashMap::new();
+        for (name, idx) in var_indexes.iter() {
+            if let Some(v) = vars.get(*idx) {
+                map.insert(name.clone(), v.clone());
+            }
+        }
+
+        Ok(Aggregate {
+            variables: map,
+            report,
+            cashflows,
+            diagnostics,
+            included,
+        })
+    }
+}
+
+#[cfg(test)]
+mod general_tests {
+    use super::*;
+
+    #[test]
+    fn test_add_node() {
+        let mut base = Box::new(Node::new_base());
+        let mut add = Box::new(Node::new_add());
+
+        let c1 = Box::new(Node::new_constant(1.0));
+        let c2 = Box::new(Node::new_constant(1.0));
+
+        add.add_child(c1);
+        add.add_child(c2);
+        base.add_child(add);
+
+        let evaluator = ExprEvaluator::new();
+        evaluator.const_visit(&base).unwrap();
+
+        assert_eq!(evaluator.digit_stack().pop().unwrap(), 2.0);
+    }
+
+    #[test]
+    fn test_subtract_node() {
         let mut base = Box::new(Node::new_base());
         let mut subtract = Node::new_subtract();
 
@@ -723,7 +2587,7 @@ mod general_tests {
         base.add_child(Box::new(subtract));
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.digit_stack().pop().unwrap(), 0.0);
     }
@@ -741,7 +2605,7 @@ mod general_tests {
         base.add_child(Box::new(multiply));
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.digit_stack().pop().unwrap(), 4.0);
     }
@@ -759,7 +2623,7 @@ mod general_tests {
         base.add_child(Box::new(divide));
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.digit_stack().pop().unwrap(), 2.0);
     }
@@ -778,7 +2642,7 @@ mod general_tests {
         base.add_child(assign);
 
         let evaluator = ExprEvaluator::new().with_variables(1);
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
         assert_eq!(evaluator.variables().pop().unwrap(), Value::Number(1.0));
     }
 
@@ -803,7 +2667,7 @@ mod general_tests {
         ]));
 
         let evaluator = ExprEvaluator::new().with_variables(3);
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.variables().get(0).unwrap(), &Value::Bool(true));
         assert_eq!(evaluator.variables().get(1).unwrap(), &Value::Bool(false));
@@ -824,7 +2688,7 @@ mod general_tests {
         base.add_child(add);
 
         let evaluator = ExprEvaluator::new().with_variables(1);
-        assert!(evaluator.const_visit(base).is_err());
+        assert!(evaluator.const_visit(&base).is_err());
     }
 
     #[test]
@@ -858,7 +2722,7 @@ mod general_tests {
         base.add_child(assign_z);
 
         let evaluator = ExprEvaluator::new().with_variables(3);
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
         assert_eq!(evaluator.variables().pop().unwrap(), Value::Number(3.0));
     }
 
@@ -876,7 +2740,7 @@ mod general_tests {
         base.add_child(equal);
 
         let evaluator = ExprEvaluator::new().with_variables(1);
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
         assert_eq!(evaluator.boolean_stack().pop().unwrap(), true);
     }
 
@@ -894,7 +2758,7 @@ mod general_tests {
         base.add_child(and);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
         assert_eq!(evaluator.boolean_stack().pop().unwrap(), true);
     }
 
@@ -912,7 +2776,7 @@ mod general_tests {
         base.add_child(and);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.boolean_stack().pop().unwrap(), true);
     }
@@ -931,7 +2795,7 @@ mod general_tests {
         base.add_child(and);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
         assert_eq!(evaluator.boolean_stack().pop().unwrap(), true);
     }
 
@@ -949,7 +2813,7 @@ mod general_tests {
         base.add_child(and);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
         assert_eq!(evaluator.boolean_stack().pop().unwrap(), true);
     }
 
@@ -977,7 +2841,7 @@ mod general_tests {
         base.add_child(and);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
         assert_eq!(evaluator.boolean_stack().pop().unwrap(), true);
     }
 
@@ -1005,7 +2869,7 @@ mod general_tests {
         base.add_child(or);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
         assert_eq!(evaluator.boolean_stack().pop().unwrap(), true);
     }
 
@@ -1027,7 +2891,7 @@ mod general_tests {
         base.add_child(not.clone());
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
         assert_eq!(evaluator.boolean_stack().pop().unwrap(), false);
     }
 
@@ -1064,7 +2928,7 @@ mod general_tests {
         base.add_child(if_node);
 
         let evaluator = ExprEvaluator::new().with_variables(1);
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
         assert_eq!(evaluator.variables().pop().unwrap(), Value::Number(2.0));
     }
 
@@ -1095,7 +2959,7 @@ mod general_tests {
         ]));
 
         let evaluator = ExprEvaluator::new().with_variables(3);
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.variables().get(0).unwrap(), &Value::Number(2.0));
         assert_eq!(evaluator.variables().get(1).unwrap(), &Value::Null);
@@ -1107,7 +2971,7 @@ mod general_tests {
 mod expr_evaluator_tests {
     use crate::{
         nodes::{
-            evaluator::Value,
+            evaluator::{Parameters, Value},
             indexer::EventIndexer,
             traits::{NodeConstVisitor, NodeVisitor},
         },
@@ -1132,7 +2996,7 @@ mod expr_evaluator_tests {
         indexer.visit(&nodes).unwrap();
 
         let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
-        evaluator.const_visit(nodes).unwrap();
+        evaluator.const_visit(&nodes).unwrap();
 
         assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Number(1.0));
         assert_eq!(*evaluator.variables().get(1).unwrap(), Value::Number(2.0));
@@ -1158,7 +3022,7 @@ mod expr_evaluator_tests {
         indexer.visit(&nodes).unwrap();
 
         let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
-        evaluator.const_visit(nodes).unwrap();
+        evaluator.const_visit(&nodes).unwrap();
 
         assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Number(2.0));
         assert_eq!(*evaluator.variables().get(1).unwrap(), Value::Number(2.0));
@@ -1186,7 +3050,7 @@ mod expr_evaluator_tests {
         indexer.visit(&nodes).unwrap();
 
         let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
-        evaluator.const_visit(nodes).unwrap();
+        evaluator.const_visit(&nodes).unwrap();
 
         assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Number(2.0));
         assert_eq!(*evaluator.variables().get(1).unwrap(), Value::Number(2.0));
@@ -1194,19 +3058,13 @@ mod expr_evaluator_tests {
     }
 
     #[test]
-    fn test_nested_if_else_conditions() {
+    fn test_if_else_true_branch() {
         let script = "
-            x = 2;
-            y = 2;
-            z = x + y;
+            x = 1;
             if x == 1 {
                 z = 3;
             } else {
-                if y == 1 {
-                    z = 4;
-                } else {
-                    z = 5;
-                }
+                z = 4;
             }
         "
         .to_string();
@@ -1218,19 +3076,132 @@ mod expr_evaluator_tests {
         indexer.visit(&nodes).unwrap();
 
         let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
-        evaluator.const_visit(nodes).unwrap();
+        evaluator.const_visit(&nodes).unwrap();
 
-        assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Number(2.0));
-        assert_eq!(*evaluator.variables().get(1).unwrap(), Value::Number(2.0));
-        assert_eq!(*evaluator.variables().get(2).unwrap(), Value::Number(5.0));
+        assert_eq!(*evaluator.variables().get(1).unwrap(), Value::Number(3.0));
     }
 
     #[test]
-    fn test_new_variable_in_if_condition() {
+    fn test_template_parameters() {
         let script = "
-            x = 2;
-            y = 2;
-            z = x + y;
+            param strike: number;
+            param call: bool;
+            x = max(120 - strike, 0);
+            y = call;
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let mut parameters = Parameters::new();
+        parameters.insert("strike".to_string(), Value::Number(100.0));
+        parameters.insert("call".to_string(), Value::Bool(true));
+        let evaluator = ExprEvaluator::new()
+            .with_variables(indexer.get_variables_size())
+            .with_parameters(&parameters);
+        evaluator.const_visit(&nodes).unwrap();
+        assert_eq!(*evaluator.variables().get(2).unwrap(), Value::Number(20.0));
+        assert_eq!(*evaluator.variables().get(3).unwrap(), Value::Bool(true));
+
+        parameters.insert("strike".to_string(), Value::String("100".to_string()));
+        let evaluator = ExprEvaluator::new()
+            .with_variables(indexer.get_variables_size())
+            .with_parameters(&parameters);
+        let err = evaluator.const_visit(&nodes).unwrap_err();
+        assert!(err.to_string().contains("Parameter strike expects a Number value"));
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        let err = evaluator.const_visit(&nodes).unwrap_err();
+        assert!(err.to_string().contains("Parameter strike is not bound"));
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let script = "
+            x = 0;
+            for s in [1, 2, 5, 3] {
+                if s > 4 {
+                    break;
+                }
+                x = x + s;
+            }
+            y = 0;
+            for s in [1, 2, 3] {
+                if s == 2 {
+                    continue;
+                }
+                y = y + s;
+            }
+            z = 0;
+            for a in [1, 2] {
+                for b in [1, 2, 3] {
+                    if b == 2 {
+                        break;
+                    }
+                    z = z + 1;
+                }
+            }
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        let variables = indexer.get_variable_indexes();
+        let value = |name: &str| evaluator.variables()[variables[name]].clone();
+        assert_eq!(value("x"), Value::Number(3.0));
+        assert_eq!(value("y"), Value::Number(4.0));
+        assert_eq!(value("z"), Value::Number(2.0));
+    }
+
+    #[test]
+    fn test_nested_if_else_conditions() {
+        let script = "
+            x = 2;
+            y = 2;
+            z = x + y;
+            if x == 1 {
+                z = 3;
+            } else {
+                if y == 1 {
+                    z = 4;
+                } else {
+                    z = 5;
+                }
+            }
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Number(2.0));
+        assert_eq!(*evaluator.variables().get(1).unwrap(), Value::Number(2.0));
+        assert_eq!(*evaluator.variables().get(2).unwrap(), Value::Number(5.0));
+    }
+
+    #[test]
+    fn test_new_variable_in_if_condition() {
+        let script = "
+            x = 2;
+            y = 2;
+            z = x + y;
             if x == 1 {
                 z = 3;
                 w = 4;
@@ -1238,199 +3209,1494 @@ mod expr_evaluator_tests {
         "
         .to_string();
 
-        let tokens = Lexer::new(script).tokenize().unwrap();
-        let nodes = Parser::new(tokens).parse().unwrap();
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Number(2.0));
+        assert_eq!(*evaluator.variables().get(1).unwrap(), Value::Number(2.0));
+        assert_eq!(*evaluator.variables().get(2).unwrap(), Value::Number(4.0));
+        assert_eq!(*evaluator.variables().get(3).unwrap(), Value::Null);
+
+        let script = "
+            x = 2;
+            y = 2;
+            z = x + y;
+            if x == 2 {
+                z = 3;
+                w = 4;
+            }
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Number(2.0));
+        assert_eq!(*evaluator.variables().get(1).unwrap(), Value::Number(2.0));
+        assert_eq!(*evaluator.variables().get(2).unwrap(), Value::Number(3.0));
+        assert_eq!(*evaluator.variables().get(3).unwrap(), Value::Number(4.0));
+    }
+
+    #[test]
+    fn test_nested_if_else_conditions_2() {
+        let script = "
+            x = 2;
+            y = 2;
+            z = x + y;
+            if x == 1 {
+                z = 3;
+            }
+            if y == 1 {
+                z = 4;
+            } else {
+                z = 5;
+            }
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Number(2.0));
+        assert_eq!(*evaluator.variables().get(1).unwrap(), Value::Number(2.0));
+        assert_eq!(*evaluator.variables().get(2).unwrap(), Value::Number(5.0));
+    }
+
+    #[test]
+    fn test_string_assignment() {
+        let script = "
+            x = \"Hello world\";
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        assert_eq!(
+            *evaluator.variables().get(0).unwrap(),
+            Value::String("Hello world".to_string())
+        );
+    }
+
+    #[test]
+    fn test_variable_reassignment() {
+        let script = "
+            x = 1;
+            x = \"String\";
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        assert_eq!(
+            *evaluator.variables().get(0).unwrap(),
+            Value::String("String".to_string())
+        );
+    }
+
+    #[test]
+    fn test_boolean_assignment_from_expression() {
+        let script = "
+            x = 1 < 2;
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_list_index_and_for_each() {
+        let script = "
+            xs = [1, 2, 3];
+            s = 0;
+            for x in xs {
+                s = s + x;
+            }
+            last = xs[2];
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        let s = indexer.get_variable_index("s").unwrap();
+        let last = indexer.get_variable_index("last").unwrap();
+        assert_eq!(*evaluator.variables().get(s).unwrap(), Value::Number(6.0));
+        assert_eq!(*evaluator.variables().get(last).unwrap(), Value::Number(3.0));
+    }
+
+    #[test]
+    fn test_map_and_filter() {
+        let script = "
+            xs = [0, 2, 3];
+            doubled = xs.map(x -> x * 2);
+            positive = xs.filter(x -> x > 0).map(x -> x + 1);
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        let doubled = indexer.get_variable_index("doubled").unwrap();
+        let positive = indexer.get_variable_index("positive").unwrap();
+        assert_eq!(
+            *evaluator.variables().get(doubled).unwrap(),
+            Value::Array(vec![
+                Value::Number(0.0),
+                Value::Number(4.0),
+                Value::Number(6.0)
+            ])
+        );
+        assert_eq!(
+            *evaluator.variables().get(positive).unwrap(),
+            Value::Array(vec![Value::Number(3.0), Value::Number(4.0)])
+        );
+
+        // lambda parameters and hidden arrays are not script variables
+        assert!(indexer.get_variable_index("x").is_none());
+        let mut variables = indexer.get_variables();
+        variables.sort();
+        assert_eq!(variables, vec!["doubled", "positive", "xs"]);
+    }
+
+    #[test]
+    fn test_in_is_not_reserved() {
+        let script = "
+            in = [1, 2];
+            s = 0;
+            for x in in {
+                s = s + x;
+            }
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        let s = indexer.get_variable_index("s").unwrap();
+        assert_eq!(*evaluator.variables().get(s).unwrap(), Value::Number(3.0));
+    }
+
+    #[test]
+    fn test_index_out_of_bounds() {
+        let script = "
+            xs = [1];
+            y = xs[3];
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        assert!(evaluator.const_visit(&nodes).is_err());
+    }
+
+    #[test]
+    fn test_linear_algebra() {
+        let script = "
+            a = [[2, 1], [1, 3]];
+            x = solve(a, [3, 5]);
+            b = matmul(a, x);
+            t = transpose([[1, 2], [3, 4]]);
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        let x = indexer.get_variable_index("x").unwrap();
+        let b = indexer.get_variable_index("b").unwrap();
+        let t = indexer.get_variable_index("t").unwrap();
+        let variables = evaluator.variables();
+        match variables.get(x).unwrap() {
+            Value::Array(items) => {
+                assert_eq!(items.len(), 2);
+                match (&items[0], &items[1]) {
+                    (Value::Number(x0), Value::Number(x1)) => {
+                        assert!((x0 - 0.8).abs() < 1e-12);
+                        assert!((x1 - 1.4).abs() < 1e-12);
+                    }
+                    _ => panic!("Expected numbers"),
+                }
+            }
+            _ => panic!("Expected an array"),
+        }
+        match variables.get(b).unwrap() {
+            Value::Array(items) => match (&items[0], &items[1]) {
+                (Value::Number(b0), Value::Number(b1)) => {
+                    assert!((b0 - 3.0).abs() < 1e-12);
+                    assert!((b1 - 5.0).abs() < 1e-12);
+                }
+                _ => panic!("Expected numbers"),
+            },
+            _ => panic!("Expected an array"),
+        }
+        assert_eq!(
+            *variables.get(t).unwrap(),
+            Value::Array(vec![
+                Value::Array(vec![Value::Number(1.0), Value::Number(3.0)]),
+                Value::Array(vec![Value::Number(2.0), Value::Number(4.0)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_schedule() {
+        let script = "
+            dates = schedule(\"2024-01-15\", \"2024-07-15\", \"3M\", \"ModifiedFollowing\", \"TARGET\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        let dates = indexer.get_variable_index("dates").unwrap();
+        assert_eq!(
+            *evaluator.variables().get(dates).unwrap(),
+            Value::Array(vec![
+                Value::String("2024-01-15".to_string()),
+                Value::String("2024-04-15".to_string()),
+                Value::String("2024-07-15".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_adjust_and_is_business_day() {
+        let script = "
+            d = adjust(\"2024-03-30\", \"Following\", \"TARGET\");
+            b = is_business_day(d, \"TARGET\");
+            h = is_business_day(\"2024-07-06\", \"NYSE\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        let variables = evaluator.variables();
+        let d = indexer.get_variable_index("d").unwrap();
+        let b = indexer.get_variable_index("b").unwrap();
+        let h = indexer.get_variable_index("h").unwrap();
+        // Easter weekend 2024, the following TARGET business day is Tuesday
+        assert_eq!(
+            *variables.get(d).unwrap(),
+            Value::String("2024-04-02".to_string())
+        );
+        assert_eq!(*variables.get(b).unwrap(), Value::Bool(true));
+        assert_eq!(*variables.get(h).unwrap(), Value::Bool(false));
+    }
+
+    #[test]
+    fn test_add_tenor() {
+        let script = "
+            d = add_tenor(\"2024-01-31\", \"1M\");
+            e = add_tenor(\"2024-03-28\", \"2D\", \"TARGET\");
+            f = add_tenor(\"2024-01-15\", \"1Y\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        let variables = evaluator.variables();
+        let d = indexer.get_variable_index("d").unwrap();
+        let e = indexer.get_variable_index("e").unwrap();
+        let f = indexer.get_variable_index("f").unwrap();
+        assert_eq!(
+            *variables.get(d).unwrap(),
+            Value::String("2024-02-29".to_string())
+        );
+        // two TARGET business days after Maundy Thursday skip the Easter holidays
+        assert_eq!(
+            *variables.get(e).unwrap(),
+            Value::String("2024-04-03".to_string())
+        );
+        assert_eq!(
+            *variables.get(f).unwrap(),
+            Value::String("2025-01-15".to_string())
+        );
+    }
+
+    #[test]
+    fn test_evaluation_error_location() {
+        let nodes = crate::nodes::node::ExprTree::try_from("x = 1;\n\n  y = z + x;").unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        let err = evaluator.const_visit(&nodes).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 3, col 3: Error while evaluating: Variable z not initialized"
+        );
+    }
+
+    #[test]
+    fn test_solve_singular() {
+        let script = "x = solve([[1, 2], [2, 4]], [1, 2]);".to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        assert!(evaluator.const_visit(&nodes).is_err());
+    }
+}
+
+#[cfg(test)]
+mod event_stream_evaluator_tests {
+    use super::*;
+
+    #[test]
+    fn test_event_stream_evaluator() {
+        let event = "
+            x = 1;
+            y = 2;
+            z = x + y;
+        "
+        .to_string();
+        let event_date = Date::new(2021, 1, 1);
+        let expr = event.try_into().unwrap();
+        let event = Event::new(event_date, expr);
+        let events = EventStream::new().with_events(vec![event]);
+        // Index expressions and initialize evaluator (adjust according to your actual logic)
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+
+        let scenarios = vec![Scenario::new()];
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let results = evaluator.visit_events(&events, &var_map).unwrap();
+
+        assert_eq!(results.get("x"), Some(&Value::Number(1.0)));
+        assert_eq!(results.get("y"), Some(&Value::Number(2.0)));
+        assert_eq!(results.get("z"), Some(&Value::Number(3.0)));
+    }
+
+    #[test]
+    fn test_event_stream_globals() {
+        let events = EventStream::new().with_events(vec![
+            Event::new(
+                Date::new(2021, 1, 1),
+                "coupon = notional * rate;".try_into().unwrap(),
+            ),
+            Event::new(
+                Date::new(2022, 1, 1),
+                "redemption = notional + coupon;".try_into().unwrap(),
+            ),
+        ]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+
+        let globals = HashMap::from([
+            ("notional".to_string(), Value::Number(100.0)),
+            ("rate".to_string(), Value::Number(0.05)),
+        ]);
+        let scenarios = vec![Scenario::new(); 2];
+        let evaluator = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .with_globals(&globals);
+        let results = evaluator.visit_events(&events, &var_map).unwrap();
+
+        assert_eq!(results.get("coupon"), Some(&Value::Number(5.0)));
+        assert_eq!(results.get("redemption"), Some(&Value::Number(105.0)));
+
+        let events = EventStream::new().with_events(vec![Event::new(
+            Date::new(2021, 1, 1),
+            "rate = 0.01;".try_into().unwrap(),
+        )]);
+        indexer.visit_events(&events).unwrap();
+        let err = evaluator.visit_events(&events, &var_map).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1, col 1: Error while evaluating: Cannot assign to global constant rate"
+        );
+    }
+
+    #[test]
+    fn test_evaluation_limits() {
+        let nested_loops = "
+            x = 0;
+            for a in [1, 2, 3] {
+                for b in [1, 2, 3] {
+                    x = x + 1;
+                }
+            }
+        ";
+        let events = EventStream::new().with_events(vec![Event::new(
+            Date::new(2021, 1, 1),
+            nested_loops.try_into().unwrap(),
+        )]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+        let scenarios = vec![Scenario::new()];
+
+        let evaluate = |limits: EvaluationLimits| {
+            EventStreamEvaluator::new(indexer.get_variables_size())
+                .with_scenarios(&scenarios)
+                .with_limits(limits)
+                .visit_events(&events, &var_map)
+        };
+
+        let limits = EvaluationLimits::new().with_max_loop_iterations(12);
+        assert_eq!(
+            evaluate(limits).unwrap().get("x"),
+            Some(&Value::Number(9.0))
+        );
+
+        let limits = EvaluationLimits::new().with_max_loop_iterations(11);
+        match evaluate(limits).unwrap_err() {
+            ScriptingError::Located(_, err) => assert!(matches!(
+                *err,
+                ScriptingError::LimitExceeded(_)
+            )),
+            err => panic!("Unexpected error {}", err),
+        }
+
+        let limits = EvaluationLimits::new().with_max_depth(2);
+        assert_eq!(
+            evaluate(limits).unwrap().get("x"),
+            Some(&Value::Number(9.0))
+        );
+
+        let limits = EvaluationLimits::new().with_max_depth(1);
+        assert!(evaluate(limits)
+            .unwrap_err()
+            .to_string()
+            .contains("Limit exceeded: Maximum nesting depth of 1 reached"));
+    }
+
+    #[test]
+    fn test_deep_expression() {
+        let evaluate = |script: String| {
+            let nodes = ExprTree::try_from(script).unwrap();
+            let indexer = EventIndexer::new();
+            indexer.visit(&nodes).unwrap();
+            let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+            evaluator.const_visit(&nodes).map(|_| evaluator.variables())
+        };
+
+        // long expressions are not nested statements
+        let sum = evaluate(format!("x = 1{};", " + 1".repeat(200))).unwrap();
+        assert_eq!(sum, vec![Value::Number(201.0)]);
+
+        let nested_ifs = format!(
+            "x = 0; {} x = 1; {}",
+            "if x < 1 { ".repeat(40),
+            "}".repeat(40)
+        );
+        let err = evaluate(nested_ifs).unwrap_err();
+        assert!(err.to_string().contains("Maximum nesting depth of 32 reached"));
+    }
+
+    #[test]
+    fn test_event_stream_evaluator_multiple_scenarios() {
+        let event = "
+            x = 1;
+            y = 2;
+            z = x + y;
+        "
+        .to_string();
+        let event_date = Date::new(2021, 1, 1);
+        let expr = event.try_into().unwrap();
+        let event = Event::new(event_date, expr);
+        let events = EventStream::new().with_events(vec![event]);
+        // Index expressions and initialize evaluator (adjust according to your actual logic)
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+
+        let var_map = indexer.get_variable_indexes();
+
+        let scenarios = vec![Scenario::new(); 10];
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let results = evaluator.visit_events(&events, &var_map).unwrap();
+
+        assert_eq!(results.get("x"), Some(&Value::Number(1.0)));
+        assert_eq!(results.get("y"), Some(&Value::Number(2.0)));
+        assert_eq!(results.get("z"), Some(&Value::Number(3.0)));
+    }
+
+    #[test]
+    fn test_event_stream_evaluator_cashflow_report() {
+        let event = "
+            swap = 0;
+            swap pays 100 in USD leg \"fixed\";
+            swap pays 0 - 80 in USD leg \"float\";
+            swap pays 10;
+        "
+        .to_string();
+        let event_date = Date::new(2024, 6, 3);
+        let expr = event.try_into().unwrap();
+        let events = EventStream::new().with_events(vec![Event::new(event_date, expr)]);
+
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+
+        let scenarios: Vec<Scenario> = [1.0, 2.0]
+            .iter()
+            .map(|&numerarie| {
+                (0..3)
+                    .map(|id| MarketData::new(id, event_date, None, None, None, numerarie))
+                    .collect()
+            })
+            .collect();
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let (results, report) = evaluator
+            .visit_events_with_report(&events, &var_map)
+            .unwrap();
+
+        assert_eq!(results.get("swap"), Some(&Value::Number(22.5)));
+        assert_eq!(report.len(), 2);
+        assert_eq!(
+            report.get(&("fixed".to_string(), Some(Currency::USD))),
+            Some(&75.0)
+        );
+        assert_eq!(
+            report.get(&("float".to_string(), Some(Currency::USD))),
+            Some(&-60.0)
+        );
+    }
+
+    #[test]
+    fn test_event_stream_evaluator_cashflows() {
+        let first = Date::new(2024, 6, 3);
+        let second = Date::new(2024, 12, 3);
+        let events = EventStream::new().with_events(vec![
+            Event::new(
+                second,
+                "x = 0; x pays 50 in USD leg \"fixed\";".try_into().unwrap(),
+            ),
+            Event::new(
+                first,
+                "x = 0; x pays 100 in USD leg \"fixed\"; x pays 10;"
+                    .try_into()
+                    .unwrap(),
+            ),
+        ]);
+
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+
+        let scenarios: Vec<Scenario> = [1.0, 2.0]
+            .iter()
+            .map(|&numerarie| {
+                (0..3)
+                    .map(|id| MarketData::new(id, first, None, None, None, numerarie))
+                    .collect()
+            })
+            .collect();
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let (_, cashflows) = evaluator
+            .visit_events_with_cashflows(&events, &var_map)
+            .unwrap();
+
+        assert_eq!(cashflows.len(), 3);
+        assert_eq!(cashflows[0].event_date(), first);
+        assert_eq!(cashflows[0].leg(), Some(&"fixed".to_string()));
+        assert_eq!(cashflows[0].currency(), Some(Currency::USD));
+        assert_eq!(cashflows[0].expected_amount(), 100.0);
+        assert_eq!(cashflows[0].discounted(), 75.0);
+        assert_eq!(cashflows[1].event_date(), first);
+        assert_eq!(cashflows[1].leg(), None);
+        assert_eq!(cashflows[1].discounted(), 7.5);
+        assert_eq!(cashflows[2].event_date(), second);
+        assert_eq!(cashflows[2].expected_amount(), 50.0);
+    }
+
+    #[test]
+    fn test_event_stream_evaluator_equity() {
+        let event = "
+            payoff = max(Stock(\"AAPL\") - 100, 0);
+        "
+        .to_string();
+        let event_date = Date::new(2024, 6, 3);
+        let expr = event.try_into().unwrap();
+        let events = EventStream::new().with_events(vec![Event::new(event_date, expr)]);
+
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+
+        let scenarios = vec![
+            vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(110.0))],
+            vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(90.0))],
+        ];
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let results = evaluator.visit_events(&events, &var_map).unwrap();
+
+        assert_eq!(results.get("payoff"), Some(&Value::Number(5.0)));
+    }
+
+    #[test]
+    fn test_assertion_policy() {
+        let event = "
+            payoff = Stock(\"AAPL\") - 100;
+            assert(payoff >= 0, \"out of the money\");
+        "
+        .to_string();
+        let event_date = Date::new(2024, 6, 3);
+        let expr = event.try_into().unwrap();
+        let events = EventStream::new().with_events(vec![Event::new(event_date, expr)]);
+
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+
+        let scenarios = vec![
+            vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(110.0))],
+            vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(90.0))],
+        ];
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let err = evaluator.visit_events(&events, &var_map).unwrap_err();
+        assert!(err.is_assertion_failure());
+        assert!(err.to_string().contains("Assertion failed: out of the money"));
+
+        let results = evaluator
+            .with_assertion_policy(AssertionPolicy::SkipScenario)
+            .visit_events(&events, &var_map)
+            .unwrap();
+        assert_eq!(results.get("payoff"), Some(&Value::Number(10.0)));
+    }
+
+    #[test]
+    fn test_visit_events_per_scenario() {
+        let event = "
+            payoff = max(Stock(\"AAPL\") - 100, 0);
+        "
+        .to_string();
+        let event_date = Date::new(2024, 6, 3);
+        let expr = event.try_into().unwrap();
+        let events = EventStream::new().with_events(vec![Event::new(event_date, expr)]);
+
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+
+        let scenarios = vec![
+            vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(110.0))],
+            vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(90.0))],
+        ];
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let results = evaluator.visit_events_per_scenario(&events, &var_map).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].get("payoff"), Some(&Value::Number(10.0)));
+        assert_eq!(results[1].get("payoff"), Some(&Value::Number(0.0)));
+    }
+
+    #[test]
+    fn test_visit_events_with_statistics() {
+        let event = "
+            payoff = Stock(\"AAPL\") - 100;
+            label = \"call\";
+        "
+        .to_string();
+        let event_date = Date::new(2024, 6, 3);
+        let expr = event.try_into().unwrap();
+        let events = EventStream::new().with_events(vec![Event::new(event_date, expr)]);
+
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+
+        let scenarios: Vec<Scenario> = [110.0, 90.0, 130.0]
+            .iter()
+            .map(|spot| {
+                vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(*spot))]
+            })
+            .collect();
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let results = evaluator.visit_events_with_statistics(&events, &var_map).unwrap();
+
+        // payoffs 10, -10 and 30: sample variance 400
+        let payoff = results.get("payoff").unwrap();
+        assert_eq!(payoff.n(), 3);
+        assert!((payoff.mean() - 10.0).abs() < 1e-12);
+        assert!((payoff.stderr() - (400.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        assert!(results.get("label").is_none());
+    }
+
+    #[test]
+    fn test_visit_events_streaming() {
+        let event = "
+            payoff = Stock(\"AAPL\") - 100;
+        "
+        .to_string();
+        let event_date = Date::new(2024, 6, 3);
+        let expr = event.try_into().unwrap();
+        let events = EventStream::new().with_events(vec![Event::new(event_date, expr)]);
+
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+
+        let scenarios: Vec<Scenario> = [110.0, 90.0, 130.0, 70.0, 100.0]
+            .iter()
+            .map(|spot| {
+                vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(*spot))]
+            })
+            .collect();
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+
+        let mut partials = Vec::new();
+        let results = evaluator
+            .visit_events_streaming(&events, &var_map, 2, |partial| {
+                partials.push(*partial.get("payoff").unwrap())
+            })
+            .unwrap();
+
+        // payoffs 10, -10, 30, -30 and 0, reported after the second and fourth scenarios
+        assert_eq!(partials.len(), 2);
+        assert_eq!((partials[0].n(), partials[0].mean()), (2, 0.0));
+        assert_eq!((partials[1].n(), partials[1].mean()), (4, 0.0));
+        let payoff = results.get("payoff").unwrap();
+        assert_eq!((payoff.n(), payoff.mean()), (5, 0.0));
+    }
+
+    #[test]
+    fn test_compensated_sum() {
+        let mut naive = 1.0;
+        let mut sum = CompensatedSum::new();
+        sum.add(1.0);
+        for _ in 0..10 {
+            naive += 1e-16;
+            sum.add(1e-16);
+        }
+        assert_eq!(naive, 1.0);
+        assert!((sum.value() - (1.0 + 1e-15)).abs() < 1e-17);
+    }
+
+    #[test]
+    fn test_target_stderr() {
+        let event = "
+            payoff = Stock(\"AAPL\") - 100;
+        "
+        .to_string();
+        let event_date = Date::new(2024, 6, 3);
+        let expr = event.try_into().unwrap();
+        let events = EventStream::new().with_events(vec![Event::new(event_date, expr)]);
+
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+
+        // payoffs alternate between 10 and -10: the stderr after n paths is about 10 / sqrt(n)
+        let scenarios: Vec<Scenario> = (0..1000)
+            .map(|i| {
+                let spot = if i % 2 == 0 { 110.0 } else { 90.0 };
+                vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(spot))]
+            })
+            .collect();
+        let evaluator = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .with_target_stderr("payoff", 1.0)
+            .with_batch_size(50);
+
+        let payoff = evaluator
+            .visit_events_with_statistics(&events, &var_map)
+            .unwrap()
+            .remove("payoff")
+            .unwrap();
+        assert_eq!(payoff.n(), 150);
+        assert!(payoff.stderr() <= 1.0);
+
+        let paths = evaluator
+            .visit_events_per_scenario(&events, &var_map)
+            .unwrap();
+        assert_eq!(paths.len(), 150);
+
+        let (results, _) = evaluator
+            .visit_events_with_report(&events, &var_map)
+            .unwrap();
+        assert_eq!(results.get("payoff"), Some(&Value::Number(0.0)));
+
+        let err = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .with_target_stderr("missing", 1.0)
+            .visit_events(&events, &var_map)
+            .unwrap_err();
+        assert!(err.to_string().contains("Variable missing not found"));
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let event = "x = 1;".to_string();
+        let expr = event.try_into().unwrap();
+        let events =
+            EventStream::new().with_events(vec![Event::new(Date::new(2024, 6, 3), expr)]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+        let scenarios: Vec<Scenario> = vec![Scenario::new(); 3];
+        let evaluate = |token: CancellationToken| {
+            EventStreamEvaluator::new(indexer.get_variables_size())
+                .with_scenarios(&scenarios)
+                .with_cancellation(token)
+                .visit_events_per_scenario(&events, &var_map)
+        };
+
+        assert_eq!(evaluate(CancellationToken::new()).unwrap().len(), 3);
+
+        let token = CancellationToken::new();
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        let err = evaluate(token).unwrap_err();
+        assert!(matches!(err, ScriptingError::Cancelled(_)));
+
+        let token = CancellationToken::new().with_timeout(Duration::ZERO);
+        let err = evaluate(token).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+    }
+
+    #[test]
+    fn test_tape_budget() {
+        thread_local! {
+            static TAPE_LEN: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+        }
+        // a fake tape that grows by one node per evaluated node
+        fn tape_len() -> usize {
+            TAPE_LEN.with(|len| {
+                len.set(len.get() + 1);
+                len.get()
+            })
+        }
+
+        let event = "x = 1; y = x + 2;".to_string();
+        let expr = event.try_into().unwrap();
+        let events =
+            EventStream::new().with_events(vec![Event::new(Date::new(2024, 6, 3), expr)]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+        let scenarios: Vec<Scenario> = vec![Scenario::new(); 100];
+        let evaluate = |max_nodes: usize| {
+            TAPE_LEN.with(|len| len.set(0));
+            EventStreamEvaluator::new(indexer.get_variables_size())
+                .with_scenarios(&scenarios)
+                .with_tape_budget(TapeBudget::new(max_nodes, tape_len))
+                .visit_events(&events, &var_map)
+        };
+
+        assert!(evaluate(1_000_000).is_ok());
+        let err = evaluate(500).unwrap_err();
+        assert!(matches!(err, ScriptingError::ResourceExhausted(_)));
+        assert!(err.to_string().contains("the budget is 500"));
+    }
+
+    #[test]
+    fn test_visit_events_with_profile() {
+        let event_date = Date::new(2024, 6, 3);
+        let events = EventStream::new().with_events(vec![
+            Event::new(
+                Date::new(2024, 1, 2),
+                "exposure = 0;".try_into().unwrap(),
+            ),
+            Event::new(
+                event_date,
+                "exposure = Stock(\"AAPL\") - 100; late = 1;"
+                    .try_into()
+                    .unwrap(),
+            ),
+        ]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+        let scenarios: Vec<Scenario> = [110.0, 130.0]
+            .iter()
+            .map(|spot| {
+                vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(*spot))]
+            })
+            .collect();
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+
+        let profile = evaluator
+            .visit_events_with_profile(
+                &events,
+                &var_map,
+                &["exposure".to_string(), "late".to_string()],
+            )
+            .unwrap();
+        assert_eq!(profile.len(), 2);
+        assert_eq!(profile[0].0, Date::new(2024, 1, 2));
+        assert_eq!(profile[0].1.get("exposure"), Some(&0.0));
+        assert_eq!(profile[0].1.get("late"), None);
+        assert_eq!(profile[1].0, event_date);
+        assert_eq!(profile[1].1.get("exposure"), Some(&20.0));
+        assert_eq!(profile[1].1.get("late"), Some(&1.0));
+
+        let err = evaluator
+            .visit_events_with_profile(&events, &var_map, &["missing".to_string()])
+            .unwrap_err();
+        assert!(err.to_string().contains("Variable missing not found"));
+    }
+
+    #[test]
+    fn test_visit_events_with_profile_event_date() {
+        // a price observed before the reference date is the fixing on the event date
+        let fixing_date = Date::new(2024, 1, 2);
+        let events = EventStream::new().with_events(vec![Event::new(
+            fixing_date,
+            "spot = Stock(\"AAPL\");".try_into().unwrap(),
+        )]);
+        let indexer = EventIndexer::new().with_reference_date(Date::new(2024, 1, 15));
+        indexer.visit_events(&events).unwrap();
+        let historical_data = HistoricalData::new(Date::new(2024, 1, 15))
+            .with_fixings("AAPL", HashMap::from([(fixing_date, 100.0)]));
+        let scenarios: Vec<Scenario> = vec![vec![]];
+
+        let profile = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .with_historical_data(&historical_data)
+            .visit_events_with_profile(
+                &events,
+                &indexer.get_variable_indexes(),
+                &["spot".to_string()],
+            )
+            .unwrap();
+        assert_eq!(profile[0].1.get("spot"), Some(&100.0));
+    }
 
+    #[test]
+    fn test_visit_events_until() {
+        let events = EventStream::new().with_events(vec![
+            Event::new(
+                Date::new(2024, 1, 2),
+                "accrued = 1; paid = 0;".try_into().unwrap(),
+            ),
+            Event::new(
+                Date::new(2024, 7, 1),
+                "accrued = accrued + 1;".try_into().unwrap(),
+            ),
+            Event::new(
+                Date::new(2025, 1, 2),
+                "paid = accrued; accrued = 0;".try_into().unwrap(),
+            ),
+        ]);
         let indexer = EventIndexer::new();
-        indexer.visit(&nodes).unwrap();
-
-        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
-        evaluator.const_visit(nodes).unwrap();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+        let scenarios: Vec<Scenario> = vec![Scenario::new(); 2];
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
 
-        assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Number(2.0));
-        assert_eq!(*evaluator.variables().get(1).unwrap(), Value::Number(2.0));
-        assert_eq!(*evaluator.variables().get(2).unwrap(), Value::Number(4.0));
-        assert_eq!(*evaluator.variables().get(3).unwrap(), Value::Null);
+        let state = evaluator
+            .visit_events_until(&events, &var_map, Date::new(2024, 7, 1))
+            .unwrap();
+        assert_eq!(state.get("accrued"), Some(&Value::Number(2.0)));
+        assert_eq!(state.get("paid"), Some(&Value::Number(0.0)));
 
-        let script = "
-            x = 2;
-            y = 2;
-            z = x + y;
-            if x == 2 {
-                z = 3;
-                w = 4;
-            }
-        "
-        .to_string();
+        let state = evaluator
+            .visit_events_until(&events, &var_map, Date::new(2023, 12, 31))
+            .unwrap();
+        assert_eq!(state.get("accrued"), Some(&Value::Null));
 
-        let tokens = Lexer::new(script).tokenize().unwrap();
-        let nodes = Parser::new(tokens).parse().unwrap();
+        let state = evaluator.visit_events(&events, &var_map).unwrap();
+        assert_eq!(state.get("paid"), Some(&Value::Number(2.0)));
+    }
 
+    #[test]
+    fn test_non_finite_policy() {
+        let event_date = Date::new(2024, 6, 3);
+        let event = "x = 1 / Stock(\"AAPL\");".to_string();
+        let events = EventStream::new()
+            .with_events(vec![Event::new(event_date, event.try_into().unwrap())]);
         let indexer = EventIndexer::new();
-        indexer.visit(&nodes).unwrap();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+        let scenarios: Vec<Scenario> = [0.0, 2.0, 4.0]
+            .iter()
+            .map(|spot| {
+                vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(*spot))]
+            })
+            .collect();
+        let evaluate = |policy: NonFinitePolicy| {
+            EventStreamEvaluator::new(indexer.get_variables_size())
+                .with_scenarios(&scenarios)
+                .with_non_finite_policy(policy)
+                .visit_events_with_diagnostics(&events, &var_map)
+        };
 
-        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
-        evaluator.const_visit(nodes).unwrap();
+        let (results, diagnostics) = evaluate(NonFinitePolicy::Propagate).unwrap();
+        assert_eq!(results.get("x"), Some(&Value::Number(f64::INFINITY)));
+        assert_eq!(diagnostics.non_finite(), 1);
 
-        assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Number(2.0));
-        assert_eq!(*evaluator.variables().get(1).unwrap(), Value::Number(2.0));
-        assert_eq!(*evaluator.variables().get(2).unwrap(), Value::Number(3.0));
-        assert_eq!(*evaluator.variables().get(3).unwrap(), Value::Number(4.0));
+        let err = evaluate(NonFinitePolicy::Error).unwrap_err();
+        assert!(err.is_non_finite());
+
+        let (results, diagnostics) = evaluate(NonFinitePolicy::SkipScenario).unwrap();
+        assert_eq!(results.get("x"), Some(&Value::Number(0.375)));
+        assert_eq!(
+            (
+                diagnostics.evaluated(),
+                diagnostics.skipped(),
+                diagnostics.non_finite()
+            ),
+            (2, 1, 1)
+        );
+
+        let (results, _) = evaluate(NonFinitePolicy::Clamp).unwrap();
+        assert_eq!(results.get("x"), Some(&Value::Number(f64::MAX / 3.0)));
     }
 
     #[test]
-    fn test_nested_if_else_conditions_2() {
-        let script = "
-            x = 2;
-            y = 2;
-            z = x + y;
-            if x == 1 {
-                z = 3;
-            }
-            if y == 1 {
-                z = 4;
-            } else {
-                z = 5;
-            }
+    fn test_visit_events_conditional() {
+        let event_date = Date::new(2024, 6, 3);
+        let event = "
+            s = Stock(\"AAPL\");
+            knocked_in = s < 80;
+            payoff = max(100 - s, 0);
         "
         .to_string();
+        let events = EventStream::new()
+            .with_events(vec![Event::new(event_date, event.try_into().unwrap())]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+        let scenarios: Vec<Scenario> = [70.0, 90.0, 60.0, 120.0]
+            .iter()
+            .map(|spot| {
+                vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(*spot))]
+            })
+            .collect();
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
 
-        let tokens = Lexer::new(script).tokenize().unwrap();
-        let nodes = Parser::new(tokens).parse().unwrap();
+        let (results, probability) = evaluator
+            .visit_events_conditional(&events, &var_map, "knocked_in")
+            .unwrap();
+        assert_eq!(probability, 0.5);
+        assert_eq!(results.get("payoff"), Some(&Value::Number(35.0)));
+        assert_eq!(results.get("knocked_in"), Some(&Value::Bool(true)));
+
+        let err = evaluator
+            .visit_events_conditional(&events, &var_map, "payoff")
+            .unwrap_err();
+        assert!(err.to_string().contains("Condition payoff is not a boolean"));
+    }
 
+    #[test]
+    fn test_visit_events_with_likelihood_ratio() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+        use rustatlas::models::blackscholes::{bs_delta, lr_delta_weight};
+
+        let event_date = Date::new(2024, 6, 3);
+        let event = "payoff = max(Stock(\"AAPL\") - 100, 0);".to_string();
+        let events = EventStream::new()
+            .with_events(vec![Event::new(event_date, event.try_into().unwrap())]);
         let indexer = EventIndexer::new();
-        indexer.visit(&nodes).unwrap();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
 
-        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
-        evaluator.const_visit(nodes).unwrap();
+        // terminal spots of a driftless GBM, standard normals by Box-Muller
+        let (s0, vol, t) = (100.0, 0.2, 1.0);
+        let mut rng = StdRng::seed_from_u64(42);
+        let spots: Vec<f64> = (0..20_000)
+            .map(|_| {
+                let (u1, u2): (f64, f64) = (rng.gen(), rng.gen());
+                let z = (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                s0 * ((-0.5 * vol * vol) * t + vol * f64::sqrt(t) * z).exp()
+            })
+            .collect();
+        let scenarios: Vec<Scenario> = spots
+            .iter()
+            .map(|spot| {
+                vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(*spot))]
+            })
+            .collect();
+        let weights = HashMap::from([(
+            "delta".to_string(),
+            spots
+                .iter()
+                .map(|spot| lr_delta_weight(*spot, s0, 0.0, vol, t))
+                .collect::<Vec<f64>>(),
+        )]);
 
-        assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Number(2.0));
-        assert_eq!(*evaluator.variables().get(1).unwrap(), Value::Number(2.0));
-        assert_eq!(*evaluator.variables().get(2).unwrap(), Value::Number(5.0));
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let greeks = evaluator
+            .visit_events_with_likelihood_ratio(&events, &var_map, "payoff", &weights)
+            .unwrap();
+        let delta = greeks.get("delta").unwrap();
+        assert_eq!(delta.n(), 20_000);
+        assert!((delta.mean() - bs_delta(s0, 100.0, 0.0, vol, t)).abs() < 4.0 * delta.stderr());
+
+        let short = HashMap::from([("delta".to_string(), vec![1.0])]);
+        let err = evaluator
+            .visit_events_with_likelihood_ratio(&events, &var_map, "payoff", &short)
+            .unwrap_err();
+        assert!(err.to_string().contains("Expected 20000 weights for delta"));
     }
 
     #[test]
-    fn test_string_assignment() {
-        let script = "
-            x = \"Hello world\";
+    fn test_visit_events_with_quantiles() {
+        let event = "
+            pnl = Stock(\"AAPL\") - 100;
         "
         .to_string();
-
-        let tokens = Lexer::new(script).tokenize().unwrap();
-        let nodes = Parser::new(tokens).parse().unwrap();
+        let event_date = Date::new(2024, 6, 3);
+        let expr = event.try_into().unwrap();
+        let events = EventStream::new().with_events(vec![Event::new(event_date, expr)]);
 
         let indexer = EventIndexer::new();
-        indexer.visit(&nodes).unwrap();
-
-        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
-        evaluator.const_visit(nodes).unwrap();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
 
-        assert_eq!(
-            *evaluator.variables().get(0).unwrap(),
-            Value::String("Hello world".to_string())
-        );
+        // pnl of -40, -30, ..., 50
+        let scenarios: Vec<Scenario> = (0..10)
+            .map(|i| {
+                let spot = 60.0 + 10.0 * i as f64;
+                vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(spot))]
+            })
+            .collect();
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let variables = vec!["pnl".to_string()];
+        let results = evaluator
+            .visit_events_with_quantiles(&events, &var_map, &variables, &[0.0, 0.05, 0.5, 1.0])
+            .unwrap();
+
+        let pnl = results.get("pnl").unwrap();
+        assert!((pnl[0] + 40.0).abs() < 1e-12);
+        assert!((pnl[1] + 35.5).abs() < 1e-12);
+        assert!((pnl[2] - 5.0).abs() < 1e-12);
+        assert!((pnl[3] - 50.0).abs() < 1e-12);
+
+        let err = evaluator
+            .visit_events_with_quantiles(&events, &var_map, &variables, &[1.5])
+            .unwrap_err();
+        assert!(err.to_string().contains("outside [0, 1]"));
     }
 
     #[test]
-    fn test_variable_reassignment() {
+    fn test_assertion_in_branch() {
         let script = "
             x = 1;
-            x = \"String\";
+            if x > 0 {
+                assert(x > 2, \"mandatory branch\");
+            }
+        ";
+        let events = EventStream::new().with_events(vec![Event::new(
+            Date::new(2021, 1, 1),
+            script.try_into().unwrap(),
+        )]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+        let scenarios: Vec<Scenario> = vec![Scenario::new(); 2];
+
+        let evaluator = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .with_assertion_policy(AssertionPolicy::SkipScenario);
+        let err = evaluator.visit_events(&events, &var_map).unwrap_err();
+        assert!(err.to_string().contains("Every scenario failed an assertion"));
+    }
+
+    #[test]
+    fn test_event_stream_evaluator_vol() {
+        let event = "
+            leverage = 0.1 / Vol(\"AAPL\");
         "
         .to_string();
-
-        let tokens = Lexer::new(script).tokenize().unwrap();
-        let nodes = Parser::new(tokens).parse().unwrap();
+        let event_date = Date::new(2024, 6, 3);
+        let expr = event.try_into().unwrap();
+        let events = EventStream::new().with_events(vec![Event::new(event_date, expr)]);
 
         let indexer = EventIndexer::new();
-        indexer.visit(&nodes).unwrap();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
 
-        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
-        evaluator.const_visit(nodes).unwrap();
+        let scenarios = vec![vec![
+            MarketData::new(0, event_date, None, None, None, 1.0).with_vol(Some(0.2)),
+        ]];
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let results = evaluator.visit_events(&events, &var_map).unwrap();
 
-        assert_eq!(
-            *evaluator.variables().get(0).unwrap(),
-            Value::String("String".to_string())
-        );
+        assert_eq!(results.get("leverage"), Some(&Value::Number(0.5)));
     }
 
     #[test]
-    fn test_boolean_assignment_from_expression() {
-        let script = "
-            x = 1 < 2;
+    fn test_event_stream_evaluator_corr() {
+        let event = "
+            payoff = 100 - Corr(\"AAPL\", \"MSFT\") * 100;
         "
         .to_string();
-
-        let tokens = Lexer::new(script).tokenize().unwrap();
-        let nodes = Parser::new(tokens).parse().unwrap();
+        let event_date = Date::new(2024, 6, 3);
+        let expr = event.try_into().unwrap();
+        let events = EventStream::new().with_events(vec![Event::new(event_date, expr)]);
 
         let indexer = EventIndexer::new();
-        indexer.visit(&nodes).unwrap();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
 
-        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
-        evaluator.const_visit(nodes).unwrap();
+        let scenarios = vec![vec![
+            MarketData::new(0, event_date, None, None, None, 1.0).with_corr(Some(0.75)),
+        ]];
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let results = evaluator.visit_events(&events, &var_map).unwrap();
 
-        assert_eq!(*evaluator.variables().get(0).unwrap(), Value::Bool(true));
+        assert_eq!(results.get("payoff"), Some(&Value::Number(25.0)));
     }
-}
-
-#[cfg(test)]
-mod event_stream_evaluator_tests {
-    use super::*;
 
     #[test]
-    fn test_event_stream_evaluator() {
+    fn test_event_stream_evaluator_barrier_hit() {
         let event = "
-            x = 1;
-            y = 2;
-            z = x + y;
+            p = barrier_hit(Stock(\"AAPL\"), 120, \"2024-01-01\", \"2024-12-26\", \"up-out\");
         "
         .to_string();
-        let event_date = Date::new(2021, 1, 1);
+        let event_date = Date::new(2024, 12, 26);
         let expr = event.try_into().unwrap();
-        let event = Event::new(event_date, expr);
-        let events = EventStream::new().with_events(vec![event]);
-        // Index expressions and initialize evaluator (adjust according to your actual logic)
+        let events = EventStream::new().with_events(vec![Event::new(event_date, expr)]);
+
         let indexer = EventIndexer::new();
         indexer.visit_events(&events).unwrap();
+        assert_eq!(indexer.get_market_requests().len(), 3);
         let var_map = indexer.get_variable_indexes();
 
-        let scenarios = vec![Scenario::new()];
+        let observations = |s1: f64, s2: f64| {
+            vec![
+                MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(s1)),
+                MarketData::new(1, event_date, None, None, None, 1.0).with_equity(Some(s2)),
+                MarketData::new(2, event_date, None, None, None, 1.0).with_vol(Some(0.2)),
+            ]
+        };
+        // 360 days apart, so the bridge variance is exactly 0.04
+        let scenarios = vec![observations(100.0, 110.0), observations(100.0, 125.0)];
         let evaluator =
             EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
         let results = evaluator.visit_events(&events, &var_map).unwrap();
 
-        assert_eq!(results.get("x"), Some(&Value::Number(1.0)));
-        assert_eq!(results.get("y"), Some(&Value::Number(2.0)));
-        assert_eq!(results.get("z"), Some(&Value::Number(3.0)));
+        // the second path crossed the barrier and is knocked out
+        let bridge = (-2.0 * (1.2_f64).ln() * (120.0_f64 / 110.0).ln() / 0.04).exp();
+        match results.get("p") {
+            Some(Value::Number(p)) => assert!((p - (1.0 - bridge) / 2.0).abs() < 1e-12),
+            _ => panic!("Expected a number"),
+        }
     }
 
     #[test]
-    fn test_event_stream_evaluator_multiple_scenarios() {
+    fn test_barrier_hit_bridges_event_dates() {
+        let (start, middle, end) = (
+            Date::new(2024, 1, 1),
+            Date::new(2024, 6, 28),
+            Date::new(2024, 12, 26),
+        );
+        let events = EventStream::new().with_events(vec![
+            Event::new(middle, "x = 1;".to_string().try_into().unwrap()),
+            Event::new(
+                end,
+                "p = barrier_hit(Stock(\"AAPL\"), 120, \"2024-01-01\", \"2024-12-26\", \"up-in\");"
+                    .to_string()
+                    .try_into()
+                    .unwrap(),
+            ),
+        ]);
+
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let requests = indexer.get_market_requests();
+        let dates: Vec<Option<Date>> = requests
+            .iter()
+            .map(|r| r.equity().and_then(|e| e.reference_date()))
+            .collect();
+        assert_eq!(dates, vec![Some(start), Some(middle), Some(end), None]);
+
+        let scenarios = vec![vec![
+            MarketData::new(0, end, None, None, None, 1.0).with_equity(Some(100.0)),
+            MarketData::new(1, end, None, None, None, 1.0).with_equity(Some(110.0)),
+            MarketData::new(2, end, None, None, None, 1.0).with_equity(Some(105.0)),
+            MarketData::new(3, end, None, None, None, 1.0).with_vol(Some(0.2)),
+        ]];
+        let evaluator =
+            EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
+        let results = evaluator
+            .visit_events(&events, &indexer.get_variable_indexes())
+            .unwrap();
+
+        // the barrier is hit unless it survives both bridges
+        let bridge = |s1: f64, s2: f64, days: f64| {
+            (-2.0 * (120.0 / s1).ln() * (120.0 / s2).ln() / (0.04 * days / 360.0)).exp()
+        };
+        let survival = (1.0 - bridge(100.0, 110.0, 179.0)) * (1.0 - bridge(110.0, 105.0, 181.0));
+        match results.get("p") {
+            Some(Value::Number(p)) => assert!((p - (1.0 - survival)).abs() < 1e-12),
+            _ => panic!("Expected a number"),
+        }
+    }
+
+    #[test]
+    fn test_event_stream_evaluator_fixings() {
         let event = "
-            x = 1;
-            y = 2;
-            z = x + y;
+            past = Fixing(\"SOFR-3M\", \"2024-01-10\");
+            future = Fixing(\"SOFR-3M\", \"2024-04-10\");
         "
         .to_string();
-        let event_date = Date::new(2021, 1, 1);
+        let event_date = Date::new(2024, 4, 10);
         let expr = event.try_into().unwrap();
-        let event = Event::new(event_date, expr);
-        let events = EventStream::new().with_events(vec![event]);
-        // Index expressions and initialize evaluator (adjust according to your actual logic)
-        let indexer = EventIndexer::new();
-        indexer.visit_events(&events).unwrap();
+        let events = EventStream::new().with_events(vec![Event::new(event_date, expr)]);
 
+        let indexer = EventIndexer::new()
+            .with_reference_date(Date::new(2024, 1, 15))
+            .with_index_provider("SOFR-3M", 0);
+        indexer.visit_events(&events).unwrap();
+        assert_eq!(indexer.get_market_requests().len(), 1);
         let var_map = indexer.get_variable_indexes();
 
-        let scenarios = vec![Scenario::new(); 10];
+        let historical_data = HistoricalData::new(Date::new(2024, 1, 15))
+            .with_fixings("SOFR-3M", HashMap::from([(Date::new(2024, 1, 10), 0.053)]));
+        let scenarios = vec![
+            vec![MarketData::new(0, event_date, None, Some(0.04), None, 1.0)],
+            vec![MarketData::new(0, event_date, None, Some(0.06), None, 1.0)],
+        ];
+        let evaluator = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .with_historical_data(&historical_data);
+        let results = evaluator.visit_events(&events, &var_map).unwrap();
+
+        assert_eq!(results.get("past"), Some(&Value::Number(0.053)));
+        match results.get("future") {
+            Some(Value::Number(v)) => assert!((v - 0.05).abs() < 1e-12),
+            _ => panic!("Expected a number"),
+        }
+
         let evaluator =
             EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);
-        let results = evaluator.visit_events(&events, &var_map).unwrap();
+        assert!(evaluator.visit_events(&events, &var_map).is_err());
+    }
 
-        assert_eq!(results.get("x"), Some(&Value::Number(1.0)));
-        assert_eq!(results.get("y"), Some(&Value::Number(2.0)));
-        assert_eq!(results.get("z"), Some(&Value::Number(3.0)));
+    #[test]
+    fn test_event_stream_evaluator_historical_equity() {
+        // a seasoned trade whose initial price was observed before the reference date
+        let events = EventStream::new().with_events(vec![
+            Event::new(Date::new(2024, 1, 2), "s0 = Stock(\"AAPL\");".try_into().unwrap()),
+            Event::new(
+                Date::new(2024, 6, 3),
+                "performance = Stock(\"AAPL\") / s0;".try_into().unwrap(),
+            ),
+        ]);
+        let indexer = EventIndexer::new().with_reference_date(Date::new(2024, 1, 15));
+        indexer.visit_events(&events).unwrap();
+        assert_eq!(
+            indexer.get_fixing_requests(),
+            vec![FixingRequest::new("AAPL".to_string(), Date::new(2024, 1, 2))]
+        );
+        assert_eq!(indexer.get_market_requests().len(), 1);
+
+        let historical_data = HistoricalData::new(Date::new(2024, 1, 15))
+            .with_fixings("AAPL", HashMap::from([(Date::new(2024, 1, 2), 100.0)]));
+        let scenarios = vec![vec![MarketData::new(0, Date::new(2024, 6, 3), None, None, None, 1.0)
+            .with_equity(Some(120.0))]];
+        let results = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .with_historical_data(&historical_data)
+            .visit_events(&events, &indexer.get_variable_indexes())
+            .unwrap();
+        assert_eq!(results.get("performance"), Some(&Value::Number(1.2)));
     }
 }
 
@@ -1449,7 +4715,7 @@ mod ai_gen_tests {
         base.add_child(unary_plus);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.digit_stack().pop().unwrap(), 1.0);
     }
@@ -1466,7 +4732,7 @@ mod ai_gen_tests {
         base.add_child(unary_minus);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.digit_stack().pop().unwrap(), -1.0);
     }
@@ -1485,7 +4751,7 @@ mod ai_gen_tests {
         base.add_child(min);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.digit_stack().pop().unwrap(), 1.0);
     }
@@ -1504,7 +4770,7 @@ mod ai_gen_tests {
         base.add_child(max);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.digit_stack().pop().unwrap(), 2.0);
     }
@@ -1523,7 +4789,7 @@ mod ai_gen_tests {
         base.add_child(pow);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.digit_stack().pop().unwrap(), 8.0);
     }
@@ -1540,7 +4806,7 @@ mod ai_gen_tests {
         base.add_child(ln);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert!((evaluator.digit_stack().pop().unwrap() - 1.0).abs() < f64::EPSILON);
     }
@@ -1557,7 +4823,7 @@ mod ai_gen_tests {
         base.add_child(exp);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert!((evaluator.digit_stack().pop().unwrap() - 2.718281828459045).abs() < f64::EPSILON);
     }
@@ -1572,11 +4838,38 @@ mod ai_gen_tests {
         base.add_child(cvg);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert!((evaluator.digit_stack().pop().unwrap() - (152.0 / 360.0)).abs() < f64::EPSILON);
     }
 
+    #[test]
+    fn test_accrual_node() {
+        let script = "
+            simple = accrual(0.05, \"2020-01-01\", \"2020-07-01\", \"Actual360\");
+            compounded = accrual(0.04, \"2020-01-01\", \"2021-01-01\", \"Thirty360\", \"Compounded\", \"Quarterly\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+
+        let variables = evaluator.variables();
+        match (&variables[0], &variables[1]) {
+            (Value::Number(simple), Value::Number(compounded)) => {
+                assert!((simple - 0.05 * 182.0 / 360.0).abs() < 1e-12);
+                assert!((compounded - (1.01_f64.powi(4) - 1.0)).abs() < 1e-12);
+            }
+            _ => panic!("accrual should evaluate to numbers"),
+        }
+    }
+
     #[test]
     fn test_cvg_with_pays() {
         let mut base = Box::new(Node::new_base());
@@ -1595,7 +4888,7 @@ mod ai_gen_tests {
         indexer.visit(&base).unwrap();
 
         let evaluator = ExprEvaluator::new().with_scenario(&scenario);
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert!(
             (evaluator.digit_stack().pop().unwrap() - (152.0 / 360.0) / 2.0).abs() < f64::EPSILON
@@ -1617,11 +4910,80 @@ mod ai_gen_tests {
         indexer.visit(&base).unwrap();
 
         let evaluator = ExprEvaluator::new().with_scenario(&scenario);
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.digit_stack().pop().unwrap(), 50.0);
     }
 
+    #[test]
+    fn test_settled_pays_discount() {
+        let base: ExprTree = "x = 0; x pays 100 settle 2bd TARGET;"
+            .to_string()
+            .try_into()
+            .unwrap();
+
+        // discounted by the numerarie to the event date, then deferred to the payment date
+        let event_date = Date::new(2024, 1, 1);
+        let scenario = vec![
+            MarketData::new(0, event_date, Some(0.8), None, None, 2.0),
+            MarketData::new(1, event_date, Some(0.72), None, None, 2.0),
+        ];
+
+        let indexer = EventIndexer::new()
+            .with_event_date(event_date)
+            .with_discount_provider(0);
+        indexer.visit(&base).unwrap();
+
+        let evaluator = ExprEvaluator::new()
+            .with_variables(indexer.get_variables_size())
+            .with_scenario(&scenario);
+        evaluator.const_visit(&base).unwrap();
+
+        match evaluator.variables().as_slice() {
+            [Value::Number(x)] => assert!((x - 45.0).abs() < 1e-12),
+            _ => panic!("Expected a number"),
+        }
+    }
+
+    #[test]
+    fn test_settle_zero_days_pays_on_the_event_date() {
+        let script = "
+            a = 0; a pays 100 settle 0bd TARGET;
+            b = 0; b pays 100;
+            c = 0; c pays 100 settle 2bd TARGET;
+        ";
+        let event_date = Date::new(2024, 6, 3);
+        let events = EventStream::new()
+            .with_events(vec![Event::new(event_date, script.try_into().unwrap())]);
+        let indexer = EventIndexer::new().with_discount_provider(0);
+        indexer.visit_events(&events).unwrap();
+
+        // a flat 3% curve, with the bank account as numerarie
+        let ref_date = Date::new(2024, 1, 2);
+        let curve = |date: Date| (-0.03 * Actual360::year_fraction::<f64>(ref_date, date)).exp();
+        let scenarios = vec![indexer
+            .get_market_requests()
+            .iter()
+            .map(|request| {
+                let df = request.df().map(|df| curve(df.date()));
+                let numerarie = 1.0 / curve(event_date);
+                MarketData::new(request.id(), event_date, df, None, None, numerarie)
+            })
+            .collect()];
+        let results = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .visit_events(&events, &indexer.get_variable_indexes())
+            .unwrap();
+
+        assert_eq!(results.get("a"), results.get("b"));
+        match results.get("c") {
+            Some(Value::Number(c)) => {
+                assert!((c - 100.0 * curve(Date::new(2024, 6, 5))).abs() < 1e-12)
+            }
+            _ => panic!("Expected a number"),
+        }
+    }
+
     #[test]
     fn test_rate_index_eval() {
         let mut base = Box::new(Node::new_base());
@@ -1645,7 +5007,7 @@ mod ai_gen_tests {
         indexer.visit(&base).unwrap();
 
         let evaluator = ExprEvaluator::new().with_scenario(&scenario);
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert!((evaluator.digit_stack().pop().unwrap() - 0.05).abs() < f64::EPSILON);
     }
@@ -1664,7 +5026,7 @@ mod ai_gen_tests {
         base.add_child(not_equal);
 
         let evaluator = ExprEvaluator::new();
-        evaluator.const_visit(base).unwrap();
+        evaluator.const_visit(&base).unwrap();
 
         assert_eq!(evaluator.boolean_stack().pop().unwrap(), true);
     }
@@ -1862,6 +5224,24 @@ mod ai_gen_tests {
         assert_eq!(*evaluator.lhs_variable.lock().unwrap(), Some(node));
     }
 
+    #[test]
+    fn test_expr_evaluator_reset() {
+        // Test the ExprEvaluator to ensure reset clears variables and stacks but keeps their size.
+        let nodes = ExprTree::try_from("x = 1; y = x > 0;").unwrap();
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        evaluator.const_visit(&nodes).unwrap();
+        evaluator.digit_stack.lock().unwrap().push(2.0);
+
+        evaluator.reset();
+        assert_eq!(evaluator.variables(), vec![Value::Null, Value::Null]);
+        assert!(evaluator.digit_stack().is_empty());
+
+        evaluator.const_visit(&nodes).unwrap();
+        assert_eq!(evaluator.variables(), vec![Value::Number(1.0), Value::Bool(true)]);
+    }
+
     #[test]
     fn test_expr_evaluator_with_scenario_none() {
         // Test the ExprEvaluator to ensure it correctly handles None scenario.
@@ -1906,7 +5286,7 @@ mod autodiff_tests {
             ExprEvaluator::<Var>::new_with_type().with_variables(indexer.get_variables_size());
         evaluator.variables.lock().unwrap()[x_idx] = Value::Number(x_var);
 
-        evaluator.const_visit(nodes).unwrap();
+        evaluator.const_visit(&nodes).unwrap();
         let vars = evaluator.variables();
         let y_var = match vars.get(y_idx).unwrap() {
             Value::Number(v) => *v,
@@ -1935,7 +5315,7 @@ mod autodiff_tests {
             ExprEvaluator::<Var>::new_with_type().with_variables(indexer.get_variables_size());
         evaluator.variables.lock().unwrap()[x_idx] = Value::Number(x_var);
 
-        evaluator.const_visit(nodes).unwrap();
+        evaluator.const_visit(&nodes).unwrap();
         let vars = evaluator.variables();
         let y_var = match vars.get(y_idx).unwrap() {
             Value::Number(v) => *v,
@@ -1,7 +1,7 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
-use rustatlas::prelude::*;
+use rustatlas::{prelude::*, time::calendars::traits::IsCalendar};
 use serde::{Deserialize, Serialize};
 
 use super::{node::Node, traits::NodeVisitor};
@@ -33,7 +33,8 @@ impl CodedEvent {
 
 /// # Event
 /// An event is a combination of a reference date and an expression tree. Represents a future action that will happen at a specific date.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Event {
     event_date: Date,
     expr: ExprTree,
@@ -73,6 +74,7 @@ impl TryFrom<CodedEvent> for Event {
 
 /// # EventStream
 /// An event stream is a collection of events that will happen in the future. An event stream could represent a series of cash flows, for example.
+#[derive(Serialize, Deserialize)]
 pub struct EventStream {
     id: Option<usize>,
     events: Vec<Event>,
@@ -91,6 +93,10 @@ impl EventStream {
         self
     }
 
+    pub fn id(&self) -> Option<usize> {
+        self.id
+    }
+
     pub fn with_events(mut self, events: Vec<Event>) -> Self {
         self.events = events;
         self
@@ -123,13 +129,175 @@ impl TryFrom<Vec<CodedEvent>> for EventStream {
     }
 }
 
+/// # IndexConvention
+/// Conventions of a rate index used to build its forward rate requests: the forward curve
+/// provider, the tenor, the compounding and frequency of the quoted rate and the calendar that
+/// adjusts fixing and maturity dates. The day count is the one of the provider's index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexConvention {
+    provider_id: usize,
+    tenor: Option<Period>,
+    compounding: Compounding,
+    frequency: Frequency,
+    calendar: Option<Calendar>,
+}
+
+impl IndexConvention {
+    /// Simple annual rates on the given provider, unadjusted
+    pub fn new(provider_id: usize) -> Self {
+        IndexConvention {
+            provider_id,
+            tenor: None,
+            compounding: Compounding::Simple,
+            frequency: Frequency::Annual,
+            calendar: None,
+        }
+    }
+
+    pub fn with_tenor(mut self, tenor: Period) -> Self {
+        self.tenor = Some(tenor);
+        self
+    }
+
+    pub fn with_compounding(mut self, compounding: Compounding) -> Self {
+        self.compounding = compounding;
+        self
+    }
+
+    pub fn with_frequency(mut self, frequency: Frequency) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    pub fn with_calendar(mut self, calendar: Calendar) -> Self {
+        self.calendar = Some(calendar);
+        self
+    }
+
+    pub fn provider_id(&self) -> usize {
+        self.provider_id
+    }
+
+    pub fn tenor(&self) -> Option<Period> {
+        self.tenor
+    }
+
+    pub fn compounding(&self) -> Compounding {
+        self.compounding
+    }
+
+    pub fn frequency(&self) -> Frequency {
+        self.frequency
+    }
+
+    pub fn calendar(&self) -> Option<&Calendar> {
+        self.calendar.as_ref()
+    }
+
+    /// Fixing date moved to the next business day
+    fn fixing_date(&self, date: Date) -> Date {
+        match &self.calendar {
+            Some(calendar) => calendar.adjust(date, Some(BusinessDayConvention::Following)),
+            None => date,
+        }
+    }
+
+    /// Maturity of a rate fixed on `date`, modified following
+    fn end_date(&self, date: Date, tenor: Period) -> Date {
+        match &self.calendar {
+            Some(calendar) => calendar.advance(
+                date,
+                tenor,
+                Some(BusinessDayConvention::ModifiedFollowing),
+                false,
+            ),
+            None => date + tenor,
+        }
+    }
+}
+
+/// # Dependencies
+/// Variables and market requests a variable depends on. Requests are identified by their id in
+/// `EventIndexer::get_market_requests`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dependencies {
+    variables: BTreeSet<String>,
+    market_requests: BTreeSet<usize>,
+}
+
+impl Dependencies {
+    pub fn variables(&self) -> &BTreeSet<String> {
+        &self.variables
+    }
+
+    pub fn market_requests(&self) -> &BTreeSet<usize> {
+        &self.market_requests
+    }
+
+    fn extend(&mut self, other: &Dependencies) {
+        self.variables.extend(other.variables.iter().cloned());
+        self.market_requests.extend(other.market_requests.iter().copied());
+    }
+
+    /// Add the variables and market requests read by `node`
+    fn read(&mut self, node: &Node) {
+        let id = |index: &std::sync::OnceLock<usize>| index.get().copied();
+        match node {
+            Node::Variable(children, name, _) => {
+                children.iter().for_each(|child| self.read(child));
+                self.variables.insert(name.clone());
+            }
+            Node::Spot(_, _, index)
+            | Node::RateIndex(_, _, _, index)
+            | Node::Fixing(_, _, index)
+            | Node::Equity(_, _, index)
+            | Node::Volatility(_, _, index)
+            | Node::Correlation(_, _, index) => self.market_requests.extend(id(index)),
+            // settled payments also read the discount factor to their payment date
+            Node::Pays(children, data, index) => {
+                children.iter().for_each(|child| self.read(child));
+                if let Some(id) = id(index) {
+                    let settled = usize::from(data.settlement_days().is_some());
+                    self.market_requests.extend(id..=id + settled);
+                }
+            }
+            Node::Exercise(children, index) => {
+                children.iter().for_each(|child| self.read(child));
+                self.market_requests.extend(id(index));
+            }
+            // the monitored spots and the volatility
+            Node::BarrierHit(children, _, _, _, _, observations, index) => {
+                children.iter().for_each(|child| self.read(child));
+                if let (Some(id), Some(dates)) = (id(index), observations.get()) {
+                    self.market_requests.extend(id..=id + dates.len());
+                }
+            }
+            Node::Constant(_)
+            | Node::String(_)
+            | Node::True
+            | Node::False
+            | Node::Break
+            | Node::Continue => (),
+            _ => node.children().iter().for_each(|child| self.read(child)),
+        }
+    }
+}
+
 /// # EventIndexer
 /// The EventIndexer is a visitor that traverses the expression tree and indexes all the variables, market requests and numerarie requests.
 pub struct EventIndexer {
     variables: RefCell<HashMap<String, usize>>,
     market_requests: RefCell<Vec<MarketRequest>>,
+    fixing_requests: RefCell<Vec<FixingRequest>>,
+    event_start: RefCell<usize>,
     event_date: RefCell<Option<Date>>,
+    event_dates: RefCell<Vec<Date>>,
     local_currency: Option<Currency>,
+    reference_date: Option<Date>,
+    index_conventions: HashMap<String, IndexConvention>,
+    discount_provider: Option<usize>,
+    discount_curves: HashMap<Currency, usize>,
+    dependencies: RefCell<HashMap<String, Dependencies>>,
 }
 
 impl NodeVisitor for EventIndexer {
@@ -147,6 +315,7 @@ impl NodeVisitor for EventIndexer {
             | Node::Exp(children)
             | Node::Pow(children)
             | Node::Ln(children)
+            | Node::Accrual(children)
             | Node::UnaryPlus(children)
             | Node::UnaryMinus(children)
             | Node::Equal(children)
@@ -158,11 +327,28 @@ impl NodeVisitor for EventIndexer {
             | Node::Inferior(children)
             | Node::SuperiorOrEqual(children)
             | Node::InferiorOrEqual(children)
+            | Node::List(children)
+            | Node::Index(children)
+            | Node::Append(children)
+            | Node::MatMul(children)
+            | Node::Transpose(children)
+            | Node::Solve(children)
+            | Node::Schedule(children)
+            | Node::Adjust(children)
+            | Node::IsBusinessDay(children)
+            | Node::AddTenor(children)
+            | Node::ForEach(children)
+            | Node::Param(children, _)
+            | Node::Assert(children, _)
             | Node::If(children, _) => {
                 children.iter().try_for_each(|child| self.visit(child))?;
                 Ok(())
             }
 
+            Node::Spanned(children, span) => children
+                .iter()
+                .try_for_each(|child| self.visit(child))
+                .map_err(|err| err.with_span(*span)),
             Node::Variable(children, name, opt_idx) => {
                 children.iter().try_for_each(|child| self.visit(child))?;
                 match opt_idx.get() {
@@ -189,15 +375,15 @@ impl NodeVisitor for EventIndexer {
                 match opt_idx.get() {
                     Some(_) => {}
                     None => {
-                        let size = self.market_requests.borrow_mut().len();
                         let exchange_request = ExchangeRateRequest::new(
                             *first,
                             second.or(self.local_currency),
                             self.event_date.borrow().clone(),
                         );
-                        let request = MarketRequest::new(size, None, None, Some(exchange_request));
-                        self.market_requests.borrow_mut().push(request.clone());
-                        opt_idx.set(size).unwrap();
+                        let id = self.add_request(|id| {
+                            MarketRequest::new(id, None, None, Some(exchange_request))
+                        });
+                        opt_idx.set(id).unwrap();
                     }
                 };
                 Ok(())
@@ -206,35 +392,263 @@ impl NodeVisitor for EventIndexer {
                 match opt_idx.get() {
                     Some(_) => {}
                     None => {
-                        let size = self.market_requests.borrow_mut().len();
-                        let provider_id = name.parse::<usize>().map_err(|_| {
-                            ScriptingError::InvalidSyntax(
-                                "Invalid rate index name".to_string(),
-                            )
-                        })?;
+                        let convention = match self.index_conventions.get(name) {
+                            Some(convention) => convention.clone(),
+                            None => IndexConvention::new(name.parse::<usize>().map_err(|_| {
+                                ScriptingError::InvalidSyntax(
+                                    "Invalid rate index name".to_string(),
+                                )
+                            })?),
+                        };
                         let fwd_request = ForwardRateRequest::new(
-                            provider_id,
+                            convention.provider_id(),
                             *start,
                             *start,
                             *end,
-                            Compounding::Simple,
-                            Frequency::Annual,
+                            convention.compounding(),
+                            convention.frequency(),
                         );
-                        let request =
-                            MarketRequest::new(size, None, Some(fwd_request), None);
-                        self.market_requests.borrow_mut().push(request.clone());
-                        opt_idx.set(size).unwrap();
+                        let id = self
+                            .add_request(|id| MarketRequest::new(id, None, Some(fwd_request), None));
+                        opt_idx.set(id).unwrap();
+                    }
+                }
+                Ok(())
+            }
+            Node::Equity(name, date, opt_idx) => {
+                let date = date.or(*self.event_date.borrow());
+                match opt_idx.get() {
+                    Some(_) => {}
+                    // past prices are read from historical data and stay unindexed
+                    None if self.add_fixing_request(name, date) => {}
+                    None => {
+                        let equity_request = EquityRequest::new(name.clone(), date);
+                        let id = self.add_request(|id| {
+                            MarketRequest::new(id, None, None, None).with_equity(equity_request)
+                        });
+                        opt_idx.set(id).unwrap();
+                    }
+                };
+                Ok(())
+            }
+            Node::Volatility(name, date, opt_idx) => {
+                match opt_idx.get() {
+                    Some(_) => {}
+                    None => {
+                        let vol_request = VolatilityRequest::new(
+                            name.clone(),
+                            date.or(self.event_date.borrow().clone()),
+                        );
+                        let id = self.add_request(|id| {
+                            MarketRequest::new(id, None, None, None).with_vol(vol_request)
+                        });
+                        opt_idx.set(id).unwrap();
                     }
+                };
+                Ok(())
+            }
+            Node::BarrierHit(children, start, end, _, _, observations, opt_idx) => {
+                // only the level is visited, the underlying is observed at the barrier dates
+                self.visit(children.get(1).unwrap())?;
+                if opt_idx.get().is_some() {
+                    return Ok(());
                 }
+
+                // the hit is known at the event only if the window has ended by then
+                if let Some(event_date) = *self.event_date.borrow() {
+                    if *end > event_date {
+                        return Err(ScriptingError::InvalidSyntax(format!(
+                            "Barrier window ending on {} is not over at the event date {}",
+                            end, event_date
+                        )));
+                    }
+                }
+
+                // the window is monitored at its ends and at the event dates within it
+                let mut dates = self
+                    .event_dates
+                    .borrow()
+                    .iter()
+                    .filter(|date| *date > start && *date < end)
+                    .copied()
+                    .collect::<Vec<Date>>();
+                dates.extend([*start, *end]);
+                dates.sort();
+                dates.dedup();
+
+                let size = self.market_requests.borrow_mut().len();
+                let (mut requests, name) = match children.get(0).unwrap().as_ref() {
+                    Node::Spot(first, second, _) => {
+                        let second = second.or(self.local_currency).ok_or(
+                            ScriptingError::InvalidSyntax(
+                                "Barrier on a spot requires a second currency".to_string(),
+                            ),
+                        )?;
+                        let requests = dates
+                            .iter()
+                            .enumerate()
+                            .map(|(i, date)| {
+                                let fx = ExchangeRateRequest::new(*first, Some(second), Some(*date));
+                                MarketRequest::new(size + i, None, None, Some(fx))
+                            })
+                            .collect::<Vec<MarketRequest>>();
+                        let name = format!("{}/{}", String::from(*first), String::from(second));
+                        (requests, name)
+                    }
+                    Node::Equity(name, _, _) => {
+                        let requests = dates
+                            .iter()
+                            .enumerate()
+                            .map(|(i, date)| {
+                                let equity = EquityRequest::new(name.clone(), Some(*date));
+                                MarketRequest::new(size + i, None, None, None).with_equity(equity)
+                            })
+                            .collect::<Vec<MarketRequest>>();
+                        (requests, name.clone())
+                    }
+                    _ => {
+                        return Err(ScriptingError::InvalidSyntax(
+                            "Barrier underlying must be a Spot or Stock observation".to_string(),
+                        ))
+                    }
+                };
+                requests.push(
+                    MarketRequest::new(size + dates.len(), None, None, None)
+                        .with_vol(VolatilityRequest::new(name, Some(*start))),
+                );
+                self.market_requests.borrow_mut().extend(requests);
+                observations.set(dates).unwrap();
+                opt_idx.set(size).unwrap();
+                Ok(())
+            }
+            Node::Correlation(first, second, opt_idx) => {
+                match opt_idx.get() {
+                    Some(_) => {}
+                    None => {
+                        let corr_request = CorrelationRequest::new(first.clone(), second.clone());
+                        let id = self.add_request(|id| {
+                            MarketRequest::new(id, None, None, None).with_corr(corr_request)
+                        });
+                        opt_idx.set(id).unwrap();
+                    }
+                };
+                Ok(())
+            }
+            Node::Fixing(name, date, opt_idx) => {
+                // historical fixings are not simulated and stay unindexed
+                if opt_idx.get().is_some() || self.add_fixing_request(name, Some(*date)) {
+                    return Ok(());
+                }
+
+                // a fixing name is either registered as a whole or follows the
+                // `<index>-<tenor>` convention, e.g. `SOFR-3M` on the registered `SOFR` index
+                let (convention, tenor) = match self.index_conventions.get(name) {
+                    Some(convention) => {
+                        let tenor = match (convention.tenor(), name.rsplit_once('-')) {
+                            (Some(tenor), _) => tenor,
+                            (None, Some((_, tenor))) => Period::from_str(tenor)?,
+                            (None, None) => {
+                                return Err(ScriptingError::InvalidSyntax(format!(
+                                    "Invalid fixing name {}",
+                                    name
+                                )))
+                            }
+                        };
+                        (convention.clone(), tenor)
+                    }
+                    None => {
+                        let convention = name
+                            .rsplit_once('-')
+                            .and_then(|(index, _)| self.index_conventions.get(index));
+                        match (convention, name.rsplit_once('-')) {
+                            (Some(convention), Some((_, tenor))) => {
+                                (convention.clone(), Period::from_str(tenor)?)
+                            }
+                            _ => {
+                                return Err(ScriptingError::InvalidSyntax(format!(
+                                    "Unknown index {}",
+                                    name
+                                )))
+                            }
+                        }
+                    }
+                };
+
+                let start = convention.fixing_date(*date);
+                let fwd_request = ForwardRateRequest::new(
+                    convention.provider_id(),
+                    *date,
+                    start,
+                    convention.end_date(start, tenor),
+                    convention.compounding(),
+                    convention.frequency(),
+                );
+                let id =
+                    self.add_request(|id| MarketRequest::new(id, None, Some(fwd_request), None));
+                opt_idx.set(id).unwrap();
                 Ok(())
             }
-            Node::Pays(children, opt_idx) => {
+            Node::Pays(children, data, opt_idx) => {
+                children.iter().try_for_each(|child| self.visit(child))?;
+                match opt_idx.get() {
+                    Some(_) => Ok(()),
+                    None => {
+                        // settled payments observe the discount factors to the event and payment
+                        // dates, deferring the payment by the settlement lag
+                        match (data.settlement_days(), data.calendar()) {
+                            (Some(days), Some(calendar)) => {
+                                let event_date = self.event_date.borrow().ok_or(
+                                    ScriptingError::InvalidSyntax(
+                                        "Settled payment outside of an event".to_string(),
+                                    ),
+                                )?;
+                                let provider_id = self.discount_curve(data.currency()).ok_or(
+                                    ScriptingError::InvalidSyntax(
+                                        "No discount curve provider for settled payment"
+                                            .to_string(),
+                                    ),
+                                )?;
+                                let payment_date = Calendar::try_from(calendar.clone())?.advance(
+                                    event_date,
+                                    Period::new(days, TimeUnit::Days),
+                                    None,
+                                    false,
+                                );
+                                let mut requests = self.market_requests.borrow_mut();
+                                let size = requests.len();
+                                requests.extend([event_date, payment_date].iter().enumerate().map(
+                                    |(i, date)| {
+                                        let df = DiscountFactorRequest::new(provider_id, *date);
+                                        MarketRequest::new(size + i, Some(df), None, None)
+                                    },
+                                ));
+                                opt_idx.set(size).unwrap();
+                            }
+                            _ => {
+                                let df = self.event_discount_factor(data.currency());
+                                let id =
+                                    self.add_request(|id| MarketRequest::new(id, df, None, None));
+                                opt_idx.set(id).unwrap();
+                            }
+                        };
+                        Ok(())
+                    }
+                }
+            }
+            Node::Exercise(children, opt_idx) => {
+                // the continuation is read back at the end of each path by the regression
+                if !matches!(children.first().unwrap().as_ref(), Node::Variable(..)) {
+                    return Err(ScriptingError::InvalidSyntax(
+                        "Continuation of exercise must be a variable".to_string(),
+                    ));
+                }
                 children.iter().try_for_each(|child| self.visit(child))?;
                 match opt_idx.get() {
                     Some(_) => Ok(()),
                     None => {
                         let size = self.market_requests.borrow_mut().len();
-                        let request = MarketRequest::new(size, None, None, None);
+                        let df = self.event_discount_factor(None);
+                        let request = MarketRequest::new(size, df, None, None);
                         self.market_requests.borrow_mut().push(request.clone());
                         opt_idx.set(size).unwrap();
                         Ok(())
@@ -251,11 +665,65 @@ impl EventIndexer {
         EventIndexer {
             variables: RefCell::new(HashMap::new()),
             market_requests: RefCell::new(Vec::new()),
+            fixing_requests: RefCell::new(Vec::new()),
+            event_start: RefCell::new(0),
             event_date: RefCell::new(None),
+            event_dates: RefCell::new(Vec::new()),
             local_currency: None,
+            reference_date: None,
+            index_conventions: HashMap::new(),
+            discount_provider: None,
+            discount_curves: HashMap::new(),
+            dependencies: RefCell::new(HashMap::new()),
         }
     }
 
+    /// Index of a request identical, ids aside, to the one built by `request` made earlier in
+    /// the current event, otherwise of the new request once added. Observing the same market
+    /// data several times in an event is then simulated once.
+    fn add_request(&self, request: impl FnOnce(usize) -> MarketRequest) -> usize {
+        let key = |r: &MarketRequest| (r.df(), r.fwd(), r.fx(), r.equity(), r.vol(), r.corr());
+        let mut requests = self.market_requests.borrow_mut();
+        let request = request(requests.len());
+        let start = *self.event_start.borrow();
+        if let Some(existing) = requests[start..].iter().find(|r| key(r) == key(&request)) {
+            return existing.id();
+        }
+        let id = request.id();
+        requests.push(request);
+        id
+    }
+
+    /// Record a fixing request if the observation is dated before the reference date, returning
+    /// whether it is historical
+    fn add_fixing_request(&self, name: &str, date: Option<Date>) -> bool {
+        let date = match (date, self.reference_date) {
+            (Some(date), Some(reference_date)) if date < reference_date => date,
+            _ => return false,
+        };
+        let request = FixingRequest::new(name.to_string(), date);
+        let mut requests = self.fixing_requests.borrow_mut();
+        if !requests.contains(&request) {
+            requests.push(request);
+        }
+        true
+    }
+
+    /// Provider of the discount curve of `currency`, or of the default discount curve
+    fn discount_curve(&self, currency: Option<Currency>) -> Option<usize> {
+        currency
+            .and_then(|ccy| self.discount_curves.get(&ccy).copied())
+            .or(self.discount_provider)
+    }
+
+    /// Discount factor to the event date requested with the numerarie of a payment or an
+    /// exercise, from which models with a stochastic numerarie derive it along the path
+    fn event_discount_factor(&self, currency: Option<Currency>) -> Option<DiscountFactorRequest> {
+        let date = (*self.event_date.borrow())?;
+        let provider_id = self.discount_curve(currency)?;
+        Some(DiscountFactorRequest::new(provider_id, date))
+    }
+
     /// # with_event_date
     /// Set the event date of the EventIndexer
     pub fn set_event_date(self, date: Date) {
@@ -281,6 +749,58 @@ impl EventIndexer {
         self
     }
 
+    /// # with_reference_date
+    /// Set the model reference date. Fixings and equity prices observed before it are read from
+    /// historical data
+    pub fn with_reference_date(mut self, date: Date) -> Self {
+        self.reference_date = Some(date);
+        self
+    }
+
+    /// # with_index_provider
+    /// Map an index name used in `Fixing` nodes to a forward curve provider id
+    pub fn with_index_provider(mut self, name: &str, provider_id: usize) -> Self {
+        self.index_conventions
+            .insert(name.to_string(), IndexConvention::new(provider_id));
+        self
+    }
+
+    /// # with_index_map
+    /// Register the indexes of an index store by name, as given by `IndexStore::get_index_map`,
+    /// so that `Fixing("SOFR-3M", ...)` reads the forward curve of the `SOFR` index. Indexes
+    /// with a registered provider or convention are kept.
+    pub fn with_index_map(mut self, index_map: &HashMap<String, usize>) -> Self {
+        index_map.iter().for_each(|(name, provider_id)| {
+            self.index_conventions
+                .entry(name.clone())
+                .or_insert_with(|| IndexConvention::new(*provider_id));
+        });
+        self
+    }
+
+    /// # with_index_convention
+    /// Register the conventions of an index used in `Fixing` or `RateIndex` nodes. Indexes
+    /// without conventions are read as simple annual rates.
+    pub fn with_index_convention(mut self, name: &str, convention: IndexConvention) -> Self {
+        self.index_conventions.insert(name.to_string(), convention);
+        self
+    }
+
+    /// # with_discount_provider
+    /// Set the curve provider id used to discount settled payments
+    pub fn with_discount_provider(mut self, provider_id: usize) -> Self {
+        self.discount_provider = Some(provider_id);
+        self
+    }
+
+    /// # with_discount_curve
+    /// Discount settled payments in the given currency on the provider `provider_id`, typically
+    /// its OIS curve, instead of the default discount provider
+    pub fn with_discount_curve(mut self, currency: Currency, provider_id: usize) -> Self {
+        self.discount_curves.insert(currency, provider_id);
+        self
+    }
+
     /// # get_variable_index
     /// Get the index of a variable by its name
     pub fn get_variable_index(&self, variable_name: &str) -> Option<usize> {
@@ -298,13 +818,21 @@ impl EventIndexer {
     }
 
     /// # get_variables
-    /// Get all the variable names
+    /// Get the names of the script variables, without the hidden ones created by the parser and
+    /// the optimizer (`__tmp0`, `__cse0`)
     pub fn get_variables(&self) -> Vec<String> {
-        self.variables.borrow_mut().keys().cloned().collect()
+        self.get_variable_indexes().into_keys().collect()
     }
 
+    /// # get_variable_indexes
+    /// Get the indexes of the script variables, without the hidden ones
     pub fn get_variable_indexes(&self) -> HashMap<String, usize> {
-        self.variables.borrow_mut().clone()
+        self.variables
+            .borrow()
+            .iter()
+            .filter(|(name, _)| !name.starts_with("__"))
+            .map(|(name, idx)| (name.clone(), *idx))
+            .collect()
     }
 
     pub fn get_variables_size(&self) -> usize {
@@ -312,16 +840,134 @@ impl EventIndexer {
     }
 
     pub fn get_market_requests(&self) -> Vec<MarketRequest> {
-        self.market_requests.borrow_mut().clone()
+        self.market_requests.borrow().clone()
+    }
+
+    /// # get_fixing_requests
+    /// Observations dated before the reference date, to be read from historical data. They
+    /// are not part of the market requests.
+    pub fn get_fixing_requests(&self) -> Vec<FixingRequest> {
+        self.fixing_requests.borrow().clone()
     }
 
     pub fn visit_events(&self, events: &EventStream) -> Result<()> {
+        *self.event_dates.borrow_mut() = events.event_dates();
         events.events().iter().try_for_each(|event| {
             *self.event_date.borrow_mut() = Some(event.event_date());
+            *self.event_start.borrow_mut() = self.market_requests.borrow().len();
             self.visit(event.expr())?;
+            self.record_dependencies(event.expr(), &Dependencies::default());
             Ok(())
         })
     }
+
+    /// # visit_events_collecting_errors
+    /// Index the events like `visit_events` but carry on after a failing statement, returning
+    /// every error with its location. Failing statements are left partly indexed, so the events
+    /// can only be evaluated when no error is returned.
+    pub fn visit_events_collecting_errors(&self, events: &EventStream) -> Vec<ScriptingError> {
+        let mut errors = Vec::new();
+        *self.event_dates.borrow_mut() = events.event_dates();
+        for event in events.events() {
+            *self.event_date.borrow_mut() = Some(event.event_date());
+            *self.event_start.borrow_mut() = self.market_requests.borrow().len();
+            let statements = match event.expr().as_ref() {
+                Node::Base(children) => children.iter().collect(),
+                _ => vec![event.expr()],
+            };
+            for node in statements {
+                match self.visit(node) {
+                    Ok(()) => self.record_dependencies(node, &Dependencies::default()),
+                    Err(err) => errors.push(err),
+                }
+            }
+        }
+        errors
+    }
+
+    /// Record what the variables assigned by a statement read directly. `conditions` holds what
+    /// the enclosing `if` conditions and loop ranges read, since they decide the assignment too.
+    fn record_dependencies(&self, node: &Node, conditions: &Dependencies) {
+        let assign = |target: &Node, reads: Dependencies| {
+            let name = match target {
+                Node::Variable(_, name, _) => name.clone(),
+                Node::Index(children) => match children.first().map(|c| c.as_ref()) {
+                    Some(Node::Variable(_, name, _)) => name.clone(),
+                    _ => return,
+                },
+                _ => return,
+            };
+            let mut dependencies = self.dependencies.borrow_mut();
+            let entry = dependencies.entry(name).or_default();
+            entry.extend(&reads);
+            entry.extend(conditions);
+        };
+        match node {
+            Node::Base(children) | Node::Spanned(children, _) => children
+                .iter()
+                .for_each(|child| self.record_dependencies(child, conditions)),
+            Node::Assign(children) => {
+                let mut reads = Dependencies::default();
+                reads.read(&children[1]);
+                // the index of an element being written
+                if let Node::Index(target) = children[0].as_ref() {
+                    target[1..].iter().for_each(|child| reads.read(child));
+                }
+                assign(&children[0], reads);
+            }
+            Node::Append(children) => {
+                let mut reads = Dependencies::default();
+                reads.read(&children[1]);
+                assign(&children[0], reads);
+            }
+            Node::If(children, _) => {
+                let mut inner = conditions.clone();
+                inner.read(&children[0]);
+                children[1..]
+                    .iter()
+                    .for_each(|child| self.record_dependencies(child, &inner));
+            }
+            Node::ForEach(children) => {
+                let mut inner = conditions.clone();
+                inner.read(&children[1]);
+                if let Node::Variable(_, name, _) = children[0].as_ref() {
+                    let mut dependencies = self.dependencies.borrow_mut();
+                    dependencies.entry(name.clone()).or_default().extend(&inner);
+                }
+                children[2..]
+                    .iter()
+                    .for_each(|child| self.record_dependencies(child, &inner));
+            }
+            _ => (),
+        }
+    }
+
+    /// # dependency_graph
+    /// For every variable assigned in the indexed events, the variables and market requests it
+    /// depends on, directly or through other variables, including those deciding whether it is
+    /// assigned. A variable is not listed among its own dependencies.
+    pub fn dependency_graph(&self) -> HashMap<String, Dependencies> {
+        let direct = self.dependencies.borrow();
+        direct
+            .keys()
+            .map(|name| {
+                let mut dependencies = Dependencies::default();
+                let mut pending = vec![name];
+                let mut visited = BTreeSet::new();
+                while let Some(current) = pending.pop() {
+                    if !visited.insert(current) {
+                        continue;
+                    }
+                    if let Some(reads) = direct.get(current) {
+                        dependencies.extend(reads);
+                        pending.extend(reads.variables());
+                    }
+                }
+                dependencies.variables.remove(name);
+                (name.clone(), dependencies)
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -409,6 +1055,241 @@ mod tests {
         assert_eq!(request.fwd().unwrap().start_date(), Date::new(2024, 1, 1));
         assert_eq!(request.fwd().unwrap().end_date(), Date::new(2024, 2, 1));
     }
+
+    #[test]
+    fn test_equity_indexer() {
+        let indexer = EventIndexer::new().with_event_date(Date::new(2024, 6, 3));
+        let node = Box::new(Node::new_equity("AAPL".to_string(), None));
+        indexer.visit(&node).unwrap();
+        let node = Box::new(Node::new_equity(
+            "MSFT".to_string(),
+            Some(Date::new(2024, 1, 2)),
+        ));
+        indexer.visit(&node).unwrap();
+        let market_requests = indexer.get_market_requests();
+        assert_eq!(market_requests.len(), 2);
+
+        let equity = market_requests.get(0).unwrap().equity().unwrap();
+        assert_eq!(equity.name(), "AAPL");
+        assert_eq!(equity.reference_date(), Some(Date::new(2024, 6, 3)));
+
+        let equity = market_requests.get(1).unwrap().equity().unwrap();
+        assert_eq!(equity.name(), "MSFT");
+        assert_eq!(equity.reference_date(), Some(Date::new(2024, 1, 2)));
+    }
+
+    #[test]
+    fn test_volatility_indexer() {
+        let indexer = EventIndexer::new().with_event_date(Date::new(2024, 6, 3));
+        let node = Box::new(Node::new_volatility("EUR/USD".to_string(), None));
+        indexer.visit(&node).unwrap();
+        let market_requests = indexer.get_market_requests();
+        assert_eq!(market_requests.len(), 1);
+
+        let vol = market_requests.get(0).unwrap().vol().unwrap();
+        assert_eq!(vol.name(), "EUR/USD");
+        assert_eq!(vol.reference_date(), Some(Date::new(2024, 6, 3)));
+        assert_eq!(vol.currency_pair(), Some((Currency::EUR, Currency::USD)));
+    }
+
+    #[test]
+    fn test_barrier_hit_indexer() {
+        let indexer = EventIndexer::new().with_local_currency(Currency::USD);
+        let node = Box::new(Node::BarrierHit(
+            vec![
+                Box::new(Node::Spot(Currency::EUR, None, OnceLock::new())),
+                Box::new(Node::new_variable("level".to_string())),
+            ],
+            Date::new(2024, 1, 1),
+            Date::new(2024, 2, 1),
+            true,
+            true,
+            OnceLock::new(),
+            OnceLock::new(),
+        ));
+        indexer.visit(&node).unwrap();
+        assert_eq!(indexer.get_variable_index("level"), Some(0));
+
+        let market_requests = indexer.get_market_requests();
+        assert_eq!(market_requests.len(), 3);
+        let start = market_requests.get(0).unwrap().fx().unwrap();
+        assert_eq!(start.second_currency(), Some(Currency::USD));
+        assert_eq!(start.reference_date(), Some(Date::new(2024, 1, 1)));
+        let end = market_requests.get(1).unwrap().fx().unwrap();
+        assert_eq!(end.reference_date(), Some(Date::new(2024, 2, 1)));
+        let vol = market_requests.get(2).unwrap().vol().unwrap();
+        assert_eq!(vol.name(), "EUR/USD");
+    }
+
+    #[test]
+    fn test_barrier_hit_after_event() {
+        let node = || {
+            Box::new(Node::BarrierHit(
+                vec![
+                    Box::new(Node::Spot(Currency::EUR, None, OnceLock::new())),
+                    Box::new(Node::new_constant(1.1)),
+                ],
+                Date::new(2024, 1, 1),
+                Date::new(2024, 2, 1),
+                true,
+                true,
+                OnceLock::new(),
+                OnceLock::new(),
+            ))
+        };
+
+        // a window ending after the event would need spots the event cannot observe yet
+        let indexer = EventIndexer::new()
+            .with_local_currency(Currency::USD)
+            .with_event_date(Date::new(2024, 1, 15));
+        assert!(indexer.visit(&node()).is_err());
+
+        let indexer = EventIndexer::new()
+            .with_local_currency(Currency::USD)
+            .with_event_date(Date::new(2024, 2, 1));
+        indexer.visit(&node()).unwrap();
+        assert_eq!(indexer.get_market_requests().len(), 3);
+    }
+
+    #[test]
+    fn test_correlation_indexer() {
+        let indexer = EventIndexer::new();
+        let node = Box::new(Node::new_correlation(
+            "AAPL".to_string(),
+            "MSFT".to_string(),
+        ));
+        indexer.visit(&node).unwrap();
+        let market_requests = indexer.get_market_requests();
+        assert_eq!(market_requests.len(), 1);
+
+        let corr = market_requests.get(0).unwrap().corr().unwrap();
+        assert_eq!(corr.first(), "AAPL");
+        assert_eq!(corr.second(), "MSFT");
+    }
+
+    #[test]
+    fn test_settled_pays_indexer() {
+        let node: ExprTree = "x pays 100 settle 2bd TARGET;".to_string().try_into().unwrap();
+
+        let indexer = EventIndexer::new().with_event_date(Date::new(2024, 3, 28));
+        assert!(indexer.visit(&node).is_err());
+
+        let indexer = EventIndexer::new()
+            .with_event_date(Date::new(2024, 3, 28))
+            .with_discount_provider(1);
+        indexer.visit(&node).unwrap();
+        let market_requests = indexer.get_market_requests();
+        assert_eq!(market_requests.len(), 2);
+        let event = market_requests.get(0).unwrap().df().unwrap();
+        assert_eq!(event.date(), Date::new(2024, 3, 28));
+
+        // the Easter holidays are skipped
+        let df = market_requests.get(1).unwrap().df().unwrap();
+        assert_eq!(df.provider_id(), 1);
+        assert_eq!(df.date(), Date::new(2024, 4, 3));
+
+        // payments in a mapped currency are discounted on its own curve
+        let node: ExprTree = "x pays 100 in USD settle 2bd TARGET;"
+            .to_string()
+            .try_into()
+            .unwrap();
+        let indexer = EventIndexer::new()
+            .with_event_date(Date::new(2024, 3, 28))
+            .with_discount_provider(1)
+            .with_discount_curve(Currency::USD, 5);
+        indexer.visit(&node).unwrap();
+        let df = indexer.get_market_requests()[1].df().unwrap();
+        assert_eq!(df.provider_id(), 5);
+    }
+
+    #[test]
+    fn test_exercise_continuation_indexer() {
+        let node: ExprTree = "y = exercise(1, 2);".to_string().try_into().unwrap();
+        assert!(EventIndexer::new().visit(&node).is_err());
+
+        let node: ExprTree = "x = 0; y = exercise(x, 2);".to_string().try_into().unwrap();
+        let indexer = EventIndexer::new();
+        indexer.visit(&node).unwrap();
+        assert_eq!(indexer.get_market_requests().len(), 1);
+    }
+
+    #[test]
+    fn test_numerarie_indexer() {
+        let node = || -> ExprTree {
+            "x pays 100; y = exercise(x, x);"
+                .to_string()
+                .try_into()
+                .unwrap()
+        };
+
+        // without a discount curve the numerarie is requested alone
+        let indexer = EventIndexer::new().with_event_date(Date::new(2024, 3, 28));
+        indexer.visit(&node()).unwrap();
+        let market_requests = indexer.get_market_requests();
+        assert_eq!(market_requests.len(), 2);
+        assert!(market_requests.iter().all(|r| r.df().is_none()));
+
+        // with one it comes with the discount factor to the event date
+        let indexer = EventIndexer::new()
+            .with_event_date(Date::new(2024, 3, 28))
+            .with_discount_provider(1);
+        indexer.visit(&node()).unwrap();
+        let market_requests = indexer.get_market_requests();
+        assert_eq!(market_requests.len(), 2);
+        for request in market_requests.iter() {
+            let df = request.df().unwrap();
+            assert_eq!((df.provider_id(), df.date()), (1, Date::new(2024, 3, 28)));
+        }
+    }
+
+    #[test]
+    fn test_fixing_indexer() {
+        let indexer = EventIndexer::new()
+            .with_reference_date(Date::new(2024, 1, 15))
+            .with_index_provider("SOFR-3M", 2);
+
+        let past = Box::new(Node::new_fixing("SOFR-3M".to_string(), Date::new(2024, 1, 10)));
+        indexer.visit(&past).unwrap();
+        assert!(indexer.get_market_requests().is_empty());
+        match past.as_ref() {
+            Node::Fixing(_, _, id) => assert!(id.get().is_none()),
+            _ => panic!("Expected a fixing node"),
+        }
+
+        let future = Box::new(Node::new_fixing("SOFR-3M".to_string(), Date::new(2024, 2, 1)));
+        indexer.visit(&future).unwrap();
+        let market_requests = indexer.get_market_requests();
+        assert_eq!(market_requests.len(), 1);
+
+        let request = market_requests.get(0).unwrap();
+        assert_eq!(request.fwd().unwrap().provider_id(), 2);
+        assert_eq!(request.fwd().unwrap().start_date(), Date::new(2024, 2, 1));
+        assert_eq!(request.fwd().unwrap().end_date(), Date::new(2024, 5, 1));
+
+        let unknown = Box::new(Node::new_fixing("ESTR-1D".to_string(), Date::new(2024, 2, 1)));
+        assert!(indexer.visit(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_fixing_on_index_map() {
+        let index_map = HashMap::from([("SOFR".to_string(), 3), ("0".to_string(), 0)]);
+        let indexer = EventIndexer::new()
+            .with_reference_date(Date::new(2024, 1, 15))
+            .with_index_map(&index_map);
+
+        let fixing = Box::new(Node::new_fixing("SOFR-3M".to_string(), Date::new(2024, 2, 1)));
+        indexer.visit(&fixing).unwrap();
+        let request = indexer.get_market_requests().get(0).unwrap().clone();
+        assert_eq!(request.fwd().unwrap().provider_id(), 3);
+        assert_eq!(request.fwd().unwrap().end_date(), Date::new(2024, 5, 1));
+
+        // a tenor suffix is never read as a numeric provider id
+        let numeric = Box::new(Node::new_fixing("1-3M".to_string(), Date::new(2024, 2, 1)));
+        match indexer.visit(&numeric) {
+            Err(ScriptingError::InvalidSyntax(msg)) => assert_eq!(msg, "Unknown index 1-3M"),
+            _ => panic!("Expected an unknown index error"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -485,4 +1366,138 @@ mod ai_gen_tests {
         indexer.visit(&node).unwrap();
         assert_eq!(indexer.get_variables_size(), 2);
     }
+
+    #[test]
+    fn test_serialize_indexed_events() {
+        let events = EventStream::new().with_id(1).with_events(vec![Event::new(
+            Date::new(2024, 1, 1),
+            "x = Spot(\"EUR\", \"USD\");\nx pays 100 leg \"fx\" in EUR;"
+                .try_into()
+                .unwrap(),
+        )]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+
+        let json = serde_json::to_string(&events).unwrap();
+        let reloaded: EventStream = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(reloaded.id(), Some(1));
+        assert_eq!(reloaded.events(), events.events());
+        match reloaded.events()[0].expr().children()[0].children()[0].as_ref() {
+            Node::Assign(children) => match children[1].as_ref() {
+                Node::Spot(_, _, id) => assert!(id.get().is_some()),
+                _ => panic!("Expected a spot node"),
+            },
+            _ => panic!("Expected an assignment"),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_requests_in_event() {
+        let script = "s = Stock(\"AAPL\"); t = Stock(\"AAPL\") + Stock(\"AAPL\");\n            v = Stock(\"MSFT\");";
+        let events = EventStream::new().with_events(vec![
+            Event::new(Date::new(2024, 1, 1), script.try_into().unwrap()),
+            Event::new(Date::new(2024, 1, 1), "u = Stock(\"AAPL\");".try_into().unwrap()),
+        ]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+
+        // one request per distinct observation in the first event, the second event gets its own
+        let requests = indexer.get_market_requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests.iter().map(|r| r.id()).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(requests[1].equity().unwrap().name(), "MSFT");
+        assert_eq!(requests[2].equity(), requests[0].equity());
+    }
+
+    #[test]
+    fn test_index_conventions() {
+        let events = EventStream::new().with_events(vec![Event::new(
+            Date::new(2024, 1, 1),
+            "x = Fixing(\"EURIBOR\", \"2024-03-30\");
+            y = RateIndex(\"ESTR\", \"2024-06-03\", \"2024-09-03\");"
+                .try_into()
+                .unwrap(),
+        )]);
+        let indexer = EventIndexer::new()
+            .with_index_convention(
+                "EURIBOR",
+                IndexConvention::new(3)
+                    .with_tenor(Period::new(3, TimeUnit::Months))
+                    .with_calendar(Calendar::TARGET(TARGET::new())),
+            )
+            .with_index_convention(
+                "ESTR",
+                IndexConvention::new(4)
+                    .with_compounding(Compounding::Compounded)
+                    .with_frequency(Frequency::Quarterly),
+            );
+        indexer.visit_events(&events).unwrap();
+
+        let requests = indexer.get_market_requests();
+        // a Saturday fixing starts on Monday and matures three business months later
+        let fixing = requests[0].fwd().unwrap();
+        assert_eq!(fixing.provider_id(), 3);
+        assert_eq!(fixing.start_date(), Date::new(2024, 4, 2));
+        assert_eq!(fixing.end_date(), Date::new(2024, 7, 2));
+        assert_eq!(fixing.compounding(), Compounding::Simple);
+
+        let rate = requests[1].fwd().unwrap();
+        assert_eq!(rate.provider_id(), 4);
+        assert_eq!(rate.compounding(), Compounding::Compounded);
+        assert_eq!(rate.frequency(), Frequency::Quarterly);
+    }
+
+    #[test]
+    fn test_visit_events_collecting_errors() {
+        let script = "a = Fixing(\"SOFR\", \"2024-01-01\");
+b = 1;
+c = RateIndex(\"X\", \"2024-01-01\", \"2024-02-01\");";
+        let events = EventStream::new().with_events(vec![Event::new(
+            Date::new(2024, 1, 1),
+            script.try_into().unwrap(),
+        )]);
+        let indexer = EventIndexer::new();
+        let errors = indexer.visit_events_collecting_errors(&events);
+
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "line 1, col 1: Invalid Syntax: Unknown index SOFR",
+                "line 3, col 1: Invalid Syntax: Invalid rate index name",
+            ]
+        );
+        assert_eq!(indexer.get_variable_index("b"), Some(1));
+    }
+
+    #[test]
+    fn test_dependency_graph() {
+        let script = "
+            s = Stock(\"AAPL\");
+            k = 100;
+            barrier = 120;
+            payoff = 0;
+            if s < barrier {
+                payoff = max(s - k, 0);
+            }
+            total = payoff * 2;
+            other = 1;
+        ";
+        let events = EventStream::new().with_events(vec![Event::new(
+            Date::new(2024, 1, 1),
+            script.try_into().unwrap(),
+        )]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let graph = indexer.dependency_graph();
+
+        let variables = |name: &str| -> Vec<String> {
+            graph[name].variables().iter().cloned().collect()
+        };
+        assert_eq!(variables("payoff"), vec!["barrier", "k", "s"]);
+        assert_eq!(variables("total"), vec!["barrier", "k", "payoff", "s"]);
+        assert_eq!(graph["total"].market_requests(), &BTreeSet::from([0]));
+        assert_eq!(graph["other"], Dependencies::default());
+    }
 }
@@ -0,0 +1,194 @@
+use rustatlas::math::ad::num::Real;
+
+use crate::utils::linalg;
+
+/// # ExercisePolicy
+/// Exercise boundary estimated by `EventStreamEvaluator::fit_exercise_policy`. Holds, for each
+/// exercise opportunity, the coefficients of the expected deflated continuation value
+/// `c0 + c1 * x + c2 * x^2`, where `x` is the deflated intrinsic value given to `exercise`.
+/// Opportunities before `held` are never exercised.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExercisePolicy<T: Real = f64> {
+    coefficients: Vec<[T; 3]>,
+    held: usize,
+}
+
+impl<T: Real> ExercisePolicy<T> {
+    pub fn new(coefficients: Vec<[T; 3]>) -> Self {
+        ExercisePolicy {
+            coefficients,
+            held: 0,
+        }
+    }
+
+    /// # with_held
+    /// Hold the first `held` opportunities whatever the values, as the backward induction does
+    /// while the continuation at an opportunity is being realised
+    pub fn with_held(mut self, held: usize) -> Self {
+        self.held = held;
+        self
+    }
+
+    pub fn coefficients(&self) -> &Vec<[T; 3]> {
+        &self.coefficients
+    }
+
+    /// # continuation
+    /// Expected deflated continuation value at the `k`-th exercise opportunity
+    pub fn continuation(&self, k: usize, intrinsic: T) -> T {
+        match self.coefficients.get(k) {
+            Some([c0, c1, c2]) => *c0 + *c1 * intrinsic + *c2 * intrinsic * intrinsic,
+            None => T::from(0.0),
+        }
+    }
+
+    /// # exercise
+    /// Whether to exercise at the `k`-th opportunity, given the deflated intrinsic value
+    pub fn exercise(&self, k: usize, intrinsic: T) -> bool {
+        k >= self.held && intrinsic > T::from(0.0) && intrinsic > self.continuation(k, intrinsic)
+    }
+}
+
+/// Least squares fit of `y` on `[1, x, x^2]`. Falls back to the mean of `y` when the normal
+/// equations are singular.
+pub(crate) fn regress<T: Real>(x: &[T], y: &[T]) -> [T; 3] {
+    let zero = T::from(0.0);
+    if y.is_empty() {
+        return [zero; 3];
+    }
+
+    let mut a = vec![vec![zero; 3]; 3];
+    let mut b = vec![vec![zero]; 3];
+    x.iter().zip(y.iter()).for_each(|(&x, &y)| {
+        let basis = [T::from(1.0), x, x * x];
+        for i in 0..3 {
+            for j in 0..3 {
+                a[i][j] = a[i][j] + basis[i] * basis[j];
+            }
+            b[i][0] = b[i][0] + basis[i] * y;
+        }
+    });
+
+    match linalg::solve(&a, &b) {
+        Ok(c) => [c[0][0], c[1][0], c[2][0]],
+        Err(_) => {
+            let mean = y.iter().fold(zero, |acc, &v| acc + v) / T::from(y.len() as f64);
+            [mean, zero, zero]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use rustatlas::{core::meta::MarketData, time::date::Date};
+
+    #[test]
+    fn test_exercise_policy() {
+        let policy = ExercisePolicy::new(vec![[1.0, 0.5, 0.0]]);
+        assert_eq!(policy.continuation(0, 2.0), 2.0);
+        assert!(policy.exercise(0, 3.0));
+        assert!(!policy.exercise(0, 1.0));
+        // past the last opportunity the continuation value is zero
+        assert!(policy.exercise(1, 1.0));
+        assert!(!policy.exercise(1, 0.0));
+
+        let policy = policy.with_held(1);
+        assert!(!policy.exercise(0, 3.0));
+        assert!(policy.exercise(1, 1.0));
+    }
+
+    #[test]
+    fn test_regress_singular() {
+        let coefficients = regress(&[1.0, 1.0], &[2.0, 4.0]);
+        assert_eq!(coefficients, [3.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_bermudan_put() {
+        let first = Date::new(2025, 1, 1);
+        let second = Date::new(2026, 1, 1);
+        let events = EventStream::new().with_events(vec![
+            Event::new(
+                first,
+                "
+                    opt = 0;
+                    alive = 1;
+                    s = Stock(\"X\");
+                    if exercise(opt, max(100 - s, 0)) == 1 {
+                        opt = pays max(100 - s, 0);
+                        alive = 0;
+                    }
+                "
+                .to_string()
+                .try_into()
+                .unwrap(),
+            ),
+            Event::new(
+                second,
+                "
+                    s = Stock(\"X\");
+                    e = exercise(opt, max(100 - s, 0));
+                    if alive == 1 and e == 1 {
+                        opt = pays max(100 - s, 0);
+                    }
+                "
+                .to_string()
+                .try_into()
+                .unwrap(),
+            ),
+        ]);
+
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let requests = indexer.get_market_requests();
+
+        let paths = [
+            (90.0, 80.0),
+            (90.0, 95.0),
+            (80.0, 70.0),
+            (80.0, 100.0),
+            (70.0, 60.0),
+            (70.0, 100.0),
+        ];
+        let scenarios: Vec<Scenario> = paths
+            .iter()
+            .map(|&(s1, s2)| {
+                requests
+                    .iter()
+                    .map(|request| {
+                        let equity = request.equity().map(|equity| {
+                            if equity.reference_date() == Some(first) {
+                                s1
+                            } else {
+                                s2
+                            }
+                        });
+                        MarketData::new(request.id(), first, None, None, None, 1.0)
+                            .with_equity(equity)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let policy = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .fit_exercise_policy(&events)
+            .unwrap();
+        assert_eq!(policy.coefficients().len(), 2);
+        assert_eq!(policy.coefficients()[1], [0.0, 0.0, 0.0]);
+
+        let results = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .with_exercise_policy(&policy)
+            .visit_events(&events, &indexer.get_variable_indexes())
+            .unwrap();
+
+        // exercised early at 80 and 70, held at 90
+        match results.get("opt") {
+            Some(Value::Number(v)) => assert!((v - 125.0 / 6.0).abs() < 1e-9),
+            _ => panic!("opt not evaluated"),
+        }
+    }
+}
@@ -1,4 +1,11 @@
 pub mod indexer;
+pub mod lsm;
+pub mod bytecode;
 pub mod evaluator;
 pub mod node;
+pub mod optimizer;
+pub mod printer;
+pub mod trace;
 pub mod traits;
+pub mod typechecker;
+pub mod vectorized;
@@ -2,24 +2,119 @@ use std::sync::OnceLock;
 
 use rustatlas::utils::num::Real;
 use rustatlas::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::prelude::*;
 
 pub type ExprTree = Box<Node>;
 
-#[derive(Debug, Clone, PartialEq)]
+/// Serde support for the ids set by the indexer, stored as an optional value
+mod once_lock {
+    use std::sync::OnceLock;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T: Serialize, S: Serializer>(
+        value: &OnceLock<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value.get().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T: Deserialize<'de>, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<OnceLock<T>, D::Error> {
+        let lock = OnceLock::new();
+        if let Some(value) = Option::<T>::deserialize(deserializer)? {
+            let _ = lock.set(value);
+        }
+        Ok(lock)
+    }
+}
+
+/// # PaysData
+/// Settlement terms of a `pays` statement. Without a settlement lag the cashflow is paid on the
+/// event date, otherwise it is paid `settlement_days` business days later in `calendar`.
+/// Cashflows with a `leg` label are aggregated per leg and currency by the evaluator.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct PaysData {
+    settlement_days: Option<i32>,
+    calendar: Option<String>,
+    leg: Option<String>,
+    currency: Option<Currency>,
+}
+
+impl PaysData {
+    pub fn new() -> PaysData {
+        PaysData::default()
+    }
+
+    pub fn with_settlement(mut self, days: i32, calendar: String) -> Self {
+        self.settlement_days = Some(days);
+        self.calendar = Some(calendar);
+        self
+    }
+
+    pub fn with_leg(mut self, leg: String) -> Self {
+        self.leg = Some(leg);
+        self
+    }
+
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    pub fn settlement_days(&self) -> Option<i32> {
+        self.settlement_days
+    }
+
+    pub fn calendar(&self) -> Option<&String> {
+        self.calendar.as_ref()
+    }
+
+    pub fn leg(&self) -> Option<&String> {
+        self.leg.as_ref()
+    }
+
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+}
+
+/// # Node
+/// Node of the expression tree. Nodes can be serialized, variable and market data ids set by the
+/// `EventIndexer` are kept so an indexed tree can be reloaded without reparsing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Node<T: Real = f64> {
     Base(Vec<ExprTree>),
+    Spanned(Vec<ExprTree>, Span),
 
     // variables
-    Variable(Vec<ExprTree>, String, OnceLock<usize>),
+    Variable(Vec<ExprTree>, String, #[serde(with = "once_lock")] OnceLock<usize>),
     Constant(T),
     String(String),
+    Param(Vec<ExprTree>, ValueType),
 
     // financial
-    Spot(Currency, Option<Currency>, OnceLock<usize>),
-    RateIndex(String, Date, Date, OnceLock<usize>),
-    Pays(Vec<ExprTree>, OnceLock<usize>),
+    Spot(Currency, Option<Currency>, #[serde(with = "once_lock")] OnceLock<usize>),
+    RateIndex(String, Date, Date, #[serde(with = "once_lock")] OnceLock<usize>),
+    Fixing(String, Date, #[serde(with = "once_lock")] OnceLock<usize>),
+    Equity(String, Option<Date>, #[serde(with = "once_lock")] OnceLock<usize>),
+    Volatility(String, Option<Date>, #[serde(with = "once_lock")] OnceLock<usize>),
+    Correlation(String, String, #[serde(with = "once_lock")] OnceLock<usize>),
+    // window, up and out flags, monitored dates and id of the first observation
+    BarrierHit(
+        Vec<ExprTree>,
+        Date,
+        Date,
+        bool,
+        bool,
+        #[serde(with = "once_lock")] OnceLock<Vec<Date>>,
+        #[serde(with = "once_lock")] OnceLock<usize>,
+    ),
+    Pays(Vec<ExprTree>, PaysData, #[serde(with = "once_lock")] OnceLock<usize>),
+    Exercise(Vec<ExprTree>, #[serde(with = "once_lock")] OnceLock<usize>),
 
     // math
     Add(Vec<ExprTree>),
@@ -33,6 +128,12 @@ pub enum Node<T: Real = f64> {
     Pow(Vec<ExprTree>),
     Ln(Vec<ExprTree>),
     Cvg(Vec<ExprTree>),
+    Accrual(Vec<ExprTree>),
+
+    // dates
+    Adjust(Vec<ExprTree>),
+    IsBusinessDay(Vec<ExprTree>),
+    AddTenor(Vec<ExprTree>),
 
     // unary
     UnaryPlus(Vec<ExprTree>),
@@ -51,8 +152,21 @@ pub enum Node<T: Real = f64> {
     SuperiorOrEqual(Vec<ExprTree>),
     InferiorOrEqual(Vec<ExprTree>),
 
+    // arrays
+    List(Vec<ExprTree>),
+    Index(Vec<ExprTree>),
+    Append(Vec<ExprTree>),
+    MatMul(Vec<ExprTree>),
+    Transpose(Vec<ExprTree>),
+    Solve(Vec<ExprTree>),
+    Schedule(Vec<ExprTree>),
+
     // control flow
     If(Vec<ExprTree>, Option<usize>),
+    ForEach(Vec<ExprTree>),
+    Break,
+    Continue,
+    Assert(Vec<ExprTree>, String),
 }
 
 impl Node {
@@ -108,6 +222,22 @@ impl Node {
         Node::Cvg(Vec::new())
     }
 
+    pub fn new_accrual() -> Node {
+        Node::Accrual(Vec::new())
+    }
+
+    pub fn new_adjust() -> Node {
+        Node::Adjust(Vec::new())
+    }
+
+    pub fn new_is_business_day() -> Node {
+        Node::IsBusinessDay(Vec::new())
+    }
+
+    pub fn new_add_tenor() -> Node {
+        Node::AddTenor(Vec::new())
+    }
+
     pub fn new_constant(value: f64) -> Node {
         Node::Constant(value)
     }
@@ -172,8 +302,44 @@ impl Node {
         Node::False
     }
 
+    pub fn new_list() -> Node {
+        Node::List(Vec::new())
+    }
+
+    pub fn new_index() -> Node {
+        Node::Index(Vec::new())
+    }
+
+    pub fn new_append() -> Node {
+        Node::Append(Vec::new())
+    }
+
+    pub fn new_matmul() -> Node {
+        Node::MatMul(Vec::new())
+    }
+
+    pub fn new_transpose() -> Node {
+        Node::Transpose(Vec::new())
+    }
+
+    pub fn new_solve() -> Node {
+        Node::Solve(Vec::new())
+    }
+
+    pub fn new_schedule() -> Node {
+        Node::Schedule(Vec::new())
+    }
+
+    pub fn new_for_each() -> Node {
+        Node::ForEach(Vec::new())
+    }
+
     pub fn new_pays() -> Node {
-        Node::Pays(Vec::new(), OnceLock::new())
+        Node::Pays(Vec::new(), PaysData::new(), OnceLock::new())
+    }
+
+    pub fn new_exercise() -> Node {
+        Node::Exercise(Vec::new(), OnceLock::new())
     }
 
     pub fn new_spot(first: Currency, second: Option<Currency>) -> Node {
@@ -184,9 +350,30 @@ impl Node {
         Node::RateIndex(name, start, end, OnceLock::new())
     }
 
+    pub fn new_fixing(name: String, date: Date) -> Node {
+        Node::Fixing(name, date, OnceLock::new())
+    }
+
+    pub fn new_equity(name: String, date: Option<Date>) -> Node {
+        Node::Equity(name, date, OnceLock::new())
+    }
+
+    pub fn new_volatility(name: String, date: Option<Date>) -> Node {
+        Node::Volatility(name, date, OnceLock::new())
+    }
+
+    pub fn new_correlation(first: String, second: String) -> Node {
+        Node::Correlation(first, second, OnceLock::new())
+    }
+
+    pub fn new_barrier_hit(start: Date, end: Date, up: bool, out: bool) -> Node {
+        Node::BarrierHit(Vec::new(), start, end, up, out, OnceLock::new(), OnceLock::new())
+    }
+
     pub fn add_child(&mut self, child: ExprTree) {
         match self {
             Node::Base(children) => children.push(child),
+            Node::Spanned(children, _) => children.push(child),
             Node::Add(children) => children.push(child),
             Node::Subtract(children) => children.push(child),
             Node::Multiply(children) => children.push(child),
@@ -210,11 +397,33 @@ impl Node {
             Node::Ln(children) => children.push(child),
             Node::Pow(children) => children.push(child),
             Node::Cvg(children) => children.push(child),
+            Node::Accrual(children) => children.push(child),
+            Node::Adjust(children) => children.push(child),
+            Node::IsBusinessDay(children) => children.push(child),
+            Node::AddTenor(children) => children.push(child),
             Node::NotEqual(children) => children.push(child),
-            Node::Pays(children, _) => children.push(child),
+            Node::Pays(children, _, _) => children.push(child),
+            Node::Exercise(children, _) => children.push(child),
+            Node::Param(children, _) => children.push(child),
+            Node::Assert(children, _) => children.push(child),
+            Node::BarrierHit(children, ..) => children.push(child),
+            Node::List(children) => children.push(child),
+            Node::Index(children) => children.push(child),
+            Node::Append(children) => children.push(child),
+            Node::MatMul(children) => children.push(child),
+            Node::Transpose(children) => children.push(child),
+            Node::Solve(children) => children.push(child),
+            Node::Schedule(children) => children.push(child),
+            Node::ForEach(children) => children.push(child),
             Node::Spot(_, _, _) => panic!("Cannot add child to spot node"),
             Node::RateIndex(_, _, _, _) => panic!("Cannot add child to rate index node"),
+            Node::Fixing(_, _, _) => panic!("Cannot add child to fixing node"),
+            Node::Equity(_, _, _) => panic!("Cannot add child to equity node"),
+            Node::Volatility(_, _, _) => panic!("Cannot add child to volatility node"),
+            Node::Correlation(_, _, _) => panic!("Cannot add child to correlation node"),
             Node::True => panic!("Cannot add child to true node"),
+            Node::Break => panic!("Cannot add child to break node"),
+            Node::Continue => panic!("Cannot add child to continue node"),
             Node::False => panic!("Cannot add child to false node"),
             Node::Constant(_) => panic!("Cannot add child to constant node"),
             Node::String(_) => panic!("Cannot add child to string node"),
@@ -224,6 +433,7 @@ impl Node {
     pub fn children(&self) -> &Vec<ExprTree> {
         match self {
             Node::Base(children) => children,
+            Node::Spanned(children, _) => children,
             Node::Add(children) => children,
             Node::Subtract(children) => children,
             Node::Multiply(children) => children,
@@ -247,13 +457,116 @@ impl Node {
             Node::Ln(children) => children,
             Node::Pow(children) => children,
             Node::Cvg(children) => children,
+            Node::Accrual(children) => children,
+            Node::Adjust(children) => children,
+            Node::IsBusinessDay(children) => children,
+            Node::AddTenor(children) => children,
             Node::NotEqual(children) => children,
-            Node::Pays(children, _) => children,
+            Node::Pays(children, _, _) => children,
+            Node::Exercise(children, _) => children,
+            Node::Param(children, _) => children,
+            Node::Assert(children, _) => children,
+            Node::BarrierHit(children, ..) => children,
+            Node::List(children) => children,
+            Node::Index(children) => children,
+            Node::Append(children) => children,
+            Node::MatMul(children) => children,
+            Node::Transpose(children) => children,
+            Node::Solve(children) => children,
+            Node::Schedule(children) => children,
+            Node::ForEach(children) => children,
             Node::Spot(_, _, _) => panic!("Cannot get children from spot node"),
             Node::RateIndex(_, _, _, _) => {
                 panic!("Cannot get children from rate index node")
             }
+            Node::Fixing(_, _, _) => panic!("Cannot get children from fixing node"),
+            Node::Equity(_, _, _) => panic!("Cannot get children from equity node"),
+            Node::Volatility(_, _, _) => panic!("Cannot get children from volatility node"),
+            Node::Correlation(_, _, _) => panic!("Cannot get children from correlation node"),
             Node::True => panic!("Cannot get children from true node"),
+            Node::Break => panic!("Cannot get children from break node"),
+            Node::Continue => panic!("Cannot get children from continue node"),
+            Node::False => panic!("Cannot get children from false node"),
+            Node::Constant(_) => panic!("Cannot get children from constant node"),
+            Node::String(_) => panic!("Cannot get children from string node"),
+        }
+    }
+
+    /// Whether the node holds no child expressions
+    pub(crate) fn is_leaf(&self) -> bool {
+        matches!(
+            self,
+            Node::Constant(_)
+                | Node::String(_)
+                | Node::True
+                | Node::False
+                | Node::Break
+                | Node::Continue
+                | Node::Spot(_, _, _)
+                | Node::RateIndex(_, _, _, _)
+                | Node::Fixing(_, _, _)
+                | Node::Equity(_, _, _)
+                | Node::Volatility(_, _, _)
+                | Node::Correlation(_, _, _)
+        )
+    }
+
+    pub fn children_mut(&mut self) -> &mut Vec<ExprTree> {
+        match self {
+            Node::Base(children) => children,
+            Node::Spanned(children, _) => children,
+            Node::Add(children) => children,
+            Node::Subtract(children) => children,
+            Node::Multiply(children) => children,
+            Node::Divide(children) => children,
+            Node::Variable(children, _, _) => children,
+            Node::Assign(children) => children,
+            Node::And(children) => children,
+            Node::Or(children) => children,
+            Node::Not(children) => children,
+            Node::Superior(children) => children,
+            Node::Inferior(children) => children,
+            Node::SuperiorOrEqual(children) => children,
+            Node::InferiorOrEqual(children) => children,
+            Node::Equal(children) => children,
+            Node::If(children, _) => children,
+            Node::UnaryPlus(children) => children,
+            Node::UnaryMinus(children) => children,
+            Node::Min(children) => children,
+            Node::Max(children) => children,
+            Node::Exp(children) => children,
+            Node::Ln(children) => children,
+            Node::Pow(children) => children,
+            Node::Cvg(children) => children,
+            Node::Accrual(children) => children,
+            Node::Adjust(children) => children,
+            Node::IsBusinessDay(children) => children,
+            Node::AddTenor(children) => children,
+            Node::NotEqual(children) => children,
+            Node::Pays(children, _, _) => children,
+            Node::Exercise(children, _) => children,
+            Node::Param(children, _) => children,
+            Node::Assert(children, _) => children,
+            Node::BarrierHit(children, ..) => children,
+            Node::List(children) => children,
+            Node::Index(children) => children,
+            Node::Append(children) => children,
+            Node::MatMul(children) => children,
+            Node::Transpose(children) => children,
+            Node::Solve(children) => children,
+            Node::Schedule(children) => children,
+            Node::ForEach(children) => children,
+            Node::Spot(_, _, _) => panic!("Cannot get children from spot node"),
+            Node::RateIndex(_, _, _, _) => {
+                panic!("Cannot get children from rate index node")
+            }
+            Node::Fixing(_, _, _) => panic!("Cannot get children from fixing node"),
+            Node::Equity(_, _, _) => panic!("Cannot get children from equity node"),
+            Node::Volatility(_, _, _) => panic!("Cannot get children from volatility node"),
+            Node::Correlation(_, _, _) => panic!("Cannot get children from correlation node"),
+            Node::True => panic!("Cannot get children from true node"),
+            Node::Break => panic!("Cannot get children from break node"),
+            Node::Continue => panic!("Cannot get children from continue node"),
             Node::False => panic!("Cannot get children from false node"),
             Node::Constant(_) => panic!("Cannot get children from constant node"),
             Node::String(_) => panic!("Cannot get children from string node"),
@@ -271,7 +584,7 @@ impl Visitable for Box<Node> {
 impl ConstVisitable for Box<Node> {
     type Output = ();
     fn const_accept(&self, visitor: &impl NodeConstVisitor) {
-        visitor.const_visit(self.clone());
+        visitor.const_visit(self);
     }
 }
 
@@ -373,6 +686,27 @@ mod ai_gen_tests {
         assert_eq!(node, Node::Cvg(Vec::new()));
     }
 
+    #[test]
+    fn test_new_accrual() {
+        // Test the creation of a new accrual node
+        let node = Node::new_accrual();
+        assert_eq!(node, Node::Accrual(Vec::new()));
+    }
+
+    #[test]
+    fn test_new_adjust() {
+        // Test the creation of a new adjust node
+        let node = Node::new_adjust();
+        assert_eq!(node, Node::Adjust(Vec::new()));
+    }
+
+    #[test]
+    fn test_new_add_tenor() {
+        // Test the creation of a new add tenor node
+        let node = Node::new_add_tenor();
+        assert_eq!(node, Node::AddTenor(Vec::new()));
+    }
+
     #[test]
     fn test_new_constant() {
         // Test the creation of a new constant node
@@ -489,7 +823,35 @@ mod ai_gen_tests {
     fn test_new_pays() {
         // Test the creation of a new pays node
         let node = Node::new_pays();
-        assert_eq!(node, Node::Pays(Vec::new(), OnceLock::new()));
+        assert_eq!(node, Node::Pays(Vec::new(), PaysData::new(), OnceLock::new()));
+    }
+
+    #[test]
+    fn test_new_list() {
+        // Test the creation of a new list node
+        let node = Node::new_list();
+        assert_eq!(node, Node::List(Vec::new()));
+    }
+
+    #[test]
+    fn test_new_for_each() {
+        // Test the creation of a new for each node
+        let node = Node::new_for_each();
+        assert_eq!(node, Node::ForEach(Vec::new()));
+    }
+
+    #[test]
+    fn test_new_matmul() {
+        // Test the creation of a new matmul node
+        let node = Node::new_matmul();
+        assert_eq!(node, Node::MatMul(Vec::new()));
+    }
+
+    #[test]
+    fn test_new_schedule() {
+        // Test the creation of a new schedule node
+        let node = Node::new_schedule();
+        assert_eq!(node, Node::Schedule(Vec::new()));
     }
 
     #[test]
@@ -503,6 +865,65 @@ mod ai_gen_tests {
         );
     }
 
+    #[test]
+    fn test_new_fixing() {
+        let date = Date::new(2024, 1, 1);
+        let node = Node::new_fixing("SOFR-3M".to_string(), date);
+        assert_eq!(
+            node,
+            Node::Fixing("SOFR-3M".to_string(), date, OnceLock::new())
+        );
+    }
+
+    #[test]
+    fn test_new_equity() {
+        let node = Node::new_equity("AAPL".to_string(), None);
+        assert_eq!(node, Node::Equity("AAPL".to_string(), None, OnceLock::new()));
+    }
+
+    #[test]
+    fn test_new_volatility() {
+        let node = Node::new_volatility("EUR/USD".to_string(), None);
+        assert_eq!(
+            node,
+            Node::Volatility("EUR/USD".to_string(), None, OnceLock::new())
+        );
+    }
+
+    #[test]
+    fn test_new_barrier_hit() {
+        let start = Date::new(2024, 1, 1);
+        let end = Date::new(2024, 2, 1);
+        let node = Node::new_barrier_hit(start, end, true, false);
+        assert_eq!(
+            node,
+            Node::BarrierHit(
+                Vec::new(),
+                start,
+                end,
+                true,
+                false,
+                OnceLock::new(),
+                OnceLock::new()
+            )
+        );
+    }
+
+    #[test]
+    fn test_new_exercise() {
+        let node = Node::new_exercise();
+        assert_eq!(node, Node::Exercise(Vec::new(), OnceLock::new()));
+    }
+
+    #[test]
+    fn test_new_correlation() {
+        let node = Node::new_correlation("AAPL".to_string(), "MSFT".to_string());
+        assert_eq!(
+            node,
+            Node::Correlation("AAPL".to_string(), "MSFT".to_string(), OnceLock::new())
+        );
+    }
+
     #[test]
     fn test_add_child_to_base() {
         // Test adding a child to a base node
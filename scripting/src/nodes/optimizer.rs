@@ -0,0 +1,633 @@
+use std::{cell::RefCell, ops::Range, sync::OnceLock};
+
+use crate::prelude::*;
+
+/// Apply a pass to every event of a stream, keeping the event dates and the stream id
+fn transform_events(events: &EventStream, pass: impl Fn(&Node) -> ExprTree) -> EventStream {
+    let stream = EventStream::new().with_events(
+        events
+            .events()
+            .iter()
+            .map(|event| Event::new(event.event_date(), pass(event.expr())))
+            .collect(),
+    );
+    match events.id() {
+        Some(id) => stream.with_id(id),
+        None => stream,
+    }
+}
+
+/// # ConstantFolder
+/// Optimization pass that replaces subtrees made only of constants, e.g. `0.5 * 100 / 360`,
+/// by their value. The same tree is evaluated on every scenario, so the work is saved once per
+/// path. Folding follows the evaluator semantics. Operations with an infinite or NaN result,
+/// e.g. `1 / 0`, are left for the evaluator to apply its `NonFinitePolicy`.
+pub struct ConstantFolder;
+
+impl ConstantFolder {
+    pub fn new() -> Self {
+        ConstantFolder
+    }
+
+    /// # visit_events
+    /// Fold the constants of every event in the stream
+    pub fn visit_events(&self, events: &EventStream) -> EventStream {
+        transform_events(events, |expr| self.const_visit(expr))
+    }
+
+    fn constants(children: &[ExprTree]) -> Option<Vec<f64>> {
+        children
+            .iter()
+            .map(|child| match child.as_ref() {
+                Node::Constant(value) => Some(*value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn booleans(children: &[ExprTree]) -> Option<Vec<bool>> {
+        children
+            .iter()
+            .map(|child| match child.as_ref() {
+                Node::True => Some(true),
+                Node::False => Some(false),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn boolean(value: bool) -> Node {
+        if value {
+            Node::True
+        } else {
+            Node::False
+        }
+    }
+
+    fn fold(node: &Node) -> Option<Node> {
+        let finite = |value: f64| value.is_finite().then_some(Node::Constant(value));
+        let unary = |children: &Vec<ExprTree>, f: fn(f64) -> f64| match Self::constants(children)?[..]
+        {
+            [a] => finite(f(a)),
+            _ => None,
+        };
+        let binary =
+            |children: &Vec<ExprTree>, f: fn(f64, f64) -> f64| match Self::constants(children)?[..]
+            {
+                [a, b] => finite(f(a, b)),
+                _ => None,
+            };
+        let compare = |children: &Vec<ExprTree>, f: fn(f64, f64) -> bool| match Self::constants(
+            children,
+        )?[..]
+        {
+            [a, b] => Some(Self::boolean(f(a, b))),
+            _ => None,
+        };
+        let logic = |children: &Vec<ExprTree>, f: fn(bool, bool) -> bool| match Self::booleans(
+            children,
+        )?[..]
+        {
+            [a, b] => Some(Self::boolean(f(a, b))),
+            _ => None,
+        };
+
+        match node {
+            Node::Add(children) => binary(children, |a, b| a + b),
+            Node::Subtract(children) => binary(children, |a, b| a - b),
+            Node::Multiply(children) => binary(children, |a, b| a * b),
+            Node::Divide(children) => binary(children, |a, b| a / b),
+            Node::Min(children) => binary(children, f64::min),
+            Node::Max(children) => binary(children, f64::max),
+            Node::Pow(children) => binary(children, f64::powf),
+            Node::Exp(children) => unary(children, f64::exp),
+            Node::Ln(children) => unary(children, f64::ln),
+            Node::UnaryPlus(children) => unary(children, |a| a),
+            Node::UnaryMinus(children) => unary(children, |a| -a),
+            Node::Equal(children) => compare(children, |a, b| (b - a).abs() < f64::EPSILON),
+            Node::NotEqual(children) => compare(children, |a, b| (b - a).abs() >= f64::EPSILON),
+            Node::Superior(children) => compare(children, |a, b| a > b),
+            Node::Inferior(children) => compare(children, |a, b| a < b),
+            Node::SuperiorOrEqual(children) => compare(children, |a, b| a >= b),
+            Node::InferiorOrEqual(children) => compare(children, |a, b| a <= b),
+            Node::And(children) => logic(children, |a, b| a && b),
+            Node::Or(children) => logic(children, |a, b| a || b),
+            Node::Not(children) => match Self::booleans(children)?[..] {
+                [a] => Some(Self::boolean(!a)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Rewrite the tree, reusing its nodes
+    fn transform(&self, mut node: ExprTree) -> ExprTree {
+        if node.is_leaf() {
+            return node;
+        }
+        let children = std::mem::take(node.children_mut());
+        *node.children_mut() = children
+            .into_iter()
+            .map(|child| self.transform(child))
+            .collect();
+        match Self::fold(&node) {
+            Some(folded) => Box::new(folded),
+            None => node,
+        }
+    }
+}
+
+impl NodeConstVisitor for ConstantFolder {
+    type Output = ExprTree;
+
+    fn const_visit(&self, node: &Node) -> Self::Output {
+        self.transform(Box::new(node.clone()))
+    }
+}
+
+/// # DeadCodeEliminator
+/// Optimization pass that removes the branches of `if true` and `if false`, the statements left
+/// empty by it, the statements following a `break` or `continue` and no-op arithmetic such as
+/// `x * 1`, `x / 1`, `x + 0` and `x - 0`. Conditions are not evaluated, so it is meant to run
+/// after the `ConstantFolder`.
+pub struct DeadCodeEliminator;
+
+impl DeadCodeEliminator {
+    pub fn new() -> Self {
+        DeadCodeEliminator
+    }
+
+    /// # visit_events
+    /// Remove the dead code of every event in the stream
+    pub fn visit_events(&self, events: &EventStream) -> EventStream {
+        transform_events(events, |expr| self.const_visit(expr))
+    }
+
+    fn is_constant(node: &ExprTree, value: f64) -> bool {
+        matches!(node.as_ref(), Node::Constant(v) if *v == value)
+    }
+
+    fn is_empty(node: &ExprTree) -> bool {
+        match node.as_ref() {
+            Node::Base(children) | Node::Spanned(children, _) => {
+                children.iter().all(Self::is_empty)
+            }
+            _ => false,
+        }
+    }
+
+    fn is_jump(node: &ExprTree) -> bool {
+        match node.as_ref() {
+            Node::Break | Node::Continue => true,
+            Node::Spanned(children, _) => children.iter().any(Self::is_jump),
+            _ => false,
+        }
+    }
+
+    /// Drop the statements that can never run because they follow a jump
+    fn reachable(mut statements: Vec<ExprTree>) -> Vec<ExprTree> {
+        if let Some(jump) = statements.iter().position(Self::is_jump) {
+            statements.truncate(jump + 1);
+        }
+        statements
+    }
+
+    fn simplify(node: Box<Node>) -> Box<Node> {
+        match *node {
+            Node::If(mut children, first_else) => match children[0].as_ref() {
+                Node::True => {
+                    children.truncate(first_else.map_or(children.len(), |i| i + 1));
+                    Box::new(Node::Base(children.split_off(1)))
+                }
+                Node::False => match first_else {
+                    Some(first_else) => Box::new(Node::Base(children.split_off(first_else + 1))),
+                    None => Box::new(Node::Base(Vec::new())),
+                },
+                _ => Box::new(Node::If(children, first_else)),
+            },
+            Node::Base(children) => Box::new(Node::Base(Self::reachable(
+                children
+                    .into_iter()
+                    .filter(|child| !Self::is_empty(child))
+                    .collect(),
+            ))),
+            Node::ForEach(mut children) => {
+                let body = Self::reachable(children.split_off(2));
+                children.extend(body);
+                Box::new(Node::ForEach(children))
+            }
+            Node::Add(mut children) if children.len() == 2 => {
+                if Self::is_constant(&children[1], 0.0) {
+                    children.swap_remove(0)
+                } else if Self::is_constant(&children[0], 0.0) {
+                    children.swap_remove(1)
+                } else {
+                    Box::new(Node::Add(children))
+                }
+            }
+            Node::Multiply(mut children) if children.len() == 2 => {
+                if Self::is_constant(&children[1], 1.0) {
+                    children.swap_remove(0)
+                } else if Self::is_constant(&children[0], 1.0) {
+                    children.swap_remove(1)
+                } else {
+                    Box::new(Node::Multiply(children))
+                }
+            }
+            Node::Subtract(mut children)
+                if children.len() == 2 && Self::is_constant(&children[1], 0.0) =>
+            {
+                children.swap_remove(0)
+            }
+            Node::Divide(mut children)
+                if children.len() == 2 && Self::is_constant(&children[1], 1.0) =>
+            {
+                children.swap_remove(0)
+            }
+            node => Box::new(node),
+        }
+    }
+
+    /// Rewrite the tree, reusing its nodes
+    fn transform(&self, mut node: ExprTree) -> ExprTree {
+        if node.is_leaf() {
+            return node;
+        }
+        let children = std::mem::take(node.children_mut());
+        *node.children_mut() = children
+            .into_iter()
+            .map(|child| self.transform(child))
+            .collect();
+        Self::simplify(node)
+    }
+}
+
+impl NodeConstVisitor for DeadCodeEliminator {
+    type Output = ExprTree;
+
+    fn const_visit(&self, node: &Node) -> Self::Output {
+        self.transform(Box::new(node.clone()))
+    }
+}
+
+/// # CommonSubexpressionEliminator
+/// Optimization pass that evaluates market observations and the arithmetic built only on them,
+/// e.g. `Spot("EUR", "USD") * 100`, once per event. Subtrees found more than once are assigned
+/// to hidden `__cse` variables at the start of the event and replaced by them. The statements of
+/// `if` branches and loop bodies do not always run, so they are rewritten on their own with their
+/// temporaries at the start of the branch or body. Temporaries need variable ids, so the pass
+/// must run before the `EventIndexer`.
+pub struct CommonSubexpressionEliminator {
+    temporaries: RefCell<usize>,
+}
+
+impl CommonSubexpressionEliminator {
+    pub fn new() -> Self {
+        CommonSubexpressionEliminator {
+            temporaries: RefCell::new(0),
+        }
+    }
+
+    /// # visit_events
+    /// Eliminate the common subexpressions of every event in the stream. Temporaries are named
+    /// uniquely across the stream.
+    pub fn visit_events(&self, events: &EventStream) -> EventStream {
+        transform_events(events, |expr| self.const_visit(expr))
+    }
+
+    fn new_temporary(&self) -> ExprTree {
+        let mut count = self.temporaries.borrow_mut();
+        let name = format!("__cse{}", *count);
+        *count += 1;
+        Box::new(Node::Variable(Vec::new(), name, OnceLock::new()))
+    }
+
+    /// Whether the node only depends on market data and constants
+    fn is_pure(node: &Node) -> bool {
+        match node {
+            Node::Constant(_)
+            | Node::Spot(_, _, _)
+            | Node::RateIndex(_, _, _, _)
+            | Node::Fixing(_, _, _)
+            | Node::Equity(_, _, _)
+            | Node::Volatility(_, _, _)
+            | Node::Correlation(_, _, _) => true,
+            Node::Add(children)
+            | Node::Subtract(children)
+            | Node::Multiply(children)
+            | Node::Divide(children)
+            | Node::Min(children)
+            | Node::Max(children)
+            | Node::Exp(children)
+            | Node::Pow(children)
+            | Node::Ln(children)
+            | Node::UnaryPlus(children)
+            | Node::UnaryMinus(children) => children.iter().all(|child| Self::is_pure(child)),
+            _ => false,
+        }
+    }
+
+    fn is_candidate(node: &Node) -> bool {
+        !matches!(node, Node::Constant(_)) && Self::is_pure(node)
+    }
+
+    /// Children that may be replaced, always evaluated with the node. Assigned variables are
+    /// kept, barrier observations need the market node itself and only the condition of an `if`
+    /// and the list of a loop are evaluated in the enclosing block.
+    fn shared(node: &Node) -> Range<usize> {
+        match node {
+            Node::BarrierHit(..) => 0..0,
+            Node::If(..) => 0..1,
+            Node::ForEach(_) => 1..2,
+            Node::Assign(children) => 1..children.len(),
+            _ => 0..node.children().len(),
+        }
+    }
+
+    fn count(node: &ExprTree, counts: &mut Vec<(ExprTree, usize)>) {
+        if Self::is_candidate(node) {
+            match counts.iter_mut().find(|(expr, _)| expr == node) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((node.clone(), 1)),
+            }
+        }
+        if !node.is_leaf() {
+            node.children()[Self::shared(node)]
+                .iter()
+                .for_each(|child| Self::count(child, counts));
+        }
+    }
+
+    fn replace(
+        &self,
+        mut node: ExprTree,
+        counts: &[(ExprTree, usize)],
+        hoisted: &mut Vec<(ExprTree, ExprTree)>,
+    ) -> ExprTree {
+        if counts.iter().any(|(expr, n)| *n > 1 && *expr == node) {
+            if let Some((_, variable)) = hoisted.iter().find(|(expr, _)| *expr == node) {
+                return variable.clone();
+            }
+            let variable = self.new_temporary();
+            hoisted.push((node, variable.clone()));
+            return variable;
+        }
+        if node.is_leaf() {
+            return node;
+        }
+        let shared = Self::shared(&node);
+        let children = std::mem::take(node.children_mut());
+        *node.children_mut() = children
+            .into_iter()
+            .enumerate()
+            .map(|(i, child)| {
+                if shared.contains(&i) {
+                    self.replace(child, counts, hoisted)
+                } else {
+                    child
+                }
+            })
+            .collect();
+        node
+    }
+
+    /// Rewrite statements run in sequence, assigning the subtrees repeated among them to
+    /// temporaries at their start
+    fn transform_block(&self, statements: Vec<ExprTree>) -> Vec<ExprTree> {
+        let statements: Vec<ExprTree> = statements
+            .into_iter()
+            .map(|statement| self.transform_nested(statement))
+            .collect();
+        let mut counts = Vec::new();
+        statements
+            .iter()
+            .for_each(|statement| Self::count(statement, &mut counts));
+
+        let mut hoisted = Vec::new();
+        let statements: Vec<ExprTree> = statements
+            .into_iter()
+            .map(|statement| self.replace(statement, &counts, &mut hoisted))
+            .collect();
+        hoisted
+            .into_iter()
+            .map(|(expr, variable)| Box::new(Node::Assign(vec![variable, expr])))
+            .chain(statements)
+            .collect()
+    }
+
+    /// Rewrite the branches and loop bodies of a statement as blocks of their own
+    fn transform_nested(&self, mut node: ExprTree) -> ExprTree {
+        match node.as_mut() {
+            Node::Spanned(children, _) => {
+                let statements = std::mem::take(children);
+                *children = statements
+                    .into_iter()
+                    .map(|statement| self.transform_nested(statement))
+                    .collect();
+            }
+            Node::If(children, first_else) => {
+                let else_branch = children.split_off(first_else.map_or(children.len(), |i| i + 1));
+                let then_branch = children.split_off(1);
+                children.extend(self.transform_block(then_branch));
+                if first_else.is_some() {
+                    *first_else = Some(children.len() - 1);
+                }
+                children.extend(self.transform_block(else_branch));
+            }
+            Node::ForEach(children) => {
+                let body = children.split_off(2);
+                children.extend(self.transform_block(body));
+            }
+            _ => {}
+        }
+        node
+    }
+
+    /// Rewrite the tree, reusing its nodes
+    fn transform(&self, node: Node) -> ExprTree {
+        match node {
+            Node::Base(children) => Box::new(Node::Base(self.transform_block(children))),
+            node => {
+                let mut statements = self.transform_block(vec![Box::new(node)]);
+                match statements.len() {
+                    1 => statements.remove(0),
+                    _ => Box::new(Node::Base(statements)),
+                }
+            }
+        }
+    }
+}
+
+impl NodeConstVisitor for CommonSubexpressionEliminator {
+    type Output = ExprTree;
+
+    fn const_visit(&self, node: &Node) -> Self::Output {
+        self.transform(node.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fold(script: &str) -> ExprTree {
+        let tokens = Lexer::new(script.to_string()).tokenize().unwrap();
+        ConstantFolder::new().const_visit(&Parser::new(tokens).parse().unwrap())
+    }
+
+    fn parse(script: &str) -> ExprTree {
+        let tokens = Lexer::new(script.to_string()).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn test_fold_arithmetic() {
+        assert_eq!(fold("x = 0.5 * 720 / 360;"), parse("x = 1;"));
+        assert_eq!(fold("x = max(2, 3) ** 2;"), parse("x = 9;"));
+        assert_eq!(fold("x = y * 0.5 * 4;"), parse("x = y * 0.5 * 4;"));
+    }
+
+    #[test]
+    fn test_fold_keeps_non_finite_operations() {
+        assert_eq!(fold("x = 1 / 0;"), parse("x = 1 / 0;"));
+        assert_eq!(fold("x = ln(0) * 2;"), parse("x = ln(0) * 2;"));
+
+        // so the evaluator applies its policy
+        let folded = fold("x = 1 / 0;");
+        let evaluator = ExprEvaluator::new()
+            .with_variables(1)
+            .with_non_finite_policy(NonFinitePolicy::Error);
+        assert!(evaluator.const_visit(&folded).is_err());
+    }
+
+    #[test]
+    fn test_fold_conditions() {
+        let folded = fold("if 1 < 2 and 3 == 3 { x = 1; }");
+        match folded.children()[0].as_ref() {
+            Node::If(children, _) => assert_eq!(*children[0], Node::True),
+            _ => panic!("Expected an if node"),
+        }
+    }
+
+    #[test]
+    fn test_fold_keeps_market_nodes() {
+        let folded = fold("x = Stock(\"AAPL\") * 100 / 50;");
+        assert_eq!(folded, parse("x = Stock(\"AAPL\") * 100 / 50;"));
+    }
+
+    #[test]
+    fn test_fold_events() {
+        let events = EventStream::new().with_id(3).with_events(vec![Event::new(
+            rustatlas::prelude::Date::new(2024, 1, 1),
+            "x = 2 * 3;".try_into().unwrap(),
+        )]);
+        let folded = ConstantFolder::new().visit_events(&events);
+
+        assert_eq!(folded.id(), Some(3));
+        assert_eq!(
+            *folded.events()[0].expr(),
+            ExprTree::try_from("x = 6;").unwrap()
+        );
+    }
+
+    fn eliminate(script: &str) -> ExprTree {
+        DeadCodeEliminator::new().const_visit(&fold(script))
+    }
+
+    #[test]
+    fn test_eliminate_dead_branches() {
+        let y = parse("y = 3;").children()[0].clone();
+        assert_eq!(
+            eliminate("if 1 > 2 { x = 1; } else { x = 2; } y = 3;"),
+            Box::new(Node::Base(vec![parse("x = 2;"), y]))
+        );
+        assert_eq!(eliminate("if 1 > 2 { x = 1; } y = 3;"), parse("y = 3;"));
+        assert_eq!(
+            eliminate("if 1 < 2 { x = 1; }"),
+            Box::new(Node::Base(vec![parse("x = 1;")]))
+        );
+    }
+
+    #[test]
+    fn test_eliminate_after_jump() {
+        assert_eq!(
+            eliminate("for v in [1] { x = v; break; x = 2; }"),
+            parse("for v in [1] { x = v; break; }")
+        );
+    }
+
+    #[test]
+    fn test_eliminate_no_ops() {
+        assert_eq!(eliminate("x = y * 1 + 0;"), parse("x = y;"));
+        assert_eq!(eliminate("x = 1 * y / 1 - 0;"), parse("x = y;"));
+        assert_eq!(eliminate("x = y * 2;"), parse("x = y * 2;"));
+    }
+
+    #[test]
+    fn test_eliminate_common_subexpressions() {
+        let cse = CommonSubexpressionEliminator::new();
+        let optimized = cse.const_visit(&parse(
+            "x = Stock(\"AAPL\") * 2; y = Stock(\"AAPL\") * 2 + Stock(\"MSFT\");",
+        ));
+        // temporaries cannot be written in scripts, compare with a renamed variable
+        let expected = parse("t = Stock(\"AAPL\") * 2; x = t; y = t + Stock(\"MSFT\");");
+        assert_eq!(
+            format!("{:?}", optimized),
+            format!("{:?}", expected).replace("\"t\"", "\"__cse0\"")
+        );
+        assert_eq!(cse.const_visit(&parse("x = y * 2; z = y * 2;")), parse("x = y * 2; z = y * 2;"));
+    }
+
+    #[test]
+    fn test_common_subexpressions_stay_in_branches() {
+        let cse = CommonSubexpressionEliminator::new();
+        // the branch may not run, so its observations are not hoisted before the condition
+        let script = "x = Stock(\"AAPL\") / 2; if x > 1 { y = Stock(\"AAPL\") / 2; }";
+        assert_eq!(cse.const_visit(&parse(script)), parse(script));
+
+        let optimized = cse.const_visit(&parse(
+            "if x > 1 { y = ln(Stock(\"AAPL\")); z = ln(Stock(\"AAPL\")); } else { y = 0; }",
+        ));
+        let expected = parse("if x > 1 { t = ln(Stock(\"AAPL\")); y = t; z = t; } else { y = 0; }");
+        assert_eq!(
+            format!("{:?}", optimized),
+            format!("{:?}", expected).replace("\"t\"", "\"__cse0\"")
+        );
+    }
+
+    #[test]
+    fn test_common_subexpressions_requests() {
+        let script = "x = Spot(\"EUR\", \"USD\") * 100; if x > 1 { y = Spot(\"EUR\", \"USD\") * 100; }";
+
+        let indexer = EventIndexer::new();
+        indexer.visit(&parse(script)).unwrap();
+        // the indexer already merges identical requests, the eliminator must not undo that
+        assert_eq!(indexer.get_market_requests().len(), 1);
+
+        let indexer = EventIndexer::new();
+        indexer
+            .visit(&CommonSubexpressionEliminator::new().const_visit(&parse(script)))
+            .unwrap();
+        assert_eq!(indexer.get_market_requests().len(), 1);
+    }
+
+    #[test]
+    fn test_common_subexpressions_events() {
+        let event = |script: &str| {
+            Event::new(
+                rustatlas::prelude::Date::new(2024, 1, 1),
+                script.try_into().unwrap(),
+            )
+        };
+        let events = EventStream::new().with_events(vec![
+            event("x = Stock(\"AAPL\"); y = Stock(\"AAPL\");"),
+            event("x = Stock(\"AAPL\"); y = Stock(\"AAPL\");"),
+        ]);
+        let optimized = CommonSubexpressionEliminator::new().visit_events(&events);
+
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&optimized).unwrap();
+        assert!(indexer.get_variable_index("__cse0").is_some());
+        assert!(indexer.get_variable_index("__cse1").is_some());
+    }
+}
@@ -0,0 +1,405 @@
+use rustatlas::currencies::traits::CurrencyDetails;
+
+use crate::prelude::*;
+
+const INDENT: &str = "    ";
+
+/// # Printer
+/// Visitor that turns a tree back into canonical source: one statement per line, four spaces of
+/// indentation and only the parentheses required by operator precedence. Sugar that the parser
+/// lowers, such as `map` and `filter`, is printed in its lowered form.
+pub struct Printer;
+
+impl Printer {
+    pub fn new() -> Self {
+        Printer
+    }
+
+    /// # visit_events
+    /// Print every event of the stream, preceded by a comment with its date
+    pub fn visit_events(&self, events: &EventStream) -> String {
+        events
+            .events()
+            .iter()
+            .map(|event| format!("# {}\n{}", event.event_date(), self.visit(event.expr())))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn is_statement(node: &Node) -> bool {
+        matches!(
+            node,
+            Node::Base(_)
+                | Node::Spanned(_, _)
+                | Node::Assign(_)
+                | Node::If(_, _)
+                | Node::ForEach(_)
+                | Node::Append(_)
+                | Node::Param(_, _)
+                | Node::Assert(_, _)
+        )
+    }
+
+    /// Binding strength of an operator, following the parser. Higher binds tighter.
+    fn precedence(node: &Node) -> u8 {
+        match node {
+            Node::Pays(_, _, _) => 0,
+            Node::Add(_) | Node::Subtract(_) | Node::And(_) | Node::Or(_) => 1,
+            Node::Equal(_)
+            | Node::NotEqual(_)
+            | Node::Superior(_)
+            | Node::Inferior(_)
+            | Node::SuperiorOrEqual(_)
+            | Node::InferiorOrEqual(_) => 2,
+            Node::Multiply(_) | Node::Divide(_) => 3,
+            Node::Pow(_) => 4,
+            Node::UnaryPlus(_) | Node::UnaryMinus(_) | Node::Not(_) => 5,
+            _ => 6,
+        }
+    }
+
+    fn statements(&self, node: &Node, depth: usize) -> Vec<String> {
+        let pad = INDENT.repeat(depth);
+        match node {
+            Node::Base(children) | Node::Spanned(children, _) => children
+                .iter()
+                .flat_map(|child| self.statements(child, depth))
+                .collect(),
+            Node::Assign(children) => {
+                let lhs = self.expression(&children[0]);
+                match children[1].as_ref() {
+                    // `x pays ...` is parsed as `x = x + pays ...`
+                    Node::Add(rhs)
+                        if rhs[0] == children[0] && matches!(*rhs[1], Node::Pays(..)) =>
+                    {
+                        vec![format!("{}{} {};", pad, lhs, self.expression(&rhs[1]))]
+                    }
+                    rhs => vec![format!("{}{} = {};", pad, lhs, self.expression(rhs))],
+                }
+            }
+            Node::If(children, first_else) => {
+                let split = first_else.map_or(children.len(), |i| i + 1);
+                let mut lines = vec![format!("{}if {} {{", pad, self.expression(&children[0]))];
+                children[1..split]
+                    .iter()
+                    .for_each(|child| lines.extend(self.statements(child, depth + 1)));
+                if first_else.is_some() {
+                    lines.push(format!("{}}} else {{", pad));
+                    children[split..]
+                        .iter()
+                        .for_each(|child| lines.extend(self.statements(child, depth + 1)));
+                }
+                lines.push(format!("{}}}", pad));
+                lines
+            }
+            Node::ForEach(children) => {
+                let mut lines = vec![format!(
+                    "{}for {} in {} {{",
+                    pad,
+                    self.expression(&children[0]),
+                    self.expression(&children[1])
+                )];
+                children[2..]
+                    .iter()
+                    .for_each(|child| lines.extend(self.statements(child, depth + 1)));
+                lines.push(format!("{}}}", pad));
+                lines
+            }
+            Node::Param(children, value_type) => {
+                let type_name = match value_type {
+                    ValueType::Number => "number",
+                    ValueType::Bool => "bool",
+                    ValueType::String => "string",
+                    ValueType::Array => "array",
+                    ValueType::Unknown => "unknown",
+                };
+                vec![format!(
+                    "{}param {}: {};",
+                    pad,
+                    self.expression(&children[0]),
+                    type_name
+                )]
+            }
+            Node::Assert(children, message) => vec![format!(
+                "{}assert({}, {});",
+                pad,
+                self.expression(&children[0]),
+                Self::quoted(message)
+            )],
+            _ => vec![format!("{}{};", pad, self.expression(node))],
+        }
+    }
+
+    /// Print an operand, in parentheses when it binds looser than `min`
+    fn operand(&self, node: &Node, min: u8) -> String {
+        if Self::precedence(node) < min {
+            format!("({})", self.expression(node))
+        } else {
+            self.expression(node)
+        }
+    }
+
+    fn binary(&self, node: &Node, op: &str, children: &[ExprTree]) -> String {
+        let precedence = Self::precedence(node);
+        format!(
+            "{} {} {}",
+            self.operand(&children[0], precedence),
+            op,
+            self.operand(&children[1], precedence + 1)
+        )
+    }
+
+    fn call(&self, name: &str, children: &[ExprTree]) -> String {
+        let args = children
+            .iter()
+            .map(|child| self.expression(child))
+            .collect::<Vec<String>>();
+        format!("{}({})", name, args.join(", "))
+    }
+
+    fn quoted<S: std::fmt::Display>(value: S) -> String {
+        format!("\"{}\"", value)
+    }
+
+    fn expression(&self, node: &Node) -> String {
+        match node {
+            Node::Base(_)
+            | Node::Spanned(_, _)
+            | Node::Assign(_)
+            | Node::If(_, _)
+            | Node::ForEach(_)
+            | Node::Param(_, _)
+            | Node::Assert(_, _) => self.statements(node, 0).join(" "),
+
+            // variables
+            Node::Variable(_, name, _) => name.clone(),
+            Node::Constant(value) => value.to_string(),
+            Node::String(value) => Self::quoted(value),
+
+            // financial
+            Node::Spot(first, second, _) => match second {
+                Some(second) => format!(
+                    "Spot({}, {})",
+                    Self::quoted(first.code()),
+                    Self::quoted(second.code())
+                ),
+                None => format!("Spot({})", Self::quoted(first.code())),
+            },
+            Node::RateIndex(name, start, end, _) => format!(
+                "RateIndex({}, {}, {})",
+                Self::quoted(name),
+                Self::quoted(start),
+                Self::quoted(end)
+            ),
+            Node::Fixing(name, date, _) => {
+                format!("Fixing({}, {})", Self::quoted(name), Self::quoted(date))
+            }
+            Node::Equity(name, date, _) | Node::Volatility(name, date, _) => {
+                let function = match node {
+                    Node::Equity(..) => "Stock",
+                    _ => "Vol",
+                };
+                match date {
+                    Some(date) => format!(
+                        "{}({}, {})",
+                        function,
+                        Self::quoted(name),
+                        Self::quoted(date)
+                    ),
+                    None => format!("{}({})", function, Self::quoted(name)),
+                }
+            }
+            Node::Correlation(first, second, _) => {
+                format!("Corr({}, {})", Self::quoted(first), Self::quoted(second))
+            }
+            Node::BarrierHit(children, start, end, up, out, _, _) => format!(
+                "barrier_hit({}, {}, {}, {}, {})",
+                self.expression(&children[0]),
+                self.expression(&children[1]),
+                Self::quoted(start),
+                Self::quoted(end),
+                Self::quoted(match (up, out) {
+                    (true, false) => "up-in",
+                    (true, true) => "up-out",
+                    (false, false) => "down-in",
+                    (false, true) => "down-out",
+                })
+            ),
+            Node::Pays(children, data, _) => {
+                let mut parts = vec!["pays".to_string()];
+                parts.extend(children.iter().map(|child| self.expression(child)));
+                if let (Some(days), Some(calendar)) = (data.settlement_days(), data.calendar()) {
+                    parts.push(format!("settle {}bd {}", days, calendar));
+                }
+                if let Some(leg) = data.leg() {
+                    parts.push(format!("leg {}", Self::quoted(leg)));
+                }
+                if let Some(currency) = data.currency() {
+                    parts.push(format!("in {}", currency.code()));
+                }
+                parts.join(" ")
+            }
+            Node::Exercise(children, _) => self.call("exercise", children),
+
+            // math
+            Node::Add(children) => self.binary(node, "+", children),
+            Node::Subtract(children) => self.binary(node, "-", children),
+            Node::Multiply(children) => self.binary(node, "*", children),
+            Node::Divide(children) => self.binary(node, "/", children),
+            Node::Pow(children) => format!(
+                "{} ^ {}",
+                self.operand(&children[0], Self::precedence(node) + 1),
+                self.operand(&children[1], Self::precedence(node))
+            ),
+            Node::Min(children) => self.call("min", children),
+            Node::Max(children) => self.call("max", children),
+            Node::Exp(children) => self.call("exp", children),
+            Node::Ln(children) => self.call("ln", children),
+            Node::Cvg(children) => self.call("cvg", children),
+            Node::Accrual(children) => self.call("accrual", children),
+
+            // dates
+            Node::Adjust(children) => self.call("adjust", children),
+            Node::IsBusinessDay(children) => self.call("is_business_day", children),
+            Node::AddTenor(children) => self.call("add_tenor", children),
+
+            // unary
+            Node::UnaryPlus(children) => format!("+{}", self.operand(&children[0], 5)),
+            Node::UnaryMinus(children) => format!("-{}", self.operand(&children[0], 5)),
+
+            // logic
+            Node::True => "true".to_string(),
+            Node::Break => "break".to_string(),
+            Node::Continue => "continue".to_string(),
+            Node::False => "false".to_string(),
+            Node::Equal(children) => self.binary(node, "==", children),
+            Node::NotEqual(children) => self.binary(node, "!=", children),
+            Node::Superior(children) => self.binary(node, ">", children),
+            Node::Inferior(children) => self.binary(node, "<", children),
+            Node::SuperiorOrEqual(children) => self.binary(node, ">=", children),
+            Node::InferiorOrEqual(children) => self.binary(node, "<=", children),
+            Node::And(children) => self.binary(node, "and", children),
+            Node::Or(children) => self.binary(node, "or", children),
+            Node::Not(children) => format!("not {}", self.operand(&children[0], 5)),
+
+            // arrays
+            Node::List(children) => {
+                let items = children
+                    .iter()
+                    .map(|child| self.expression(child))
+                    .collect::<Vec<String>>();
+                format!("[{}]", items.join(", "))
+            }
+            Node::Index(children) => format!(
+                "{}[{}]",
+                self.operand(&children[0], 6),
+                self.expression(&children[1])
+            ),
+            Node::Append(children) => self.call("append", children),
+            Node::MatMul(children) => self.call("matmul", children),
+            Node::Transpose(children) => self.call("transpose", children),
+            Node::Solve(children) => self.call("solve", children),
+            Node::Schedule(children) => self.call("schedule", children),
+        }
+    }
+}
+
+impl NodeVisitor for Printer {
+    type Output = String;
+
+    /// Statements are printed one per line, a lone expression is printed without a semicolon
+    fn visit(&self, node: &Box<Node>) -> Self::Output {
+        if Self::is_statement(node) {
+            self.statements(node, 0)
+                .into_iter()
+                .map(|line| line + "\n")
+                .collect()
+        } else {
+            self.expression(node)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(script: &str) -> ExprTree {
+        let tokens = Lexer::new(script.to_string()).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn test_print_canonical() {
+        let script = "x=1;y = x*2+Spot(\"EUR\",\"USD\") ** 2;if x>1 and y<=3 {z=[1,2][0];} else {z pays max(y, 0) settle 2bd TARGET leg \"fixed\" in EUR;}for v in [1,2] {z = z - v / 2;}assert(z>0,\"positive\");";
+        let expected = "\
+x = 1;
+y = x * 2 + Spot(\"EUR\", \"USD\") ^ 2;
+if x > 1 and y <= 3 {
+    z = [1, 2][0];
+} else {
+    z pays max(y, 0) settle 2bd TARGET leg \"fixed\" in EUR;
+}
+for v in [1, 2] {
+    z = z - v / 2;
+}
+assert(z > 0, \"positive\");
+";
+        assert_eq!(Printer::new().visit(&parse(script)), expected);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let script = "
+            param k: number;
+            for v in [1, 2] {
+                if v > 1 {
+                    break;
+                }
+                continue;
+            }
+            d = add_tenor(\"2024-01-31\", \"1M\");
+            s = Stock(\"AAPL\", \"2024-06-28\");
+            hit = barrier_hit(Stock(\"AAPL\"), 120, \"2024-01-01\", \"2024-06-28\", \"up-out\");
+            r = RateIndex(\"SOFR\", \"2024-01-01\", \"2024-04-01\") * cvg(\"2024-01-01\", \"2024-04-01\", \"Actual360\");
+            e = exercise(opt, max(100 - s, 0));
+            opt = pays exp(ln(s)) - Fixing(\"SOFR\", \"2024-01-02\");
+        ";
+        let tree = parse(script);
+        let printed = Printer::new().visit(&tree);
+        assert_eq!(parse(&printed), tree);
+    }
+
+    #[test]
+    fn test_print_precedence() {
+        let tree = Box::new(Node::Multiply(vec![
+            Box::new(Node::Subtract(vec![
+                Box::new(Node::Constant(1.0)),
+                Box::new(Node::Subtract(vec![
+                    Box::new(Node::Constant(2.0)),
+                    Box::new(Node::Constant(3.0)),
+                ])),
+            ])),
+            Box::new(Node::Constant(4.0)),
+        ]));
+        assert_eq!(Printer::new().visit(&tree), "(1 - (2 - 3)) * 4");
+    }
+
+    #[test]
+    fn test_print_events() {
+        let events = EventStream::new().with_events(vec![
+            Event::new(
+                rustatlas::prelude::Date::new(2024, 1, 1),
+                "x = 1;".try_into().unwrap(),
+            ),
+            Event::new(
+                rustatlas::prelude::Date::new(2024, 2, 1),
+                "y = x;".try_into().unwrap(),
+            ),
+        ]);
+        assert_eq!(
+            Printer::new().visit_events(&events),
+            "# 2024-01-01\nx = 1;\n\n# 2024-02-01\ny = x;\n"
+        );
+    }
+}
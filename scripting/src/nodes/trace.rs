@@ -0,0 +1,241 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use rustatlas::{math::ad::num::Real, prelude::MarketData};
+
+use crate::prelude::*;
+use crate::utils::errors::{Result, ScriptingError};
+
+/// # TraceStep
+/// One top level statement of an event as evaluated on a scenario: its source, the variables
+/// it assigned and the market data it references
+#[derive(Debug, Clone)]
+pub struct TraceStep<T: Real = f64> {
+    event: usize,
+    statement: usize,
+    source: String,
+    assigned: Vec<(String, Value<T>)>,
+    market_data: Vec<MarketData<T>>,
+}
+
+impl<T: Real> TraceStep<T> {
+    /// Position of the event in the stream
+    pub fn event(&self) -> usize {
+        self.event
+    }
+
+    /// Position of the statement in its event
+    pub fn statement(&self) -> usize {
+        self.statement
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Variables whose value changed, with their new value
+    pub fn assigned(&self) -> &[(String, Value<T>)] {
+        &self.assigned
+    }
+
+    pub fn market_data(&self) -> &[MarketData<T>] {
+        &self.market_data
+    }
+}
+
+/// # TraceEvaluator
+/// Wraps an `ExprEvaluator` and evaluates an event stream on a single scenario one statement at
+/// a time, recording what every statement did. Used to step through a payoff that misprices.
+pub struct TraceEvaluator<'a, T: Real = f64> {
+    evaluator: ExprEvaluator<'a, T>,
+    scenario: Option<&'a Scenario<T>>,
+}
+
+impl<'a, T: Real> TraceEvaluator<'a, T> {
+    pub fn new(evaluator: ExprEvaluator<'a, T>) -> Self {
+        TraceEvaluator {
+            evaluator,
+            scenario: None,
+        }
+    }
+
+    pub fn with_scenario(mut self, scenario: &'a Scenario<T>) -> Self {
+        self.evaluator = self.evaluator.with_scenario(scenario);
+        self.scenario = Some(scenario);
+        self
+    }
+
+    /// Ids of the market data read by the nodes of `node`. Statements are traced as a whole, so
+    /// the market data of a branch that was not taken is listed too.
+    fn market_data_ids(node: &Node, ids: &mut Vec<usize>) {
+        let id = |index: &OnceLock<usize>| index.get().copied();
+        match node {
+            Node::Spot(_, _, index)
+            | Node::RateIndex(_, _, _, index)
+            | Node::Fixing(_, _, index)
+            | Node::Equity(_, _, index)
+            | Node::Volatility(_, _, index)
+            | Node::Correlation(_, _, index) => ids.extend(id(index)),
+            // settled payments also read the discount factor to their payment date
+            Node::Pays(children, data, index) => {
+                children
+                    .iter()
+                    .for_each(|child| Self::market_data_ids(child, ids));
+                if let Some(id) = id(index) {
+                    let settled = usize::from(data.settlement_days().is_some());
+                    ids.extend(id..=id + settled);
+                }
+            }
+            Node::Exercise(children, index) => {
+                children
+                    .iter()
+                    .for_each(|child| Self::market_data_ids(child, ids));
+                ids.extend(id(index));
+            }
+            // the monitored spots and the volatility
+            Node::BarrierHit(children, _, _, _, _, observations, index) => {
+                Self::market_data_ids(&children[1], ids);
+                if let (Some(id), Some(dates)) = (id(index), observations.get()) {
+                    ids.extend(id..=id + dates.len());
+                }
+            }
+            Node::Constant(_)
+            | Node::String(_)
+            | Node::True
+            | Node::False
+            | Node::Break
+            | Node::Continue => (),
+            _ => node
+                .children()
+                .iter()
+                .for_each(|child| Self::market_data_ids(child, ids)),
+        }
+    }
+
+    /// # trace
+    /// Evaluate the event stream, returning one step per top level statement in evaluation
+    /// order. Variables are named after `var_indexes`.
+    pub fn trace(
+        &self,
+        event_stream: &EventStream,
+        var_indexes: &HashMap<String, usize>,
+    ) -> Result<Vec<TraceStep<T>>> {
+        let scenario = self.scenario.ok_or(ScriptingError::EvaluationError(
+            "No scenario set".to_string(),
+        ))?;
+        let names: HashMap<usize, &String> =
+            var_indexes.iter().map(|(name, idx)| (*idx, name)).collect();
+        let printer = Printer::new();
+
+        let mut steps = Vec::new();
+        for (event, stream_event) in event_stream.events().iter().enumerate() {
+            self.evaluator.set_event_date(stream_event.event_date());
+            let expr = stream_event.expr();
+            let statements = match expr.as_ref() {
+                Node::Base(children) => children.iter().collect(),
+                _ => vec![expr],
+            };
+            for (statement, node) in statements.into_iter().enumerate() {
+                let before = self.evaluator.variables();
+                self.evaluator.const_visit(node)?;
+                let after = self.evaluator.variables();
+
+                let mut assigned: Vec<(String, Value<T>)> = after
+                    .iter()
+                    .enumerate()
+                    .filter(|(idx, value)| before.get(*idx) != Some(*value))
+                    .filter_map(|(idx, value)| Some(((*names.get(&idx)?).clone(), value.clone())))
+                    .collect();
+                assigned.sort_by(|a, b| a.0.cmp(&b.0));
+
+                let mut ids = Vec::new();
+                Self::market_data_ids(node, &mut ids);
+                ids.sort_unstable();
+                ids.dedup();
+                let market_data = ids
+                    .into_iter()
+                    .filter_map(|id| scenario.get(id).copied())
+                    .collect();
+
+                steps.push(TraceStep {
+                    event,
+                    statement,
+                    source: printer.visit(node).trim_end().to_string(),
+                    assigned,
+                    market_data,
+                });
+            }
+        }
+        Ok(steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustatlas::prelude::{Date, HistoricalData};
+
+    #[test]
+    fn test_trace() {
+        let script = "
+            s = Stock(\"AAPL\");
+            k = 100;
+            payoff = max(s - k, 0);
+        ";
+        let event_date = Date::new(2024, 6, 3);
+        let events = EventStream::new()
+            .with_events(vec![Event::new(event_date, script.try_into().unwrap())]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let scenario =
+            vec![MarketData::new(0, event_date, None, None, None, 1.0).with_equity(Some(110.0))];
+
+        let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
+        let steps = TraceEvaluator::new(evaluator)
+            .with_scenario(&scenario)
+            .trace(&events, &indexer.get_variable_indexes())
+            .unwrap();
+
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0].source(), "s = Stock(\"AAPL\");");
+        assert_eq!(
+            steps[0].assigned(),
+            &[("s".to_string(), Value::Number(110.0))]
+        );
+        assert_eq!(steps[0].market_data().len(), 1);
+        assert_eq!(steps[0].market_data()[0].equity().unwrap(), 110.0);
+        assert!(steps[1].market_data().is_empty());
+        assert_eq!((steps[2].event(), steps[2].statement()), (0, 2));
+        assert_eq!(
+            steps[2].assigned(),
+            &[("payoff".to_string(), Value::Number(10.0))]
+        );
+    }
+
+    #[test]
+    fn test_trace_event_date() {
+        // a price observed before the reference date is the fixing on the event date
+        let event_date = Date::new(2024, 1, 2);
+        let events = EventStream::new().with_events(vec![Event::new(
+            event_date,
+            "s = Stock(\"AAPL\");".try_into().unwrap(),
+        )]);
+        let indexer = EventIndexer::new().with_reference_date(Date::new(2024, 1, 15));
+        indexer.visit_events(&events).unwrap();
+        let historical_data = HistoricalData::new(Date::new(2024, 1, 15))
+            .with_fixings("AAPL", HashMap::from([(event_date, 100.0)]));
+        let scenario = vec![];
+
+        let evaluator = ExprEvaluator::new()
+            .with_variables(indexer.get_variables_size())
+            .with_historical_data(&historical_data);
+        let steps = TraceEvaluator::new(evaluator)
+            .with_scenario(&scenario)
+            .trace(&events, &indexer.get_variable_indexes())
+            .unwrap();
+
+        assert_eq!(
+            steps[0].assigned(),
+            &[("s".to_string(), Value::Number(100.0))]
+        );
+    }
+}
@@ -7,7 +7,7 @@ pub trait NodeVisitor {
 
 pub trait NodeConstVisitor {
     type Output;
-    fn const_visit(&self, node: Box<Node>) -> Self::Output;
+    fn const_visit(&self, node: &Node) -> Self::Output;
 }
 
 pub trait Visitable {
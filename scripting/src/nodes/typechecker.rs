@@ -0,0 +1,429 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use rustatlas::{math::ad::num::Real, prelude::Date};
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+use crate::utils::errors::{Result, ScriptingError};
+
+/// # ValueType
+/// Static type of an expression. `Unknown` is used for statements and for values whose type is
+/// only known at evaluation time, such as array elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValueType {
+    Number,
+    Bool,
+    String,
+    Array,
+    Unknown,
+}
+
+/// # TypeChecker
+/// Visitor that flags type errors before evaluation: mixing numbers with strings or booleans,
+/// indexing a scalar and reading a variable before it is assigned. It relies on the variable
+/// ids set by the `EventIndexer`, so it must run after it.
+pub struct TypeChecker {
+    variables: RefCell<Vec<Option<ValueType>>>,
+    globals: HashMap<String, ValueType>,
+    event_date: RefCell<Option<Date>>,
+}
+
+impl TypeChecker {
+    pub fn new(n_vars: usize) -> Self {
+        TypeChecker {
+            variables: RefCell::new(vec![None; n_vars]),
+            globals: HashMap::new(),
+            event_date: RefCell::new(None),
+        }
+    }
+
+    /// # with_globals
+    /// Host constants given to the evaluator, they are readable without being assigned
+    pub fn with_globals<T: Real>(mut self, globals: &HashMap<String, Value<T>>) -> Self {
+        self.globals = globals
+            .iter()
+            .map(|(name, value)| {
+                let value_type = match value {
+                    Value::Number(_) => ValueType::Number,
+                    Value::Bool(_) => ValueType::Bool,
+                    Value::String(_) => ValueType::String,
+                    Value::Array(_) => ValueType::Array,
+                    Value::Null => ValueType::Unknown,
+                };
+                (name.clone(), value_type)
+            })
+            .collect();
+        self
+    }
+
+    /// # visit_events
+    /// Check the events in order, variables assigned in an event are visible in the next ones.
+    /// Errors on variables read before any assignment name the date of the event reading them.
+    pub fn visit_events(&self, events: &EventStream) -> Result<()> {
+        events.events().iter().try_for_each(|event| {
+            *self.event_date.borrow_mut() = Some(event.event_date());
+            self.visit(event.expr()).map(|_| ())
+        })
+    }
+
+    fn type_error(msg: String) -> ScriptingError {
+        ScriptingError::TypeError(msg)
+    }
+
+    /// Check that a node evaluates to the expected type
+    fn expect(&self, node: &ExprTree, expected: ValueType, context: &str) -> Result<()> {
+        match self.visit(node)? {
+            ValueType::Unknown => Ok(()),
+            found if found == expected => Ok(()),
+            found => Err(Self::type_error(format!(
+                "Expected {:?} in {}, found {:?}",
+                expected, context, found
+            ))),
+        }
+    }
+
+    fn expect_all(&self, nodes: &[ExprTree], expected: ValueType, context: &str) -> Result<()> {
+        nodes
+            .iter()
+            .try_for_each(|node| self.expect(node, expected, context))
+    }
+
+    fn variable_id(node: &ExprTree) -> Result<usize> {
+        match node.as_ref() {
+            Node::Variable(_, name, index) => index
+                .get()
+                .copied()
+                .ok_or(Self::type_error(format!("Variable {} not indexed", name))),
+            _ => Err(Self::type_error("Expected a variable".to_string())),
+        }
+    }
+
+    fn assign(&self, node: &ExprTree, value_type: ValueType) -> Result<()> {
+        if let Node::Variable(_, name, _) = node.as_ref() {
+            if self.globals.contains_key(name) {
+                return Err(Self::type_error(format!(
+                    "Cannot assign to global constant {}",
+                    name
+                )));
+            }
+        }
+        let id = Self::variable_id(node)?;
+        let mut variables = self.variables.borrow_mut();
+        if id >= variables.len() {
+            variables.resize(id + 1, None);
+        }
+        variables[id] = Some(value_type);
+        Ok(())
+    }
+}
+
+impl NodeVisitor for TypeChecker {
+    type Output = Result<ValueType>;
+
+    fn visit(&self, node: &Box<Node>) -> Self::Output {
+        match node.as_ref() {
+            Node::Base(children) => {
+                children
+                    .iter()
+                    .try_for_each(|child| self.visit(child).map(|_| ()))?;
+                Ok(ValueType::Unknown)
+            }
+            Node::Spanned(children, span) => {
+                children
+                    .iter()
+                    .try_for_each(|child| self.visit(child).map(|_| ()))
+                    .map_err(|err| err.with_span(*span))?;
+                Ok(ValueType::Unknown)
+            }
+            Node::Variable(_, name, _) if self.globals.contains_key(name) => {
+                Ok(self.globals[name])
+            }
+            Node::Variable(_, name, index) => {
+                let id = index
+                    .get()
+                    .ok_or(Self::type_error(format!("Variable {} not indexed", name)))?;
+                let event = match *self.event_date.borrow() {
+                    Some(date) => format!(" in the event of {}", date),
+                    None => String::new(),
+                };
+                match self.variables.borrow().get(*id) {
+                    Some(Some(value_type)) => Ok(*value_type),
+                    _ => Err(Self::type_error(format!(
+                        "Variable {} used before it is assigned{}",
+                        name, event
+                    ))),
+                }
+            }
+            Node::Param(children, value_type) => {
+                self.assign(children.get(0).unwrap(), *value_type)?;
+                Ok(ValueType::Unknown)
+            }
+            Node::Constant(_) => Ok(ValueType::Number),
+            Node::String(_) => Ok(ValueType::String),
+            Node::True | Node::False => Ok(ValueType::Bool),
+            Node::Break | Node::Continue => Ok(ValueType::Unknown),
+            Node::Assert(children, _) => {
+                self.expect(children.get(0).unwrap(), ValueType::Bool, "assertion")?;
+                Ok(ValueType::Unknown)
+            }
+
+            // financial
+            Node::Spot(_, _, _)
+            | Node::RateIndex(_, _, _, _)
+            | Node::Fixing(_, _, _)
+            | Node::Equity(_, _, _)
+            | Node::Volatility(_, _, _)
+            | Node::Correlation(_, _, _) => Ok(ValueType::Number),
+            Node::BarrierHit(children, ..) => {
+                self.expect_all(children, ValueType::Number, "barrier")?;
+                Ok(ValueType::Number)
+            }
+            Node::Pays(children, _, _) => {
+                self.expect_all(children, ValueType::Number, "pays")?;
+                Ok(ValueType::Number)
+            }
+            Node::Exercise(children, _) => {
+                self.expect_all(children, ValueType::Number, "exercise")?;
+                Ok(ValueType::Number)
+            }
+
+            // math
+            Node::Add(children)
+            | Node::Subtract(children)
+            | Node::Multiply(children)
+            | Node::Divide(children)
+            | Node::Min(children)
+            | Node::Max(children)
+            | Node::Exp(children)
+            | Node::Pow(children)
+            | Node::Ln(children)
+            | Node::UnaryPlus(children)
+            | Node::UnaryMinus(children) => {
+                self.expect_all(children, ValueType::Number, "arithmetic")?;
+                Ok(ValueType::Number)
+            }
+            Node::Assign(children) => {
+                let value_type = self.visit(children.get(1).unwrap())?;
+                self.assign(children.get(0).unwrap(), value_type)?;
+                Ok(ValueType::Unknown)
+            }
+            Node::Cvg(children) => {
+                self.expect_all(children, ValueType::String, "cvg")?;
+                Ok(ValueType::Number)
+            }
+            Node::Accrual(children) => {
+                self.expect(children.get(0).unwrap(), ValueType::Number, "accrual")?;
+                self.expect_all(&children[1..], ValueType::String, "accrual")?;
+                Ok(ValueType::Number)
+            }
+
+            // dates
+            Node::Adjust(children) | Node::AddTenor(children) => {
+                self.expect_all(children, ValueType::String, "date function")?;
+                Ok(ValueType::String)
+            }
+            Node::IsBusinessDay(children) => {
+                self.expect_all(children, ValueType::String, "is_business_day")?;
+                Ok(ValueType::Bool)
+            }
+
+            // logic
+            Node::Equal(children)
+            | Node::NotEqual(children)
+            | Node::Superior(children)
+            | Node::Inferior(children)
+            | Node::SuperiorOrEqual(children)
+            | Node::InferiorOrEqual(children) => {
+                self.expect_all(children, ValueType::Number, "comparison")?;
+                Ok(ValueType::Bool)
+            }
+            Node::And(children) | Node::Or(children) | Node::Not(children) => {
+                self.expect_all(children, ValueType::Bool, "logical operation")?;
+                Ok(ValueType::Bool)
+            }
+
+            // arrays
+            Node::List(children) => {
+                children
+                    .iter()
+                    .try_for_each(|child| self.visit(child).map(|_| ()))?;
+                Ok(ValueType::Array)
+            }
+            Node::Index(children) => {
+                match self.visit(children.get(0).unwrap())? {
+                    ValueType::Array | ValueType::Unknown => {}
+                    found => {
+                        return Err(Self::type_error(format!(
+                            "Cannot index a value of type {:?}",
+                            found
+                        )))
+                    }
+                }
+                self.expect(children.get(1).unwrap(), ValueType::Number, "index")?;
+                Ok(ValueType::Unknown)
+            }
+            Node::Append(children) => {
+                self.expect(children.get(0).unwrap(), ValueType::Array, "append")?;
+                self.visit(children.get(1).unwrap())?;
+                Ok(ValueType::Unknown)
+            }
+            Node::MatMul(children) | Node::Transpose(children) | Node::Solve(children) => {
+                self.expect_all(children, ValueType::Array, "matrix operation")?;
+                Ok(ValueType::Array)
+            }
+            Node::Schedule(children) => {
+                self.expect_all(children, ValueType::String, "schedule")?;
+                Ok(ValueType::Array)
+            }
+
+            // control flow
+            Node::If(children, _) => {
+                self.expect(children.get(0).unwrap(), ValueType::Bool, "condition")?;
+                children
+                    .iter()
+                    .skip(1)
+                    .try_for_each(|child| self.visit(child).map(|_| ()))?;
+                Ok(ValueType::Unknown)
+            }
+            Node::ForEach(children) => {
+                self.expect(children.get(1).unwrap(), ValueType::Array, "for loop")?;
+                self.assign(children.get(0).unwrap(), ValueType::Unknown)?;
+                children
+                    .iter()
+                    .skip(2)
+                    .try_for_each(|child| self.visit(child).map(|_| ()))?;
+                Ok(ValueType::Unknown)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(script: &str) -> Result<()> {
+        let nodes = ExprTree::try_from(script)?;
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes)?;
+        TypeChecker::new(indexer.get_variables_size())
+            .visit(&nodes)
+            .map(|_| ())
+    }
+
+    #[test]
+    fn test_valid_script() {
+        let script = "
+            x = [1, 2, 3];
+            y = 0;
+            for v in x {
+                y = y + v;
+            }
+            d = add_tenor(\"2024-01-31\", \"1M\");
+            b = is_business_day(d, \"TARGET\");
+            if y > 3 and x[1] == 2 {
+                z = x[0] * 2;
+            }
+        ";
+        check(script).unwrap();
+    }
+
+    #[test]
+    fn test_string_in_arithmetic() {
+        let err = check("x = \"a\";\ny = x + 1;").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2, col 1: Type error: Expected Number in arithmetic, found String"
+        );
+    }
+
+    #[test]
+    fn test_index_scalar() {
+        let err = check("x = 1; y = x[0];").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Cannot index a value of type Number"));
+    }
+
+    #[test]
+    fn test_uninitialized_variable() {
+        let err = check("x pays 100;").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Variable x used before it is assigned"));
+        assert!(check("x = 0; x pays 100;").is_ok());
+    }
+
+    #[test]
+    fn test_param_type() {
+        assert!(check("param strike: number; x = strike * 2;").is_ok());
+        let err = check("param ccy: string; x = ccy * 2;").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Expected Number in arithmetic, found String"));
+    }
+
+    #[test]
+    fn test_globals() {
+        let nodes = ExprTree::try_from("x = notional * 2; notional = 1;").unwrap();
+        let indexer = EventIndexer::new();
+        indexer.visit(&nodes).unwrap();
+
+        let globals = HashMap::from([("notional".to_string(), Value::Number(100.0))]);
+        let err = TypeChecker::new(indexer.get_variables_size())
+            .with_globals(&globals)
+            .visit(&nodes)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1, col 19: Type error: Cannot assign to global constant notional"
+        );
+    }
+
+    #[test]
+    fn test_events() {
+        let events = EventStream::new().with_events(vec![
+            Event::new(
+                rustatlas::prelude::Date::new(2024, 1, 1),
+                "x = 1;".try_into().unwrap(),
+            ),
+            Event::new(
+                rustatlas::prelude::Date::new(2024, 2, 1),
+                "y = x > 0;\nz = y + 1;".try_into().unwrap(),
+            ),
+        ]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+
+        let err = TypeChecker::new(indexer.get_variables_size())
+            .visit_events(&events)
+            .unwrap_err();
+        assert!(err.to_string().starts_with("line 2, col 1"));
+    }
+
+    #[test]
+    fn test_use_before_assignment_event() {
+        let events = EventStream::new().with_events(vec![
+            Event::new(
+                rustatlas::prelude::Date::new(2024, 1, 1),
+                "notional = 100;".try_into().unwrap(),
+            ),
+            Event::new(
+                rustatlas::prelude::Date::new(2024, 7, 1),
+                "x = notional * rate;".try_into().unwrap(),
+            ),
+        ]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+
+        let err = TypeChecker::new(indexer.get_variables_size())
+            .visit_events(&events)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1, col 1: Type error: Variable rate used before it is assigned in the event of \
+             2024-07-01"
+        );
+    }
+}
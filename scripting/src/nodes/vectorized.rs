@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+
+use rustatlas::prelude::MarketData;
+
+use crate::prelude::*;
+use crate::utils::errors::{Result, ScriptingError};
+
+/// Values of a variable or stack entry across all scenarios
+#[derive(Debug, Clone, PartialEq)]
+enum Column {
+    Number(Vec<f64>),
+    Bool(Vec<bool>),
+    Null,
+}
+
+fn pop<V>(stack: &mut Vec<V>) -> Result<V> {
+    stack.pop().ok_or(ScriptingError::EvaluationError(
+        "Stack underflow".to_string(),
+    ))
+}
+
+/// Compensated sum of a column, matching the averages of `EventStreamEvaluator`
+fn sum(values: &[f64]) -> f64 {
+    let mut sum = CompensatedSum::new();
+    values.iter().for_each(|v| sum.add(*v));
+    sum.value()
+}
+
+/// Apply `op` element-wise, writing into `left`
+fn zip_with(left: &mut [f64], right: &[f64], op: impl Fn(f64, f64) -> f64) {
+    left.iter_mut()
+        .zip(right.iter())
+        .for_each(|(l, r)| *l = op(*l, *r));
+}
+
+fn compare(left: &[f64], right: &[f64], op: impl Fn(f64, f64) -> bool) -> Vec<bool> {
+    left.iter()
+        .zip(right.iter())
+        .map(|(l, r)| op(*l, *r))
+        .collect()
+}
+
+/// # VectorizedEvaluator
+/// Runs a compiled `Program` on all scenarios at once: every instruction processes a column
+/// holding one value per scenario, so the arithmetic runs in tight loops the compiler can
+/// vectorize. Only branch-free programs are supported, programs with `if` are evaluated with
+/// `BytecodeEvaluator`.
+pub struct VectorizedEvaluator<'a> {
+    n_vars: usize,
+    scenarios: Option<&'a Vec<Scenario>>,
+}
+
+impl<'a> VectorizedEvaluator<'a> {
+    pub fn new(n_vars: usize) -> Self {
+        VectorizedEvaluator {
+            n_vars,
+            scenarios: None,
+        }
+    }
+
+    pub fn with_scenarios(mut self, scenarios: &'a Vec<Scenario>) -> Self {
+        self.scenarios = Some(scenarios);
+        self
+    }
+
+    /// Value of a market data request on every scenario
+    fn gather(
+        scenarios: &[Scenario],
+        id: usize,
+        read: impl Fn(&MarketData<f64>) -> Result<f64>,
+    ) -> Result<Vec<f64>> {
+        scenarios
+            .iter()
+            .map(|scenario| {
+                let market_data = scenario.get(id).ok_or(ScriptingError::EvaluationError(
+                    "Market data not found".to_string(),
+                ))?;
+                read(market_data)
+            })
+            .collect()
+    }
+
+    fn run(&self, program: &Program) -> Result<(Vec<Column>, CashflowReport)> {
+        let scenarios = self.scenarios.ok_or(ScriptingError::EvaluationError(
+            "No scenarios set".to_string(),
+        ))?;
+        let n = scenarios.len();
+
+        let mut variables = vec![Column::Null; self.n_vars];
+        let mut cashflows = CashflowReport::new();
+        let mut digits: Vec<Vec<f64>> = Vec::new();
+        let mut booleans: Vec<Vec<bool>> = Vec::new();
+
+        for instruction in program.instructions() {
+            match instruction {
+                Instruction::Constant(value) => digits.push(vec![*value; n]),
+                Instruction::Bool(value) => booleans.push(vec![*value; n]),
+                Instruction::Load(id) => match &variables[*id] {
+                    Column::Number(values) => digits.push(values.clone()),
+                    Column::Bool(values) => booleans.push(values.clone()),
+                    Column::Null => {
+                        return Err(ScriptingError::EvaluationError(format!(
+                            "Variable {} not initialized",
+                            id
+                        )))
+                    }
+                },
+                Instruction::Store(id) => {
+                    variables[*id] = match booleans.pop() {
+                        Some(values) => Column::Bool(values),
+                        None => Column::Number(pop(&mut digits)?),
+                    };
+                }
+                Instruction::Spot(id) => {
+                    digits.push(Self::gather(scenarios, *id, |m| Ok(m.fx()?))?)
+                }
+                Instruction::Rate(id) => {
+                    digits.push(Self::gather(scenarios, *id, |m| Ok(m.fwd()?))?)
+                }
+                Instruction::Equity(id) => {
+                    digits.push(Self::gather(scenarios, *id, |m| Ok(m.equity()?))?)
+                }
+                Instruction::Vol(id) => {
+                    digits.push(Self::gather(scenarios, *id, |m| Ok(m.vol()?))?)
+                }
+                Instruction::Corr(id) => {
+                    digits.push(Self::gather(scenarios, *id, |m| Ok(m.corr()?))?)
+                }
+                Instruction::Pays { id, settled, leg } => {
+                    let mut values = pop(&mut digits)?;
+                    if *settled {
+                        let mut delays = Self::gather(scenarios, *id + 1, |m| Ok(m.df()?))?;
+                        let event_dfs = Self::gather(scenarios, *id, |m| Ok(m.df()?))?;
+                        zip_with(&mut delays, &event_dfs, |df, event_df| df / event_df);
+                        zip_with(&mut values, &delays, |v, delay| v * delay);
+                    }
+                    let numeraires = Self::gather(scenarios, *id, |m| Ok(m.numerarie()))?;
+                    zip_with(&mut values, &numeraires, |v, numeraire| v / numeraire);
+                    if let Some(key) = leg {
+                        *cashflows.entry(key.clone()).or_insert(0.0) += sum(&values);
+                    }
+                    digits.push(values);
+                }
+                Instruction::Negate | Instruction::Ln | Instruction::Exp => {
+                    let mut values = pop(&mut digits)?;
+                    let op: fn(f64) -> f64 = match instruction {
+                        Instruction::Negate => |v| -v,
+                        Instruction::Ln => f64::ln,
+                        _ => f64::exp,
+                    };
+                    values.iter_mut().for_each(|v| *v = op(*v));
+                    digits.push(values);
+                }
+                Instruction::Add
+                | Instruction::Subtract
+                | Instruction::Multiply
+                | Instruction::Divide
+                | Instruction::Min
+                | Instruction::Max
+                | Instruction::Pow => {
+                    let right = pop(&mut digits)?;
+                    let mut left = pop(&mut digits)?;
+                    match instruction {
+                        Instruction::Add => zip_with(&mut left, &right, |l, r| l + r),
+                        Instruction::Subtract => zip_with(&mut left, &right, |l, r| l - r),
+                        Instruction::Multiply => zip_with(&mut left, &right, |l, r| l * r),
+                        Instruction::Divide => zip_with(&mut left, &right, |l, r| l / r),
+                        Instruction::Min => zip_with(&mut left, &right, f64::min),
+                        Instruction::Max => zip_with(&mut left, &right, f64::max),
+                        _ => zip_with(&mut left, &right, f64::powf),
+                    }
+                    digits.push(left);
+                }
+                Instruction::Equal
+                | Instruction::NotEqual
+                | Instruction::Superior
+                | Instruction::Inferior
+                | Instruction::SuperiorOrEqual
+                | Instruction::InferiorOrEqual => {
+                    let right = pop(&mut digits)?;
+                    let left = pop(&mut digits)?;
+                    booleans.push(match instruction {
+                        Instruction::Equal => {
+                            compare(&left, &right, |l, r| (r - l).abs() < f64::EPSILON)
+                        }
+                        Instruction::NotEqual => {
+                            compare(&left, &right, |l, r| (r - l).abs() >= f64::EPSILON)
+                        }
+                        Instruction::Superior => compare(&left, &right, |l, r| l > r),
+                        Instruction::Inferior => compare(&left, &right, |l, r| l < r),
+                        Instruction::SuperiorOrEqual => compare(&left, &right, |l, r| l >= r),
+                        _ => compare(&left, &right, |l, r| l <= r),
+                    });
+                }
+                Instruction::And | Instruction::Or => {
+                    let right = pop(&mut booleans)?;
+                    let mut left = pop(&mut booleans)?;
+                    let and = matches!(instruction, Instruction::And);
+                    left.iter_mut()
+                        .zip(right.iter())
+                        .for_each(|(l, r)| *l = if and { *l && *r } else { *l || *r });
+                    booleans.push(left);
+                }
+                Instruction::Not => {
+                    let mut values = pop(&mut booleans)?;
+                    values.iter_mut().for_each(|v| *v = !*v);
+                    booleans.push(values);
+                }
+                Instruction::Assert(message) => {
+                    if pop(&mut booleans)?.iter().any(|holds| !holds) {
+                        return Err(ScriptingError::AssertionFailed(message.clone()));
+                    }
+                }
+                Instruction::Jump(_) | Instruction::JumpIfFalse(_) => {
+                    return Err(ScriptingError::EvaluationError(
+                        "Branches are not supported by the vectorized evaluator".to_string(),
+                    ))
+                }
+            }
+        }
+        Ok((variables, cashflows))
+    }
+
+    /// # visit_events
+    /// Run the program on all scenarios and return the averaged variables. Non numeric
+    /// variables keep the value of the first scenario.
+    pub fn visit_events(
+        &self,
+        program: &Program,
+        var_indexes: &HashMap<String, usize>,
+    ) -> Result<HashMap<String, Value>> {
+        let (variables, _) = self.visit_events_with_report(program, var_indexes)?;
+        Ok(variables)
+    }
+
+    /// # visit_events_with_report
+    /// Run the program on all scenarios, returning the averaged variables together with the
+    /// expected discounted cashflows of each leg
+    pub fn visit_events_with_report(
+        &self,
+        program: &Program,
+        var_indexes: &HashMap<String, usize>,
+    ) -> Result<(HashMap<String, Value>, CashflowReport)> {
+        let (columns, mut report) = self.run(program)?;
+        let len = self.scenarios.map_or(0, |scenarios| scenarios.len()) as f64;
+
+        let variables = var_indexes
+            .iter()
+            .filter_map(|(name, idx)| {
+                let value = match columns.get(*idx)? {
+                    Column::Number(values) => Value::Number(sum(values) / len),
+                    Column::Bool(values) => Value::Bool(*values.first()?),
+                    Column::Null => Value::Null,
+                };
+                Some((name.clone(), value))
+            })
+            .collect();
+        report.values_mut().for_each(|v| *v /= len);
+        Ok((variables, report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustatlas::prelude::Date;
+
+    fn scenarios(event_date: Date, spots: &[f64]) -> Vec<Scenario> {
+        spots
+            .iter()
+            .map(|spot| {
+                (0..2)
+                    .map(|id| {
+                        MarketData::new(id, event_date, None, None, None, 2.0)
+                            .with_equity(Some(*spot))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_matches_tree_evaluator() {
+        let script = "
+            s = Stock(\"AAPL\");
+            payoff = 0;
+            payoff pays max(s - 100, 0) ^ 2 / 10 leg \"call\";
+            itm = s > 100;
+        ";
+        let event_date = Date::new(2024, 6, 3);
+        let events = EventStream::new()
+            .with_events(vec![Event::new(event_date, script.try_into().unwrap())]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+        let scenarios = scenarios(event_date, &[90.0, 110.0, 130.0]);
+
+        let program = Compiler::new().compile_events(&events).unwrap();
+        let (vectorized, report) = VectorizedEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .visit_events_with_report(&program, &var_map)
+            .unwrap();
+        let (expected, expected_report) = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .visit_events_with_report(&events, &var_map)
+            .unwrap();
+
+        assert_eq!(vectorized.get("payoff"), expected.get("payoff"));
+        assert_eq!(vectorized.get("s"), expected.get("s"));
+        assert_eq!(vectorized.get("itm"), Some(&Value::Bool(false)));
+        assert_eq!(report, expected_report);
+    }
+
+    #[test]
+    fn test_settled_pays_match_tree_evaluator() {
+        let script = "x = 0; x pays 100 settle 2bd TARGET;";
+        let event_date = Date::new(2024, 6, 3);
+        let events = EventStream::new()
+            .with_events(vec![Event::new(event_date, script.try_into().unwrap())]);
+        let indexer = EventIndexer::new().with_discount_provider(0);
+        indexer.visit_events(&events).unwrap();
+        let var_map = indexer.get_variable_indexes();
+        let scenarios: Vec<Scenario> = [(0.8, 0.72), (0.9, 0.85)]
+            .iter()
+            .map(|(event_df, payment_df)| {
+                vec![
+                    MarketData::new(0, event_date, Some(*event_df), None, None, 2.0),
+                    MarketData::new(1, event_date, Some(*payment_df), None, None, 2.0),
+                ]
+            })
+            .collect();
+
+        let program = Compiler::new().compile_events(&events).unwrap();
+        let vectorized = VectorizedEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .visit_events(&program, &var_map)
+            .unwrap();
+        let compiled = BytecodeEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .visit_events(&program, &var_map)
+            .unwrap();
+        let expected = EventStreamEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .visit_events(&events, &var_map)
+            .unwrap();
+
+        assert_eq!(vectorized.get("x"), expected.get("x"));
+        assert_eq!(compiled.get("x"), expected.get("x"));
+    }
+
+    #[test]
+    fn test_branches_are_rejected() {
+        let script = "x = 1; if x > 0 { x = 2; }";
+        let events = EventStream::new().with_events(vec![Event::new(
+            Date::new(2024, 6, 3),
+            script.try_into().unwrap(),
+        )]);
+        let indexer = EventIndexer::new();
+        indexer.visit_events(&events).unwrap();
+        let scenarios = vec![Scenario::new()];
+
+        let program = Compiler::new().compile_events(&events).unwrap();
+        let err = VectorizedEvaluator::new(indexer.get_variables_size())
+            .with_scenarios(&scenarios)
+            .visit_events(&program, &indexer.get_variable_indexes())
+            .unwrap_err();
+        assert!(err.to_string().contains("Branches are not supported"));
+    }
+}
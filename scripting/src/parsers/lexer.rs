@@ -1,4 +1,5 @@
 use crate::utils::errors::{Result, ScriptingError};
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -25,23 +26,52 @@ pub enum Token {
     CloseParen,
     OpenCurlyParen,
     CloseCurlyParen,
+    OpenBracket,
+    CloseBracket,
+    Dot,
+    Colon,
+    Arrow,
     If,
     Then,
     Else,
     End,
     Comma,
     Power,
+    Percent,
     For,
     Semicolon, // for end of an expression or statement
     Newline,   // for end of a line
     EOF,
 }
 
+/// # Span
+/// Line and column (both starting at 1) where a token starts in the script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Span {
+    line: usize,
+    column: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize) -> Span {
+        Span { line, column }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
 /// # Lexer
 /// The Lexer struct is used to tokenize the input string.
 pub struct Lexer {
     input: Vec<char>,
     position: RefCell<usize>,
+    token_start: RefCell<usize>,
 }
 
 impl Lexer {
@@ -49,9 +79,29 @@ impl Lexer {
         Self {
             input: input.chars().collect(),
             position: RefCell::new(0),
+            token_start: RefCell::new(0),
         }
     }
 
+    /// Span of the token being read
+    fn span(&self) -> Span {
+        let start = *self.token_start.borrow();
+        let consumed = &self.input[..start.min(self.input.len())];
+        let line = consumed.iter().filter(|&&c| c == '\n').count() + 1;
+        let column = start - consumed.iter().rposition(|&c| c == '\n').map_or(0, |i| i + 1) + 1;
+        Span::new(line, column)
+    }
+
+    fn invalid_syntax_err(&self, msg: &str) -> ScriptingError {
+        let span = self.span();
+        ScriptingError::InvalidSyntax(format!(
+            "Error at line {}, column {}: {}",
+            span.line(),
+            span.column(),
+            msg
+        ))
+    }
+
     fn next_char(&self) -> char {
         if *self.position.borrow() >= self.input.len() {
             '\0'
@@ -70,12 +120,28 @@ impl Lexer {
         }
     }
 
+    /// Character `offset` positions after the next one, without consuming anything
+    fn peek_char_at(&self, offset: usize) -> char {
+        self.input
+            .get(*self.position.borrow() + offset)
+            .copied()
+            .unwrap_or('\0')
+    }
+
     pub fn next_token(&self) -> Result<Token> {
         self.skip_whitespace();
+        *self.token_start.borrow_mut() = *self.position.borrow();
         let ch = self.next_char();
         match ch {
             '+' => Ok(Token::Plus),
-            '-' => Ok(Token::Minus),
+            '-' => {
+                if self.peek_char() == '>' {
+                    self.next_char();
+                    Ok(Token::Arrow)
+                } else {
+                    Ok(Token::Minus)
+                }
+            }
             '*' => {
                 if self.peek_char() == '*' {
                     self.next_char();
@@ -84,13 +150,36 @@ impl Lexer {
                     Ok(Token::Multiply)
                 }
             }
+            '^' => Ok(Token::Power),
             '#' => {
                 while self.peek_char() != '\n' && self.peek_char() != '\0' {
                     self.next_char();
                 }
                 self.next_token()
             }
-            '/' => Ok(Token::Divide),
+            '/' => match self.peek_char() {
+                '/' => {
+                    while self.peek_char() != '\n' && self.peek_char() != '\0' {
+                        self.next_char();
+                    }
+                    self.next_token()
+                }
+                '*' => {
+                    self.next_char();
+                    loop {
+                        match self.next_char() {
+                            '*' if self.peek_char() == '/' => {
+                                self.next_char();
+                                break;
+                            }
+                            '\0' => return Err(self.invalid_syntax_err("Unterminated block comment")),
+                            _ => {}
+                        }
+                    }
+                    self.next_token()
+                }
+                _ => Ok(Token::Divide),
+            },
             '=' => {
                 if self.peek_char() == '=' {
                     self.next_char();
@@ -106,15 +195,18 @@ impl Lexer {
                     self.next_char();
                     Ok(Token::NotEqual)
                 } else {
-                    Err(ScriptingError::InvalidSyntax(
-                        "Invalid character: !".to_string(),
-                    ))
+                    Err(self.invalid_syntax_err("Invalid character: !"))
                 }
             }
             '(' => Ok(Token::OpenParen),
             ')' => Ok(Token::CloseParen),
             '{' => Ok(Token::OpenCurlyParen),
             '}' => Ok(Token::CloseCurlyParen),
+            '[' => Ok(Token::OpenBracket),
+            ']' => Ok(Token::CloseBracket),
+            '.' => Ok(Token::Dot),
+            ':' => Ok(Token::Colon),
+            '%' => Ok(Token::Percent),
             ';' => Ok(Token::Semicolon),
             '\0' => Ok(Token::EOF),
             '>' => {
@@ -136,10 +228,7 @@ impl Lexer {
             '\"' => self.read_string(),
             _ if ch.is_digit(10) => self.read_number(ch),
             _ if ch.is_alphabetic() => self.read_identifier(ch),
-            _ => Err(ScriptingError::InvalidSyntax(format!(
-                "Invalid character: {}",
-                ch
-            ))),
+            _ => Err(self.invalid_syntax_err(&format!("Invalid character: {}", ch))),
         }
     }
 
@@ -152,12 +241,31 @@ impl Lexer {
         Ok(Token::String(string))
     }
 
-    // This function is used to read numerical literals, including floating point numbers.
+    // This function is used to read numerical literals, including floating point numbers,
+    // scientific notation (`1e-4`, `2.5E6`) and digit separators (`1_000_000`).
     // Should fail if the number is not valid or if it is not a number.
     fn read_number(&self, first_char: char) -> Result<Token> {
         let mut number = first_char.to_string();
-        while self.peek_char().is_digit(10) || self.peek_char() == '.' {
-            number.push(self.next_char());
+        loop {
+            match self.peek_char() {
+                c if c.is_digit(10) || c == '.' => number.push(self.next_char()),
+                '_' if self.peek_char_at(1).is_digit(10) => {
+                    self.next_char();
+                }
+                'e' | 'E' => {
+                    let sign = matches!(self.peek_char_at(1), '+' | '-');
+                    let offset = if sign { 2 } else { 1 };
+                    if !self.peek_char_at(offset).is_digit(10) {
+                        break;
+                    }
+                    (0..offset).for_each(|_| number.push(self.next_char()));
+                    while self.peek_char().is_digit(10) {
+                        number.push(self.next_char());
+                    }
+                    break;
+                }
+                _ => break,
+            }
         }
 
         Ok(Token::Value(Some(number.parse::<f64>()?), None))
@@ -191,6 +299,20 @@ impl Lexer {
         }
     }
 
+    /// # tokenize_with_spans
+    /// Tokenize the input, keeping the position of every token
+    pub fn tokenize_with_spans(&self) -> Result<Vec<(Token, Span)>> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            if token == Token::EOF {
+                break;
+            }
+            tokens.push((token, self.span()));
+        }
+        Ok(tokens)
+    }
+
     pub fn tokenize(&self) -> Result<Vec<Token>> {
         let mut tokens = Vec::new();
         loop {
@@ -587,6 +709,63 @@ mod tests {
         assert_eq!(tokens, expected_tokens);
     }
 
+    #[test]
+    fn test_scientific_notation() {
+        let lexer = Lexer::new("1e-4 2.5E6 3e+2 1E2".to_string());
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Value(Some(1e-4), None),
+                Token::Value(Some(2.5e6), None),
+                Token::Value(Some(300.0), None),
+                Token::Value(Some(100.0), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_digit_separators() {
+        let lexer = Lexer::new("1_000_000 1_000.5".to_string());
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Value(Some(1_000_000.0), None),
+                Token::Value(Some(1_000.5), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exponent_without_digits() {
+        // `2e` is not an exponent: the `e` is left for the next token
+        let lexer = Lexer::new("2end".to_string());
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens, vec![Token::Value(Some(2.0), None), Token::End]);
+        assert!(Lexer::new("1_".to_string()).tokenize().is_err());
+    }
+
+    #[test]
+    fn test_percent() {
+        let lexer = Lexer::new("4.5%".to_string());
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens, vec![Token::Value(Some(4.5), None), Token::Percent]);
+    }
+
+    #[test]
+    fn test_caret_power_operator() {
+        let input = "2 ^ 3";
+        let expected_tokens = vec![
+            Token::Value(Some(2.0), None),
+            Token::Power,
+            Token::Value(Some(3.0), None),
+        ];
+        let lexer = Lexer::new(input.to_string());
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens, expected_tokens);
+    }
+
     #[test]
     fn test_power_operator_with_parentheses() {
         let input = "(2 + 3) ** 2";
@@ -656,4 +835,113 @@ mod tests {
         assert_eq!(tokens, expected_tokens);
     }
 
+    #[test]
+    fn test_list_and_index() {
+        let input = "x = [1, 2]; y = x[0];";
+        let expected_tokens = vec![
+            Token::Identifier("x".to_string()),
+            Token::Assign,
+            Token::OpenBracket,
+            Token::Value(Some(1.0), None),
+            Token::Comma,
+            Token::Value(Some(2.0), None),
+            Token::CloseBracket,
+            Token::Semicolon,
+            Token::Identifier("y".to_string()),
+            Token::Assign,
+            Token::Identifier("x".to_string()),
+            Token::OpenBracket,
+            Token::Value(Some(0.0), None),
+            Token::CloseBracket,
+            Token::Semicolon,
+        ];
+        let lexer = Lexer::new(input.to_string());
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens, expected_tokens);
+    }
+
+    #[test]
+    fn test_for_in_and_lambda() {
+        let input = "for x in arr.map(y -> y - 1)";
+        let expected_tokens = vec![
+            Token::For,
+            Token::Identifier("x".to_string()),
+            Token::Identifier("in".to_string()),
+            Token::Identifier("arr".to_string()),
+            Token::Dot,
+            Token::Identifier("map".to_string()),
+            Token::OpenParen,
+            Token::Identifier("y".to_string()),
+            Token::Arrow,
+            Token::Identifier("y".to_string()),
+            Token::Minus,
+            Token::Value(Some(1.0), None),
+            Token::CloseParen,
+        ];
+        let lexer = Lexer::new(input.to_string());
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens, expected_tokens);
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = "x = 1; // trailing comment\n/* block\ncomment */ y = x / 2;";
+        let expected_tokens = vec![
+            Token::Identifier("x".to_string()),
+            Token::Assign,
+            Token::Value(Some(1.0), None),
+            Token::Semicolon,
+            Token::Newline,
+            Token::Identifier("y".to_string()),
+            Token::Assign,
+            Token::Identifier("x".to_string()),
+            Token::Divide,
+            Token::Value(Some(2.0), None),
+            Token::Semicolon,
+        ];
+
+        let lexer = Lexer::new(input.to_string());
+        let tokens = lexer.tokenize().unwrap();
+
+        assert_eq!(tokens, expected_tokens);
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let lexer = Lexer::new("x = 1; /* never closed".to_string());
+        assert!(lexer.tokenize().is_err());
+    }
+
+    #[test]
+    fn test_tokenize_with_spans() {
+        let lexer = Lexer::new("x = 1;\n  /* note */ y = \"a\";".to_string());
+        let spans: Vec<Span> = lexer
+            .tokenize_with_spans()
+            .unwrap()
+            .into_iter()
+            .map(|(_, span)| span)
+            .collect();
+
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(1, 1),
+                Span::new(1, 3),
+                Span::new(1, 5),
+                Span::new(1, 6),
+                Span::new(1, 7),
+                Span::new(2, 14),
+                Span::new(2, 16),
+                Span::new(2, 18),
+                Span::new(2, 21),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_character_position() {
+        let lexer = Lexer::new("x = 1;\ny = 2 $ 3;".to_string());
+        let err = lexer.tokenize().unwrap_err().to_string();
+        assert!(err.contains("line 2, column 7"), "{}", err);
+    }
 }
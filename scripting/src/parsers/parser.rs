@@ -1,4 +1,5 @@
 use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::OnceLock;
 
 use rustatlas::currencies::enums::Currency;
@@ -7,6 +8,10 @@ use rustatlas::prelude::*;
 use crate::prelude::*;
 use crate::utils::errors::{Result, ScriptingError};
 
+/// # ScriptResolver
+/// Host callback returning the source of an imported script library from its path
+pub type ScriptResolver = Rc<dyn Fn(&str) -> Result<String>>;
+
 /// # Parser
 /// The parser is responsible for parsing the tokens generated by the lexer.
 pub struct Parser {
@@ -15,6 +20,12 @@ pub struct Parser {
     line: RefCell<usize>,
     column: RefCell<usize>,
     reserved_keywords: Vec<String>,
+    hoisted: RefCell<Vec<ExprTree>>,
+    temporaries: RefCell<usize>,
+    spans: Vec<Span>,
+    resolver: Option<ScriptResolver>,
+    imported: Rc<RefCell<Vec<String>>>,
+    loop_depth: RefCell<usize>,
 }
 
 /// public methods
@@ -42,9 +53,32 @@ impl Parser {
                 "max".to_string(),
                 "cvg".to_string(),
             ],
+            hoisted: RefCell::new(Vec::new()),
+            temporaries: RefCell::new(0),
+            spans: Vec::new(),
+            resolver: None,
+            imported: Rc::new(RefCell::new(Vec::new())),
+            loop_depth: RefCell::new(0),
         }
     }
 
+    /// # with_spans
+    /// Positions of the tokens in the script. When set, errors report the exact location of the
+    /// offending token and every statement is wrapped in a `Spanned` node so that indexing and
+    /// evaluation errors can be located as well.
+    pub fn with_spans(mut self, spans: Vec<Span>) -> Self {
+        self.spans = spans;
+        self
+    }
+
+    /// # with_resolver
+    /// Resolver used to load the libraries of `import "path";` statements. Each library is
+    /// included once per script, so diamond and circular imports are allowed.
+    pub fn with_resolver(mut self, resolver: ScriptResolver) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
     pub fn parse(&self) -> Result<ExprTree> {
         let mut expressions = Vec::new();
         while self.current_token() != Token::EOF {
@@ -108,24 +142,35 @@ impl Parser {
 
     /// Create a new error for invalid syntax
     fn invalid_syntax_err(&self, msg: &str) -> ScriptingError {
-        let line = *self.line.borrow();
-        let column = *self.column.borrow();
+        let span = self.current_span();
         ScriptingError::InvalidSyntax(format!(
             "Error at line {}, column {}: {}",
-            line, column, msg
+            span.line(),
+            span.column(),
+            msg
         ))
     }
 
     /// Create a new error for unexpected token
     fn unexpected_token_err(&self, expected: Token, received: Token) -> ScriptingError {
-        let line = *self.line.borrow();
-        let column = *self.column.borrow();
+        let span = self.current_span();
         ScriptingError::UnexpectedToken(format!(
             "Error at line {}, column {}: Expected token {:?}, found {:?}",
-            line, column, expected, received
+            span.line(),
+            span.column(),
+            expected,
+            received
         ))
     }
 
+    /// Position of the current token, estimated when the token spans are unknown
+    fn current_span(&self) -> Span {
+        match self.spans.get(*self.position.borrow()) {
+            Some(span) => *span,
+            None => Span::new(*self.line.borrow(), *self.column.borrow()),
+        }
+    }
+
     /// Expect a token, if it is not the current token, return an error
     fn expect_token(&self, expected: Token) -> Result<()> {
         if self.current_token() == expected {
@@ -135,10 +180,43 @@ impl Parser {
         }
     }
 
-    /// Parse an expression
+    /// Parse an expression. Statements hoisted while parsing it (e.g. the loops behind `map` and
+    /// `filter`) are placed right before it.
     fn parse_expression(&self) -> Result<ExprTree> {
+        let span = self.spans.get(*self.position.borrow()).copied();
+        let outer = self.hoisted.take();
+        let expr = self.parse_statement();
+        let mut nodes = self.hoisted.replace(outer);
+        let expr = expr?;
+        let expr = if nodes.is_empty() {
+            expr
+        } else {
+            nodes.push(expr);
+            Box::new(Node::Base(nodes))
+        };
+        match span {
+            Some(span) => Ok(Box::new(Node::Spanned(vec![expr], span))),
+            None => Ok(expr),
+        }
+    }
+
+    /// Parse a single statement
+    fn parse_statement(&self) -> Result<ExprTree> {
+        if self.is_import() {
+            return self.parse_import();
+        }
+        if self.is_param() {
+            return self.parse_param();
+        }
+        if let Some(jump) = self.loop_control() {
+            return self.parse_loop_control(jump);
+        }
+        if self.is_assert() {
+            return self.parse_assert();
+        }
         match self.current_token() {
             Token::If => self.parse_if(),
+            Token::For => self.parse_for(),
             Token::Pays => {
                 let expr = self.parse_pays()?;
                 if self.current_token() == Token::Semicolon {
@@ -167,19 +245,203 @@ impl Parser {
         }
     }
 
+    /// Whether the current statement is an `import "path";` or `include "path";`. The keywords
+    /// are not reserved, so they can still be used as variable names.
+    fn is_import(&self) -> bool {
+        let next = self.tokens.borrow().get(*self.position.borrow() + 1).cloned();
+        let keyword = matches!(
+            self.current_token(),
+            Token::Identifier(name) if name == "import" || name == "include"
+        );
+        keyword && matches!(next, Some(Token::String(_)))
+    }
+
+    /// Parse an import statement, the statements of the library are inlined in place
+    fn parse_import(&self) -> Result<ExprTree> {
+        self.advance();
+        let path = match *self.parse_string()? {
+            Node::String(path) => path,
+            _ => return Err(self.invalid_syntax_err("Expected a library path")),
+        };
+        if self.current_token() == Token::Semicolon {
+            self.advance();
+        }
+
+        if self.imported.borrow().contains(&path) {
+            return Ok(Box::new(Node::Base(Vec::new())));
+        }
+        self.imported.borrow_mut().push(path.clone());
+
+        let resolver = self
+            .resolver
+            .clone()
+            .ok_or(self.invalid_syntax_err("No script resolver set for import"))?;
+        let library = resolver(&path)
+            .and_then(|source| {
+                let mut parser = Parser::new(Lexer::new(source).tokenize()?)
+                    .with_resolver(resolver.clone());
+                parser.imported = self.imported.clone();
+                parser.temporaries.replace(*self.temporaries.borrow());
+                let library = parser.parse()?;
+                self.temporaries.replace(parser.temporaries.take());
+                Ok(library)
+            })
+            .map_err(|err| {
+                self.invalid_syntax_err(&format!("Error in import \"{}\": {}", path, err))
+            })?;
+        Ok(library)
+    }
+
+    /// The `break` or `continue` node of the current statement, if it is one. The keywords are
+    /// not reserved, `break = 1;` is still an assignment.
+    fn loop_control(&self) -> Option<Node> {
+        let next = self.tokens.borrow().get(*self.position.borrow() + 1).cloned();
+        match (self.current_token(), next) {
+            (Token::Identifier(name), Some(Token::Semicolon)) => match name.as_str() {
+                "break" => Some(Node::Break),
+                "continue" => Some(Node::Continue),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Parse a `break;` or `continue;` statement, only valid inside a loop
+    fn parse_loop_control(&self, jump: Node) -> Result<ExprTree> {
+        if *self.loop_depth.borrow() == 0 {
+            return Err(self.invalid_syntax_err(&format!(
+                "{} outside of a loop",
+                if jump == Node::Break { "break" } else { "continue" }
+            )));
+        }
+        self.advance();
+        self.advance();
+        Ok(Box::new(jump))
+    }
+
+    /// Whether the current statement is an `assert(condition, "message");`. The keyword is not
+    /// reserved.
+    fn is_assert(&self) -> bool {
+        let next = self.tokens.borrow().get(*self.position.borrow() + 1).cloned();
+        matches!(self.current_token(), Token::Identifier(name) if name == "assert")
+            && next == Some(Token::OpenParen)
+    }
+
+    /// Parse an assertion, e.g. `assert(notional > 0, "negative notional");`
+    fn parse_assert(&self) -> Result<ExprTree> {
+        self.advance();
+        self.expect_token(Token::OpenParen)?;
+        self.advance();
+        let condition = self
+            .parse_conditions()?
+            .pop()
+            .ok_or(self.invalid_syntax_err("Expected an assertion condition"))?;
+        self.expect_token(Token::Comma)?;
+        self.advance();
+        let message = match self.current_token() {
+            Token::String(message) => message,
+            _ => return Err(self.invalid_syntax_err("Expected an assertion message")),
+        };
+        self.advance();
+        self.expect_token(Token::CloseParen)?;
+        self.advance();
+        self.expect_token(Token::Semicolon)?;
+        self.advance();
+        Ok(Box::new(Node::Assert(vec![condition], message)))
+    }
+
+    /// Whether the current statement is a `param name: type;` declaration. As for imports, the
+    /// keyword is not reserved.
+    fn is_param(&self) -> bool {
+        let position = *self.position.borrow();
+        let tokens = self.tokens.borrow();
+        matches!(tokens.get(position), Some(Token::Identifier(name)) if name == "param")
+            && matches!(tokens.get(position + 1), Some(Token::Identifier(_)))
+            && matches!(tokens.get(position + 2), Some(Token::Colon))
+    }
+
+    /// Parse a template parameter declaration, e.g. `param strike: number;`. The value is bound
+    /// by the evaluator.
+    fn parse_param(&self) -> Result<ExprTree> {
+        self.advance();
+        let variable = self.parse_variable()?;
+        self.expect_token(Token::Colon)?;
+        self.advance();
+        let value_type = match self.current_token() {
+            Token::Identifier(name) => match name.as_str() {
+                "number" => ValueType::Number,
+                "bool" => ValueType::Bool,
+                "string" => ValueType::String,
+                "array" => ValueType::Array,
+                _ => return Err(self.invalid_syntax_err("Invalid parameter type")),
+            },
+            _ => return Err(self.invalid_syntax_err("Expected a parameter type")),
+        };
+        self.advance();
+        self.expect_token(Token::Semicolon)?;
+        self.advance();
+        Ok(Box::new(Node::Param(vec![variable], value_type)))
+    }
+
     /// Parse a pays expression
     fn parse_pays(&self) -> Result<ExprTree> {
         self.expect_token(Token::Pays)?;
         self.advance();
         let mut pays = Vec::new();
+        let mut data = PaysData::new();
         while self.current_token() != Token::EOF
             && self.current_token() != Token::Semicolon
             && self.current_token() != Token::CloseParen
         {
+            if self.current_token() == Token::Identifier("settle".to_string()) {
+                data = self.parse_settlement(data)?;
+                continue;
+            }
+            if self.current_token() == Token::Identifier("leg".to_string()) {
+                self.advance();
+                let leg = match self.current_token() {
+                    Token::String(leg) => leg,
+                    _ => return Err(self.invalid_syntax_err("Expected a leg label")),
+                };
+                self.advance();
+                data = data.with_leg(leg);
+                continue;
+            }
+            if self.current_token() == Token::Identifier("in".to_string()) {
+                self.advance();
+                let currency = match self.current_token() {
+                    Token::Identifier(ccy) => Currency::try_from(ccy)
+                        .map_err(|_| self.invalid_syntax_err("Invalid payment currency"))?,
+                    _ => return Err(self.invalid_syntax_err("Expected a payment currency")),
+                };
+                self.advance();
+                data = data.with_currency(currency);
+                continue;
+            }
             let expr = self.parse_expr()?;
             pays.push(expr);
         }
-        Ok(Box::new(Node::Pays(pays, OnceLock::new())))
+        Ok(Box::new(Node::Pays(pays, data, OnceLock::new())))
+    }
+
+    /// Parse a settlement lag, e.g. `settle 2bd TARGET`
+    fn parse_settlement(&self, data: PaysData) -> Result<PaysData> {
+        self.advance();
+        let days = match self.current_token() {
+            Token::Value(Some(days), None) if days >= 0.0 && days.fract() == 0.0 => days as i32,
+            _ => return Err(self.invalid_syntax_err("Expected a settlement lag in days")),
+        };
+        self.advance();
+        self.expect_token(Token::Identifier("bd".to_string()))?;
+        self.advance();
+        let calendar = match self.current_token() {
+            Token::Identifier(name) | Token::String(name) => name,
+            _ => return Err(self.invalid_syntax_err("Expected a settlement calendar")),
+        };
+        Calendar::try_from(calendar.clone())
+            .map_err(|_| self.invalid_syntax_err("Invalid calendar"))?;
+        self.advance();
+        Ok(data.with_settlement(days, calendar))
     }
 
     /// Parse an if expression
@@ -224,6 +486,33 @@ impl Parser {
         Ok(Box::new(Node::If(nodes, else_index)))
     }
 
+    /// Parse a for loop over the elements of an array
+    fn parse_for(&self) -> Result<ExprTree> {
+        self.expect_token(Token::For)?;
+        self.advance();
+        let variable = self.parse_variable()?;
+        self.expect_token(Token::Identifier("in".to_string()))?;
+        self.advance();
+        let iterable = self.parse_expr()?;
+
+        self.expect_token(Token::OpenCurlyParen)?;
+        self.advance();
+
+        let mut nodes = vec![variable, iterable];
+        *self.loop_depth.borrow_mut() += 1;
+        while self.current_token() != Token::CloseCurlyParen {
+            if self.current_token() == Token::EOF {
+                return Err(self.invalid_syntax_err("Unexpected end of input in for body"));
+            }
+            let expr = self.parse_expression()?;
+            nodes.push(expr);
+        }
+        *self.loop_depth.borrow_mut() -= 1;
+        self.advance();
+
+        Ok(Box::new(Node::ForEach(nodes)))
+    }
+
     /// Parse a variable
     fn parse_variable(&self) -> Result<ExprTree> {
         match self.current_token() {
@@ -267,7 +556,7 @@ impl Parser {
                     Some(true) => Ok(Box::new(Node::True)),
                     Some(false) => Ok(Box::new(Node::False)),
                     None => match value {
-                        Some(v) => Ok(Box::new(Node::Constant(v))),
+                        Some(v) => Ok(Box::new(Node::Constant(v / self.parse_unit_suffix()))),
                         None => Err(self.invalid_syntax_err("Invalid constant")),
                     },
                 }
@@ -276,6 +565,18 @@ impl Parser {
         }
     }
 
+    /// Parse an optional unit suffix after a numeric literal (`25bp`, `4.5%`) and return the
+    /// scale the literal is divided by
+    fn parse_unit_suffix(&self) -> f64 {
+        let scale = match self.current_token() {
+            Token::Percent => 100.0,
+            Token::Identifier(name) if name == "bp" => 10_000.0,
+            _ => return 1.0,
+        };
+        self.advance();
+        scale
+    }
+
     /// Parse a condition
     fn parse_conditions(&self) -> Result<Vec<ExprTree>> {
         let mut conditions = Vec::new();
@@ -399,6 +700,9 @@ impl Parser {
             Token::Pays => {
                 return self.parse_pays();
             }
+            Token::OpenBracket => {
+                return self.parse_list();
+            }
             Token::Identifier(name) => match name.as_str() {
                 "ln" => {
                     min_args = 1;
@@ -430,12 +734,72 @@ impl Parser {
                     max_args = 3;
                     expr = Some(Node::new_cvg());
                 }
+                "accrual" => {
+                    min_args = 4;
+                    max_args = 6;
+                    expr = Some(Node::new_accrual());
+                }
+                "exercise" => {
+                    min_args = 2;
+                    max_args = 2;
+                    expr = Some(Node::new_exercise());
+                }
+                "adjust" => {
+                    min_args = 3;
+                    max_args = 3;
+                    expr = Some(Node::new_adjust());
+                }
+                "is_business_day" => {
+                    min_args = 2;
+                    max_args = 2;
+                    expr = Some(Node::new_is_business_day());
+                }
+                "add_tenor" => {
+                    min_args = 2;
+                    max_args = 3;
+                    expr = Some(Node::new_add_tenor());
+                }
+                "matmul" => {
+                    min_args = 2;
+                    max_args = 2;
+                    expr = Some(Node::new_matmul());
+                }
+                "transpose" => {
+                    min_args = 1;
+                    max_args = 1;
+                    expr = Some(Node::new_transpose());
+                }
+                "solve" => {
+                    min_args = 2;
+                    max_args = 2;
+                    expr = Some(Node::new_solve());
+                }
+                "schedule" => {
+                    min_args = 5;
+                    max_args = 5;
+                    expr = Some(Node::new_schedule());
+                }
                 "Spot" => {
                     return self.parse_spot();
                 }
                 "RateIndex" => {
                     return self.parse_rate_index();
                 }
+                "Fixing" => {
+                    return self.parse_fixing();
+                }
+                "Stock" => {
+                    return self.parse_stock();
+                }
+                "Vol" => {
+                    return self.parse_vol();
+                }
+                "Corr" => {
+                    return self.parse_corr();
+                }
+                "barrier_hit" => {
+                    return self.parse_barrier_hit();
+                }
                 _ => (),
             },
             _ => {
@@ -471,6 +835,39 @@ impl Parser {
                 }
             }
 
+            if matches!(expr, Some(Node::Accrual(_))) {
+                let get_str = |n: &ExprTree| match n.as_ref() {
+                    Node::String(s) => Ok(s.clone()),
+                    _ => Err(self.invalid_syntax_err("Invalid argument, expected string")),
+                };
+                let strs = args[1..].iter().map(get_str).collect::<Result<Vec<String>>>()?;
+                Date::from_str(&strs[0], "%Y-%m-%d").map_err(|_| self.invalid_syntax_err("Invalid date"))?;
+                Date::from_str(&strs[1], "%Y-%m-%d").map_err(|_| self.invalid_syntax_err("Invalid date"))?;
+                DayCounter::try_from(strs[2].clone()).map_err(|_| self.invalid_syntax_err("Invalid day counter"))?;
+                if let Some(compounding) = strs.get(3) {
+                    Compounding::try_from(compounding.clone())
+                        .map_err(|_| self.invalid_syntax_err("Invalid compounding"))?;
+                }
+                if let Some(frequency) = strs.get(4) {
+                    Frequency::try_from(frequency.clone())
+                        .map_err(|_| self.invalid_syntax_err("Invalid frequency"))?;
+                }
+            }
+
+            if matches!(expr, Some(Node::Schedule(_))) {
+                let get_str = |n: &ExprTree| match n.as_ref() {
+                    Node::String(s) => Ok(s.clone()),
+                    _ => Err(self.invalid_syntax_err("Invalid argument, expected string")),
+                };
+                let strs = args.iter().map(get_str).collect::<Result<Vec<String>>>()?;
+                Date::from_str(&strs[0], "%Y-%m-%d").map_err(|_| self.invalid_syntax_err("Invalid date"))?;
+                Date::from_str(&strs[1], "%Y-%m-%d").map_err(|_| self.invalid_syntax_err("Invalid date"))?;
+                Period::from_str(&strs[2]).map_err(|_| self.invalid_syntax_err("Invalid tenor"))?;
+                BusinessDayConvention::try_from(strs[3].clone())
+                    .map_err(|_| self.invalid_syntax_err("Invalid business day convention"))?;
+                Calendar::try_from(strs[4].clone()).map_err(|_| self.invalid_syntax_err("Invalid calendar"))?;
+            }
+
             args.iter().for_each(|arg| expr.as_mut().unwrap().add_child(arg.clone()));
             return Ok(Box::new(expr.unwrap()));
         }
@@ -479,6 +876,109 @@ impl Parser {
         self.parse_variable()
     }
 
+    /// Parse a list literal
+    fn parse_list(&self) -> Result<ExprTree> {
+        self.expect_token(Token::OpenBracket)?;
+        self.advance();
+        let mut items = Vec::new();
+        while self.current_token() != Token::CloseBracket {
+            let item = self.parse_expr()?;
+            items.push(item);
+            match self.current_token() {
+                Token::Comma => self.advance(),
+                Token::CloseBracket => (),
+                _ => return Err(self.invalid_syntax_err("Expected comma or closing bracket")),
+            };
+        }
+        self.advance();
+        Ok(Box::new(Node::List(items)))
+    }
+
+    /// Parse a variable, constant or function followed by any index or method suffixes
+    fn parse_postfix(&self) -> Result<ExprTree> {
+        let mut expr = self.parse_var_const_func()?;
+        loop {
+            match self.current_token() {
+                Token::OpenBracket => {
+                    self.advance();
+                    let index = self.parse_expr()?;
+                    self.expect_token(Token::CloseBracket)?;
+                    self.advance();
+                    expr = Box::new(Node::Index(vec![expr, index]));
+                }
+                Token::Dot => {
+                    self.advance();
+                    expr = self.parse_method(expr)?;
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    /// Parse a `map` or `filter` call. The call is lowered to a loop, hoisted before the current
+    /// statement, that fills a hidden array; the call itself is replaced by that array.
+    fn parse_method(&self, array: ExprTree) -> Result<ExprTree> {
+        let method = match self.current_token() {
+            Token::Identifier(name) => name,
+            _ => return Err(self.invalid_syntax_err("Expected method name")),
+        };
+        self.advance();
+        self.expect_token(Token::OpenParen)?;
+        self.advance();
+
+        let name = match self.current_token() {
+            Token::Identifier(name) => name,
+            _ => return Err(self.invalid_syntax_err("Expected lambda parameter")),
+        };
+        self.advance();
+        self.expect_token(Token::Arrow)?;
+        self.advance();
+
+        let hoisted = self.hoisted.borrow().len();
+        let mut body = self.parse_expr()?;
+        if self.hoisted.borrow().len() != hoisted {
+            return Err(self.invalid_syntax_err("Nested map or filter inside a lambda"));
+        }
+        self.expect_token(Token::CloseParen)?;
+        self.advance();
+
+        let result = self.new_temporary();
+
+        // the parameter is local to the lambda, it becomes a hidden variable
+        let parameter = self.new_temporary();
+        if let Node::Variable(_, local, _) = parameter.as_ref() {
+            rename_variable(&mut body, &name, local);
+        }
+        let loop_body = match method.as_str() {
+            "map" => Box::new(Node::Append(vec![result.clone(), body])),
+            "filter" => Box::new(Node::If(
+                vec![
+                    body,
+                    Box::new(Node::Append(vec![result.clone(), parameter.clone()])),
+                ],
+                None,
+            )),
+            _ => return Err(self.invalid_syntax_err("Unknown method, expected map or filter")),
+        };
+
+        let mut hoisted = self.hoisted.borrow_mut();
+        hoisted.push(Box::new(Node::Assign(vec![
+            result.clone(),
+            Box::new(Node::List(Vec::new())),
+        ])));
+        hoisted.push(Box::new(Node::ForEach(vec![parameter, array, loop_body])));
+        Ok(result)
+    }
+
+    /// Create a hidden variable. Its name cannot clash with user variables since identifiers
+    /// must start with a letter.
+    fn new_temporary(&self) -> ExprTree {
+        let mut count = self.temporaries.borrow_mut();
+        let name = format!("__tmp{}", *count);
+        *count += 1;
+        Box::new(Node::Variable(Vec::new(), name, OnceLock::new()))
+    }
+
     /// Parse a spot expression
     fn parse_spot(&self) -> Result<ExprTree> {
         self.expect_token(Token::Identifier("Spot".to_string()))?;
@@ -533,6 +1033,131 @@ impl Parser {
         Ok(Box::new(Node::RateIndex(name, start, end, OnceLock::new())))
     }
 
+    fn parse_stock(&self) -> Result<ExprTree> {
+        let (name, date) = self.parse_underlying("Stock")?;
+        Ok(Box::new(Node::Equity(name, date, OnceLock::new())))
+    }
+
+    fn parse_vol(&self) -> Result<ExprTree> {
+        let (name, date) = self.parse_underlying("Vol")?;
+        Ok(Box::new(Node::Volatility(name, date, OnceLock::new())))
+    }
+
+    fn parse_corr(&self) -> Result<ExprTree> {
+        self.expect_token(Token::Identifier("Corr".to_string()))?;
+        self.advance();
+        self.expect_token(Token::OpenParen)?;
+        self.advance();
+        let first = match *self.parse_string()? {
+            Node::String(s) => s,
+            _ => return Err(self.invalid_syntax_err("Invalid argument, expected string")),
+        };
+        self.expect_token(Token::Comma)?;
+        self.advance();
+        let second = match *self.parse_string()? {
+            Node::String(s) => s,
+            _ => return Err(self.invalid_syntax_err("Invalid argument, expected string")),
+        };
+        self.expect_token(Token::CloseParen)?;
+        self.advance();
+        Ok(Box::new(Node::Correlation(first, second, OnceLock::new())))
+    }
+
+    /// Parse `barrier_hit(underlying, level, start, end, kind)`, where the underlying is a `Spot`
+    /// or `Stock` observation and kind is one of `up-in`, `up-out`, `down-in` or `down-out`. It
+    /// evaluates to the probability that an `-in` barrier is hit within the window, or that an
+    /// `-out` barrier is not.
+    fn parse_barrier_hit(&self) -> Result<ExprTree> {
+        self.expect_token(Token::Identifier("barrier_hit".to_string()))?;
+        self.advance();
+        self.expect_token(Token::OpenParen)?;
+        self.advance();
+        let underlying = self.parse_var_const_func()?;
+        if !matches!(underlying.as_ref(), Node::Spot(..) | Node::Equity(..)) {
+            return Err(self.invalid_syntax_err("Expected a Spot or Stock observation"));
+        }
+        self.expect_token(Token::Comma)?;
+        self.advance();
+        let level = self.parse_expr()?;
+
+        let mut strs = Vec::new();
+        for _ in 0..3 {
+            self.expect_token(Token::Comma)?;
+            self.advance();
+            match *self.parse_string()? {
+                Node::String(s) => strs.push(s),
+                _ => return Err(self.invalid_syntax_err("Invalid argument, expected string")),
+            }
+        }
+        self.expect_token(Token::CloseParen)?;
+        self.advance();
+
+        let start = Date::from_str(&strs[0], "%Y-%m-%d").map_err(|_| self.invalid_syntax_err("Invalid date"))?;
+        let end = Date::from_str(&strs[1], "%Y-%m-%d").map_err(|_| self.invalid_syntax_err("Invalid date"))?;
+        if end <= start {
+            return Err(self.invalid_syntax_err("Barrier end date must be after its start date"));
+        }
+        let (up, out) = match strs[2].as_str() {
+            "up-in" => (true, false),
+            "up-out" => (true, true),
+            "down-in" => (false, false),
+            "down-out" => (false, true),
+            _ => return Err(self.invalid_syntax_err("Invalid barrier kind")),
+        };
+
+        let mut node = Node::new_barrier_hit(start, end, up, out);
+        node.add_child(underlying);
+        node.add_child(level);
+        Ok(Box::new(node))
+    }
+
+    /// Parse the `(name[, date])` arguments of an underlying observation
+    fn parse_underlying(&self, function: &str) -> Result<(String, Option<Date>)> {
+        self.expect_token(Token::Identifier(function.to_string()))?;
+        self.advance();
+        self.expect_token(Token::OpenParen)?;
+        self.advance();
+        let name = match *self.parse_string()? {
+            Node::String(s) => s,
+            _ => return Err(self.invalid_syntax_err("Invalid argument, expected string")),
+        };
+        let mut date = None;
+        if self.current_token() == Token::Comma {
+            self.advance();
+            date = match *self.parse_string()? {
+                Node::String(s) => Some(Date::from_str(&s, "%Y-%m-%d").map_err(|_| self.invalid_syntax_err("Invalid date"))?),
+                _ => return Err(self.invalid_syntax_err("Invalid argument, expected string")),
+            };
+        }
+        self.expect_token(Token::CloseParen)?;
+        self.advance();
+        Ok((name, date))
+    }
+
+    fn parse_fixing(&self) -> Result<ExprTree> {
+        self.expect_token(Token::Identifier("Fixing".to_string()))?;
+        self.advance();
+        self.expect_token(Token::OpenParen)?;
+        self.advance();
+
+        let name = match *self.parse_string()? {
+            Node::String(s) => s,
+            _ => return Err(self.invalid_syntax_err("Invalid argument, expected string")),
+        };
+        self.expect_token(Token::Comma)?;
+        self.advance();
+        let date_str = match *self.parse_string()? {
+            Node::String(s) => s,
+            _ => return Err(self.invalid_syntax_err("Invalid argument, expected string")),
+        };
+        self.expect_token(Token::CloseParen)?;
+        self.advance();
+
+        let date = Date::from_str(&date_str, "%Y-%m-%d").map_err(|_| self.invalid_syntax_err("Invalid date"))?;
+
+        Ok(Box::new(Node::Fixing(name, date, OnceLock::new())))
+    }
+
     /// Parse an expression
     fn parse_expr(&self) -> Result<ExprTree> {
         let mut lhs = self.parse_comparison()?;
@@ -591,21 +1216,21 @@ impl Parser {
         Ok(lhs)
     }
 
-    /// Parse an expression
+    /// Parse a power (`^` or `**`), which is right associative: `a ^ b ^ c` is `a ^ (b ^ c)`
     fn parse_expr_l3(&self) -> Result<ExprTree> {
-        let mut lhs = self.parse_var_const_func()?;
+        let lhs = self.parse_postfix()?;
 
-        while self.current_token() == Token::Power && self.current_token() != Token::EOF {
-            self.advance();
-            match self.current_token() {
-                Token::EOF => return Err(self.invalid_syntax_err("Unexpected end of expression")),
-                _ => {
-                    let rhs = self.parse_var_const_func()?;
-                    lhs = Box::new(Node::Pow(vec![lhs, rhs]));
-                }
+        if self.current_token() != Token::Power {
+            return Ok(lhs);
+        }
+        self.advance();
+        match self.current_token() {
+            Token::EOF => Err(self.invalid_syntax_err("Unexpected end of expression")),
+            _ => {
+                let rhs = self.parse_expr_l3()?;
+                Ok(Box::new(Node::Pow(vec![lhs, rhs])))
             }
         }
-        Ok(lhs)
     }
 
     // fn parse_expr_l4(&self) -> Result<ExprTree> {
@@ -628,8 +1253,8 @@ impl TryFrom<String> for ExprTree {
     type Error = ScriptingError;
 
     fn try_from(script: String) -> Result<ExprTree> {
-        let tokens = script.tokenize()?;
-        let parser = Parser::new(tokens);
+        let (tokens, spans) = Lexer::new(script).tokenize_with_spans()?.into_iter().unzip();
+        let parser = Parser::new(tokens).with_spans(spans);
         parser.parse()
     }
 }
@@ -638,9 +1263,21 @@ impl TryFrom<&str> for ExprTree {
     type Error = ScriptingError;
 
     fn try_from(script: &str) -> Result<ExprTree> {
-        let tokens = script.to_string().tokenize()?;
-        let parser = Parser::new(tokens);
-        parser.parse()
+        ExprTree::try_from(script.to_string())
+    }
+}
+
+/// Rename the variable `from` to `to` everywhere in `expr`
+fn rename_variable(expr: &mut ExprTree, from: &str, to: &str) {
+    if let Node::Variable(_, name, _) = expr.as_mut() {
+        if name == from {
+            *name = to.to_string();
+        }
+    }
+    if !expr.is_leaf() {
+        expr.children_mut()
+            .iter_mut()
+            .for_each(|child| rename_variable(child, from, to));
     }
 }
 
@@ -650,6 +1287,36 @@ mod other_tests {
     use super::*;
     use crate::parsers::lexer::Lexer;
 
+    #[test]
+    fn test_parse_with_spans() {
+        let expr = ExprTree::try_from("x = 1;\n  y = x;").unwrap();
+
+        let expected = Box::new(Node::Base(vec![
+            Box::new(Node::Spanned(
+                vec![Box::new(Node::Assign(vec![
+                    Box::new(Node::Variable(Vec::new(), "x".to_string(), OnceLock::new())),
+                    Box::new(Node::Constant(1.0)),
+                ]))],
+                Span::new(1, 1),
+            )),
+            Box::new(Node::Spanned(
+                vec![Box::new(Node::Assign(vec![
+                    Box::new(Node::Variable(Vec::new(), "y".to_string(), OnceLock::new())),
+                    Box::new(Node::Variable(Vec::new(), "x".to_string(), OnceLock::new())),
+                ]))],
+                Span::new(2, 3),
+            )),
+        ]));
+
+        assert_eq!(expr, expected);
+    }
+
+    #[test]
+    fn test_syntax_error_position() {
+        let err = ExprTree::try_from("x = 1;\ny = ln(1, 2);").unwrap_err();
+        assert!(err.to_string().contains("line 2, column 13"), "{}", err);
+    }
+
     #[test]
     fn test_advance_token() {
         let tokens = Lexer::new("a = 1;".to_string()).tokenize().unwrap();
@@ -1170,6 +1837,35 @@ mod tests_expect_token {
         assert_eq!(nodes, expected);
     }
 
+    #[test]
+    fn test_accrual_function() {
+        let script = "
+            x = accrual(0.05, \"2020-01-01\", \"2021-01-01\", \"Actual360\", \"Compounded\", \"Quarterly\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![Box::new(Node::Assign(vec![
+            Box::new(Node::Variable(Vec::new(), "x".to_string(), OnceLock::new())),
+            Box::new(Node::Accrual(vec![
+                Box::new(Node::Constant(0.05)),
+                Box::new(Node::String("2020-01-01".to_string())),
+                Box::new(Node::String("2021-01-01".to_string())),
+                Box::new(Node::String("Actual360".to_string())),
+                Box::new(Node::String("Compounded".to_string())),
+                Box::new(Node::String("Quarterly".to_string())),
+            ])),
+        ]))]));
+
+        assert_eq!(nodes, expected);
+
+        let script = "x = accrual(0.05, \"2020-01-01\", \"2021-01-01\", \"Actual360\", \"Weird\");";
+        let tokens = Lexer::new(script.to_string()).tokenize().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+    }
+
     #[test]
     fn test_max_function() {
         let script = "
@@ -1256,6 +1952,175 @@ mod tests_expect_token {
 
         assert_eq!(nodes, expected);
     }
+
+    #[test]
+    fn test_stock_function() {
+        let script = "
+            x = Stock(\"AAPL\");
+            y = Stock(\"AAPL\", \"2024-06-03\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![
+            Box::new(Node::Assign(vec![
+                Box::new(Node::Variable(Vec::new(), "x".to_string(), OnceLock::new())),
+                Box::new(Node::Equity("AAPL".to_string(), None, OnceLock::new())),
+            ])),
+            Box::new(Node::Assign(vec![
+                Box::new(Node::Variable(Vec::new(), "y".to_string(), OnceLock::new())),
+                Box::new(Node::Equity(
+                    "AAPL".to_string(),
+                    Some(Date::new(2024, 6, 3)),
+                    OnceLock::new(),
+                )),
+            ])),
+        ]));
+
+        assert_eq!(nodes, expected);
+    }
+
+    #[test]
+    fn test_vol_function() {
+        let script = "
+            x = Vol(\"EUR/USD\", \"2024-06-03\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![Box::new(Node::Assign(vec![
+            Box::new(Node::Variable(Vec::new(), "x".to_string(), OnceLock::new())),
+            Box::new(Node::Volatility(
+                "EUR/USD".to_string(),
+                Some(Date::new(2024, 6, 3)),
+                OnceLock::new(),
+            )),
+        ]))]));
+
+        assert_eq!(nodes, expected);
+    }
+
+    #[test]
+    fn test_corr_function() {
+        let script = "
+            x = Corr(\"AAPL\", \"EUR/USD\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![Box::new(Node::Assign(vec![
+            Box::new(Node::Variable(Vec::new(), "x".to_string(), OnceLock::new())),
+            Box::new(Node::Correlation(
+                "AAPL".to_string(),
+                "EUR/USD".to_string(),
+                OnceLock::new(),
+            )),
+        ]))]));
+
+        assert_eq!(nodes, expected);
+
+        let script = "x = Corr(\"AAPL\");".to_string();
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+    }
+
+    #[test]
+    fn test_barrier_hit_function() {
+        let script = "
+            p = barrier_hit(Spot(\"EUR\", \"USD\"), 1.2, \"2024-01-01\", \"2024-02-01\", \"up-out\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![Box::new(Node::Assign(vec![
+            Box::new(Node::Variable(Vec::new(), "p".to_string(), OnceLock::new())),
+            Box::new(Node::BarrierHit(
+                vec![
+                    Box::new(Node::Spot(Currency::EUR, Some(Currency::USD), OnceLock::new())),
+                    Box::new(Node::Constant(1.2)),
+                ],
+                Date::new(2024, 1, 1),
+                Date::new(2024, 2, 1),
+                true,
+                true,
+                OnceLock::new(),
+                OnceLock::new(),
+            )),
+        ]))]));
+
+        assert_eq!(nodes, expected);
+
+        let script = "
+            p = barrier_hit(x, 1.2, \"2024-01-01\", \"2024-02-01\", \"up-out\");
+        "
+        .to_string();
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+
+        let script = "
+            p = barrier_hit(Stock(\"AAPL\"), 150, \"2024-01-01\", \"2024-02-01\", \"sideways\");
+        "
+        .to_string();
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+    }
+
+    #[test]
+    fn test_exercise_function() {
+        let script = "ex = exercise(opt, max(100 - s, 0));".to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![Box::new(Node::Assign(vec![
+            Box::new(Node::Variable(Vec::new(), "ex".to_string(), OnceLock::new())),
+            Box::new(Node::Exercise(
+                vec![
+                    Box::new(Node::Variable(Vec::new(), "opt".to_string(), OnceLock::new())),
+                    Box::new(Node::Max(vec![
+                        Box::new(Node::Subtract(vec![
+                            Box::new(Node::Constant(100.0)),
+                            Box::new(Node::Variable(Vec::new(), "s".to_string(), OnceLock::new())),
+                        ])),
+                        Box::new(Node::Constant(0.0)),
+                    ])),
+                ],
+                OnceLock::new(),
+            )),
+        ]))]));
+
+        assert_eq!(nodes, expected);
+    }
+
+    #[test]
+    fn test_fixing_function() {
+        let script = "
+            x = Fixing(\"SOFR-3M\", \"2024-01-10\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![Box::new(Node::Assign(vec![
+            Box::new(Node::Variable(Vec::new(), "x".to_string(), OnceLock::new())),
+            Box::new(Node::Fixing(
+                "SOFR-3M".to_string(),
+                Date::new(2024, 1, 10),
+                OnceLock::new(),
+            )),
+        ]))]));
+
+        assert_eq!(nodes, expected);
+    }
 }
 
 /// tests for reserved keywords. These are keywords that are reserved in the scripting language
@@ -1646,6 +2511,7 @@ mod test_pays_expression {
                     ])),
                     Box::new(Node::Constant(0.0)),
                 ]))],
+                PaysData::new(),
                 OnceLock::new(),
             )),
         ]))]));
@@ -1669,10 +2535,579 @@ mod test_pays_assignment {
             Box::new(Node::Variable(Vec::new(), "prd".to_string(), OnceLock::new())),
             Box::new(Node::Add(vec![
                 Box::new(Node::Variable(Vec::new(), "prd".to_string(), OnceLock::new())),
-                Box::new(Node::Pays(vec![Box::new(Node::Constant(100.0))], OnceLock::new())),
+                Box::new(Node::Pays(
+                    vec![Box::new(Node::Constant(100.0))],
+                    PaysData::new(),
+                    OnceLock::new(),
+                )),
             ])),
         ]))]));
 
         assert_eq!(nodes, expected);
     }
+
+    #[test]
+    fn test_pays_with_settlement() {
+        let script = "prd pays 100 settle 2bd TARGET;".to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![Box::new(Node::Assign(vec![
+            Box::new(Node::Variable(Vec::new(), "prd".to_string(), OnceLock::new())),
+            Box::new(Node::Add(vec![
+                Box::new(Node::Variable(Vec::new(), "prd".to_string(), OnceLock::new())),
+                Box::new(Node::Pays(
+                    vec![Box::new(Node::Constant(100.0))],
+                    PaysData::new().with_settlement(2, "TARGET".to_string()),
+                    OnceLock::new(),
+                )),
+            ])),
+        ]))]));
+
+        assert_eq!(nodes, expected);
+
+        let script = "prd pays 100 settle 2bd NOWHERE;".to_string();
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+    }
+
+    #[test]
+    fn test_pays_with_leg() {
+        let script = "pays 100 in EUR leg \"funding\";".to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![Box::new(Node::Pays(
+            vec![Box::new(Node::Constant(100.0))],
+            PaysData::new()
+                .with_currency(Currency::EUR)
+                .with_leg("funding".to_string()),
+            OnceLock::new(),
+        ))]));
+
+        assert_eq!(nodes, expected);
+    }
+}
+
+#[cfg(test)]
+mod test_dates {
+    use super::*;
+
+    #[test]
+    fn test_adjust_and_is_business_day() {
+        let script = "
+            d = adjust(\"2024-03-30\", \"Following\", \"TARGET\");
+            b = is_business_day(d, \"NYSE\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![
+            Box::new(Node::Assign(vec![
+                Box::new(Node::Variable(Vec::new(), "d".to_string(), OnceLock::new())),
+                Box::new(Node::Adjust(vec![
+                    Box::new(Node::String("2024-03-30".to_string())),
+                    Box::new(Node::String("Following".to_string())),
+                    Box::new(Node::String("TARGET".to_string())),
+                ])),
+            ])),
+            Box::new(Node::Assign(vec![
+                Box::new(Node::Variable(Vec::new(), "b".to_string(), OnceLock::new())),
+                Box::new(Node::IsBusinessDay(vec![
+                    Box::new(Node::Variable(Vec::new(), "d".to_string(), OnceLock::new())),
+                    Box::new(Node::String("NYSE".to_string())),
+                ])),
+            ])),
+        ]));
+
+        assert_eq!(nodes, expected);
+    }
+
+    #[test]
+    fn test_add_tenor() {
+        let script = "
+            d = add_tenor(\"2024-01-31\", \"1M\");
+            e = add_tenor(d, \"2D\", \"TARGET\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![
+            Box::new(Node::Assign(vec![
+                Box::new(Node::Variable(Vec::new(), "d".to_string(), OnceLock::new())),
+                Box::new(Node::AddTenor(vec![
+                    Box::new(Node::String("2024-01-31".to_string())),
+                    Box::new(Node::String("1M".to_string())),
+                ])),
+            ])),
+            Box::new(Node::Assign(vec![
+                Box::new(Node::Variable(Vec::new(), "e".to_string(), OnceLock::new())),
+                Box::new(Node::AddTenor(vec![
+                    Box::new(Node::Variable(Vec::new(), "d".to_string(), OnceLock::new())),
+                    Box::new(Node::String("2D".to_string())),
+                    Box::new(Node::String("TARGET".to_string())),
+                ])),
+            ])),
+        ]));
+
+        assert_eq!(nodes, expected);
+    }
+}
+
+#[cfg(test)]
+mod test_arrays {
+    use super::*;
+
+    #[test]
+    fn test_list_and_index() {
+        let script = "x = [1, 2]; y = x[1];".to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![
+            Box::new(Node::Assign(vec![
+                Box::new(Node::Variable(Vec::new(), "x".to_string(), OnceLock::new())),
+                Box::new(Node::List(vec![
+                    Box::new(Node::Constant(1.0)),
+                    Box::new(Node::Constant(2.0)),
+                ])),
+            ])),
+            Box::new(Node::Assign(vec![
+                Box::new(Node::Variable(Vec::new(), "y".to_string(), OnceLock::new())),
+                Box::new(Node::Index(vec![
+                    Box::new(Node::Variable(Vec::new(), "x".to_string(), OnceLock::new())),
+                    Box::new(Node::Constant(1.0)),
+                ])),
+            ])),
+        ]));
+
+        assert_eq!(nodes, expected);
+    }
+
+    #[test]
+    fn test_linear_algebra_builtins() {
+        let script = "x = solve(a, transpose(b));".to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![Box::new(Node::Assign(vec![
+            Box::new(Node::Variable(Vec::new(), "x".to_string(), OnceLock::new())),
+            Box::new(Node::Solve(vec![
+                Box::new(Node::Variable(Vec::new(), "a".to_string(), OnceLock::new())),
+                Box::new(Node::Transpose(vec![Box::new(Node::Variable(
+                    Vec::new(),
+                    "b".to_string(),
+                    OnceLock::new(),
+                ))])),
+            ])),
+        ]))]));
+
+        assert_eq!(nodes, expected);
+
+        let script = "x = matmul(a);".to_string();
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+    }
+
+    #[test]
+    fn test_schedule() {
+        let script = "
+            dates = schedule(\"2024-01-15\", \"2025-01-15\", \"3M\", \"ModifiedFollowing\", \"TARGET\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![Box::new(Node::Assign(vec![
+            Box::new(Node::Variable(Vec::new(), "dates".to_string(), OnceLock::new())),
+            Box::new(Node::Schedule(vec![
+                Box::new(Node::String("2024-01-15".to_string())),
+                Box::new(Node::String("2025-01-15".to_string())),
+                Box::new(Node::String("3M".to_string())),
+                Box::new(Node::String("ModifiedFollowing".to_string())),
+                Box::new(Node::String("TARGET".to_string())),
+            ])),
+        ]))]));
+
+        assert_eq!(nodes, expected);
+    }
+
+    #[test]
+    fn test_schedule_invalid_arguments() {
+        let script = "
+            dates = schedule(\"2024-01-15\", \"2025-01-15\", \"3Q\", \"ModifiedFollowing\", \"TARGET\");
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+
+        let script = "dates = schedule(\"2024-01-15\", \"2025-01-15\", \"3M\", \"Following\");".to_string();
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+    }
+
+    #[test]
+    fn test_for_each() {
+        let script = "
+            for x in xs {
+                s = s + x;
+            }
+        "
+        .to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let expected = Box::new(Node::Base(vec![Box::new(Node::ForEach(vec![
+            Box::new(Node::Variable(Vec::new(), "x".to_string(), OnceLock::new())),
+            Box::new(Node::Variable(Vec::new(), "xs".to_string(), OnceLock::new())),
+            Box::new(Node::Assign(vec![
+                Box::new(Node::Variable(Vec::new(), "s".to_string(), OnceLock::new())),
+                Box::new(Node::Add(vec![
+                    Box::new(Node::Variable(Vec::new(), "s".to_string(), OnceLock::new())),
+                    Box::new(Node::Variable(Vec::new(), "x".to_string(), OnceLock::new())),
+                ])),
+            ])),
+        ]))]));
+
+        assert_eq!(nodes, expected);
+    }
+
+    #[test]
+    fn test_map_is_lowered_to_loop() {
+        let script = "y = xs.map(x -> x * 2);".to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let tmp = Box::new(Node::Variable(Vec::new(), "__tmp0".to_string(), OnceLock::new()));
+        let x = Box::new(Node::Variable(Vec::new(), "__tmp1".to_string(), OnceLock::new()));
+        let expected = Box::new(Node::Base(vec![Box::new(Node::Base(vec![
+            Box::new(Node::Assign(vec![tmp.clone(), Box::new(Node::List(Vec::new()))])),
+            Box::new(Node::ForEach(vec![
+                x.clone(),
+                Box::new(Node::Variable(Vec::new(), "xs".to_string(), OnceLock::new())),
+                Box::new(Node::Append(vec![
+                    tmp.clone(),
+                    Box::new(Node::Multiply(vec![x, Box::new(Node::Constant(2.0))])),
+                ])),
+            ])),
+            Box::new(Node::Assign(vec![
+                Box::new(Node::Variable(Vec::new(), "y".to_string(), OnceLock::new())),
+                tmp,
+            ])),
+        ]))]));
+
+        assert_eq!(nodes, expected);
+    }
+
+    #[test]
+    fn test_filter_is_lowered_to_conditional_loop() {
+        let script = "y = xs.filter(x -> x > 0);".to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        let nodes = Parser::new(tokens).parse().unwrap();
+
+        let tmp = Box::new(Node::Variable(Vec::new(), "__tmp0".to_string(), OnceLock::new()));
+        let x = Box::new(Node::Variable(Vec::new(), "__tmp1".to_string(), OnceLock::new()));
+        let expected = Box::new(Node::Base(vec![Box::new(Node::Base(vec![
+            Box::new(Node::Assign(vec![tmp.clone(), Box::new(Node::List(Vec::new()))])),
+            Box::new(Node::ForEach(vec![
+                x.clone(),
+                Box::new(Node::Variable(Vec::new(), "xs".to_string(), OnceLock::new())),
+                Box::new(Node::If(
+                    vec![
+                        Box::new(Node::Superior(vec![
+                            x.clone(),
+                            Box::new(Node::Constant(0.0)),
+                        ])),
+                        Box::new(Node::Append(vec![tmp.clone(), x])),
+                    ],
+                    None,
+                )),
+            ])),
+            Box::new(Node::Assign(vec![
+                Box::new(Node::Variable(Vec::new(), "y".to_string(), OnceLock::new())),
+                tmp,
+            ])),
+        ]))]));
+
+        assert_eq!(nodes, expected);
+    }
+
+    #[test]
+    fn test_unknown_method() {
+        let script = "y = xs.reduce(x -> x);".to_string();
+
+        let tokens = Lexer::new(script).tokenize().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_imports {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::parsers::lexer::Lexer;
+
+    fn resolver(libraries: Vec<(&str, &str)>) -> ScriptResolver {
+        let libraries: HashMap<String, String> = libraries
+            .into_iter()
+            .map(|(path, source)| (path.to_string(), source.to_string()))
+            .collect();
+        Rc::new(move |path: &str| {
+            libraries.get(path).cloned().ok_or(ScriptingError::EvaluationError(format!(
+                "Library {} not found",
+                path
+            )))
+        })
+    }
+
+    fn parse(script: &str, resolver: ScriptResolver) -> Result<ExprTree> {
+        let tokens = Lexer::new(script.to_string()).tokenize()?;
+        Parser::new(tokens).with_resolver(resolver).parse()
+    }
+
+    fn assign(name: &str, value: f64) -> ExprTree {
+        Box::new(Node::Assign(vec![
+            Box::new(Node::Variable(Vec::new(), name.to_string(), OnceLock::new())),
+            Box::new(Node::Constant(value)),
+        ]))
+    }
+
+    #[test]
+    fn test_import() {
+        let resolver = resolver(vec![("lib/helpers.ox", "rate = 0.05;")]);
+        let result = parse("import \"lib/helpers.ox\"\nx = 1;", resolver).unwrap();
+
+        let expected = Box::new(Node::Base(vec![
+            Box::new(Node::Base(vec![assign("rate", 0.05)])),
+            assign("x", 1.0),
+        ]));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_import_once() {
+        let resolver = resolver(vec![
+            ("a.ox", "import \"b.ox\"; a = 1;"),
+            ("b.ox", "include \"a.ox\"; b = 2;"),
+        ]);
+        let result = parse("import \"a.ox\"; import \"b.ox\";", resolver).unwrap();
+
+        let expected = Box::new(Node::Base(vec![
+            Box::new(Node::Base(vec![
+                Box::new(Node::Base(vec![
+                    Box::new(Node::Base(Vec::new())),
+                    assign("b", 2.0),
+                ])),
+                assign("a", 1.0),
+            ])),
+            Box::new(Node::Base(Vec::new())),
+        ]));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let tokens = Lexer::new("import \"a.ox\";".to_string()).tokenize().unwrap();
+        let err = Parser::new(tokens).parse().unwrap_err();
+        assert!(err.to_string().contains("No script resolver set for import"));
+
+        let err = parse("import \"missing.ox\";", resolver(Vec::new())).unwrap_err();
+        assert!(err.to_string().contains("Library missing.ox not found"));
+
+        let err = parse("import \"bad.ox\";", resolver(vec![("bad.ox", "x = ;")])).unwrap_err();
+        assert!(err.to_string().contains("Error in import \"bad.ox\""));
+    }
+
+    #[test]
+    fn test_import_as_variable() {
+        let result = parse("import = 1;", resolver(Vec::new())).unwrap();
+        assert_eq!(result, Box::new(Node::Base(vec![assign("import", 1.0)])));
+    }
+}
+
+#[cfg(test)]
+mod test_params {
+    use super::*;
+    use crate::parsers::lexer::Lexer;
+
+    fn parse(script: &str) -> Result<ExprTree> {
+        let tokens = Lexer::new(script.to_string()).tokenize()?;
+        Parser::new(tokens).parse()
+    }
+
+    #[test]
+    fn test_param_declaration() {
+        let result = parse("param strike: number;\nparam ccy: string;").unwrap();
+
+        let variable = |name: &str| {
+            Box::new(Node::Variable(Vec::new(), name.to_string(), OnceLock::new()))
+        };
+        let expected = Box::new(Node::Base(vec![
+            Box::new(Node::Param(vec![variable("strike")], ValueType::Number)),
+            Box::new(Node::Param(vec![variable("ccy")], ValueType::String)),
+        ]));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_param_errors() {
+        let err = parse("param strike: integer;").unwrap_err();
+        assert!(err.to_string().contains("Invalid parameter type"));
+        assert!(parse("param = 1; x = param;").is_ok());
+    }
+}
+
+#[cfg(test)]
+mod test_loop_control {
+    use super::*;
+    use crate::parsers::lexer::Lexer;
+
+    fn parse(script: &str) -> Result<ExprTree> {
+        let tokens = Lexer::new(script.to_string()).tokenize()?;
+        Parser::new(tokens).parse()
+    }
+
+    #[test]
+    fn test_break_in_loop() {
+        let result = parse("for v in [1] { continue; break; }").unwrap();
+        match result.children()[0].as_ref() {
+            Node::ForEach(children) => {
+                assert_eq!(*children[2], Node::Continue);
+                assert_eq!(*children[3], Node::Break);
+            }
+            _ => panic!("Expected a for loop"),
+        }
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let err = parse("x = 1; break;").unwrap_err();
+        assert!(err.to_string().contains("break outside of a loop"));
+        let err = parse("if x > 1 { continue; }").unwrap_err();
+        assert!(err.to_string().contains("continue outside of a loop"));
+        assert!(parse("break = 1;").is_ok());
+    }
+}
+
+#[cfg(test)]
+mod test_power {
+    use super::*;
+    use crate::parsers::lexer::Lexer;
+
+    fn parse_expr(script: &str) -> ExprTree {
+        let tokens = Lexer::new(script.to_string()).tokenize().unwrap();
+        Parser::new(tokens).parse_expr().unwrap()
+    }
+
+    fn pow(lhs: Node, rhs: Node) -> Node {
+        Node::Pow(vec![Box::new(lhs), Box::new(rhs)])
+    }
+
+    #[test]
+    fn test_right_associative() {
+        let expected = pow(
+            Node::Constant(2.0),
+            pow(Node::Constant(3.0), Node::Constant(2.0)),
+        );
+        assert_eq!(*parse_expr("2 ^ 3 ^ 2"), expected);
+        assert_eq!(*parse_expr("2 ** 3 ^ 2"), expected);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let expected = Node::Add(vec![
+            Box::new(Node::Constant(1.0)),
+            Box::new(Node::Multiply(vec![
+                Box::new(Node::Constant(2.0)),
+                Box::new(pow(Node::Constant(3.0), Node::Constant(2.0))),
+            ])),
+        ]);
+        assert_eq!(*parse_expr("1 + 2 * 3 ^ 2"), expected);
+    }
+}
+
+#[cfg(test)]
+mod test_unit_suffixes {
+    use super::*;
+    use crate::parsers::lexer::Lexer;
+
+    fn parse(script: &str) -> Result<ExprTree> {
+        let tokens = Lexer::new(script.to_string()).tokenize()?;
+        Parser::new(tokens).parse()
+    }
+
+    fn assigned(script: &str) -> Node {
+        let result = parse(script).unwrap();
+        match result.children()[0].as_ref() {
+            Node::Assign(children) => *children[1].clone(),
+            _ => panic!("Expected an assignment"),
+        }
+    }
+
+    #[test]
+    fn test_basis_points() {
+        assert_eq!(assigned("spread = 25bp;"), Node::Constant(0.0025));
+    }
+
+    #[test]
+    fn test_percent() {
+        assert_eq!(assigned("coupon = 4.5%;"), Node::Constant(0.045));
+        assert_eq!(
+            assigned("x = 1 + 50%;"),
+            Node::Add(vec![
+                Box::new(Node::Constant(1.0)),
+                Box::new(Node::Constant(0.5))
+            ])
+        );
+    }
+
+    #[test]
+    fn test_bp_variable() {
+        // `bp` is only a suffix right after a number
+        assert!(parse("bp = 1; x = bp * 2;").is_ok());
+    }
+}
+
+#[cfg(test)]
+mod test_assert {
+    use super::*;
+    use crate::parsers::lexer::Lexer;
+
+    fn parse(script: &str) -> Result<ExprTree> {
+        let tokens = Lexer::new(script.to_string()).tokenize()?;
+        Parser::new(tokens).parse()
+    }
+
+    #[test]
+    fn test_assert() {
+        let result = parse("assert(notional > 0, \"negative notional\");").unwrap();
+        match result.children()[0].as_ref() {
+            Node::Assert(children, message) => {
+                assert!(matches!(*children[0], Node::Superior(_)));
+                assert_eq!(message, "negative notional");
+            }
+            _ => panic!("Expected an assertion"),
+        }
+    }
+
+    #[test]
+    fn test_assert_requires_message() {
+        let err = parse("assert(x > 0);").unwrap_err();
+        assert!(err.to_string().contains("Unexpected token"));
+        assert!(parse("assert = 1;").is_ok());
+    }
 }
@@ -1,4 +1,4 @@
 pub use crate::{
-    nodes::{evaluator::*, indexer::*, node::*, traits::*},
+    nodes::{bytecode::*, evaluator::*, indexer::*, lsm::*, node::*, optimizer::*, printer::*, trace::*, traits::*, typechecker::*, vectorized::*},
     parsers::{lexer::*, parser::*},
 };
@@ -1,6 +1,8 @@
 use rustatlas::prelude::*;
 use thiserror::Error;
 
+use crate::parsers::lexer::Span;
+
 #[derive(Debug, Error)]
 pub enum ScriptingError {
     #[error("Invalid Syntax: {0}")]
@@ -13,8 +15,53 @@ pub enum ScriptingError {
     UnexpectedToken(String),
     #[error("Error while evaluating: {0}")]
     EvaluationError(String),
+    #[error("Type error: {0}")]
+    TypeError(String),
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+    #[error("Assertion failed: {0}")]
+    AssertionFailed(String),
+    #[error("Evaluation cancelled: {0}")]
+    Cancelled(String),
+    #[error("Resource exhausted: {0}")]
+    ResourceExhausted(String),
+    #[error("Non-finite value: {0}")]
+    NonFiniteValue(String),
     #[error("AtlasError: {0}")]
     AtlasError(#[from] AtlasError),
+    #[error("line {}, col {}: {}", .0.line(), .0.column(), .1)]
+    Located(Span, Box<ScriptingError>),
+}
+
+impl ScriptingError {
+    /// # with_span
+    /// Attach the location of the statement that raised the error, keeping the innermost one
+    pub fn with_span(self, span: Span) -> ScriptingError {
+        match self {
+            ScriptingError::Located(_, _) => self,
+            _ => ScriptingError::Located(span, Box::new(self)),
+        }
+    }
+
+    /// # is_assertion_failure
+    /// Whether the error was raised by a failed `assert`, located or not
+    pub fn is_assertion_failure(&self) -> bool {
+        match self {
+            ScriptingError::AssertionFailed(_) => true,
+            ScriptingError::Located(_, inner) => inner.is_assertion_failure(),
+            _ => false,
+        }
+    }
+
+    /// # is_non_finite
+    /// Whether the error was raised by an infinite or NaN value, located or not
+    pub fn is_non_finite(&self) -> bool {
+        match self {
+            ScriptingError::NonFiniteValue(_) => true,
+            ScriptingError::Located(_, inner) => inner.is_non_finite(),
+            _ => false,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, ScriptingError>;
@@ -0,0 +1,142 @@
+use rustatlas::math::ad::num::Real;
+
+use crate::utils::errors::{Result, ScriptingError};
+
+/// # Matrix
+/// Dense row-major matrix used by the linear algebra builtins.
+pub type Matrix<T> = Vec<Vec<T>>;
+
+fn dimensions<T>(m: &Matrix<T>) -> Result<(usize, usize)> {
+    let cols = m.first().map(|row| row.len()).unwrap_or(0);
+    if m.iter().any(|row| row.len() != cols) {
+        return Err(ScriptingError::EvaluationError(
+            "Matrix rows must have the same length".to_string(),
+        ));
+    }
+    Ok((m.len(), cols))
+}
+
+/// # transpose
+/// Transpose a matrix.
+pub fn transpose<T: Real>(m: &Matrix<T>) -> Result<Matrix<T>> {
+    let (rows, cols) = dimensions(m)?;
+    Ok((0..cols)
+        .map(|j| (0..rows).map(|i| m[i][j]).collect())
+        .collect())
+}
+
+/// # matmul
+/// Multiply two matrices.
+pub fn matmul<T: Real>(a: &Matrix<T>, b: &Matrix<T>) -> Result<Matrix<T>> {
+    let (rows, inner) = dimensions(a)?;
+    let (b_rows, cols) = dimensions(b)?;
+    if inner != b_rows {
+        return Err(ScriptingError::EvaluationError(format!(
+            "Cannot multiply a {}x{} matrix by a {}x{} matrix",
+            rows, inner, b_rows, cols
+        )));
+    }
+    Ok((0..rows)
+        .map(|i| {
+            (0..cols)
+                .map(|j| {
+                    (0..inner).fold(T::from(0.0), |acc, k| acc + a[i][k] * b[k][j])
+                })
+                .collect()
+        })
+        .collect())
+}
+
+/// # solve
+/// Solve `a * x = b` by Gaussian elimination with partial pivoting.
+/// `b` may hold several right-hand sides as columns.
+pub fn solve<T: Real>(a: &Matrix<T>, b: &Matrix<T>) -> Result<Matrix<T>> {
+    let (n, cols) = dimensions(a)?;
+    let (b_rows, rhs) = dimensions(b)?;
+    if n != cols {
+        return Err(ScriptingError::EvaluationError(
+            "Cannot solve a non-square system".to_string(),
+        ));
+    }
+    if b_rows != n {
+        return Err(ScriptingError::EvaluationError(format!(
+            "Right-hand side has {} rows, expected {}",
+            b_rows, n
+        )));
+    }
+
+    let mut a = a.clone();
+    let mut x = b.clone();
+    for k in 0..n {
+        let pivot = (k..n)
+            .max_by(|&i, &j| {
+                a[i][k]
+                    .abs()
+                    .partial_cmp(&a[j][k].abs())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap();
+        if a[pivot][k].abs() < T::from(1e-14) {
+            return Err(ScriptingError::EvaluationError(
+                "Matrix is singular".to_string(),
+            ));
+        }
+        a.swap(k, pivot);
+        x.swap(k, pivot);
+
+        for i in (k + 1)..n {
+            let factor = a[i][k] / a[k][k];
+            for j in k..n {
+                a[i][j] = a[i][j] - factor * a[k][j];
+            }
+            for j in 0..rhs {
+                x[i][j] = x[i][j] - factor * x[k][j];
+            }
+        }
+    }
+
+    for k in (0..n).rev() {
+        for j in 0..rhs {
+            let sum = ((k + 1)..n).fold(x[k][j], |acc, i| acc - a[k][i] * x[i][j]);
+            x[k][j] = sum / a[k][k];
+        }
+    }
+    Ok(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transpose() {
+        let m = vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]];
+        let t = transpose(&m).unwrap();
+        assert_eq!(t, vec![vec![1.0, 4.0], vec![2.0, 5.0], vec![3.0, 6.0]]);
+    }
+
+    #[test]
+    fn test_matmul() {
+        let a = vec![vec![1.0, 2.0], vec![3.0, 4.0]];
+        let b = vec![vec![5.0], vec![6.0]];
+        let c = matmul(&a, &b).unwrap();
+        assert_eq!(c, vec![vec![17.0], vec![39.0]]);
+        assert!(matmul(&b, &b).is_err());
+    }
+
+    #[test]
+    fn test_solve() {
+        let a = vec![vec![0.0, 2.0], vec![1.0, 1.0]];
+        let b = vec![vec![4.0], vec![3.0]];
+        let x = solve(&a, &b).unwrap();
+        assert!((x[0][0] - 1.0_f64).abs() < 1e-12);
+        assert!((x[1][0] - 2.0_f64).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_solve_singular() {
+        let a = vec![vec![1.0, 2.0], vec![2.0, 4.0]];
+        let b = vec![vec![1.0], vec![2.0]];
+        assert!(solve(&a, &b).is_err());
+    }
+}
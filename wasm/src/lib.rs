@@ -10,6 +10,7 @@ use lefi::prelude::*;
 use rustatlas::math::ad::{backward, reset_tape, Var};
 use rustatlas::prelude::*;
 use rustatlas::models::deterministicmodel::DeterministicModel;
+use rustatlas::models::stochasticmodel::StochasticModel;
 use serde::{Deserialize, Serialize};
 use serde_json;
 use wasm_bindgen::prelude::*;
@@ -54,11 +55,21 @@ struct FxInput {
     value: f64,
 }
 
+#[derive(Deserialize)]
+struct VolatilitySurfaceInput {
+    name: String,
+    expiries: Vec<f64>,
+    moneyness: Vec<f64>,
+    volatilities: Vec<Vec<f64>>,
+}
+
 #[derive(Deserialize)]
 struct MarketDataInput {
     reference_date: Date,
     fx: Vec<FxInput>,
     curves: Vec<CurveInput>,
+    #[serde(default)]
+    volatility_surfaces: Vec<VolatilitySurfaceInput>,
 }
 
 #[derive(Deserialize)]
@@ -72,10 +83,26 @@ struct ScriptDataInput {
     events: Vec<ScriptEventInput>,
 }
 
+/// Model generating the scenarios of a pricing request, the market as of today by default
+#[derive(Deserialize, Default)]
+#[serde(tag = "type")]
+enum ModelInput {
+    #[default]
+    Simple,
+    LocalVol {
+        simulations: usize,
+        /// add the antithetic scenario of each simulation
+        #[serde(default)]
+        antithetic: bool,
+    },
+}
+
 #[derive(Deserialize)]
 struct PricingRequest {
     market_data: MarketDataInput,
     script_data: ScriptDataInput,
+    #[serde(default)]
+    model: ModelInput,
 }
 
 #[derive(Deserialize)]
@@ -140,7 +167,7 @@ pub fn run_pricing(script_json: &str) -> std::result::Result<JsValue, JsValue> {
 
     let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
     evaluator
-        .const_visit(expr)
+        .const_visit(&expr)
         .map_err(|e| JsValue::from_str(&format!("{e}")))?;
 
     let mut map = HashMap::new();
@@ -179,7 +206,7 @@ pub fn run_pricing_with_risk(
     // First pass with f64 to obtain values
     let evaluator = ExprEvaluator::new().with_variables(indexer.get_variables_size());
     evaluator
-        .const_visit(expr.clone())
+        .const_visit(&expr)
         .map_err(|e| JsValue::from_str(&format!("{e}")))?;
     let values = evaluator.variables();
 
@@ -196,7 +223,7 @@ pub fn run_pricing_with_risk(
     }
 
     evaluator_ad
-        .const_visit(expr)
+        .const_visit(&expr)
         .map_err(|e| JsValue::from_str(&format!("{e}")))?;
     let vars_ad = evaluator_ad.variables();
     let price_var = match vars_ad.get(target_idx) {
@@ -329,6 +356,21 @@ fn build_market_store(
     Ok((store, local_ccy))
 }
 
+fn build_historical_data(data: &MarketDataInput) -> std::result::Result<HistoricalData, JsValue> {
+    data.volatility_surfaces.iter().try_fold(
+        HistoricalData::new(data.reference_date),
+        |historical_data, s| {
+            let surface = VolatilitySurface::new(
+                s.expiries.clone(),
+                s.moneyness.clone(),
+                s.volatilities.clone(),
+            )
+            .map_err(|e| JsValue::from_str(&format!("{e}")))?;
+            Ok(historical_data.with_volatility_surface(&s.name, surface))
+        },
+    )
+}
+
 // ------------------------------------------------------------
 // Core evaluation using provided market data and script events
 // ------------------------------------------------------------
@@ -339,6 +381,7 @@ pub fn run_event_pricing(json: &str) -> std::result::Result<JsValue, JsValue> {
         serde_json::from_str(json).map_err(|e| JsValue::from_str(&e.to_string()))?;
 
     // ----- Build MarketStore -----
+    let historical_data = build_historical_data(&input.market_data)?;
     let (store, local_ccy) = build_market_store(input.market_data)?;
 
     // ----- Parse events -----
@@ -355,13 +398,48 @@ pub fn run_event_pricing(json: &str) -> std::result::Result<JsValue, JsValue> {
     indexer
         .visit_events(&events)
         .map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    TypeChecker::new(indexer.get_variables_size())
+        .visit_events(&events)
+        .map_err(|e| JsValue::from_str(&format!("{e}")))?;
 
     let requests = indexer.get_market_requests();
     let model = SimpleModel::new(&store);
-    let scenario = model
-        .gen_market_data(&requests)
-        .map_err(|e| JsValue::from_str(&format!("{e}")))?;
-    let scenarios = vec![scenario];
+    let scenarios = match input.model {
+        ModelInput::Simple => vec![model.gen_market_data(&requests)],
+        ModelInput::LocalVol { simulations: 0, .. } => {
+            return Err(JsValue::from_str("at least one simulation is needed"))
+        }
+        ModelInput::LocalVol {
+            simulations,
+            antithetic: false,
+        } => {
+            let local_vol = LocalVolModel::new(model, &historical_data);
+            (0..simulations as u64)
+                .map(|seed| local_vol.clone().with_seed(seed).gen_scenario(&requests))
+                .collect()
+        }
+        ModelInput::LocalVol {
+            simulations,
+            antithetic: true,
+        } => {
+            let local_vol = LocalVolModel::new(model, &historical_data);
+            let mut scenarios = Vec::with_capacity(2 * simulations);
+            for seed in 0..simulations as u64 {
+                let pair = local_vol.clone().with_seed(seed);
+                match pair.gen_scenario_pair(&requests) {
+                    Ok((plain, antithetic)) => {
+                        scenarios.push(Ok(plain));
+                        scenarios.push(Ok(antithetic));
+                    }
+                    Err(e) => scenarios.push(Err(e)),
+                }
+            }
+            scenarios
+        }
+    }
+    .into_iter()
+    .collect::<Result<Vec<_>>>()
+    .map_err(|e| JsValue::from_str(&format!("{e}")))?;
 
     let evaluator =
         EventStreamEvaluator::new(indexer.get_variables_size()).with_scenarios(&scenarios);